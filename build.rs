@@ -0,0 +1,16 @@
+use std::{env, fs, io, path::PathBuf};
+
+#[path = "src/cli.rs"]
+mod cli;
+
+// Renders the man page from the CLI definition into `OUT_DIR`.
+fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=src/cli.rs");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").ok_or(io::ErrorKind::NotFound)?);
+    let mut buffer = Vec::new();
+    clap_mangen::Man::new(cli::command()).render(&mut buffer)?;
+    fs::write(out_dir.join("payments-engine.1"), buffer)?;
+
+    Ok(())
+}
@@ -1,10 +1,12 @@
+use std::collections::HashMap;
+
 use bigdecimal::{BigDecimal, Zero};
 
 use crate::error::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Account {
     client_id: u16,
     available: BigDecimal,
@@ -82,14 +84,50 @@ impl Account {
     }
 }
 
-#[cfg(test)]
+// Engine-level suspense account receiving the funds charged back from client accounts, so the
+// books stay balanced and the losses can be reported per client.
+#[derive(Debug, Clone, Default)]
+pub struct ClearingAccount {
+    balance: BigDecimal,
+    by_client: HashMap<u16, BigDecimal>,
+}
+
+impl ClearingAccount {
+    pub fn credit(&mut self, client_id: u16, amount: &BigDecimal) {
+        self.balance += amount;
+        *self.by_client.entry(client_id).or_default() += amount;
+    }
+
+    pub fn balance(&self) -> BigDecimal {
+        self.balance.clone()
+    }
+
+    // Funds charged back from the given client.
+    pub fn client_balance(&self, client_id: u16) -> BigDecimal {
+        self.by_client
+            .get(&client_id)
+            .cloned()
+            .unwrap_or_else(BigDecimal::zero)
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = (&u16, &BigDecimal)> {
+        self.by_client.iter()
+    }
 
+    pub fn merge(&mut self, other: &ClearingAccount) {
+        for (client_id, amount) in other.clients() {
+            self.credit(*client_id, amount);
+        }
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use bigdecimal::{BigDecimal, One, Zero};
 
     use crate::error::Error;
 
-    use super::Account;
+    use super::{Account, ClearingAccount};
 
     #[test]
     fn add_available_success() {
@@ -136,8 +174,30 @@ mod tests {
     #[test]
     fn set_locked() {
         let mut account = Account::new_unlocked(0);
-        assert_eq!(account.is_locked(), false);
+        assert!(!account.is_locked());
         account.set_locked(true);
-        assert_eq!(account.is_locked(), true);
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn clearing_credit() {
+        let mut clearing = ClearingAccount::default();
+        clearing.credit(1, &BigDecimal::from(10));
+        clearing.credit(2, &BigDecimal::one());
+        clearing.credit(1, &BigDecimal::one());
+        assert_eq!(clearing.balance(), BigDecimal::from(12));
+        assert_eq!(clearing.client_balance(1), BigDecimal::from(11));
+        assert_eq!(clearing.client_balance(3), BigDecimal::zero());
+    }
+
+    #[test]
+    fn clearing_merge() {
+        let mut clearing = ClearingAccount::default();
+        clearing.credit(1, &BigDecimal::from(10));
+        let mut other = ClearingAccount::default();
+        other.credit(2, &BigDecimal::one());
+        clearing.merge(&other);
+        assert_eq!(clearing.balance(), BigDecimal::from(11));
+        assert_eq!(clearing.client_balance(2), BigDecimal::one());
     }
 }
@@ -0,0 +1,198 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use bigdecimal::{BigDecimal, Zero};
+use csv_async::AsyncWriterBuilder;
+use serde::Serialize;
+use tokio::io::AsyncWrite;
+
+use crate::{
+    format::format_amount,
+    outcome::TxOutcome,
+    payments::{Tx, TxType},
+    plugin::Plugin,
+};
+
+// Width of the time buckets of an activity report, transaction timestamps being seconds since
+// the epoch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bucket {
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    pub fn seconds(self) -> u64 {
+        match self {
+            Bucket::Hour => 60 * 60,
+            Bucket::Day => 24 * 60 * 60,
+        }
+    }
+
+    // Start of the bucket holding the timestamp.
+    pub fn start(self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.seconds()
+    }
+}
+
+// Activity of the transactions applied within a time bucket.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BucketTotals {
+    pub deposits: u64,
+    pub deposited: BigDecimal,
+    pub withdrawals: u64,
+    pub withdrawn: BigDecimal,
+    pub disputes: u64,
+}
+
+impl BucketTotals {
+    // Deposited minus withdrawn funds.
+    pub fn net_flow(&self) -> BigDecimal {
+        &self.deposited - &self.withdrawn
+    }
+}
+
+// Row of the activity report.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ActivityRow {
+    // Start of the bucket, in seconds since the epoch.
+    pub bucket: u64,
+    pub deposits: u64,
+    pub deposited: String,
+    pub withdrawals: u64,
+    pub withdrawn: String,
+    pub disputes: u64,
+    pub net_flow: String,
+}
+
+#[derive(Default)]
+struct Buckets {
+    totals: BTreeMap<u64, BucketTotals>,
+    // Applied transactions without a timestamp, which don't belong to any bucket.
+    untimed: u64,
+}
+
+// Plugin summarizing the applied transactions per time bucket of their `timestamp`: deposit and
+// withdrawal volumes, dispute counts and net flow, e.g. for capacity planning or to spot
+// anomalies. Clones share the same buckets, so a clone can be kept around for reporting after
+// registering the plugin.
+#[derive(Clone)]
+pub struct Activity {
+    bucket: Bucket,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl Activity {
+    pub fn new(bucket: Bucket) -> Self {
+        Activity {
+            bucket,
+            buckets: Arc::new(Mutex::new(Buckets::default())),
+        }
+    }
+
+    // Totals of every bucket with activity, by bucket start.
+    pub fn buckets(&self) -> BTreeMap<u64, BucketTotals> {
+        self.buckets.lock().unwrap().totals.clone()
+    }
+
+    pub fn untimed(&self) -> u64 {
+        self.buckets.lock().unwrap().untimed
+    }
+
+    // Writes the buckets with activity as CSV, in chronological order.
+    pub async fn write<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut serializer = AsyncWriterBuilder::new().create_serializer(writer);
+        for (bucket, totals) in self.buckets() {
+            serializer
+                .serialize(ActivityRow {
+                    bucket,
+                    deposits: totals.deposits,
+                    deposited: format_amount(totals.deposited.clone()),
+                    withdrawals: totals.withdrawals,
+                    withdrawn: format_amount(totals.withdrawn.clone()),
+                    disputes: totals.disputes,
+                    net_flow: format_amount(totals.net_flow()),
+                })
+                .await?;
+        }
+        serializer.flush().await
+    }
+}
+
+impl Plugin for Activity {
+    fn name(&self) -> &str {
+        "activity"
+    }
+
+    fn on_outcome(&self, tx: &Tx, outcome: &TxOutcome) {
+        if *outcome != TxOutcome::Applied {
+            return;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        let Some(timestamp) = tx.timestamp() else {
+            buckets.untimed += 1;
+            return;
+        };
+        let totals = buckets
+            .totals
+            .entry(self.bucket.start(timestamp))
+            .or_default();
+        let amount = || tx.amount().cloned().unwrap_or_else(BigDecimal::zero);
+        match tx.tx_type() {
+            TxType::Deposit => {
+                totals.deposits += 1;
+                totals.deposited += amount();
+            }
+            TxType::Withdrawal => {
+                totals.withdrawals += 1;
+                totals.withdrawn += amount();
+            }
+            TxType::Dispute => totals.disputes += 1,
+            TxType::Resolve | TxType::Chargeback => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{Activity, Bucket};
+
+    #[tokio::test]
+    async fn hourly_activity() {
+        let activity = Activity::new(Bucket::Hour);
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(activity.clone())
+        .build()
+        .unwrap();
+
+        engine
+            .handle_txs(
+                "type,client,tx,amount,timestamp\ndeposit,1,1,2.0,3600\ndeposit,1,2,3.0,3700\n\
+                withdrawal,1,3,1.5,7200\ndispute,1,1,,7300\nwithdrawal,2,4,1.0,7400\n\
+                deposit,2,5,1.0,"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        // The withdrawal of client 2 was rejected.
+        let mut output = Vec::new();
+        activity.write(&mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "bucket,deposits,deposited,withdrawals,withdrawn,disputes,net_flow\n\
+            3600,2,5.0,0,0,0,5.0\n7200,0,0,1,1.5,1,-1.5\n"
+        );
+        assert_eq!(activity.untimed(), 1);
+    }
+}
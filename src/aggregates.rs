@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::{
+    account::Account,
+    payments::{Tx, TxType},
+    plugin::Plugin,
+};
+
+// Activity of a client over the transactions applied in this run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientTotals {
+    pub deposits: u64,
+    pub deposited: BigDecimal,
+    pub withdrawals: u64,
+    pub withdrawn: BigDecimal,
+    pub disputes: u64,
+    pub disputed: BigDecimal,
+    pub resolves: u64,
+    pub resolved: BigDecimal,
+    pub chargebacks: u64,
+    pub charged_back: BigDecimal,
+    // Held balance after the last transaction, from which dispute amounts are derived.
+    held: BigDecimal,
+}
+
+impl ClientTotals {
+    pub fn open_disputes(&self) -> u64 {
+        self.disputes - self.resolves - self.chargebacks
+    }
+}
+
+// Dispute activity over all the clients.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DisputeTotals {
+    pub disputes: u64,
+    pub disputed: BigDecimal,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    pub charged_back: BigDecimal,
+    // Amount still held by open disputes.
+    pub open: BigDecimal,
+}
+
+#[derive(Default)]
+struct Models {
+    clients: HashMap<u16, ClientTotals>,
+    disputes: DisputeTotals,
+}
+
+// Plugin maintaining denormalized per-client and dispute aggregates, updated as transactions get
+// applied, so they can be queried (or reported at the end of the run) without scanning the
+// ledgers. Clones share the same aggregates, so a clone can be kept around for querying after
+// registering the plugin.
+#[derive(Clone, Default)]
+pub struct Aggregates(Arc<Mutex<Models>>);
+
+impl Aggregates {
+    pub fn client(&self, client: u16) -> Option<ClientTotals> {
+        self.0.lock().unwrap().clients.get(&client).cloned()
+    }
+
+    pub fn clients(&self) -> HashMap<u16, ClientTotals> {
+        self.0.lock().unwrap().clients.clone()
+    }
+
+    pub fn disputes(&self) -> DisputeTotals {
+        self.0.lock().unwrap().disputes.clone()
+    }
+}
+
+impl Plugin for Aggregates {
+    fn name(&self) -> &str {
+        "aggregates"
+    }
+
+    fn on_account(&self, tx: &Tx, account: &Account) {
+        let mut models = self.0.lock().unwrap();
+        let Models { clients, disputes } = &mut *models;
+        let totals = clients.entry(account.client_id()).or_default();
+        let held = account.held();
+        // Disputes, resolves and chargebacks carry no amount: it's the change of the held balance.
+        let moved = (&held - &totals.held).abs();
+        match tx.tx_type() {
+            TxType::Deposit => {
+                totals.deposits += 1;
+                totals.deposited += tx.amount().cloned().unwrap_or_else(BigDecimal::zero);
+            }
+            TxType::Withdrawal => {
+                totals.withdrawals += 1;
+                totals.withdrawn += tx.amount().cloned().unwrap_or_else(BigDecimal::zero);
+            }
+            TxType::Dispute => {
+                totals.disputes += 1;
+                totals.disputed += &moved;
+                disputes.disputes += 1;
+                disputes.disputed += &moved;
+                disputes.open += moved;
+            }
+            TxType::Resolve => {
+                totals.resolves += 1;
+                totals.resolved += &moved;
+                disputes.resolves += 1;
+                disputes.open -= moved;
+            }
+            TxType::Chargeback => {
+                totals.chargebacks += 1;
+                totals.charged_back += &moved;
+                disputes.chargebacks += 1;
+                disputes.charged_back += &moved;
+                disputes.open -= moved;
+            }
+        }
+        totals.held = held;
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        let models = self.0.lock().unwrap();
+        let disputes = &models.disputes;
+        vec![
+            ("clients".to_string(), models.clients.len().to_string()),
+            ("disputes".to_string(), disputes.disputes.to_string()),
+            ("disputed".to_string(), disputes.disputed.to_string()),
+            ("resolves".to_string(), disputes.resolves.to_string()),
+            ("chargebacks".to_string(), disputes.chargebacks.to_string()),
+            (
+                "charged_back".to_string(),
+                disputes.charged_back.to_string(),
+            ),
+            ("open_disputed".to_string(), disputes.open.to_string()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::Aggregates;
+
+    #[tokio::test]
+    async fn aggregates_activity() {
+        let aggregates = Aggregates::default();
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(aggregates.clone())
+        .build()
+        .unwrap();
+
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,2,3.0\ndispute,1,1,\n\
+                dispute,1,2,\nresolve,1,1,\nwithdrawal,1,3,1.0\ndeposit,2,4,4.0\ndispute,2,4,\n\
+                chargeback,2,4,\nwithdrawal,2,5,1.0"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let amount = |amount| BigDecimal::from_str(amount).unwrap();
+        let client = aggregates.client(1).unwrap();
+        assert_eq!(
+            (client.deposits, &client.deposited, client.withdrawals),
+            (2, &amount("5.0"), 1)
+        );
+        assert_eq!(
+            (client.open_disputes(), &client.disputed, &client.resolved),
+            (1, &amount("5.0"), &amount("2.0"))
+        );
+        // The withdrawal of the locked account was rejected.
+        assert_eq!(aggregates.client(2).unwrap().withdrawals, 0);
+
+        let disputes = aggregates.disputes();
+        assert_eq!(
+            (disputes.disputes, disputes.resolves, disputes.chargebacks),
+            (3, 1, 1)
+        );
+        assert_eq!(
+            (disputes.charged_back, disputes.open),
+            (amount("4.0"), amount("3.0"))
+        );
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+
+use crate::{
+    account::Account,
+    outcome::TxOutcome,
+    payments::{Tx, TxType},
+    plugin::Plugin,
+};
+
+// Condition raising an alert while processing. Windows are measured in processed transactions,
+// since the input carries no timestamps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertRule {
+    // The held funds of a client went above the amount.
+    HeldAbove(BigDecimal),
+    // More than `count` chargebacks among the last `window` transactions.
+    Chargebacks { count: u64, window: u64 },
+    // More than `count` accounts got locked among the last `window` transactions.
+    LockedAccounts { count: u64, window: u64 },
+}
+
+impl AlertRule {
+    fn name(&self) -> &'static str {
+        match self {
+            AlertRule::HeldAbove(_) => "held_above",
+            AlertRule::Chargebacks { .. } => "chargebacks",
+            AlertRule::LockedAccounts { .. } => "locked_accounts",
+        }
+    }
+}
+
+fn parse_threshold(value: &str) -> Option<(u64, u64)> {
+    let (count, window) = value.split_once('/')?;
+    Some((count.parse().ok()?, window.parse().ok()?))
+}
+
+// Parses rules like `held_above=1000`, `chargebacks=5/1000` or `locked_accounts=3/1000`, the
+// latter meaning more than 3 locked accounts among 1000 consecutive transactions.
+impl FromStr for AlertRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid alert rule: {rule}");
+        let (name, value) = rule.split_once('=').ok_or_else(invalid)?;
+        match name {
+            "held_above" => BigDecimal::from_str(value)
+                .map(AlertRule::HeldAbove)
+                .map_err(|_| invalid()),
+            "chargebacks" => parse_threshold(value)
+                .map(|(count, window)| AlertRule::Chargebacks { count, window })
+                .ok_or_else(invalid),
+            "locked_accounts" => parse_threshold(value)
+                .map(|(count, window)| AlertRule::LockedAccounts { count, window })
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Destination of the raised alerts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertSink {
+    Stderr,
+    // Appends alerts as JSON lines.
+    File(PathBuf),
+    // Posts alerts as JSON.
+    #[cfg(feature = "webhooks")]
+    Webhook(String),
+}
+
+// Parses `stderr`, `file:<path>` or `webhook:<url>` (the latter requires the `webhooks` feature).
+impl FromStr for AlertSink {
+    type Err = String;
+
+    fn from_str(sink: &str) -> Result<Self, Self::Err> {
+        if sink == "stderr" {
+            return Ok(AlertSink::Stderr);
+        }
+        if let Some(path) = sink.strip_prefix("file:") {
+            return Ok(AlertSink::File(PathBuf::from(path)));
+        }
+        #[cfg(feature = "webhooks")]
+        if let Some(url) = sink.strip_prefix("webhook:") {
+            return Ok(AlertSink::Webhook(url.to_string()));
+        }
+        Err(format!("Invalid alert sink: {sink}"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub rule: &'static str,
+    pub tx: u32,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct AlertState {
+    seq: u64,
+    chargebacks: VecDeque<u64>,
+    locks: VecDeque<u64>,
+    held_above: HashSet<u16>,
+}
+
+// Counts the events of the last `window` transactions, returning whether `count` was just exceeded.
+fn exceeded(events: &mut VecDeque<u64>, seq: u64, count: u64, window: u64) -> bool {
+    while events.front().is_some_and(|event| seq - event >= window) {
+        events.pop_front();
+    }
+    events.len() as u64 == count + 1
+}
+
+// Plugin evaluating the alert rules on every transaction and emitting the raised alerts to all the
+// configured sinks. Each alert is raised once when its rule's threshold gets crossed, rather than
+// for every transaction past it.
+pub struct Alerting {
+    rules: Vec<AlertRule>,
+    sinks: Vec<AlertSink>,
+    state: Mutex<AlertState>,
+    raised: AtomicU64,
+}
+
+impl Alerting {
+    pub fn new(rules: Vec<AlertRule>, sinks: Vec<AlertSink>) -> Self {
+        Alerting {
+            rules,
+            sinks,
+            state: Mutex::new(AlertState::default()),
+            raised: AtomicU64::new(0),
+        }
+    }
+
+    pub fn raised(&self) -> u64 {
+        self.raised.load(Ordering::Relaxed)
+    }
+
+    fn raise(&self, alert: Alert) {
+        self.raised.fetch_add(1, Ordering::Relaxed);
+        for sink in self.sinks.iter() {
+            match sink {
+                AlertSink::Stderr => {
+                    eprintln!("ALERT [{}] tx {}: {}", alert.rule, alert.tx, alert.message)
+                }
+                AlertSink::File(path) => {
+                    let res = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .and_then(|mut file| {
+                            let line =
+                                serde_json::to_string(&alert).map_err(std::io::Error::from)?;
+                            writeln!(file, "{line}")
+                        });
+                    if let Err(err) = res {
+                        tracing::warn!("Error while writing alert to {path:?}: {err}");
+                    }
+                }
+                #[cfg(feature = "webhooks")]
+                AlertSink::Webhook(url) => post_alert(url.clone(), alert.clone()),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "webhooks")]
+fn post_alert(url: String, alert: Alert) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        tracing::warn!("Alert to {url} not posted outside of a runtime");
+        return;
+    };
+    runtime.spawn(async move {
+        let res = reqwest::Client::new().post(&url).json(&alert).send().await;
+        if let Err(err) = res.and_then(|response| response.error_for_status()) {
+            tracing::warn!("Error while posting alert to {url}: {err}");
+        }
+    });
+}
+
+impl Plugin for Alerting {
+    fn name(&self) -> &str {
+        "alerts"
+    }
+
+    fn on_account(&self, tx: &Tx, account: &Account) {
+        let mut alerts = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            for rule in self.rules.iter() {
+                if let AlertRule::HeldAbove(threshold) = rule {
+                    let held = account.held();
+                    if &held <= threshold {
+                        state.held_above.remove(&account.client_id());
+                    } else if state.held_above.insert(account.client_id()) {
+                        alerts.push(Alert {
+                            rule: rule.name(),
+                            tx: tx.id(),
+                            message: format!(
+                                "Client {} holds {held}, above {threshold}",
+                                account.client_id()
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+        alerts.into_iter().for_each(|alert| self.raise(alert));
+    }
+
+    fn on_outcome(&self, tx: &Tx, outcome: &TxOutcome) {
+        let mut alerts = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.seq += 1;
+            let seq = state.seq;
+            if tx.tx_type() == &TxType::Chargeback {
+                state.chargebacks.push_back(seq);
+                // Successful chargebacks lock the client's account.
+                if outcome.is_applied() {
+                    state.locks.push_back(seq);
+                }
+            }
+            for rule in self.rules.iter() {
+                let (events, count, window, what) = match rule {
+                    AlertRule::Chargebacks { count, window } => {
+                        (&mut state.chargebacks, *count, *window, "chargebacks")
+                    }
+                    AlertRule::LockedAccounts { count, window } => {
+                        (&mut state.locks, *count, *window, "locked accounts")
+                    }
+                    AlertRule::HeldAbove(_) => continue,
+                };
+                if exceeded(events, seq, count, window) {
+                    alerts.push(Alert {
+                        rule: rule.name(),
+                        tx: tx.id(),
+                        message: format!("More than {count} {what} in the last {window} txs"),
+                    });
+                }
+            }
+        }
+        alerts.into_iter().for_each(|alert| self.raise(alert));
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        vec![("raised".to_string(), self.raised().to_string())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{AlertRule, AlertSink, Alerting};
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(
+            AlertRule::from_str("held_above=10.5"),
+            Ok(AlertRule::HeldAbove(BigDecimal::from_str("10.5").unwrap()))
+        );
+        assert_eq!(
+            AlertRule::from_str("chargebacks=2/100"),
+            Ok(AlertRule::Chargebacks {
+                count: 2,
+                window: 100
+            })
+        );
+        assert!(AlertRule::from_str("locked_accounts=2").is_err());
+        assert!(AlertRule::from_str("unknown=1").is_err());
+        assert_eq!(AlertSink::from_str("stderr"), Ok(AlertSink::Stderr));
+        assert!(AlertSink::from_str("file:").is_ok());
+        assert!(AlertSink::from_str("syslog").is_err());
+    }
+
+    #[tokio::test]
+    async fn raises_alerts_once_per_crossing() {
+        let path = std::env::temp_dir().join("payments-engine-alerts.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let alerting = Alerting::new(
+            vec![
+                AlertRule::from_str("held_above=1").unwrap(),
+                AlertRule::from_str("locked_accounts=1/10").unwrap(),
+            ],
+            vec![AlertSink::File(path.clone())],
+        );
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(alerting)
+        .build()
+        .unwrap();
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,2,2.0\ndispute,1,1,\n\
+                dispute,1,2,\ndeposit,2,3,1.0\ndispute,2,3,\nchargeback,2,3,\nchargeback,1,1,"
+                    .as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        let alerts = std::fs::read_to_string(&path).unwrap();
+        let rules: Vec<&str> = alerts
+            .lines()
+            .map(|line| line.split('"').nth(3).unwrap())
+            .collect();
+        assert_eq!(rules, vec!["held_above", "locked_accounts"]);
+        assert_eq!(
+            engine.plugin_reports(),
+            vec![("alerts", vec![("raised".to_string(), "2".to_string())])]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
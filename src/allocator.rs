@@ -0,0 +1,75 @@
+// Global allocator of the binary, counting the allocations made through it for the stats of the
+// run with the `alloc-stats` feature. The counted allocator is mimalloc or jemalloc with the
+// features of the same name, the system one otherwise.
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+#[cfg(feature = "mimalloc")]
+pub const NAME: &str = "mimalloc";
+#[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
+pub const NAME: &str = "jemalloc";
+#[cfg(not(any(feature = "mimalloc", feature = "jemalloc")))]
+pub const NAME: &str = "system";
+
+// Whether allocations are counted, `allocations` and `allocated_bytes` staying at zero otherwise.
+pub const COUNTED: bool = cfg!(any(test, feature = "alloc-stats"));
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub struct Counting<A>(pub A);
+
+impl<A> Counting<A> {
+    fn count(&self, size: usize) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Counting<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.count(layout.size());
+        self.0.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.count(layout.size());
+        self.0.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+
+    // Reallocations are counted as allocations of the new size.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.count(new_size);
+        self.0.realloc(ptr, layout, new_size)
+    }
+}
+
+// Number of allocations made since the start of the process.
+pub fn allocations() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+// Total size of the allocations made since the start of the process, in bytes.
+pub fn allocated_bytes() -> u64 {
+    ALLOCATED_BYTES.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allocated_bytes, allocations};
+
+    #[test]
+    fn counts_allocations() {
+        let (before, before_bytes) = (allocations(), allocated_bytes());
+        let buf = std::hint::black_box(vec![0u8; 4096]);
+        assert!(allocations() > before);
+        assert!(allocated_bytes() >= before_bytes + 4096);
+        drop(buf);
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use bigdecimal::BigDecimal;
+use tracing::warn;
+
+use crate::{error::Error, outcome::TxOutcome, payments::Tx, plugin::Plugin};
+
+// Number of recent amounts per client the median is computed over.
+const HISTORY: usize = 100;
+// Number of amounts a client needs before its transactions get checked against its median.
+const MIN_HISTORY: usize = 3;
+
+// Plugin enforcing an absolute bound on deposit and withdrawal amounts, and flagging amounts which
+// are likely unit errors (at least `suspicious_factor` times the median of the client's recent
+// amounts). Flagged transactions are still applied, being only reported apart from rejections,
+// unless their clients get placed under review.
+pub struct AmountChecks {
+    max_amount: Option<BigDecimal>,
+    suspicious_factor: Option<BigDecimal>,
+    review_flagged: bool,
+    history: Mutex<HashMap<u16, VecDeque<BigDecimal>>>,
+    // Flagged transactions still being handled.
+    pending_review: Mutex<HashSet<u32>>,
+    rejected: AtomicU64,
+    flagged: AtomicU64,
+}
+
+impl AmountChecks {
+    pub fn new(max_amount: Option<BigDecimal>, suspicious_factor: Option<u64>) -> Self {
+        AmountChecks {
+            max_amount,
+            suspicious_factor: suspicious_factor.map(BigDecimal::from),
+            review_flagged: false,
+            history: Mutex::new(HashMap::new()),
+            pending_review: Mutex::new(HashSet::new()),
+            rejected: AtomicU64::new(0),
+            flagged: AtomicU64::new(0),
+        }
+    }
+
+    // Places the clients of flagged transactions under review.
+    pub fn review_flagged(mut self) -> Self {
+        self.review_flagged = true;
+        self
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn flagged(&self) -> u64 {
+        self.flagged.load(Ordering::Relaxed)
+    }
+
+    // Records the amount in the client's history, returning whether it is suspicious.
+    fn record(&self, client: u16, amount: &BigDecimal) -> bool {
+        let mut history = self.history.lock().unwrap();
+        let amounts = history.entry(client).or_default();
+        let suspicious = match &self.suspicious_factor {
+            Some(factor) if amounts.len() >= MIN_HISTORY => {
+                let mut sorted: Vec<&BigDecimal> = amounts.iter().collect();
+                sorted.sort();
+                let median = sorted[sorted.len() / 2];
+                amount >= &(median * factor)
+            }
+            _ => false,
+        };
+        if amounts.len() == HISTORY {
+            amounts.pop_front();
+        }
+        amounts.push_back(amount.clone());
+        suspicious
+    }
+}
+
+impl Plugin for AmountChecks {
+    fn name(&self) -> &str {
+        "amounts"
+    }
+
+    fn on_tx(&self, tx: &Tx) -> Result<(), Error> {
+        let Some(amount) = tx.amount().filter(|_| tx.storable()) else {
+            return Ok(());
+        };
+        if self.max_amount.as_ref().is_some_and(|max| amount > max) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::AmountAboveLimit(tx.id()));
+        }
+        if self.record(tx.client(), amount) {
+            self.flagged.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Suspicious amount {amount} of tx {} for client {}",
+                tx.id(),
+                tx.client()
+            );
+            if self.review_flagged {
+                self.pending_review.lock().unwrap().insert(tx.id());
+            }
+        }
+        Ok(())
+    }
+
+    fn on_outcome(&self, tx: &Tx, outcome: &TxOutcome) {
+        if !outcome.is_applied() {
+            self.pending_review.lock().unwrap().remove(&tx.id());
+        }
+    }
+
+    fn review(&self, tx: &Tx) -> Option<String> {
+        self.pending_review
+            .lock()
+            .unwrap()
+            .remove(&tx.id())
+            .then(|| format!("suspicious amount of tx {}", tx.id()))
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        vec![
+            ("rejected".to_string(), self.rejected().to_string()),
+            ("flagged".to_string(), self.flagged().to_string()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        error::Error,
+        payments::{Tx, TxType},
+        plugin::Plugin,
+    };
+
+    use super::AmountChecks;
+
+    fn deposit(id: u32, amount: &str) -> Tx {
+        Tx::new(
+            TxType::Deposit,
+            1,
+            id,
+            Some(BigDecimal::from_str(amount).unwrap()),
+        )
+    }
+
+    #[test]
+    fn rejects_amounts_above_limit() {
+        let checks = AmountChecks::new(Some(BigDecimal::from(100)), None);
+        assert!(checks.on_tx(&deposit(1, "100")).is_ok());
+        assert_eq!(
+            checks.on_tx(&deposit(2, "100.0001")),
+            Err(Error::AmountAboveLimit(2))
+        );
+        assert!(checks.on_tx(&Tx::new(TxType::Dispute, 1, 2, None)).is_ok());
+        assert_eq!((checks.rejected(), checks.flagged()), (1, 0));
+    }
+
+    #[test]
+    fn flags_likely_unit_errors() {
+        let checks = AmountChecks::new(None, Some(10_000));
+        for (id, amount) in [(1, "2"), (2, "2"), (3, "1"), (4, "19999"), (5, "20000")] {
+            assert!(checks.on_tx(&deposit(id, amount)).is_ok());
+        }
+        assert_eq!((checks.rejected(), checks.flagged()), (0, 1));
+    }
+}
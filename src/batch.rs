@@ -0,0 +1,221 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use futures::StreamExt;
+
+use crate::{
+    error::Error,
+    payments::{Engine, Tx, TxType},
+    source::{BoxedSource, TxSource},
+    storage::{AccountsDal, TxsDal},
+};
+
+// Tags every transaction of the source with the ingestion batch it arrived in (e.g. its input file
+// or the id of the job which fed it), replacing any previous tag. The batch is stored with the
+// transactions in the ledger, so that balance changes can be traced back to their batch.
+pub fn batched(source: impl TxSource + 'static, batch: Arc<str>) -> BoxedSource {
+    Box::new(source.map(move |record| record.map(|tx| tx.with_batch(batch.clone()))))
+}
+
+// Number of transactions in the ledger per batch. Transactions stored without a batch aren't
+// counted.
+pub async fn batches<T: TxsDal>(txs: &T) -> BTreeMap<String, u64> {
+    let mut batches = BTreeMap::new();
+    for tx in txs.txs().await.values() {
+        if let Some(batch) = tx.lock().await.batch() {
+            *batches.entry(batch.to_string()).or_default() += 1;
+        }
+    }
+    batches
+}
+
+// Transactions of the ledger which arrived in the given batch, ordered by id.
+pub async fn batch_txs<T: TxsDal>(txs: &T, batch: &str) -> Vec<Tx> {
+    let mut selected = Vec::new();
+    for tx in txs.txs().await.values() {
+        let tx = tx.lock().await;
+        if tx.batch() == Some(batch) {
+            selected.push(tx.clone());
+        }
+    }
+    selected.sort_by_key(Tx::id);
+    selected
+}
+
+// Reversal of a transaction of a reverted batch: the id of its compensating transaction, or why it
+// couldn't be reverted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reversal {
+    pub tx: u32,
+    pub client: u16,
+    pub outcome: Result<u32, Error>,
+}
+
+// Undoes the balance changes of a batch by applying compensating transactions, a withdrawal for
+// every deposit and a deposit for every withdrawal, latest first. Compensating transactions get
+// ids above every id of the ledger and are stored in the `revert:<batch>` batch, while the
+// reverted transactions are removed from the ledger, so they can't be disputed anymore and
+// reverting the batch again is a no-op. Disputed transactions and transactions of locked accounts
+// are left untouched, for someone to look at them.
+pub async fn revert_batch<A, T>(engine: &mut Engine<A, T>, batch: &str) -> Vec<Reversal>
+where
+    A: AccountsDal + Send + Sync + Clone,
+    T: TxsDal + Send + Sync + Clone,
+{
+    let mut next_id = engine
+        .txs()
+        .await
+        .keys()
+        .max()
+        .map_or(0, |id| id.saturating_add(1));
+    let revert: Arc<str> = Arc::from(format!("revert:{batch}"));
+    let mut reversals = Vec::new();
+    for tx in batch_txs(engine, batch).await.into_iter().rev() {
+        let outcome = async {
+            if tx.disputed() {
+                return Err(Error::TxAlreadyDisputed(tx.id()));
+            }
+            if let Some(account) = engine.account(tx.client()).await {
+                if account.lock().await.is_locked() {
+                    return Err(Error::AccountLocked(tx.client()));
+                }
+            }
+            let r#type = match tx.tx_type() {
+                TxType::Deposit => TxType::Withdrawal,
+                _ => TxType::Deposit,
+            };
+            let compensation = Tx::new(r#type, tx.client(), next_id, tx.amount().cloned())
+                .with_batch(revert.clone());
+            if let Some(err) = engine.handle_tx(compensation).await.error() {
+                return Err(err.clone());
+            }
+            engine.remove(tx.id()).await;
+            next_id = next_id.saturating_add(1);
+            Ok(next_id - 1)
+        }
+        .await;
+        reversals.push(Reversal {
+            tx: tx.id(),
+            client: tx.client(),
+            outcome,
+        });
+    }
+    reversals
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, str::FromStr, sync::Arc};
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        error::Error,
+        payments::Engine,
+        source,
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{batch_txs, batched, batches, revert_batch, Reversal};
+
+    #[tokio::test]
+    async fn txs_by_batch() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        for (batch, input) in [
+            (
+                "monday.csv",
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0",
+            ),
+            (
+                "tuesday.csv",
+                "type,client,tx,amount\nwithdrawal,1,3,1.0\ndispute,1,1,",
+            ),
+        ] {
+            let txs = batched(source::from_csv(input.as_bytes()), Arc::from(batch));
+            engine.handle_source(txs).await.unwrap();
+        }
+
+        assert_eq!(
+            batches(&engine).await,
+            BTreeMap::from([
+                ("monday.csv".to_string(), 2),
+                ("tuesday.csv".to_string(), 1)
+            ])
+        );
+        let tuesday: Vec<u32> = batch_txs(&engine, "tuesday.csv")
+            .await
+            .iter()
+            .map(|tx| tx.id())
+            .collect();
+        assert_eq!(tuesday, [3]);
+    }
+
+    #[tokio::test]
+    async fn reverted_batch() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        for (batch, input) in [
+            (
+                "monday.csv",
+                "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\nwithdrawal,1,3,2.0\n\
+                deposit,3,4,1.0",
+            ),
+            (
+                "tuesday.csv",
+                "type,client,tx,amount\ndeposit,1,5,1.0\ndispute,2,2,\ndispute,3,4,\n\
+                chargeback,3,4,",
+            ),
+        ] {
+            let txs = batched(source::from_csv(input.as_bytes()), Arc::from(batch));
+            engine.handle_source(txs).await.unwrap();
+        }
+
+        // The disputed deposit and the deposit of the account locked by a chargeback are kept.
+        assert_eq!(
+            revert_batch(&mut engine, "monday.csv").await,
+            [
+                Reversal {
+                    tx: 4,
+                    client: 3,
+                    outcome: Err(Error::AccountLocked(3)),
+                },
+                Reversal {
+                    tx: 3,
+                    client: 1,
+                    outcome: Ok(6),
+                },
+                Reversal {
+                    tx: 2,
+                    client: 2,
+                    outcome: Err(Error::TxAlreadyDisputed(2)),
+                },
+                Reversal {
+                    tx: 1,
+                    client: 1,
+                    outcome: Ok(7),
+                },
+            ]
+        );
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(
+            account.lock().await.available(),
+            BigDecimal::from_str("1.0").unwrap()
+        );
+        assert_eq!(
+            batches(&engine).await,
+            BTreeMap::from([
+                ("monday.csv".to_string(), 2),
+                ("revert:monday.csv".to_string(), 2),
+                ("tuesday.csv".to_string(), 1)
+            ])
+        );
+
+        // Only the skipped transactions are left to revert.
+        let reverted = revert_batch(&mut engine, "monday.csv").await;
+        assert!(reverted.iter().all(|reversal| reversal.outcome.is_err()));
+    }
+}
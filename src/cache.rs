@@ -0,0 +1,465 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::Hash,
+    sync::Arc,
+};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    account::Account,
+    payments::Tx,
+    storage::{AccountsDal, TxsDal},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    // Maximum number of entries kept in memory, for each of the ledgers.
+    pub capacity: usize,
+    // Number of modified entries after which they are written to the underlying storage.
+    pub flush_threshold: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            capacity: 10_000,
+            flush_threshold: 1_000,
+        }
+    }
+}
+
+// Lookups served by a cache, for tuning its capacity.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    // Share of the lookups served from memory.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+// LRU cache of ledger entries, tracking which of them were handed out for modification and
+// haven't been written back yet.
+struct Cache<K, V> {
+    entries: HashMap<K, (u64, Arc<Mutex<V>>)>,
+    recency: BTreeMap<u64, K>,
+    dirty: HashSet<K>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Copy, V> Default for Cache<K, V> {
+    fn default() -> Self {
+        Cache {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            dirty: HashSet::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Copy, V> Cache<K, V> {
+    fn touch(&mut self, key: K) -> Option<Arc<Mutex<V>>> {
+        self.tick += 1;
+        let Some((tick, value)) = self.entries.get_mut(&key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        self.recency.remove(tick);
+        self.recency.insert(self.tick, key);
+        *tick = self.tick;
+        // Entries are handed out behind a mutex, so they have to be assumed modified.
+        self.dirty.insert(key);
+        Some(value.clone())
+    }
+
+    // Caches `value` and returns it, along with the dirty entries evicted to make room for it.
+    fn put(&mut self, key: K, value: V, capacity: usize) -> (Arc<Mutex<V>>, Vec<Arc<Mutex<V>>>) {
+        self.tick += 1;
+        let value = Arc::new(Mutex::new(value));
+        if let Some((tick, _)) = self.entries.insert(key, (self.tick, value.clone())) {
+            self.recency.remove(&tick);
+        }
+        self.recency.insert(self.tick, key);
+        self.dirty.insert(key);
+
+        let mut evicted = Vec::new();
+        while self.entries.len() > capacity.max(1) {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((_, entry)) = self.entries.remove(&oldest) {
+                self.stats.evictions += 1;
+                if self.dirty.remove(&oldest) {
+                    evicted.push(entry);
+                }
+            }
+        }
+        (value, evicted)
+    }
+
+    fn take_dirty(&mut self) -> Vec<Arc<Mutex<V>>> {
+        let dirty: Vec<K> = self.dirty.drain().collect();
+        dirty
+            .iter()
+            .filter_map(|key| self.entries.get(key).map(|(_, value)| value.clone()))
+            .collect()
+    }
+}
+
+// Read-through/write-behind caching decorator over any accounts and/or transactions DAL. Entries
+// are copied from the underlying storage on their first access and served from memory afterwards,
+// while modifications are written back in bulk once `flush_threshold` entries are dirty, when they
+// get evicted, or on an explicit `flush`. Hit rates are tracked, and the configuration can be tuned
+// while running, shared by all the clones.
+#[derive(Clone)]
+pub struct Cached<D> {
+    inner: D,
+    config: Arc<std::sync::RwLock<CacheConfig>>,
+    accounts: Arc<std::sync::Mutex<Cache<u16, Account>>>,
+    txs: Arc<std::sync::Mutex<Cache<u32, Tx>>>,
+}
+
+impl<D: Clone> Cached<D> {
+    pub fn new(inner: D, config: CacheConfig) -> Self {
+        Cached {
+            inner,
+            config: Arc::new(std::sync::RwLock::new(config)),
+            accounts: Arc::new(std::sync::Mutex::new(Cache::default())),
+            txs: Arc::new(std::sync::Mutex::new(Cache::default())),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn config(&self) -> CacheConfig {
+        *self.config.read().unwrap()
+    }
+
+    // Changes the configuration, e.g. to trade memory for fewer lookups in the underlying storage.
+    // A smaller capacity evicts the extra entries on the next insertion.
+    pub fn set_config(&self, config: CacheConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    pub fn account_stats(&self) -> CacheStats {
+        self.accounts.lock().unwrap().stats
+    }
+
+    pub fn tx_stats(&self) -> CacheStats {
+        self.txs.lock().unwrap().stats
+    }
+}
+
+impl<D: AccountsDal + Clone + Send + Sync> Cached<D> {
+    async fn write_accounts(&self, entries: Vec<Arc<Mutex<Account>>>) {
+        let mut inner = self.inner.clone();
+        for entry in entries {
+            let account = entry.lock().await.clone();
+            inner.insert(account).await;
+        }
+    }
+
+    async fn write_accounts_behind(&self) {
+        let dirty = {
+            let mut cache = self.accounts.lock().unwrap();
+            if cache.dirty.len() < self.config().flush_threshold {
+                return;
+            }
+            cache.take_dirty()
+        };
+        self.write_accounts(dirty).await;
+    }
+
+    // Writes all the modified accounts to the underlying storage.
+    pub async fn flush_accounts(&self) {
+        let dirty = self.accounts.lock().unwrap().take_dirty();
+        self.write_accounts(dirty).await;
+    }
+}
+
+impl<D: TxsDal + Clone + Send + Sync> Cached<D> {
+    async fn write_txs(&self, entries: Vec<Arc<Mutex<Tx>>>) {
+        for entry in entries {
+            let tx = entry.lock().await.clone();
+            self.inner.insert(tx).await;
+        }
+    }
+
+    async fn write_txs_behind(&self) {
+        let dirty = {
+            let mut cache = self.txs.lock().unwrap();
+            if cache.dirty.len() < self.config().flush_threshold {
+                return;
+            }
+            cache.take_dirty()
+        };
+        self.write_txs(dirty).await;
+    }
+
+    // Writes all the modified transactions to the underlying storage.
+    pub async fn flush_txs(&self) {
+        let dirty = self.txs.lock().unwrap().take_dirty();
+        self.write_txs(dirty).await;
+    }
+}
+
+impl<D: AccountsDal + Clone + Send + Sync> AccountsDal for Cached<D> {
+    async fn account(&self, id: u16) -> Option<Arc<Mutex<Account>>> {
+        let hit = self.accounts.lock().unwrap().touch(id);
+        if hit.is_some() {
+            return hit;
+        }
+
+        let account = self.inner.account(id).await?.lock().await.clone();
+        let (entry, evicted) =
+            self.accounts
+                .lock()
+                .unwrap()
+                .put(id, account, self.config().capacity);
+        self.write_accounts(evicted).await;
+        self.write_accounts_behind().await;
+        Some(entry)
+    }
+
+    async fn insert(&mut self, account: Account) {
+        let (_, evicted) =
+            self.accounts
+                .lock()
+                .unwrap()
+                .put(account.client_id(), account, self.config().capacity);
+        self.write_accounts(evicted).await;
+        self.write_accounts_behind().await;
+    }
+
+    // Listing goes to the underlying storage, so the cache is flushed first. Cached entries are
+    // kept, now clean, so handles handed out before keep being the ones served by `account`, while
+    // the listed handles are copies for reading.
+    async fn accounts(
+        &self,
+    ) -> tokio::sync::RwLockReadGuard<'_, HashMap<u16, Arc<Mutex<Account>>>> {
+        self.flush_accounts().await;
+        self.inner.accounts().await
+    }
+
+    async fn prefetch(&self, ids: &[u16]) {
+        self.inner.prefetch(ids).await
+    }
+}
+
+impl<D: TxsDal + Clone + Send + Sync> TxsDal for Cached<D> {
+    async fn tx(&self, id: u32) -> Option<Arc<Mutex<Tx>>> {
+        let hit = self.txs.lock().unwrap().touch(id);
+        if hit.is_some() {
+            return hit;
+        }
+
+        let tx = self.inner.tx(id).await?.lock().await.clone();
+        let (entry, evicted) = self.txs.lock().unwrap().put(id, tx, self.config().capacity);
+        self.write_txs(evicted).await;
+        self.write_txs_behind().await;
+        Some(entry)
+    }
+
+    async fn insert(&self, tx: Tx) {
+        let (_, evicted) = self
+            .txs
+            .lock()
+            .unwrap()
+            .put(tx.id(), tx, self.config().capacity);
+        self.write_txs(evicted).await;
+        self.write_txs_behind().await;
+    }
+
+    // See `AccountsDal::accounts` above.
+    async fn txs(&self) -> tokio::sync::RwLockReadGuard<'_, HashMap<u32, Arc<Mutex<Tx>>>> {
+        self.flush_txs().await;
+        self.inner.txs().await
+    }
+
+    async fn prefetch(&self, ids: &[u32]) {
+        self.inner.prefetch(ids).await
+    }
+
+    async fn remove(&self, id: u32) {
+        {
+            let mut cache = self.txs.lock().unwrap();
+            if let Some((tick, _)) = cache.entries.remove(&id) {
+                cache.recency.remove(&tick);
+            }
+            cache.dirty.remove(&id);
+        }
+        self.inner.remove(id).await
+    }
+
+    // The index is owned by the underlying storage, so pending writes are flushed before using it.
+    async fn client_txs(&self, client: u16) -> Vec<Arc<Mutex<Tx>>> {
+        self.flush_txs().await;
+        let mut ids = Vec::new();
+        for tx in self.inner.client_txs(client).await {
+            ids.push(tx.lock().await.id());
+        }
+
+        let mut txs = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(tx) = self.tx(id).await {
+                txs.push(tx);
+            }
+        }
+        txs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        account::Account,
+        payments::{Tx, TxType},
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger, TxsDal},
+    };
+
+    use super::{CacheConfig, CacheStats, Cached};
+
+    #[tokio::test]
+    async fn write_behind_on_flush() {
+        let ledger = InMemoryAccountLedger::default();
+        let mut cached = Cached::new(
+            ledger.clone(),
+            CacheConfig {
+                capacity: 10,
+                flush_threshold: 10,
+            },
+        );
+
+        cached.insert(Account::new_unlocked(1)).await;
+        assert!(ledger.account(1).await.is_none());
+
+        let account = cached.account(1).await.unwrap();
+        account.lock().await.add_available(&BigDecimal::from(2));
+        cached.flush_accounts().await;
+        let stored = ledger.account(1).await.unwrap();
+        assert_eq!(stored.lock().await.available(), BigDecimal::from(2));
+    }
+
+    #[tokio::test]
+    async fn write_behind_on_threshold() {
+        let ledger = InMemoryTxLedger::default();
+        let cached = Cached::new(
+            ledger.clone(),
+            CacheConfig {
+                capacity: 10,
+                flush_threshold: 2,
+            },
+        );
+
+        cached.insert(Tx::new(TxType::Deposit, 1, 1, None)).await;
+        assert!(ledger.tx(1).await.is_none());
+        cached.insert(Tx::new(TxType::Deposit, 1, 2, None)).await;
+        assert!(ledger.tx(1).await.is_some());
+        assert!(ledger.tx(2).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn write_back_on_eviction() {
+        let ledger = InMemoryAccountLedger::default();
+        let mut cached = Cached::new(
+            ledger.clone(),
+            CacheConfig {
+                capacity: 1,
+                flush_threshold: 10,
+            },
+        );
+
+        cached.insert(Account::new_unlocked(1)).await;
+        let account = cached.account(1).await.unwrap();
+        account.lock().await.set_locked(true);
+        cached.insert(Account::new_unlocked(2)).await;
+
+        let stored = ledger.account(1).await.unwrap();
+        assert!(stored.lock().await.is_locked());
+        assert!(ledger.account(2).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stats_and_tuning() {
+        let mut ledger = InMemoryAccountLedger::default();
+        for client in 1..=3 {
+            ledger.insert(Account::new_unlocked(client)).await;
+        }
+        let cached = Cached::new(ledger, CacheConfig::default());
+        for client in [1, 1, 2, 1, 4] {
+            cached.account(client).await;
+        }
+        let stats = cached.account_stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 3, 0));
+        assert_eq!(stats.hit_rate(), 0.4);
+
+        cached.set_config(CacheConfig {
+            capacity: 1,
+            ..cached.config()
+        });
+        cached.account(3).await;
+        assert_eq!(cached.account_stats().evictions, 2);
+        // Only the most recently used account is left.
+        cached.account(1).await;
+        assert_eq!(cached.account_stats().misses, 5);
+        assert_eq!(cached.tx_stats(), CacheStats::default());
+    }
+
+    #[tokio::test]
+    async fn read_through() {
+        let mut ledger = InMemoryAccountLedger::default();
+        ledger.insert(Account::new_unlocked(1)).await;
+        let cached = Cached::new(ledger.clone(), CacheConfig::default());
+
+        let first = cached.account(1).await.unwrap();
+        first.lock().await.add_available(&BigDecimal::from(1));
+        let second = cached.account(1).await.unwrap();
+        assert_eq!(second.lock().await.available(), BigDecimal::from(1));
+        assert_eq!(
+            ledger.account(1).await.unwrap().lock().await.available(),
+            BigDecimal::from(0)
+        );
+
+        assert_eq!(cached.accounts().await.len(), 1);
+        assert_eq!(
+            ledger.account(1).await.unwrap().lock().await.available(),
+            BigDecimal::from(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn listing_keeps_handles() {
+        let ledger = InMemoryTxLedger::default();
+        let cached = Cached::new(ledger.clone(), CacheConfig::default());
+        cached.insert(Tx::new(TxType::Deposit, 1, 1, None)).await;
+        let tx = cached.tx(1).await.unwrap();
+
+        assert_eq!(cached.txs().await.len(), 1);
+        assert_eq!(cached.tx_stats().misses, 0);
+        // Handles handed out before the listing are still the cached ones.
+        let again = cached.tx(1).await.unwrap();
+        assert!(Arc::ptr_eq(&tx, &again));
+        tx.lock().await.mark_disputed();
+        cached.flush_txs().await;
+        assert!(ledger.tx(1).await.unwrap().lock().await.disputed());
+    }
+}
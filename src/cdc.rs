@@ -0,0 +1,179 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    account::Account,
+    payments::{Tx, TxType},
+    plugin::Plugin,
+};
+
+// A state change of the ledgers: the transaction applied and the resulting state of its client's
+// account. Changes are numbered by `seq`, increasing by one across runs appending to the same log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    pub seq: u64,
+    pub tx: u32,
+    pub r#type: TxType,
+    pub client: u16,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+struct Log {
+    writer: BufWriter<File>,
+    next: u64,
+}
+
+// Plugin appending every state change to a JSON lines log, from which external systems can build
+// their own read models. Consumers keep track of the last `seq` they processed and resume after it
+// (see `read`), so the log is ordered and resumable, also across runs appending to it.
+pub struct Cdc {
+    log: Mutex<Log>,
+}
+
+impl Cdc {
+    // Opens the log, continuing the numbering of the changes it already holds.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let next = match File::open(path) {
+            Ok(file) => last_seq(file)?.map_or(0, |seq| seq + 1),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Cdc {
+            log: Mutex::new(Log {
+                writer: BufWriter::new(file),
+                next,
+            }),
+        })
+    }
+}
+
+fn last_seq(file: File) -> std::io::Result<Option<u64>> {
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let change: Change = serde_json::from_str(&line).map_err(std::io::Error::from)?;
+        last = Some(change.seq);
+    }
+    Ok(last)
+}
+
+// Writes the changes of the log following `after` (all of them when `None`) to `out`.
+pub fn read(log: &Path, after: Option<u64>, out: &mut impl Write) -> std::io::Result<u64> {
+    let mut count = 0;
+    for line in BufReader::new(File::open(log)?).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let change: Change = serde_json::from_str(&line).map_err(std::io::Error::from)?;
+        if after.is_none_or(|after| change.seq > after) {
+            writeln!(out, "{line}")?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+impl Plugin for Cdc {
+    fn name(&self) -> &str {
+        "cdc"
+    }
+
+    fn on_account(&self, tx: &Tx, account: &Account) {
+        let mut log = self.log.lock().unwrap();
+        let change = Change {
+            seq: log.next,
+            tx: tx.id(),
+            r#type: tx.tx_type().clone(),
+            client: account.client_id(),
+            available: account.available().to_string(),
+            held: account.held().to_string(),
+            total: account.total().to_string(),
+            locked: account.is_locked(),
+        };
+        let res = serde_json::to_writer(&mut log.writer, &change)
+            .map_err(std::io::Error::from)
+            .and_then(|()| log.writer.write_all(b"\n"));
+        match res {
+            Ok(()) => log.next += 1,
+            Err(err) => warn!(
+                "Error while appending change {} to the CDC log: {err}",
+                change.seq
+            ),
+        }
+    }
+
+    fn on_shutdown(&self) {
+        if let Err(err) = self.log.lock().unwrap().writer.flush() {
+            warn!("Error while flushing the CDC log: {err}");
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        let next = self.log.lock().unwrap().next;
+        vec![("next_seq".to_string(), next.to_string())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{read, Cdc, Change};
+
+    async fn run(path: &std::path::Path, input: &str) {
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(Cdc::open(path).unwrap())
+        .build()
+        .unwrap();
+        engine.handle_txs(input.as_bytes()).await.unwrap();
+        engine.shutdown();
+    }
+
+    #[tokio::test]
+    async fn resumable_change_log() {
+        let path = std::env::temp_dir().join("payments-engine-cdc.jsonl");
+        let _ = std::fs::remove_file(&path);
+        run(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,5.0\ndispute,1,1,",
+        )
+        .await;
+        // A later run continues the numbering.
+        run(&path, "type,client,tx,amount\ndeposit,2,3,1.0").await;
+
+        let mut out = Vec::new();
+        assert_eq!(read(&path, Some(0), &mut out).unwrap(), 2);
+        let changes: Vec<Change> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|change| (change.seq, change.tx, change.client, change.held.as_str()))
+            .collect();
+        assert_eq!(summary, vec![(1, 1, 1, "2.0"), (2, 3, 2, "0")]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
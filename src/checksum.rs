@@ -0,0 +1,121 @@
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncRead;
+
+use crate::{
+    account::Account,
+    error::Error,
+    import::import_accounts,
+    storage::{AccountsDal, InMemoryAccountLedger},
+};
+
+// Leaves and inner nodes are hashed with different prefixes, so that an inner node can't be passed
+// off as a leaf.
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+
+fn leaf(account: &Account) -> [u8; 32] {
+    // Amounts are normalized, so that the same balance gets the same checksum whatever its scale.
+    let state = format!(
+        "{},{},{},{}",
+        account.client_id(),
+        account.available().normalized(),
+        account.held().normalized(),
+        account.is_locked()
+    );
+    Sha256::new()
+        .chain_update([LEAF])
+        .chain_update(state)
+        .finalize()
+        .into()
+}
+
+// Root of the Merkle tree over the given leaves, the last node of a level with an odd number of
+// them being carried to the next level as is.
+fn merkle_root(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    if level.is_empty() {
+        return Sha256::digest([]).into();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => Sha256::new()
+                    .chain_update([NODE])
+                    .chain_update(left)
+                    .chain_update(right)
+                    .finalize()
+                    .into(),
+                [last] => *last,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+// Deterministic checksum of the accounts ledger: the hex encoded root of a SHA-256 Merkle tree over
+// the states (balances and lock) of the accounts sorted by client, so that operators can prove two
+// environments produced identical results.
+pub async fn accounts_checksum<A: AccountsDal>(accounts: &A) -> String {
+    let mut leaves = Vec::new();
+    for account in accounts.accounts().await.values() {
+        let account = account.lock().await;
+        leaves.push((account.client_id(), leaf(&account)));
+    }
+    leaves.sort_unstable_by_key(|(client, _)| *client);
+    let root = merkle_root(leaves.into_iter().map(|(_, leaf)| leaf).collect());
+    root.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// Checksum of the accounts of a report (as written by `report::write_accounts` and the like, or
+// any CSV with `client,available,held,locked` columns), matching the one of the ledger it was
+// written from.
+pub async fn report_checksum(reader: impl AsyncRead + Send + Unpin) -> Result<String, Error> {
+    let mut accounts = InMemoryAccountLedger::default();
+    import_accounts(&mut accounts, reader).await?;
+    Ok(accounts_checksum(&accounts).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        payments::Engine,
+        report,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{accounts_checksum, report_checksum};
+
+    #[tokio::test]
+    async fn checksum_matches_report() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.5\ndeposit,3,3,1\n\
+                    dispute,2,2,\nchargeback,2,2,"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let checksum = accounts_checksum(&engine).await;
+        assert_eq!(checksum.len(), 64);
+
+        let mut output = Vec::new();
+        report::write_accounts(&engine, &mut output).await.unwrap();
+        assert_eq!(report_checksum(output.as_slice()).await.unwrap(), checksum);
+
+        // Rows can come in any order, with amounts at any scale.
+        let reordered = "client,available,held,total,locked\n3,1.00,0,1,false\n\
+            2,0,0,0,true\n1,2,0,2,false";
+        assert_eq!(
+            report_checksum(reordered.as_bytes()).await.unwrap(),
+            checksum
+        );
+        let changed = "client,available,held,total,locked\n3,1,0,1,false\n\
+            2,0,0,0,false\n1,2,0,2,false";
+        assert_ne!(report_checksum(changed.as_bytes()).await.unwrap(), checksum);
+    }
+}
@@ -0,0 +1,535 @@
+// Command line interface definition. Kept free of any dependency on the rest of the crate, since
+// it is also compiled by the build script to generate the man page.
+use std::path::PathBuf;
+
+use clap::{builder::BoolishValueParser, ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+/// Processes a CSV of transactions and outputs the resulting client accounts.
+#[derive(Parser, Debug)]
+#[command(
+    name = "payments-engine",
+    version,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
+pub struct Args {
+    /// Paths to the CSV files holding the transactions, `-` (the default) standing for stdin.
+    /// Several independent files (e.g. one per region or day), or glob patterns matching them,
+    /// are processed concurrently, with their accounts merged in a single report.
+    #[arg(default_value = "-")]
+    pub input: Vec<String>,
+    /// Maximum number of input files processed at once.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub jobs: u64,
+    /// Applies the transactions of all the input files on a single engine, instead of processing
+    /// every file in isolation and merging the results, which requires files not to share
+    /// clients.
+    #[arg(long)]
+    pub shared_engine: bool,
+    /// Produces the same output as processing the input files one after another, while still
+    /// parsing them concurrently: their transactions are applied in input order on a single engine
+    /// and the accounts are reported ordered by client id.
+    #[arg(long, conflicts_with = "shared_engine")]
+    pub deterministic: bool,
+    /// Applies the transactions of all the input files on a single engine ordered across files by
+    /// their `seq` or their optional `timestamp` column, each file being expected to be ordered by
+    /// it, so that e.g. a deposit from one file gets applied before its dispute from another.
+    #[arg(long, value_enum, conflicts_with_all = ["shared_engine", "deterministic"])]
+    pub merge_by: Option<ReorderKeyArg>,
+    /// Maximum time a transaction is held back by `--merge-by` waiting for the other inputs to
+    /// get to its key, in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub merge_lateness_ms: u64,
+    /// Parses the inputs by hand rather than through serde, for maximum throughput. Only supports
+    /// the canonical `type,client,tx,amount` header.
+    #[arg(long)]
+    pub fast_parse: bool,
+    /// Reads the input files through memory maps rather than read calls, for very large inputs on
+    /// fast local storage. Inputs must not be truncated while being processed.
+    #[arg(long)]
+    pub mmap: bool,
+    /// Processes a single input file on this many engines working concurrently, each of them
+    /// handling a subset of the clients.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub shards: Option<u64>,
+    /// How clients are assigned to shards: `modulo`, `rendezvous` or explicit ranges, e.g.
+    /// `ranges:0-999=0,1000-1999=1` (clients outside of the ranges fall back to modulo).
+    #[arg(long, default_value = "modulo", requires = "shards")]
+    pub shard_routing: String,
+    /// Moves clients sending at least this share (between 0 and 1) of the transactions to a
+    /// dedicated queue, applied in batches, so they don't stall the other clients of their shard.
+    #[arg(long, requires = "shards")]
+    pub hot_account_share: Option<f64>,
+    /// Minimum number of transactions of a client before it can be considered hot.
+    #[arg(long, default_value_t = 1000)]
+    pub hot_account_min_txs: u64,
+    /// Maximum number of transactions of hot clients applied in a single batch.
+    #[arg(long, default_value_t = 100)]
+    pub hot_account_batch: usize,
+    /// Logs every transaction taking longer than this many milliseconds to be handled.
+    #[arg(long)]
+    pub slow_tx_threshold_ms: Option<u64>,
+    /// Rejects transactions taking longer than this many milliseconds to be applied (e.g. stuck on
+    /// a remote storage), so that the following ones keep being processed.
+    #[arg(long)]
+    pub tx_timeout_ms: Option<u64>,
+    /// Yields to the other tasks of the runtime every this many transactions, so that large inputs
+    /// don't starve them.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub yield_every: Option<u64>,
+    /// Stops at the first transaction which can't be applied, exiting with an error naming its row,
+    /// instead of logging it and going on. Only supports a single input file.
+    #[arg(long)]
+    pub strict: bool,
+    /// Lets clients dispute withdrawals too: their funds are held until the dispute is resolved,
+    /// the withdrawal then standing, or charged back, the funds then being credited back.
+    #[arg(long)]
+    pub dispute_withdrawals: bool,
+    /// Rounds amounts with more than four decimal places with this rounding mode, instead of
+    /// rejecting their transactions.
+    #[arg(long, value_name = "MODE")]
+    pub round_amounts: Option<RoundingArg>,
+    /// Logs only one in this many rejected transactions.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub log_sample_rate: u64,
+    /// Logs an aggregated summary of the rejections every this many rows.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub log_summary_interval: Option<u64>,
+    /// Stops accepting deposits after this many transactions.
+    #[arg(long)]
+    pub max_txs: Option<u64>,
+    /// Stops accepting deposits once their total volume would exceed this amount.
+    #[arg(long)]
+    pub max_deposit_volume: Option<String>,
+    /// Rejects deposits and withdrawals above this amount.
+    #[arg(long)]
+    pub max_amount: Option<String>,
+    /// Parks deposits and withdrawals above this amount until approved with `pending approve`,
+    /// rather than applying them. Parked transactions are saved with `--save-state`.
+    #[arg(long)]
+    pub quarantine_above: Option<String>,
+    /// Drops the transactions parked for approval once they waited for this many seconds.
+    #[arg(long)]
+    pub pending_ttl: Option<u64>,
+    /// Flags deposits and withdrawals of at least this many times the median of the client's
+    /// recent amounts as likely unit errors. Flagged transactions are logged but still applied.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub suspicious_factor: Option<u64>,
+    /// Places clients with a suspicious amount under review, queueing their later transactions
+    /// instead of applying them.
+    #[arg(long)]
+    pub review_suspicious: bool,
+    /// Drops deposits from the ledger once this many transactions were processed after them, past
+    /// which they can no longer be disputed.
+    #[arg(long)]
+    pub dispute_window: Option<u64>,
+    /// Unlocks accounts locked by a chargeback with their first transaction once this many
+    /// transactions were processed after the chargeback. Without it, locked accounts stay locked.
+    #[arg(long)]
+    pub chargeback_cooling_off: Option<u64>,
+    /// Unlocks the account of this client, locked by a chargeback, after an admin review, e.g. in
+    /// the state loaded through `--state`. Can be given multiple times.
+    #[arg(long)]
+    pub unlock: Vec<u16>,
+    /// Drops withdrawals and the transactions of locked accounts from the ledger, as they can't be
+    /// disputed.
+    #[arg(long)]
+    pub prune_terminal: bool,
+    /// Seeds the accounts from a CSV of opening balances with a `client,available,held,locked`
+    /// header before processing the input, e.g. when migrating from another system.
+    #[arg(long)]
+    pub import_accounts: Option<PathBuf>,
+    /// Loads the accounts and transactions from a snapshot of a previous run before processing the
+    /// input, so that only new transactions need to be processed.
+    #[arg(long)]
+    pub state: Option<PathBuf>,
+    /// Saves a snapshot of the accounts and transactions after processing the input.
+    #[arg(long)]
+    pub save_state: Option<PathBuf>,
+    /// Writes the metrics of the run in the Prometheus text format to this file, e.g. for the
+    /// node_exporter textfile collector.
+    #[arg(long)]
+    pub metrics_file: Option<PathBuf>,
+    /// Periodically writes a JSON checkpoint of the run (rows handled, last tx id, update time) to
+    /// this file, so schedulers can detect stuck runs and resume points.
+    #[arg(long)]
+    pub progress_file: Option<PathBuf>,
+    /// How often the progress file gets updated, in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub progress_interval_ms: u64,
+    /// Checks accounts for impossible states (negative held balance, funds held without an open
+    /// dispute, locked account with open disputes) after every transaction, either logging
+    /// violations (`warn`) or aborting the process (`abort`).
+    #[arg(long, value_enum)]
+    pub check_invariants: Option<InvariantModeArg>,
+    /// Maintains per-client and dispute aggregates (counts and volumes of deposits, withdrawals,
+    /// disputes, resolves and chargebacks) as transactions are applied, reporting the dispute ones
+    /// at the end of the run.
+    #[arg(long)]
+    pub aggregates: bool,
+    /// Appends every state change (the transaction applied and the resulting account) as a JSON
+    /// line numbered by an increasing `seq` to this log, which is continued by later runs.
+    #[arg(long)]
+    pub cdc_log: Option<PathBuf>,
+    /// Streams the new balances of accounts as `tx,client,available,held,total,locked` CSV rows to
+    /// this file (e.g. a named pipe) every time a transaction changes them.
+    #[arg(long)]
+    pub deltas: Option<PathBuf>,
+    /// How often the streamed account deltas get flushed, in milliseconds.
+    #[arg(long, default_value_t = 100)]
+    pub deltas_flush_ms: u64,
+    /// Ships the state to this directory for a warm standby: a snapshot on startup and at the end
+    /// of the run, and the changes applied in between as WAL segments.
+    #[arg(long)]
+    pub ship_to: Option<PathBuf>,
+    /// How often WAL segments get shipped, in milliseconds.
+    #[arg(long, default_value_t = 5000)]
+    pub ship_interval_ms: u64,
+    /// Appends the summary statistics of the run as a JSON line to this file, for dashboards of
+    /// rejection rates and throughput across runs.
+    #[arg(long)]
+    pub stats_history: Option<PathBuf>,
+    /// Identifies the run in the stats history. Defaults to one derived from the start time.
+    #[arg(long, requires = "stats_history")]
+    pub run_id: Option<String>,
+    /// Checks the optional `seq` column, holding increasing sequence numbers per client, and
+    /// handles the transactions after a gap or out of order according to the policy.
+    #[arg(long, value_enum)]
+    pub sequence_policy: Option<SequencePolicyArg>,
+    /// Maximum number of transactions held back per client while waiting for a missing one, with
+    /// the `reorder` sequence policy.
+    #[arg(long, default_value_t = 1000)]
+    pub reorder_capacity: usize,
+    /// Restores the order of transactions arriving slightly out of order, by their `seq` or their
+    /// optional `timestamp` column, before they are applied.
+    #[arg(long, value_enum)]
+    pub reorder_by: Option<ReorderKeyArg>,
+    /// Maximum number of transactions held back by the reordering buffer.
+    #[arg(long, default_value_t = 1000)]
+    pub reorder_window: usize,
+    /// Maximum time a transaction is held back by the reordering buffer, in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub reorder_max_delay_ms: u64,
+    /// File holding the hashes of the rows applied by previous runs. Rows already applied are
+    /// skipped, protecting against the same input being fed twice.
+    #[arg(long)]
+    pub replay_log: Option<PathBuf>,
+    /// CSV file with an `old,new` header, remapping client ids during ingestion (e.g. after
+    /// upstream systems renumbered customers). Transactions of clients colliding on the same id
+    /// are rejected rather than merged.
+    #[arg(long)]
+    pub remap: Option<PathBuf>,
+    /// CSV file with a `client,tag` header, attaching tags (e.g. customer segments) to accounts.
+    #[arg(long)]
+    pub tags: Option<PathBuf>,
+    /// Only reports the accounts having this tag. Can be given multiple times, in which case
+    /// accounts need to have all the tags.
+    #[arg(long)]
+    pub tag: Vec<String>,
+    /// Columns of the accounts report, separated by commas, out of `client`, `available`, `held`,
+    /// `total`, `locked`, `opening`, `activity`, `open_disputes`, `last_activity` and `tags`.
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+    /// Writes the accounts report to this file (replaced atomically once complete) rather than to
+    /// stdout.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+    /// Orders the accounts report by client id, so that reports can be diffed.
+    #[arg(long)]
+    pub sort: bool,
+    /// Writes the rows which failed parsing or handling to this CSV file, with their line, tx id
+    /// and error.
+    #[arg(long)]
+    pub rejects: Option<PathBuf>,
+    /// Ingestion batch id the transactions of this run are stored with, rather than the path of
+    /// their input file.
+    #[arg(long)]
+    pub batch_id: Option<String>,
+    /// Format of the accounts report. The JSON formats only hold the default columns.
+    #[arg(long, value_enum, default_value = "csv", conflicts_with = "columns")]
+    pub format: FormatArg,
+    /// Rounds the amounts of the accounts report (half to even) to this many decimal places.
+    #[arg(long)]
+    pub precision: Option<u32>,
+    /// Writes the amounts of the accounts report with as few digits as possible, without trailing
+    /// zeros.
+    #[arg(long)]
+    pub minimal_amounts: bool,
+    /// Decimal separator of the amounts of the accounts report.
+    #[arg(long, default_value_t = '.')]
+    pub decimal_separator: char,
+    /// When the fields of CSV reports get quoted.
+    #[arg(long, value_enum, default_value = "necessary")]
+    pub quote: QuoteArg,
+    /// Raises an alert when a rule is met while processing: `held_above=<amount>`,
+    /// `chargebacks=<count>/<window>` or `locked_accounts=<count>/<window>`, windows being
+    /// measured in transactions. Can be given multiple times.
+    #[arg(long)]
+    pub alert: Vec<String>,
+    /// Where alerts are emitted: `stderr` (the default), `file:<path>` or `webhook:<url>` (with the
+    /// `webhooks` feature). Can be given multiple times.
+    #[arg(long)]
+    pub alert_sink: Vec<String>,
+    /// Exits with an error once this many transactions were handled, to exercise recovery.
+    #[arg(long, hide = true)]
+    pub fail_after: Option<u64>,
+    /// Aborts the process right before handling a transaction (`tx=<id>`), to exercise recovery.
+    /// Can be given multiple times.
+    #[arg(long, hide = true)]
+    pub crash_on: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// Prefix of the environment variables the options can also be set with, e.g.
+// `PAYMENTS_ENGINE_MAX_AMOUNT` for `--max-amount`.
+pub const ENV_PREFIX: &str = "PAYMENTS_ENGINE_";
+
+// Command line definition, with every option of a run (not the subcommands' ones) also readable
+// from a `PAYMENTS_ENGINE_*` environment variable, so that deployments (e.g. on Kubernetes) can
+// configure the engine without templating argument lists. Options given on the command line take
+// precedence, and flags are set by any of `true`, `1`, `yes` or `on`. Hidden options (e.g. the
+// failure injection ones) can only be given on the command line, so that a stray variable can't
+// break production runs.
+pub fn command() -> clap::Command {
+    Args::command().mut_args(|arg| {
+        if arg.is_hide_set() {
+            return arg;
+        }
+        let name = format!("{ENV_PREFIX}{}", arg.get_id().as_str().to_uppercase());
+        let arg = arg.env(name);
+        match arg.get_action() {
+            ArgAction::SetTrue => arg.value_parser(BoolishValueParser::new()),
+            _ => arg,
+        }
+    })
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SequencePolicyArg {
+    /// Rejects the transactions after a gap or out of order.
+    Reject,
+    /// Holds back the transactions after a gap until the missing ones arrive.
+    Reorder,
+    /// Only logs gaps and out of order transactions.
+    Report,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum InvariantModeArg {
+    /// Logs violations as internal errors.
+    Warn,
+    /// Logs the violation and aborts the process.
+    Abort,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ReorderKeyArg {
+    Seq,
+    Timestamp,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum BucketArg {
+    Hour,
+    Day,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum FormatArg {
+    Csv,
+    /// A JSON array of accounts.
+    Json,
+    /// A JSON object per account and line.
+    Jsonl,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum MeasureArg {
+    Held,
+    Volume,
+    Chargebacks,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum QuoteArg {
+    /// Quotes the fields holding delimiters, quotes or line breaks.
+    Necessary,
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum RoundingArg {
+    /// Towards the nearest neighbor, ties towards the even one.
+    HalfEven,
+    /// Towards the nearest neighbor, ties away from zero.
+    HalfUp,
+    /// Towards the nearest neighbor, ties towards zero.
+    HalfDown,
+    /// Away from zero.
+    Up,
+    /// Towards zero.
+    Down,
+    /// Towards positive infinity.
+    Ceiling,
+    /// Towards negative infinity.
+    Floor,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SchemaArg {
+    Input,
+    Report,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Prints the completion script for the given shell.
+    Completions { shell: Shell },
+    /// Prints the changes of a CDC log, resuming after the given sequence number.
+    Cdc {
+        log: PathBuf,
+        #[arg(long)]
+        after: Option<u64>,
+    },
+    /// Lists the ingestion batches of the transactions saved in a state snapshot, with their
+    /// number of transactions, or the transactions of a batch.
+    Batches {
+        state: PathBuf,
+        #[arg(long)]
+        batch: Option<String>,
+    },
+    /// Reverts an ingestion batch of a state snapshot with compensating transactions, printing
+    /// the compensating transaction of every reverted transaction or why it was skipped.
+    /// Disputed transactions and transactions of locked accounts are skipped.
+    RevertBatch {
+        batch: String,
+        #[arg(long)]
+        state: PathBuf,
+        /// Where to save the state once reverted. Nothing gets saved without it.
+        #[arg(long)]
+        save_state: Option<PathBuf>,
+    },
+    /// Checks that the accounts of a report match the ledger checksum of a run, printing the
+    /// checksum of the report.
+    Verify {
+        report: PathBuf,
+        #[arg(long)]
+        checksum: String,
+    },
+    /// Prints the JSON Schema of a row of the input or of the accounts report.
+    Schema {
+        #[arg(value_enum)]
+        of: SchemaArg,
+        /// Columns of the report, as with `--columns`.
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<String>,
+    },
+    /// Generates auxiliary files.
+    #[command(subcommand)]
+    Generate(GenerateCommand),
+    /// Reports on the transactions of inputs.
+    #[command(subcommand)]
+    Report(ReportCommand),
+    /// Reviews the transactions awaiting approval in a state snapshot. Expired transactions are
+    /// dropped first.
+    #[command(subcommand)]
+    Pending(PendingCommand),
+    /// Bootstraps a standby from the latest state shipped by a primary (`--ship-to`), saving it
+    /// for a run to pick up with `--state`.
+    Standby {
+        from: PathBuf,
+        #[arg(long)]
+        save_state: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PendingCommand {
+    /// Lists the transactions awaiting approval.
+    List {
+        #[arg(long)]
+        state: PathBuf,
+    },
+    /// Handles a pending transaction, printing its outcome.
+    Approve {
+        tx: u32,
+        #[arg(long)]
+        state: PathBuf,
+        /// Where to save the state once updated. Nothing gets saved without it.
+        #[arg(long)]
+        save_state: Option<PathBuf>,
+    },
+    /// Drops a pending transaction.
+    Reject {
+        tx: u32,
+        #[arg(long)]
+        state: PathBuf,
+        /// Where to save the state once updated. Nothing gets saved without it.
+        #[arg(long)]
+        save_state: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReportCommand {
+    /// Summarizes the deposit and withdrawal volumes, dispute counts and net flow of the applied
+    /// transactions per time bucket of their `timestamp` (seconds since the epoch).
+    Activity {
+        #[arg(default_value = "-")]
+        input: Vec<String>,
+        #[arg(long, value_enum, default_value = "day")]
+        bucket: BucketArg,
+    },
+    /// Lists the clients with the largest held balance, volume (deposited plus withdrawn funds) or
+    /// number of chargebacks once the inputs are applied.
+    Top {
+        #[arg(default_value = "-")]
+        input: Vec<String>,
+        #[arg(long, value_enum)]
+        by: MeasureArg,
+        #[arg(long, default_value_t = 20)]
+        n: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GenerateCommand {
+    /// Writes pathological transaction CSVs (duplicate ids, cross-client disputes, precision edge
+    /// cases, locked account sequences) to the given directory.
+    Fixtures {
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::command;
+
+    #[test]
+    fn options_from_env() {
+        // Checked on the definition, as setting variables would leak into concurrent tests.
+        let command = command();
+        let env = |id: &str| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_id() == id)
+                .and_then(|arg| arg.get_env())
+        };
+        assert_eq!(
+            env("max_amount"),
+            Some(OsStr::new("PAYMENTS_ENGINE_MAX_AMOUNT"))
+        );
+        assert_eq!(env("sort"), Some(OsStr::new("PAYMENTS_ENGINE_SORT")));
+        assert_eq!(env("fail_after"), None);
+        assert!(command
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+            .all(|arg| arg.get_env().is_some()));
+    }
+}
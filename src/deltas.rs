@@ -0,0 +1,136 @@
+use std::{
+    io::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::{account::Account, format::write_amount, payments::Tx, plugin::Plugin};
+
+const HEADER: &[u8] = b"tx,client,available,held,total,locked\n";
+
+struct State {
+    writer: Box<dyn Write + Send>,
+    flushed: Instant,
+    row: Vec<u8>,
+    failed: bool,
+}
+
+// Plugin streaming the new balances of an account every time a transaction changes it, as
+// `tx,client,available,held,total,locked` CSV rows, so downstream consumers can follow a long run
+// instead of waiting for the final report. Rows are flushed at most once per `flush_interval` and
+// on shutdown.
+pub struct Deltas {
+    flush_interval: Duration,
+    state: Mutex<State>,
+}
+
+impl Deltas {
+    pub fn new(writer: impl Write + Send + 'static, flush_interval: Duration) -> Self {
+        Deltas {
+            flush_interval,
+            state: Mutex::new(State {
+                writer: Box::new(std::io::BufWriter::new(writer)),
+                flushed: Instant::now(),
+                row: Vec::with_capacity(128),
+                failed: false,
+            }),
+        }
+    }
+}
+
+impl State {
+    // Stops streaming after the first error, e.g. when the consumer went away, rather than
+    // logging it for every row.
+    fn check(&mut self, res: std::io::Result<()>) {
+        if let Err(err) = res {
+            warn!("Error while writing account deltas, no longer streaming them: {err}");
+            self.failed = true;
+        }
+    }
+}
+
+impl Plugin for Deltas {
+    fn name(&self) -> &str {
+        "deltas"
+    }
+
+    fn on_startup(&self) {
+        let mut state = self.state.lock().unwrap();
+        let res = state.writer.write_all(HEADER);
+        state.check(res);
+    }
+
+    fn on_account(&self, tx: &Tx, account: &Account) {
+        let mut state = self.state.lock().unwrap();
+        if state.failed {
+            return;
+        }
+        let State { writer, row, .. } = &mut *state;
+        row.clear();
+        let _ = write!(row, "{},{},", tx.id(), account.client_id());
+        write_amount(row, account.available());
+        row.push(b',');
+        write_amount(row, account.held());
+        row.push(b',');
+        write_amount(row, account.total());
+        let _ = writeln!(row, ",{}", account.is_locked());
+        let mut res = writer.write_all(row);
+        if res.is_ok() && state.flushed.elapsed() >= self.flush_interval {
+            res = state.writer.flush();
+            state.flushed = Instant::now();
+        }
+        state.check(res);
+    }
+
+    fn on_shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.failed {
+            let res = state.writer.flush();
+            state.check(res);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::Deltas;
+
+    #[tokio::test]
+    async fn streams_account_deltas() {
+        let path = std::env::temp_dir().join("payments-engine-deltas.csv");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(Deltas::new(file, Duration::from_secs(3600)))
+        .build()
+        .unwrap();
+
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,5.0\ndeposit,2,3,1.5\n\
+                dispute,1,1,"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        engine.shutdown();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "tx,client,available,held,total,locked\n1,1,2.0,0,2.0,false\n\
+            3,2,1.5,0,1.5,false\n1,1,0.0,2.0,2.0,false\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
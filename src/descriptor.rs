@@ -0,0 +1,143 @@
+// Authoritative descriptors of the CSV input and of the accounts report, as JSON Schemas (of a
+// single row, every value being a string in CSV) and, with the `arrow` feature, as Arrow schemas,
+// so integrating teams can validate their producers and consumers against them. Amounts are
+// described as decimal strings, as they have an arbitrary precision.
+use serde_json::{json, Map, Value};
+
+use crate::report::Column;
+
+const DECIMAL: &str = "^[+-]?([0-9]+(\\.[0-9]*)?|\\.[0-9]+)$";
+
+fn uint(max: u64) -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": max })
+}
+
+// JSON Schema of a transaction row, including the optional `seq` and `timestamp` columns.
+pub fn input_json_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Transaction",
+        "type": "object",
+        "properties": {
+            "type": {
+                "enum": ["deposit", "withdrawal", "dispute", "resolve", "chargeback"]
+            },
+            "client": uint(u16::MAX.into()),
+            "tx": uint(u32::MAX.into()),
+            "amount": { "type": "string", "pattern": DECIMAL },
+            "seq": uint(u64::MAX),
+            "timestamp": uint(u64::MAX),
+        },
+        "required": ["type", "client", "tx"],
+        "if": { "properties": { "type": { "enum": ["deposit", "withdrawal"] } } },
+        "then": { "required": ["amount"] },
+    })
+}
+
+fn column_json_schema(column: Column) -> Value {
+    match column {
+        Column::Client => uint(u16::MAX.into()),
+        Column::Available | Column::Held | Column::Total => {
+            json!({ "type": "string", "pattern": DECIMAL })
+        }
+        // Empty without opening balances.
+        Column::Opening | Column::Activity => {
+            json!({ "type": "string", "pattern": format!("{DECIMAL}|^$") })
+        }
+        Column::Locked => json!({ "type": "boolean" }),
+        Column::OpenDisputes => uint(u64::MAX),
+        Column::LastActivity => json!({ "anyOf": [uint(u64::MAX), { "const": "" }] }),
+        Column::Tags => json!({ "type": "string" }),
+    }
+}
+
+// JSON Schema of a row of the accounts report with the given columns (see `report::Column`).
+pub fn report_json_schema(columns: &[Column]) -> Value {
+    let properties: Map<String, Value> = columns
+        .iter()
+        .map(|column| (column.name().to_string(), column_json_schema(*column)))
+        .collect();
+    let names: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Account",
+        "type": "object",
+        "properties": properties,
+        "required": names,
+        "additionalProperties": false,
+    })
+}
+
+#[cfg(feature = "arrow")]
+pub use self::arrow::{input_arrow_schema, report_arrow_schema};
+
+#[cfg(feature = "arrow")]
+mod arrow {
+    use arrow_schema::{DataType, Field, Schema};
+
+    use crate::report::Column;
+
+    // Arrow schema of the transactions, amounts being kept as decimal strings.
+    pub fn input_arrow_schema() -> Schema {
+        Schema::new(vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("client", DataType::UInt16, false),
+            Field::new("tx", DataType::UInt32, false),
+            Field::new("amount", DataType::Utf8, true),
+            Field::new("seq", DataType::UInt64, true),
+            Field::new("timestamp", DataType::UInt64, true),
+        ])
+    }
+
+    // Arrow schema of the accounts report with the given columns.
+    pub fn report_arrow_schema(columns: &[Column]) -> Schema {
+        let fields: Vec<Field> = columns
+            .iter()
+            .map(|column| {
+                let (data_type, nullable) = match column {
+                    Column::Client => (DataType::UInt16, false),
+                    Column::Available | Column::Held | Column::Total => (DataType::Utf8, false),
+                    Column::Opening | Column::Activity => (DataType::Utf8, true),
+                    Column::Locked => (DataType::Boolean, false),
+                    Column::OpenDisputes => (DataType::UInt64, false),
+                    Column::LastActivity => (DataType::UInt64, true),
+                    Column::Tags => (DataType::Utf8, false),
+                };
+                Field::new(column.name(), data_type, nullable)
+            })
+            .collect();
+        Schema::new(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::Column;
+
+    use super::{input_json_schema, report_json_schema};
+
+    #[test]
+    fn json_schemas() {
+        let input = input_json_schema();
+        assert_eq!(input["properties"]["client"]["maximum"], 65535);
+        assert_eq!(input["then"]["required"][0], "amount");
+
+        let report = report_json_schema(&Column::DEFAULT);
+        let properties = report["properties"].as_object().unwrap();
+        assert_eq!(properties.len(), 5);
+        assert_eq!(report["properties"]["locked"]["type"], "boolean");
+        assert_eq!(report["required"][4], "locked");
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn arrow_schemas() {
+        use arrow_schema::DataType;
+
+        let input = super::input_arrow_schema();
+        assert_eq!(input.field(1).data_type(), &DataType::UInt16);
+        let report = super::report_arrow_schema(&Column::PERIOD);
+        assert_eq!(report.fields().len(), 7);
+        assert!(report.field_with_name("opening").unwrap().is_nullable());
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+
+use crate::{error::Error, payments::Tx, plugin::Plugin};
+
+// Rewrites or annotates an incoming transaction, rejecting it by returning an error.
+pub type Transformer = Box<dyn Fn(&mut Tx) -> Result<(), Error> + Send + Sync>;
+
+// Plugin running incoming transactions through a chain of transformers, in the order they were
+// added, before they reach the engine. Several enrichment plugins compose like any other plugins,
+// in registration order.
+pub struct Enrichment {
+    name: String,
+    transformers: Vec<Transformer>,
+}
+
+impl Enrichment {
+    pub fn new(name: impl Into<String>) -> Self {
+        Enrichment {
+            name: name.into(),
+            transformers: Vec::new(),
+        }
+    }
+
+    pub fn with(
+        mut self,
+        transformer: impl Fn(&mut Tx) -> Result<(), Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.transformers.push(Box::new(transformer));
+        self
+    }
+}
+
+impl Plugin for Enrichment {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn enrich(&self, tx: &mut Tx) -> Result<(), Error> {
+        self.transformers
+            .iter()
+            .try_for_each(|transformer| transformer(tx))
+    }
+}
+
+// Multiplies amounts by `factor`, e.g. to normalize amounts sent in another unit (cents).
+pub fn scale_amounts(factor: BigDecimal) -> impl Fn(&mut Tx) -> Result<(), Error> {
+    move |tx| {
+        let scaled = tx.amount().map(|amount| amount * &factor);
+        tx.set_amount(scaled);
+        Ok(())
+    }
+}
+
+// Maps external client ids to the engine's ones, leaving the clients without a mapping untouched.
+pub fn map_clients(ids: HashMap<u16, u16>) -> impl Fn(&mut Tx) -> Result<(), Error> {
+    move |tx| {
+        if let Some(client) = ids.get(&tx.client()) {
+            tx.remap_client(*client);
+        }
+        Ok(())
+    }
+}
+
+// Sets the timestamp of the transactions without one.
+pub fn default_timestamp(timestamp: u64) -> impl Fn(&mut Tx) -> Result<(), Error> {
+    move |tx| {
+        if tx.timestamp().is_none() {
+            tx.set_timestamp(Some(timestamp));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr};
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        error::Error,
+        outcome::TxOutcome,
+        payments::{Engine, Tx, TxType},
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{map_clients, scale_amounts, Enrichment};
+
+    #[tokio::test]
+    async fn transformers_are_chained() {
+        let cents = BigDecimal::from_str("0.01").unwrap();
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(
+            Enrichment::new("normalize")
+                .with(map_clients(HashMap::from([(100, 1)])))
+                .with(scale_amounts(cents)),
+        )
+        .plugin(Enrichment::new("validate").with(|tx: &mut Tx| {
+            if tx
+                .amount()
+                .is_some_and(|amount| *amount < BigDecimal::from(1))
+            {
+                return Err(Error::PluginRejected("amount too small".to_string()));
+            }
+            Ok(())
+        }))
+        .build()
+        .unwrap();
+
+        let deposit = Tx::new(TxType::Deposit, 100, 1, Some(BigDecimal::from(150)));
+        assert_eq!(engine.handle_tx(deposit).await, TxOutcome::Applied);
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(
+            account.lock().await.available(),
+            BigDecimal::from_str("1.5").unwrap()
+        );
+        assert!(engine.account(100).await.is_none());
+
+        // Amounts are validated once normalized.
+        let deposit = Tx::new(TxType::Deposit, 100, 2, Some(BigDecimal::from(50)));
+        assert!(engine.handle_tx(deposit).await.is_rejected());
+    }
+}
@@ -26,4 +26,100 @@ pub enum Error {
     UnexpectedMissingAccount(u16),
     #[error("Invalid dispute")]
     InvalidDispute(u32),
+    #[error("Account conflict while merging: {0}")]
+    AccountConflict(u16),
+    #[error("Transaction conflict while merging: {0}")]
+    TxConflict(u32),
+    #[error("Quota exceeded by tx: {0}")]
+    QuotaExceeded(u32),
+    #[error("Rejected by plugin: {0}")]
+    PluginRejected(String),
+    #[error("Invalid record: {0}")]
+    InvalidRecord(String),
+    #[error("Client id collision after remapping: {0}")]
+    ClientCollision(u16),
+    #[error("Amount above limit for tx: {0}")]
+    AmountAboveLimit(u32),
+    #[error("Row already applied by a previous run, tx: {0}")]
+    ReplayedRow(u32),
+    #[error("Sequence gap before tx: {0}")]
+    SequenceGap(u32),
+    #[error("Out of order tx: {0}")]
+    OutOfOrder(u32),
+    #[error("Client under review, tx queued: {0}")]
+    UnderReview(u32),
+    #[error("Transaction timed out: {0}")]
+    Timeout(u32),
+    #[error("Account not locked: {0}")]
+    AccountNotLocked(u16),
+    #[error("Transaction pending approval: {0}")]
+    PendingApproval(u32),
+    #[error("Transaction not pending: {0}")]
+    NotPending(u32),
+    #[error("Transaction owned by another client: {0}")]
+    ClientMismatch(u32),
+    #[error("Duplicate transaction id: {0}")]
+    DuplicateTx(u32),
+    #[error("Amount with more than four decimal places for tx: {0}")]
+    PrecisionExceeded(u32),
+    #[error("Non-positive amount for tx: {0}")]
+    NonPositiveAmount(u32),
+    #[error("Invalid engine configuration: {0}")]
+    InvalidConfig(String),
+}
+
+// Error of the row processing stopped at under `ErrorPolicy::Strict`, rows being numbered from 1
+// after the header.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("Row {row}: {error}")]
+pub struct RowError {
+    pub row: u64,
+    pub error: Error,
+}
+
+impl Error {
+    // Whether the error signals a bug or a storage inconsistency, rather than a transaction which
+    // was rejected by the business rules.
+    pub fn is_internal(&self) -> bool {
+        matches!(self, Error::UnexpectedMissingAccount(_))
+    }
+
+    // Name of the variant, for machine readable reports.
+    pub fn variant(&self) -> &'static str {
+        match self {
+            Error::MissingAmount(_) => "MissingAmount",
+            Error::TotalOverflow => "TotalOverflow",
+            Error::TxNotFound => "TxNotFound",
+            Error::AccountLocked(_) => "AccountLocked",
+            Error::TxNotDisputed(_) => "TxNotDisputed",
+            Error::TxAlreadyDisputed(_) => "TxAlreadyDisputed",
+            Error::InvalidAmount(_) => "InvalidAmount",
+            Error::MaxAvailableOverflow => "MaxAvailableOverflow",
+            Error::MaxHeldOverflow => "MaxHeldOverflow",
+            Error::MinAvailableUnderflow => "MinAvailableUnderflow",
+            Error::MinHeldUnderflow => "MinHeldUnderflow",
+            Error::UnexpectedMissingAccount(_) => "UnexpectedMissingAccount",
+            Error::InvalidDispute(_) => "InvalidDispute",
+            Error::AccountConflict(_) => "AccountConflict",
+            Error::TxConflict(_) => "TxConflict",
+            Error::QuotaExceeded(_) => "QuotaExceeded",
+            Error::PluginRejected(_) => "PluginRejected",
+            Error::InvalidRecord(_) => "InvalidRecord",
+            Error::ClientCollision(_) => "ClientCollision",
+            Error::AmountAboveLimit(_) => "AmountAboveLimit",
+            Error::ReplayedRow(_) => "ReplayedRow",
+            Error::SequenceGap(_) => "SequenceGap",
+            Error::OutOfOrder(_) => "OutOfOrder",
+            Error::UnderReview(_) => "UnderReview",
+            Error::Timeout(_) => "Timeout",
+            Error::AccountNotLocked(_) => "AccountNotLocked",
+            Error::PendingApproval(_) => "PendingApproval",
+            Error::NotPending(_) => "NotPending",
+            Error::ClientMismatch(_) => "ClientMismatch",
+            Error::DuplicateTx(_) => "DuplicateTx",
+            Error::PrecisionExceeded(_) => "PrecisionExceeded",
+            Error::NonPositiveAmount(_) => "NonPositiveAmount",
+            Error::InvalidConfig(_) => "InvalidConfig",
+        }
+    }
 }
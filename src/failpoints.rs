@@ -0,0 +1,122 @@
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tracing::error;
+
+use crate::{error::Error, payments::Tx, plugin::Plugin};
+
+// Point of the input at which the process gets crashed (see `FailureInjection`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrashPoint {
+    // Right before the transaction with the id is handled.
+    Tx(u32),
+}
+
+// Parses points like `tx=42`.
+impl FromStr for CrashPoint {
+    type Err = String;
+
+    fn from_str(point: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid crash point: {point}");
+        match point.split_once('=').ok_or_else(invalid)? {
+            ("tx", id) => id.parse().map(CrashPoint::Tx).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Failure injected by `FailureInjection`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    // The process exits with an error, like on an unrecoverable error.
+    Error(String),
+    // The process aborts, like on a kill, without flushing or saving anything.
+    Crash(String),
+}
+
+// Plugin deliberately failing the run at given points, so that integration tests and game days
+// can exercise the recovery paths (`--replay-log`, `--state`, progress checkpoints): erroring out
+// once `fail_after` transactions were handled, or crashing right before handling a transaction.
+#[derive(Debug, Default)]
+pub struct FailureInjection {
+    fail_after: Option<u64>,
+    crash_on: Vec<CrashPoint>,
+    handled: AtomicU64,
+}
+
+impl FailureInjection {
+    pub fn new(fail_after: Option<u64>, crash_on: Vec<CrashPoint>) -> Self {
+        FailureInjection {
+            fail_after,
+            crash_on,
+            handled: AtomicU64::new(0),
+        }
+    }
+
+    // Failure due before handling the transaction, if any.
+    pub fn due(&self, tx: &Tx) -> Option<Failure> {
+        if self.crash_on.contains(&CrashPoint::Tx(tx.id())) {
+            return Some(Failure::Crash(format!(
+                "Injected crash before tx {}",
+                tx.id()
+            )));
+        }
+        let handled = self.handled.fetch_add(1, Ordering::SeqCst);
+        match self.fail_after {
+            Some(fail_after) if handled >= fail_after => Some(Failure::Error(format!(
+                "Injected failure after {fail_after} transactions"
+            ))),
+            _ => None,
+        }
+    }
+}
+
+impl Plugin for FailureInjection {
+    fn name(&self) -> &str {
+        "failure_injection"
+    }
+
+    fn on_tx(&self, tx: &Tx) -> Result<(), Error> {
+        match self.due(tx) {
+            Some(Failure::Error(reason)) => {
+                error!("{reason}");
+                std::process::exit(1);
+            }
+            Some(Failure::Crash(reason)) => {
+                eprintln!("{reason}");
+                std::process::abort();
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::payments::{Tx, TxType};
+
+    use super::{CrashPoint, Failure, FailureInjection};
+
+    #[test]
+    fn failures_due() {
+        assert_eq!(CrashPoint::from_str("tx=3"), Ok(CrashPoint::Tx(3)));
+        assert!(CrashPoint::from_str("row=3").is_err());
+        assert!(CrashPoint::from_str("tx=three").is_err());
+
+        let injection = FailureInjection::new(Some(2), vec![CrashPoint::Tx(2)]);
+        let tx = |id| Tx::new(TxType::Deposit, 1, id, None);
+        assert_eq!(injection.due(&tx(1)), None);
+        assert!(matches!(injection.due(&tx(2)), Some(Failure::Crash(_))));
+        assert_eq!(injection.due(&tx(3)), None);
+        assert_eq!(
+            injection.due(&tx(4)),
+            Some(Failure::Error(
+                "Injected failure after 2 transactions".to_string()
+            ))
+        );
+    }
+}
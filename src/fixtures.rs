@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use tokio::fs;
+
+// Pathological transaction inputs, exercising the corner cases any compatible implementation
+// needs to get right. Each fixture is a `(name, csv)` pair.
+pub const FIXTURES: &[(&str, &str)] = &[
+    (
+        "duplicate_ids",
+        "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,1,2.0
+withdrawal,1,1,0.5
+deposit,2,1,3.0
+dispute,1,1,
+resolve,1,1,
+",
+    ),
+    (
+        "cross_client_disputes",
+        "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,1.0
+dispute,2,1,
+resolve,2,1,
+dispute,2,1,
+chargeback,2,1,
+chargeback,1,1,
+",
+    ),
+    (
+        "precision_edge_cases",
+        "type,client,tx,amount
+deposit,1,1,0.0001
+deposit,1,2,9999999999.9999
+withdrawal,1,3,0.00009
+deposit,1,4,1.00000
+withdrawal,1,5,10000000000.0000
+deposit,2,6,001.1000
+withdrawal,2,7,1.1
+deposit,3,8,0
+deposit,3,9,-1.0
+deposit,3,10,
+",
+    ),
+    (
+        "locked_account_sequences",
+        "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,5.0
+dispute,1,1,
+chargeback,1,1,
+deposit,1,3,1.0
+withdrawal,1,4,1.0
+dispute,1,2,
+resolve,1,2,
+chargeback,1,2,
+",
+    ),
+];
+
+// Writes every fixture as `<name>.csv` under the given directory.
+pub async fn write_fixtures(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir).await?;
+    for (name, content) in FIXTURES {
+        fs::write(dir.join(format!("{name}.csv")), content).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        payments::Engine,
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::FIXTURES;
+
+    fn fixture(name: &str) -> &'static str {
+        FIXTURES
+            .iter()
+            .find(|(fixture_name, _)| *fixture_name == name)
+            .unwrap()
+            .1
+    }
+
+    #[tokio::test]
+    async fn fixtures_are_processable() {
+        for (_, content) in FIXTURES {
+            let mut engine = Engine::new(
+                InMemoryAccountLedger::default(),
+                InMemoryTxLedger::default(),
+            );
+            engine
+                .handle_txs(tokio::io::BufReader::new(content.as_bytes()))
+                .await
+                .unwrap();
+            assert!(!engine.accounts().await.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn locked_account_sequences() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                fixture("locked_account_sequences").as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        let account = engine.account(1).await.unwrap();
+        let inner = account.lock().await;
+        assert!(inner.is_locked());
+        assert_eq!(inner.available().to_string(), "5.0");
+        assert_eq!(inner.held().to_string(), "0.0");
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::Write;
+
+use bigdecimal::{num_bigint::BigInt, BigDecimal, ToPrimitive};
+
+// Leading zeros after the decimal point past which `BigDecimal`'s `Display` switches to exponential
+// notation.
+const MAX_LEADING_ZEROS: u64 = 5;
+// Same for the trailing zeros of amounts with a negative scale.
+const MAX_TRAILING_ZEROS: u64 = 15;
+
+// Decimal representation of `amount` (see `write_amount`).
+pub fn format_amount(amount: BigDecimal) -> String {
+    let mut buf = Vec::with_capacity(24);
+    write_amount(&mut buf, amount);
+    String::from_utf8(buf).expect("amounts are written as ASCII")
+}
+
+// Appends the decimal representation of `amount` to `buf`, the same as its `Display` output, but
+// formatted from its unscaled integer and scale without going through intermediate strings. Amounts
+// which don't fit 128 bits or which `Display` writes in exponential notation fall back to it.
+pub fn write_amount(buf: &mut Vec<u8>, amount: BigDecimal) {
+    let (unscaled, scale) = amount.into_bigint_and_exponent();
+    let Some(value) = unscaled.to_i128() else {
+        return fallback(buf, unscaled, scale);
+    };
+
+    // Digits of the absolute value, right-aligned.
+    let mut digits = [0u8; 40];
+    let mut start = digits.len();
+    let mut abs = value.unsigned_abs();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (abs % 10) as u8;
+        abs /= 10;
+        if abs == 0 {
+            break;
+        }
+    }
+    let digits = &digits[start..];
+    let len = digits.len() as u64;
+
+    if scale > 0 && (scale as u64).saturating_sub(len) > MAX_LEADING_ZEROS
+        || scale < 0 && scale.unsigned_abs() > MAX_TRAILING_ZEROS
+    {
+        return fallback(buf, unscaled, scale);
+    }
+
+    if value < 0 {
+        buf.push(b'-');
+    }
+    if scale <= 0 {
+        buf.extend_from_slice(digits);
+        buf.resize(buf.len() + scale.unsigned_abs() as usize, b'0');
+    } else if len > scale as u64 {
+        let point = digits.len() - scale as usize;
+        buf.extend_from_slice(&digits[..point]);
+        buf.push(b'.');
+        buf.extend_from_slice(&digits[point..]);
+    } else {
+        buf.extend_from_slice(b"0.");
+        buf.resize(buf.len() + (scale as u64 - len) as usize, b'0');
+        buf.extend_from_slice(digits);
+    }
+}
+
+fn fallback(buf: &mut Vec<u8>, unscaled: BigInt, scale: i64) {
+    let _ = write!(buf, "{}", BigDecimal::new(unscaled, scale));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use super::write_amount;
+
+    #[test]
+    fn matches_display() {
+        let mut buf = Vec::new();
+        for amount in [
+            "0",
+            "0.0",
+            "1.5",
+            "2.0",
+            "-3.25",
+            "0.0001",
+            "-0.05",
+            "10.1000",
+            "1e3",
+            "12345678901234567890123456789012345.1234",
+            "123456789012345678901234567890123456789012345",
+            "0.0000001",
+            "1e20",
+        ] {
+            let amount = BigDecimal::from_str(amount).unwrap();
+            buf.clear();
+            write_amount(&mut buf, amount.clone());
+            assert_eq!(String::from_utf8(buf.clone()).unwrap(), amount.to_string());
+        }
+    }
+}
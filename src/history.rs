@@ -0,0 +1,164 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::{
+    allocator,
+    payments::Engine,
+    storage::{AccountsDal, TxsDal},
+};
+
+// Summary statistics of a run, appended as a JSON line to the stats history so that trends of
+// rejection rates and throughput can be charted across runs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunStats {
+    pub run_id: String,
+    pub inputs: Vec<String>,
+    // Milliseconds since the epoch.
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub txs: u64,
+    pub rejected: u64,
+    pub rejection_rate: f64,
+    // Transactions per second.
+    pub throughput: f64,
+    pub accounts: u64,
+    pub locked_accounts: u64,
+    pub clearing_balance: String,
+    // Allocator of the binary and the allocations made through it since the start of the process,
+    // zero unless they're counted (see `allocator::COUNTED`).
+    #[serde(default)]
+    pub allocator: String,
+    #[serde(default)]
+    pub allocations: u64,
+    #[serde(default)]
+    pub allocated_bytes: u64,
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl RunStats {
+    // Collects the statistics of a run which started at `started_at` and just finished.
+    pub async fn collect<A, T>(
+        engine: &Engine<A, T>,
+        run_id: String,
+        inputs: Vec<String>,
+        started_at: u64,
+    ) -> Self
+    where
+        A: AccountsDal + Send + Sync + Clone,
+        T: TxsDal + Send + Sync + Clone,
+    {
+        let mut accounts = 0;
+        let mut locked_accounts = 0;
+        for account in engine.accounts().await.values() {
+            accounts += 1;
+            if account.lock().await.is_locked() {
+                locked_accounts += 1;
+            }
+        }
+        let finished_at = now_millis().max(started_at);
+        let txs = engine.latency().count();
+        let rejected = engine.rejected();
+        let elapsed = (finished_at - started_at) as f64 / 1000.0;
+        RunStats {
+            run_id,
+            inputs,
+            started_at,
+            finished_at,
+            txs,
+            rejected,
+            rejection_rate: if txs == 0 {
+                0.0
+            } else {
+                rejected as f64 / txs as f64
+            },
+            throughput: if elapsed > 0.0 {
+                txs as f64 / elapsed
+            } else {
+                0.0
+            },
+            accounts,
+            locked_accounts,
+            clearing_balance: engine.clearing().balance().to_string(),
+            allocator: allocator::NAME.to_string(),
+            allocations: allocator::allocations(),
+            allocated_bytes: allocator::allocated_bytes(),
+        }
+    }
+}
+
+// Appends the statistics of a run to the JSONL history file, creating it if missing.
+pub async fn append(path: &Path, stats: &RunStats) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(stats)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&line).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{append, now_millis, RunStats};
+
+    #[tokio::test]
+    async fn append_run_stats() {
+        let path = std::env::temp_dir().join("payments-engine-history.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let started_at = now_millis();
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,5.0\ndeposit,2,3,1.0\nwithdrawal,2,4,1.0"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        for run in ["first", "second"] {
+            let stats = RunStats::collect(
+                &engine,
+                run.to_string(),
+                vec!["in.csv".to_string()],
+                started_at,
+            )
+            .await;
+            append(&path, &stats).await.unwrap();
+        }
+
+        let history = std::fs::read_to_string(&path).unwrap();
+        let runs: Vec<RunStats> = history
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].run_id, "second");
+        assert_eq!((runs[0].txs, runs[0].rejected), (4, 1));
+        assert_eq!(runs[0].rejection_rate, 0.25);
+        assert_eq!(runs[0].accounts, 2);
+        assert!(runs[0].finished_at >= started_at);
+        assert!(runs[1].allocations >= runs[0].allocations && runs[0].allocations > 0);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+
+use bigdecimal::{BigDecimal, Zero};
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+
+use crate::{account::Account, error::Error, schema::parse_amount, storage::AccountsDal};
+
+#[derive(Deserialize)]
+struct OpeningBalance {
+    client: u16,
+    available: String,
+    held: String,
+    locked: bool,
+}
+
+fn parse_balance(amount: &str) -> Result<BigDecimal, Error> {
+    let amount = parse_amount(amount)?;
+    if amount < BigDecimal::zero() {
+        return Err(Error::InvalidAmount(amount.to_string()));
+    }
+    Ok(amount)
+}
+
+// Seeds the accounts ledger from a CSV of opening balances with a `client,available,held,locked`
+// header (e.g. when migrating from another system), returning the number of imported accounts.
+// The whole input is validated first, so nothing gets imported if any row is invalid or clashes
+// with an existing account.
+pub async fn import_accounts<A: AccountsDal>(
+    accounts: &mut A,
+    reader: impl AsyncRead + Send + Unpin,
+) -> Result<usize, Error> {
+    let mut records = csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .create_deserializer(reader)
+        .into_deserialize::<OpeningBalance>();
+    let mut imported = Vec::new();
+    let mut clients = HashSet::new();
+    while let Some(record) = records.next().await {
+        let record = record.map_err(|err| Error::InvalidRecord(err.to_string()))?;
+        if !clients.insert(record.client) || accounts.account(record.client).await.is_some() {
+            return Err(Error::AccountConflict(record.client));
+        }
+        imported.push(Account::new(
+            record.client,
+            parse_balance(&record.available)?,
+            parse_balance(&record.held)?,
+            record.locked,
+        ));
+    }
+
+    let count = imported.len();
+    for account in imported {
+        accounts.insert(account).await;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        storage::{AccountsDal, InMemoryAccountLedger},
+    };
+
+    use super::import_accounts;
+
+    #[tokio::test]
+    async fn import_opening_balances() {
+        let mut ledger = InMemoryAccountLedger::default();
+        let count = import_accounts(
+            &mut ledger,
+            "client,available,held,locked\n1,10.5,1,false\n2,0,0,true".as_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(count, 2);
+
+        let account = ledger.account(1).await.unwrap();
+        assert_eq!(account.lock().await.total().to_string(), "11.5");
+        assert!(ledger.account(2).await.unwrap().lock().await.is_locked());
+    }
+
+    #[tokio::test]
+    async fn import_is_all_or_nothing() {
+        let mut ledger = InMemoryAccountLedger::default();
+        let res = import_accounts(
+            &mut ledger,
+            "client,available,held,locked\n1,1,0,false\n2,-1,0,false".as_bytes(),
+        )
+        .await;
+        assert_eq!(res, Err(Error::InvalidAmount("-1".to_string())));
+        assert!(ledger.account(1).await.is_none());
+
+        let res = import_accounts(
+            &mut ledger,
+            "client,available,held,locked\n1,1,0,false\n1,2,0,false".as_bytes(),
+        )
+        .await;
+        assert_eq!(res, Err(Error::AccountConflict(1)));
+        assert!(ledger.accounts().await.is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use bigdecimal::{BigDecimal, Zero};
+use tracing::error;
+
+use crate::{
+    account::Account,
+    payments::{Tx, TxType},
+    plugin::Plugin,
+};
+
+// What happens when an account reaches an impossible state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnViolation {
+    Warn,
+    Abort,
+}
+
+// Plugin checking the state of every account after a transaction was applied against states the
+// business rules should make impossible: a negative held balance, funds held without an open
+// dispute, or a locked account still having open disputes. Violations are logged (as internal
+// errors, so they also reach Sentry) with the transaction which caused them, and optionally abort
+// the process. Open disputes are only known for the transactions of this run.
+pub struct Invariants {
+    on_violation: OnViolation,
+    // Open disputes per client.
+    open: Mutex<HashMap<u16, u64>>,
+    violations: AtomicU64,
+}
+
+impl Invariants {
+    pub fn new(on_violation: OnViolation) -> Self {
+        Invariants {
+            on_violation,
+            open: Mutex::new(HashMap::new()),
+            violations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn violations(&self) -> u64 {
+        self.violations.load(Ordering::Relaxed)
+    }
+
+    fn violated(&self, tx: &Tx, account: &Account, invariant: &str) {
+        self.violations.fetch_add(1, Ordering::Relaxed);
+        error!(
+            "Invariant violated by tx {} on client {}: {invariant} (available {}, held {}, locked {})",
+            tx.id(),
+            account.client_id(),
+            account.available(),
+            account.held(),
+            account.is_locked()
+        );
+        if self.on_violation == OnViolation::Abort {
+            std::process::abort();
+        }
+    }
+}
+
+impl Plugin for Invariants {
+    fn name(&self) -> &str {
+        "invariants"
+    }
+
+    fn on_account(&self, tx: &Tx, account: &Account) {
+        let open = {
+            let mut open = self.open.lock().unwrap();
+            let count = open.entry(account.client_id()).or_default();
+            match tx.tx_type() {
+                TxType::Dispute => *count += 1,
+                TxType::Resolve | TxType::Chargeback => *count = count.saturating_sub(1),
+                TxType::Deposit | TxType::Withdrawal => (),
+            }
+            *count
+        };
+        let held = account.held();
+        if held < BigDecimal::zero() {
+            self.violated(tx, account, "negative held balance");
+        }
+        if held > BigDecimal::zero() && open == 0 {
+            self.violated(tx, account, "funds held without an open dispute");
+        }
+        if account.is_locked() && open > 0 {
+            self.violated(tx, account, "locked account with open disputes");
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        vec![("violations".to_string(), self.violations().to_string())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        account::Account,
+        payments::{Tx, TxType},
+        plugin::Plugin,
+    };
+
+    use super::{Invariants, OnViolation};
+
+    #[test]
+    fn detects_impossible_states() {
+        let invariants = Invariants::new(OnViolation::Warn);
+        let amount = |amount| BigDecimal::from_str(amount).unwrap();
+        let deposit = Tx::new(TxType::Deposit, 1, 1, Some(amount("1.0")));
+        let dispute = Tx::new(TxType::Dispute, 1, 1, None);
+        let chargeback = Tx::new(TxType::Chargeback, 1, 1, None);
+
+        invariants.on_account(
+            &deposit,
+            &Account::new(1, amount("1.0"), amount("0"), false),
+        );
+        invariants.on_account(
+            &dispute,
+            &Account::new(1, amount("0"), amount("1.0"), false),
+        );
+        assert_eq!(invariants.violations(), 0);
+
+        invariants.on_account(
+            &deposit,
+            &Account::new(2, amount("1.0"), amount("-1.0"), false),
+        );
+        assert_eq!(invariants.violations(), 1);
+        invariants.on_account(
+            &deposit,
+            &Account::new(3, amount("1.0"), amount("1.0"), false),
+        );
+        assert_eq!(invariants.violations(), 2);
+        // Client 1 still has its dispute open.
+        invariants.on_account(
+            &dispute,
+            &Account::new(1, amount("0"), amount("2.0"), false),
+        );
+        invariants.on_account(
+            &chargeback,
+            &Account::new(1, amount("0"), amount("1.0"), true),
+        );
+        assert_eq!(invariants.violations(), 3);
+    }
+}
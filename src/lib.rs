@@ -0,0 +1,74 @@
+// Payments engine holding account and transaction ledgers, usable as a library by other services,
+// with the `payments-engine` binary being a thin CLI over it. The most commonly used types are
+// re-exported at the root.
+pub mod account;
+pub mod activity;
+pub mod aggregates;
+pub mod alerts;
+pub mod allocator;
+pub mod amounts;
+pub mod batch;
+pub mod cache;
+pub mod cdc;
+pub mod checksum;
+pub mod deltas;
+pub mod descriptor;
+pub mod enrich;
+pub mod error;
+pub mod failpoints;
+pub mod fixtures;
+pub mod format;
+pub mod history;
+pub mod import;
+pub mod invariants;
+pub mod lockout;
+pub mod logging;
+pub mod merge;
+pub mod metrics;
+pub mod mmap;
+pub mod offload;
+pub mod outcome;
+pub mod output;
+pub mod payments;
+pub mod plugin;
+pub mod precision;
+pub mod progress;
+pub mod prometheus;
+pub mod quarantine;
+pub mod quota;
+pub mod rejects;
+pub mod remap;
+pub mod reorder;
+pub mod replay;
+pub mod report;
+pub mod retention;
+pub mod review;
+pub mod runner;
+pub mod schema;
+pub mod sequence;
+pub mod shard;
+pub mod shipping;
+pub mod snapshot;
+pub mod source;
+pub mod stats;
+pub mod storage;
+pub mod tags;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod top;
+
+pub use account::Account;
+pub use error::{Error, RowError};
+pub use outcome::TxOutcome;
+pub use payments::{
+    DisputePolicy, Engine, EngineBuilder, ErrorPolicy, ProcessingReport, Tx, TxType,
+};
+pub use storage::{
+    AccountsDal, InMemoryAccountLedger, InMemoryPendingLedger, InMemoryTxLedger, PendingDal, TxsDal,
+};
+
+// The binary picks the global allocator, counted for the stats of the run, which unit tests need
+// too.
+#[cfg(test)]
+#[global_allocator]
+static GLOBAL: allocator::Counting<std::alloc::System> = allocator::Counting(std::alloc::System);
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default)]
+struct Lockout {
+    // Transactions processed when the account got locked by a chargeback, while still locked.
+    locked_at: Option<u64>,
+    chargebacks: u64,
+    unlocks: u64,
+}
+
+// Lockouts of the accounts locked by chargebacks. Locked accounts stay locked, unless a cooling-off
+// period is set, in which case they get unlocked by their first transaction once `cooling_off`
+// transactions were processed after their chargeback, or unless an admin approves unlocking them
+// (see `Engine::unlock`). Like the dispute window, the period is measured in processed
+// transactions, and only starts for chargebacks seen by this engine. Clients charged back more than
+// once are tracked as repeat offenders.
+#[derive(Clone, Debug, Default)]
+pub struct Lockouts {
+    cooling_off: Option<u64>,
+    seq: u64,
+    // Transactions processed before the lockouts were forked (see `fork`).
+    forked_at: u64,
+    clients: HashMap<u16, Lockout>,
+}
+
+impl Lockouts {
+    pub fn new(cooling_off: Option<u64>) -> Self {
+        Lockouts {
+            cooling_off,
+            ..Default::default()
+        }
+    }
+
+    // Lockouts for a worker engine (see `Engine::isolated`), starting from the locks of these ones
+    // without their counts, so that merging them back only adds what happened in the worker.
+    pub fn fork(&self) -> Self {
+        let clients = self
+            .clients
+            .iter()
+            .filter(|(_, lockout)| lockout.locked_at.is_some())
+            .map(|(client, lockout)| {
+                let lockout = Lockout {
+                    locked_at: lockout.locked_at,
+                    ..Default::default()
+                };
+                (*client, lockout)
+            })
+            .collect();
+        Lockouts {
+            cooling_off: self.cooling_off,
+            seq: self.seq,
+            forked_at: self.seq,
+            clients,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.seq += 1;
+    }
+
+    pub fn record_chargeback(&mut self, client: u16) {
+        let lockout = self.clients.entry(client).or_default();
+        lockout.chargebacks += 1;
+        lockout.locked_at = Some(self.seq);
+    }
+
+    // Whether the cooling-off period of the client's lockout is over.
+    pub fn cooled_off(&self, client: u16) -> bool {
+        let locked_at = self
+            .clients
+            .get(&client)
+            .and_then(|lockout| lockout.locked_at);
+        match (self.cooling_off, locked_at) {
+            (Some(cooling_off), Some(locked_at)) => self.seq - locked_at > cooling_off,
+            _ => false,
+        }
+    }
+
+    pub fn record_unlock(&mut self, client: u16) {
+        let lockout = self.clients.entry(client).or_default();
+        lockout.locked_at = None;
+        lockout.unlocks += 1;
+    }
+
+    // Number of accounts unlocked so far.
+    pub fn unlocks(&self) -> u64 {
+        self.clients.values().map(|lockout| lockout.unlocks).sum()
+    }
+
+    // Clients charged back more than once, with their number of chargebacks, by client id.
+    pub fn repeat_offenders(&self) -> Vec<(u16, u64)> {
+        let mut offenders: Vec<(u16, u64)> = self
+            .clients
+            .iter()
+            .filter(|(_, lockout)| lockout.chargebacks > 1)
+            .map(|(client, lockout)| (*client, lockout.chargebacks))
+            .collect();
+        offenders.sort_unstable();
+        offenders
+    }
+
+    // Adds the lockouts of another engine (e.g. a worker, see `fork`), whose transactions count
+    // as processed after the ones of this engine. Counts are summed, and the latest lock of the
+    // clients is kept, unless the other engine unlocked them.
+    pub fn merge(&mut self, other: &Lockouts) {
+        self.seq += other.seq - other.forked_at;
+        for (client, lockout) in other.clients.iter() {
+            // Lock times are kept relative to the last transaction processed.
+            let locked_at = lockout
+                .locked_at
+                .map(|locked_at| self.seq.saturating_sub(other.seq - locked_at));
+            let merged = self.clients.entry(*client).or_default();
+            merged.chargebacks += lockout.chargebacks;
+            merged.unlocks += lockout.unlocks;
+            merged.locked_at = match (merged.locked_at, locked_at) {
+                (Some(current), Some(other)) => Some(current.max(other)),
+                (_, None) if lockout.unlocks > 0 => None,
+                (current, other) => current.or(other),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lockouts;
+
+    #[test]
+    fn cooling_off() {
+        let mut lockouts = Lockouts::new(Some(2));
+        lockouts.tick();
+        lockouts.record_chargeback(1);
+        lockouts.tick();
+        lockouts.tick();
+        assert!(!lockouts.cooled_off(1));
+        lockouts.tick();
+        assert!(lockouts.cooled_off(1));
+        assert!(!lockouts.cooled_off(2));
+
+        lockouts.record_unlock(1);
+        assert!(!lockouts.cooled_off(1));
+        lockouts.record_chargeback(1);
+        lockouts.record_chargeback(2);
+        assert_eq!(lockouts.repeat_offenders(), vec![(1, 2)]);
+        assert_eq!(lockouts.unlocks(), 1);
+
+        // Without a cooling-off period, only admins unlock accounts.
+        let mut lockouts = Lockouts::new(None);
+        lockouts.record_chargeback(1);
+        (0..10).for_each(|_| lockouts.tick());
+        assert!(!lockouts.cooled_off(1));
+    }
+
+    #[test]
+    fn merge() {
+        let mut lockouts = Lockouts::new(Some(2));
+        lockouts.tick();
+        lockouts.record_chargeback(1);
+        lockouts.record_chargeback(3);
+
+        let mut other = lockouts.fork();
+        (0..3).for_each(|_| other.tick());
+        other.record_chargeback(1);
+        other.record_chargeback(2);
+        other.record_unlock(3);
+        lockouts.tick();
+        lockouts.merge(&other);
+        assert_eq!(lockouts.repeat_offenders(), vec![(1, 2)]);
+        assert_eq!(lockouts.unlocks(), 1);
+        // Client 1 is locked as of the worker's chargeback, the later one.
+        lockouts.tick();
+        lockouts.tick();
+        assert!(!lockouts.cooled_off(1));
+        lockouts.tick();
+        assert!(lockouts.cooled_off(1));
+        assert!(lockouts.cooled_off(2));
+        assert!(!lockouts.cooled_off(3));
+
+        // Without a common history, counts still add up.
+        let mut other = Lockouts::new(Some(2));
+        other.record_chargeback(1);
+        lockouts.merge(&other);
+        assert_eq!(lockouts.repeat_offenders(), vec![(1, 3)]);
+    }
+}
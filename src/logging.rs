@@ -0,0 +1,96 @@
+// Sampling of the per-row rejection logs, which are unusable at millions of rows: only one in
+// `rate` rejections gets logged, while an aggregated summary can be logged every `summary_interval`
+// rows, so that sampled out rejections are still accounted for.
+#[derive(Clone, Debug)]
+pub struct LogSampler {
+    rate: u64,
+    summary_interval: Option<u64>,
+    rows: u64,
+    rejections: u64,
+    rejections_since_summary: u64,
+}
+
+impl Default for LogSampler {
+    fn default() -> Self {
+        LogSampler::new(1, None)
+    }
+}
+
+impl LogSampler {
+    pub fn new(rate: u64, summary_interval: Option<u64>) -> Self {
+        LogSampler {
+            rate: rate.max(1),
+            summary_interval: summary_interval.filter(|interval| *interval > 0),
+            rows: 0,
+            rejections: 0,
+            rejections_since_summary: 0,
+        }
+    }
+
+    // Records a rejection and returns whether it should be logged.
+    pub fn rejection(&mut self) -> bool {
+        self.rejections += 1;
+        self.rejections_since_summary += 1;
+        (self.rejections - 1).is_multiple_of(self.rate)
+    }
+
+    // Records a processed row and, when a summary is due, returns the number of rejections since
+    // the previous summary.
+    pub fn row(&mut self) -> Option<u64> {
+        self.rows += 1;
+        let interval = self.summary_interval?;
+        if !self.rows.is_multiple_of(interval) {
+            return None;
+        }
+
+        Some(std::mem::take(&mut self.rejections_since_summary))
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn summary_interval(&self) -> Option<u64> {
+        self.summary_interval
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn rejections(&self) -> u64 {
+        self.rejections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogSampler;
+
+    #[test]
+    fn rejection_sampling() {
+        let mut sampler = LogSampler::new(3, None);
+        let logged: Vec<bool> = (0..7).map(|_| sampler.rejection()).collect();
+        assert_eq!(logged, vec![true, false, false, true, false, false, true]);
+        assert_eq!(sampler.rejections(), 7);
+    }
+
+    #[test]
+    fn no_sampling_by_default() {
+        let mut sampler = LogSampler::default();
+        assert!((0..5).all(|_| sampler.rejection()));
+        assert!((0..5).all(|_| sampler.row().is_none()));
+    }
+
+    #[test]
+    fn periodic_summaries() {
+        let mut sampler = LogSampler::new(10, Some(2));
+        sampler.rejection();
+        assert_eq!(sampler.row(), None);
+        sampler.rejection();
+        assert_eq!(sampler.row(), Some(2));
+        assert_eq!(sampler.row(), None);
+        assert_eq!(sampler.row(), Some(0));
+        assert_eq!(sampler.rows(), 4);
+    }
+}
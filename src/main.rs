@@ -1,50 +1,672 @@
+use std::{convert::TryFrom, path::Path, str::FromStr, sync::Arc, time::Duration};
+
 use anyhow::anyhow;
-use clap::Parser;
-use payments::Engine;
-use storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger};
+use bigdecimal::{BigDecimal, RoundingMode};
+use clap::FromArgMatches;
+use cli::{
+    Args, BucketArg, Command, FormatArg, GenerateCommand, InvariantModeArg, MeasureArg,
+    PendingCommand, QuoteArg, ReorderKeyArg, ReportCommand, RoundingArg, SchemaArg,
+    SequencePolicyArg,
+};
+use csv_async::{AsyncWriterBuilder, QuoteStyle};
+use payments_engine::{
+    activity::{Activity, Bucket},
+    aggregates::Aggregates,
+    alerts::{AlertRule, AlertSink, Alerting},
+    allocator,
+    amounts::AmountChecks,
+    batch,
+    cdc::{self, Cdc},
+    checksum,
+    deltas::Deltas,
+    descriptor,
+    failpoints::{CrashPoint, FailureInjection},
+    fixtures, history,
+    invariants::{Invariants, OnViolation},
+    merge::MergeWindow,
+    precision::PrecisionPolicy,
+    progress::Progress,
+    quarantine::Quarantine,
+    quota::Quota,
+    reorder::{ReorderBuffer, ReorderKey},
+    replay::ReplayGuard,
+    report::{Column, Format, Style},
+    retention::RetentionPolicy,
+    runner::{self, EngineMode, FileOutcome, Finished, Run},
+    sequence::SequencePolicy,
+    shard::{HotAccounts, Routing},
+    shipping, snapshot,
+    source::{CsvParser, InputReader},
+    top::{self, Measure},
+    DisputePolicy, Engine, ErrorPolicy, InMemoryAccountLedger, InMemoryTxLedger,
+};
 use tokio::fs::File;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-pub mod account;
-pub mod error;
-pub mod payments;
-pub mod storage;
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: allocator::Counting<mimalloc::MiMalloc> = allocator::Counting(mimalloc::MiMalloc);
+#[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
+#[global_allocator]
+static GLOBAL: allocator::Counting<tikv_jemallocator::Jemalloc> =
+    allocator::Counting(tikv_jemallocator::Jemalloc);
+#[cfg(all(
+    feature = "alloc-stats",
+    not(any(feature = "mimalloc", feature = "jemalloc"))
+))]
+#[global_allocator]
+static GLOBAL: allocator::Counting<std::alloc::System> = allocator::Counting(std::alloc::System);
 
-#[derive(Parser, Debug)]
-pub struct Args {
-    pub input: String,
-}
+mod cli;
+mod validate;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let args =
+        Args::from_arg_matches(&cli::command().get_matches()).unwrap_or_else(|err| err.exit());
+    match args.command {
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(
+                shell,
+                &mut cli::command(),
+                "payments-engine",
+                &mut std::io::stdout(),
+            );
+            return Ok(());
+        }
+        Some(Command::Generate(GenerateCommand::Fixtures { out })) => {
+            fixtures::write_fixtures(&out)
+                .await
+                .map_err(|err| anyhow!("Error while writing fixtures: {err}"))?;
+            return Ok(());
+        }
+        Some(Command::Report(ReportCommand::Activity { input, bucket })) => {
+            let bucket = match bucket {
+                BucketArg::Hour => Bucket::Hour,
+                BucketArg::Day => Bucket::Day,
+            };
+            let activity = Activity::new(bucket);
+            let mut engine = Engine::builder(
+                InMemoryAccountLedger::default(),
+                InMemoryTxLedger::default(),
+            )
+            .plugin(activity.clone())
+            .build()?;
+            for path in &input {
+                let file = InputReader::File
+                    .open(path)
+                    .await
+                    .map_err(|err| anyhow!("Error while opening {path}: {err}"))?;
+                engine.handle_txs(file).await?;
+            }
+            if activity.untimed() > 0 {
+                eprintln!(
+                    "{} transactions without a timestamp left out of the report",
+                    activity.untimed()
+                );
+            }
+            activity
+                .write(&mut tokio::io::stdout())
+                .await
+                .map_err(|err| anyhow!("Error while writing the activity report: {err}"))?;
+            return Ok(());
+        }
+        Some(Command::Report(ReportCommand::Top { input, by, n })) => {
+            let measure = match by {
+                MeasureArg::Held => Measure::Held,
+                MeasureArg::Volume => Measure::Volume,
+                MeasureArg::Chargebacks => Measure::Chargebacks,
+            };
+            let aggregates = Aggregates::default();
+            let mut engine = Engine::builder(
+                InMemoryAccountLedger::default(),
+                InMemoryTxLedger::default(),
+            )
+            .plugin(aggregates.clone())
+            .build()?;
+            for path in &input {
+                let file = InputReader::File
+                    .open(path)
+                    .await
+                    .map_err(|err| anyhow!("Error while opening {path}: {err}"))?;
+                engine.handle_txs(file).await?;
+            }
+            let ranked = top::top(&engine, &aggregates, measure, n).await;
+            top::write_top(ranked, measure, &mut tokio::io::stdout())
+                .await
+                .map_err(|err| anyhow!("Error while writing the top report: {err}"))?;
+            return Ok(());
+        }
+        Some(Command::Batches { state, batch }) => {
+            let engine = restore(&state).await?;
+            let mut serializer = AsyncWriterBuilder::new()
+                .has_headers(false)
+                .create_serializer(tokio::io::stdout());
+            match batch {
+                Some(batch) => {
+                    serializer
+                        .serialize(("type", "client", "tx", "amount"))
+                        .await?;
+                    for tx in batch::batch_txs(&engine, &batch).await {
+                        let amount = tx.amount().map(ToString::to_string);
+                        serializer
+                            .serialize((tx.tx_type(), tx.client(), tx.id(), amount))
+                            .await?;
+                    }
+                }
+                None => {
+                    serializer.serialize(("batch", "txs")).await?;
+                    for batch in batch::batches(&engine).await {
+                        serializer.serialize(batch).await?;
+                    }
+                }
+            }
+            serializer.flush().await?;
+            return Ok(());
+        }
+        Some(Command::RevertBatch {
+            batch,
+            state,
+            save_state,
+        }) => {
+            let mut engine = restore(&state).await?;
+            let reversals = batch::revert_batch(&mut engine, &batch).await;
+            if reversals.is_empty() {
+                eprintln!("No transactions to revert in batch {batch}");
+            }
+            let mut serializer = AsyncWriterBuilder::new()
+                .has_headers(false)
+                .create_serializer(tokio::io::stdout());
+            serializer
+                .serialize(("tx", "client", "compensation", "skipped"))
+                .await?;
+            for reversal in reversals {
+                let (compensation, skipped) = match reversal.outcome {
+                    Ok(id) => (Some(id), None),
+                    Err(err) => (None, Some(err.to_string())),
+                };
+                serializer
+                    .serialize((reversal.tx, reversal.client, compensation, skipped))
+                    .await?;
+            }
+            serializer.flush().await?;
+            if let Some(save_state) = save_state {
+                snapshot::save(&save_state, &engine.snapshot().await)
+                    .await
+                    .map_err(|err| anyhow!("Error while saving state: {err}"))?;
+            }
+            return Ok(());
+        }
+        Some(Command::Pending(command)) => {
+            let (PendingCommand::List { state }
+            | PendingCommand::Approve { state, .. }
+            | PendingCommand::Reject { state, .. }) = &command;
+            let mut engine = restore(state).await?;
+            for expired in engine.expire_pending(history::now_millis() / 1000).await {
+                eprintln!("Pending tx {} expired", expired.tx.id());
+            }
+            let save_state = match command {
+                PendingCommand::List { .. } => {
+                    let mut serializer = AsyncWriterBuilder::new()
+                        .has_headers(false)
+                        .create_serializer(tokio::io::stdout());
+                    serializer
+                        .serialize(("type", "client", "tx", "amount", "reason", "expires_at"))
+                        .await?;
+                    for pending in engine.pending().pending().await {
+                        let tx = &pending.tx;
+                        let amount = tx.amount().map(ToString::to_string);
+                        serializer
+                            .serialize((
+                                tx.tx_type(),
+                                tx.client(),
+                                tx.id(),
+                                amount,
+                                &pending.reason,
+                                pending.expires_at,
+                            ))
+                            .await?;
+                    }
+                    serializer.flush().await?;
+                    None
+                }
+                PendingCommand::Approve { tx, save_state, .. } => {
+                    let outcome = engine
+                        .approve_pending(tx)
+                        .await
+                        .map_err(|err| anyhow!(err))?;
+                    match outcome.error() {
+                        Some(err) => println!("Tx {tx} rejected: {err}"),
+                        None => println!("Tx {tx} applied"),
+                    }
+                    save_state
+                }
+                PendingCommand::Reject { tx, save_state, .. } => {
+                    engine
+                        .reject_pending(tx)
+                        .await
+                        .map_err(|err| anyhow!(err))?;
+                    save_state
+                }
+            };
+            if let Some(save_state) = save_state {
+                snapshot::save(&save_state, &engine.snapshot().await)
+                    .await
+                    .map_err(|err| anyhow!("Error while saving state: {err}"))?;
+            }
+            return Ok(());
+        }
+        Some(Command::Standby { from, save_state }) => {
+            let mut engine = Engine::new(
+                InMemoryAccountLedger::default(),
+                InMemoryTxLedger::default(),
+            );
+            let bootstrap = shipping::bootstrap(&mut engine, &from)
+                .await
+                .map_err(|err| anyhow!("Error while bootstrapping from {from:?}: {err}"))?;
+            match bootstrap.snapshot {
+                Some(seq) => eprintln!("Restored snapshot {seq}"),
+                None => eprintln!("No snapshot shipped"),
+            }
+            eprintln!(
+                "Replayed {} changes from {} WAL segments",
+                bootstrap.changes, bootstrap.segments
+            );
+            snapshot::save(&save_state, &engine.snapshot().await)
+                .await
+                .map_err(|err| anyhow!("Error while saving state: {err}"))?;
+            return Ok(());
+        }
+        Some(Command::Verify { report, checksum }) => {
+            let file = File::open(&report)
+                .await
+                .map_err(|err| anyhow!("Error while opening report: {err}"))?;
+            let actual = checksum::report_checksum(file)
+                .await
+                .map_err(|err| anyhow!("Invalid report: {err}"))?;
+            println!("{actual}");
+            if !actual.eq_ignore_ascii_case(&checksum) {
+                return Err(anyhow!("Checksum mismatch: expected {checksum}"));
+            }
+            return Ok(());
+        }
+        Some(Command::Schema { of, columns }) => {
+            let schema = match of {
+                SchemaArg::Input => descriptor::input_json_schema(),
+                SchemaArg::Report if columns.is_empty() => {
+                    descriptor::report_json_schema(&Column::DEFAULT)
+                }
+                SchemaArg::Report => {
+                    let columns = columns
+                        .iter()
+                        .map(|column| Column::from_str(column))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|err| anyhow!(err))?;
+                    descriptor::report_json_schema(&columns)
+                }
+            };
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(());
+        }
+        Some(Command::Cdc { log, after }) => {
+            cdc::read(&log, after, &mut std::io::stdout().lock())
+                .map_err(|err| anyhow!("Error while reading CDC log: {err}"))?;
+            return Ok(());
+        }
+        None => (),
+    }
 
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
+    // Reports panics and `error!` events (unexpected internal errors, not routine business
+    // rejections) to the DSN configured through `SENTRY_DSN`.
+    #[cfg(feature = "sentry")]
+    let _sentry = sentry::init(sentry::ClientOptions {
+        release: sentry::release_name!(),
+        ..Default::default()
+    });
 
-    let file = File::open(args.input)
-        .await
-        .map_err(|err| anyhow!("Error while opening file: {err}"))?;
-    let mut engine = Engine::new(
+    let registry = tracing_subscriber::registry()
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(EnvFilter::from_default_env());
+    #[cfg(feature = "sentry")]
+    let registry = registry.with(sentry::integrations::tracing::layer());
+    registry.init();
+
+    let res = run(args).await;
+    #[cfg(feature = "sentry")]
+    if let Err(err) = &res {
+        sentry::capture_message(&format!("{err:#}"), sentry::Level::Error);
+    }
+    res
+}
+
+async fn run(mut args: Args) -> anyhow::Result<()> {
+    args.input = runner::expand_inputs(&args.input).map_err(|err| anyhow!(err))?;
+    if args.input.is_empty() {
+        return Err(anyhow!("Missing input file"));
+    }
+    let problems = validate::validate(&args);
+    if !problems.is_empty() {
+        let problems: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        return Err(anyhow!(
+            "Invalid configuration:\n  {}",
+            problems.join("\n  ")
+        ));
+    }
+    let routing = Routing::from_str(&args.shard_routing).map_err(|err| anyhow!(err))?;
+    let columns = args
+        .columns
+        .iter()
+        .map(|column| Column::from_str(column))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| anyhow!(err))?;
+    let hot = args.hot_account_share.map(|share| HotAccounts {
+        min_txs: args.hot_account_min_txs,
+        share,
+        batch_size: args.hot_account_batch,
+    });
+    #[cfg(feature = "sentry")]
+    sentry::configure_scope(|scope| scope.set_tag("input", args.input.join(",")));
+
+    let max_deposit_volume = args
+        .max_deposit_volume
+        .map(|volume| BigDecimal::from_str(&volume))
+        .transpose()
+        .map_err(|err| anyhow!("Invalid max deposit volume: {err}"))?;
+    let mut builder = Engine::builder(
         InMemoryAccountLedger::default(),
         InMemoryTxLedger::default(),
+    )
+    .log_sampling(args.log_sample_rate, args.log_summary_interval)
+    .quota(Quota::new(args.max_txs, max_deposit_volume))
+    .retention(RetentionPolicy::new(
+        args.dispute_window,
+        args.prune_terminal,
+    ));
+    if let Some(threshold) = args.slow_tx_threshold_ms {
+        builder = builder.slow_tx_threshold(Duration::from_millis(threshold));
+    }
+    if let Some(cooling_off) = args.chargeback_cooling_off {
+        builder = builder.chargeback_cooling_off(cooling_off);
+    }
+    if let Some(timeout) = args.tx_timeout_ms {
+        builder = builder.tx_timeout(Duration::from_millis(timeout));
+    }
+    if let Some(budget) = args.yield_every {
+        builder = builder.yield_every(budget);
+    }
+    if args.strict {
+        builder = builder.error_policy(ErrorPolicy::Strict);
+    }
+    if args.dispute_withdrawals {
+        builder = builder.dispute_policy(DisputePolicy::DepositsAndWithdrawals);
+    }
+    let max_amount = args
+        .max_amount
+        .map(|amount| BigDecimal::from_str(&amount))
+        .transpose()
+        .map_err(|err| anyhow!("Invalid max amount: {err}"))?;
+    if max_amount.is_some() || args.suspicious_factor.is_some() {
+        let mut checks = AmountChecks::new(max_amount, args.suspicious_factor);
+        if args.review_suspicious {
+            checks = checks.review_flagged();
+        }
+        builder = builder.plugin(checks);
+    }
+    if let Some(threshold) = &args.quarantine_above {
+        let threshold = BigDecimal::from_str(threshold)
+            .map_err(|err| anyhow!("Invalid quarantine threshold: {err}"))?;
+        builder = builder.plugin(Quarantine::new(threshold));
+    }
+    if args.fail_after.is_some() || !args.crash_on.is_empty() {
+        let crash_on = args
+            .crash_on
+            .iter()
+            .map(|point| CrashPoint::from_str(point))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| anyhow!(err))?;
+        builder = builder.plugin(FailureInjection::new(args.fail_after, crash_on));
+    }
+    if let Some(ttl) = args.pending_ttl {
+        builder = builder.pending_ttl(ttl);
+    }
+    if let Some(log) = args.replay_log {
+        let guard = ReplayGuard::load(log)
+            .map_err(|err| anyhow!("Error while loading the replay log: {err}"))?;
+        builder = builder.plugin(guard);
+    }
+    if !args.alert.is_empty() {
+        let rules = args
+            .alert
+            .iter()
+            .map(|rule| AlertRule::from_str(rule))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| anyhow!(err))?;
+        let mut sinks = args
+            .alert_sink
+            .iter()
+            .map(|sink| AlertSink::from_str(sink))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| anyhow!(err))?;
+        if sinks.is_empty() {
+            sinks.push(AlertSink::Stderr);
+        }
+        builder = builder.plugin(Alerting::new(rules, sinks));
+    }
+    if let Some(path) = args.progress_file {
+        builder = builder.plugin(Progress::new(
+            path,
+            Duration::from_millis(args.progress_interval_ms),
+        ));
+    }
+    if let Some(mode) = args.check_invariants {
+        builder = builder.plugin(Invariants::new(match mode {
+            InvariantModeArg::Warn => OnViolation::Warn,
+            InvariantModeArg::Abort => OnViolation::Abort,
+        }));
+    }
+    if args.aggregates {
+        builder = builder.plugin(Aggregates::default());
+    }
+    if let Some(path) = &args.cdc_log {
+        let cdc = Cdc::open(path).map_err(|err| anyhow!("Error while opening CDC log: {err}"))?;
+        builder = builder.plugin(cdc);
+    }
+    if let Some(path) = &args.deltas {
+        let file = std::fs::File::create(path)
+            .map_err(|err| anyhow!("Error while creating deltas file: {err}"))?;
+        builder = builder.plugin(Deltas::new(
+            file,
+            Duration::from_millis(args.deltas_flush_ms),
+        ));
+    }
+    let capacity = args.reorder_capacity;
+    let sequence_policy = args.sequence_policy.map(|policy| match policy {
+        SequencePolicyArg::Reject => SequencePolicy::Reject,
+        SequencePolicyArg::Reorder => SequencePolicy::Reorder { capacity },
+        SequencePolicyArg::Report => SequencePolicy::Report,
+    });
+    let (window, max_delay) = (
+        args.reorder_window,
+        Duration::from_millis(args.reorder_max_delay_ms),
+    );
+    let reorder = args.reorder_by.map(|key| ReorderBuffer {
+        key: reorder_key(key),
+        capacity: window,
+        max_delay,
+    });
+    let precision = match args.round_amounts {
+        None => PrecisionPolicy::Reject,
+        Some(mode) => PrecisionPolicy::Round(match mode {
+            RoundingArg::HalfEven => RoundingMode::HalfEven,
+            RoundingArg::HalfUp => RoundingMode::HalfUp,
+            RoundingArg::HalfDown => RoundingMode::HalfDown,
+            RoundingArg::Up => RoundingMode::Up,
+            RoundingArg::Down => RoundingMode::Down,
+            RoundingArg::Ceiling => RoundingMode::Ceiling,
+            RoundingArg::Floor => RoundingMode::Floor,
+        }),
+    };
+    let mode = if let Some(key) = args.merge_by {
+        EngineMode::Merged(MergeWindow {
+            key: reorder_key(key),
+            lateness: Duration::from_millis(args.merge_lateness_ms),
+        })
+    } else if args.deterministic {
+        EngineMode::Ordered
+    } else if args.shared_engine {
+        EngineMode::Shared
+    } else {
+        EngineMode::Isolated
+    };
+    let format = match args.format {
+        FormatArg::Csv => Format::Csv,
+        FormatArg::Json => Format::Json,
+        FormatArg::Jsonl => Format::Jsonl,
+    };
+    let style = Style {
+        precision: args.precision,
+        trailing_zeros: !args.minimal_amounts,
+        decimal_separator: u8::try_from(args.decimal_separator)
+            .ok()
+            .filter(u8::is_ascii)
+            .ok_or_else(|| anyhow!("The decimal separator must be an ASCII character"))?,
+        quote: match args.quote {
+            QuoteArg::Necessary => QuoteStyle::Necessary,
+            QuoteArg::Always => QuoteStyle::Always,
+            QuoteArg::Never => QuoteStyle::Never,
+        },
+    };
+    let run = Run {
+        reader: if args.mmap {
+            InputReader::Mmap
+        } else {
+            InputReader::File
+        },
+        parser: if args.fast_parse {
+            CsvParser::Fast
+        } else {
+            CsvParser::Serde
+        },
+        mode,
+        jobs: usize::try_from(args.jobs).unwrap_or(usize::MAX),
+        shards: args
+            .shards
+            .map(|shards| usize::try_from(shards).unwrap_or(usize::MAX)),
+        routing,
+        hot,
+        import_accounts: args.import_accounts,
+        state: args.state,
+        save_state: args.save_state,
+        unlock: args.unlock,
+        ship_to: args.ship_to,
+        ship_interval: Duration::from_millis(args.ship_interval_ms),
+        precision,
+        batch_id: args.batch_id.as_deref().map(Arc::from),
+        remap: args.remap,
+        reorder,
+        sequence_policy,
+        rejects: args.rejects,
+        metrics_file: args.metrics_file,
+        stats_history: args.stats_history,
+        run_id: args.run_id,
+        tags: args.tags,
+        tag: args.tag,
+        output: args.output,
+        format,
+        style,
+        columns,
+        sorted: args.sort || args.deterministic,
+        ..Run::new(args.input, builder)
+    };
+
+    // Interrupting the processing of a single input still reports the accounts as of the last
+    // transaction applied, interrupting it again aborts.
+    let cancel = CancellationToken::new();
+    let interrupt = (run.inputs.len() == 1 && run.shards.is_none()).then(|| {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                warn!("Interrupted, stopping after the current transaction");
+                cancel.cancel();
+                let _ = tokio::signal::ctrl_c().await;
+                std::process::exit(130);
+            }
+        })
+    });
+    let finished = run.execute(&cancel).await;
+    if let Some(interrupt) = interrupt {
+        interrupt.abort();
+    }
+    let Finished { engine, outcomes } = finished?;
+    // Input files or shards which couldn't be processed.
+    let failed = print_outcomes(&outcomes);
+    eprint!("{}", engine.stats());
+    let latency = engine.latency();
+    if let (Some(p50), Some(p95), Some(p99)) = (
+        latency.percentile(50.0),
+        latency.percentile(95.0),
+        latency.percentile(99.0),
+    ) {
+        eprintln!("TX handling latency: p50 {p50:?}, p95 {p95:?}, p99 {p99:?}");
+    }
+    eprintln!(
+        "Ledger checksum: {}",
+        checksum::accounts_checksum(&engine).await
     );
-    engine.handle_txs(file).await?;
-
-    println!("client,available,held,total,locked");
-    for account in engine.accounts().await.values() {
-        let inner = account.lock().await;
-        println!(
-            "{},{},{},{},{}",
-            inner.client_id(),
-            inner.available(),
-            inner.held(),
-            inner.total(),
-            inner.is_locked()
+    if allocator::COUNTED {
+        eprintln!(
+            "{} allocations ({} bytes) with the {} allocator",
+            allocator::allocations(),
+            allocator::allocated_bytes(),
+            allocator::NAME
         );
     }
+    if failed > 0 {
+        return Err(anyhow!("Processing failed for {failed} inputs or shards"));
+    }
 
     Ok(())
 }
+
+fn reorder_key(key: ReorderKeyArg) -> ReorderKey {
+    match key {
+        ReorderKeyArg::Seq => ReorderKey::Sequence,
+        ReorderKeyArg::Timestamp => ReorderKey::Timestamp,
+    }
+}
+
+// Engine over the ledgers of a state snapshot, for the subcommands working on saved state.
+async fn restore(state: &Path) -> anyhow::Result<Engine<InMemoryAccountLedger, InMemoryTxLedger>> {
+    let snapshot = snapshot::load(state)
+        .await
+        .map_err(|err| anyhow!("Error while loading state: {err}"))?;
+    let mut engine = Engine::new(
+        InMemoryAccountLedger::default(),
+        InMemoryTxLedger::default(),
+    );
+    engine
+        .restore(&snapshot)
+        .await
+        .map_err(|err| anyhow!("Invalid state: {err}"))?;
+    Ok(engine)
+}
+
+// Prints the summary of every input file, returning how many of them failed.
+fn print_outcomes(outcomes: &[FileOutcome]) -> usize {
+    for outcome in outcomes {
+        eprint!(
+            "{}: {} rows, {} rejected, {} ignored",
+            outcome.path, outcome.rows, outcome.rejected, outcome.ignored
+        );
+        match &outcome.error {
+            Some(err) => eprintln!(", failed: {err}"),
+            None => eprintln!(),
+        }
+    }
+    outcomes
+        .iter()
+        .filter(|outcome| outcome.error.is_some())
+        .count()
+}
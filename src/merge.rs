@@ -0,0 +1,251 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+    time::Duration,
+};
+
+use futures::{stream, Stream, StreamExt};
+use tokio::time::Instant;
+
+use crate::{
+    payments::Tx,
+    reorder::ReorderKey,
+    source::{BoxedSource, TxRecord, TxSource},
+};
+
+// Merge stage of several concurrent sources (e.g. files, a message queue and an API feed) into a
+// single one ordered by a global key, so that causality across sources holds, like a deposit read
+// from one source being applied before its dispute read from another. Every source is expected to
+// be ordered by the key: a transaction is released once every source still open got to its key,
+// or once it waited for `lateness`, so that an idle or lagging source doesn't hold back the others
+// forever. Transactions without the key and unreadable records are passed through as they arrive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MergeWindow {
+    pub key: ReorderKey,
+    pub lateness: Duration,
+}
+
+struct Merger {
+    window: MergeWindow,
+    // Largest key read from every source, and whether it's still open.
+    watermarks: Vec<Option<u64>>,
+    open: Vec<bool>,
+    // Keys of the buffered transactions along with their arrival order, smallest first.
+    order: BinaryHeap<Reverse<(u64, u64)>>,
+    // Buffered transactions, with the index of their source, by arrival order.
+    arrivals: BTreeMap<u64, (Instant, usize, Tx)>,
+    arrived: u64,
+}
+
+impl Merger {
+    fn new(window: MergeWindow, sources: usize) -> Self {
+        Merger {
+            window,
+            watermarks: vec![None; sources],
+            open: vec![true; sources],
+            order: BinaryHeap::new(),
+            arrivals: BTreeMap::new(),
+            arrived: 0,
+        }
+    }
+
+    fn push(&mut self, idx: usize, tx: Tx, ready: &mut VecDeque<(usize, TxRecord)>) {
+        let Some(key) = self.window.key.of(&tx) else {
+            ready.push_back((idx, Ok(tx)));
+            return;
+        };
+        self.watermarks[idx] = self.watermarks[idx].max(Some(key));
+        self.arrived += 1;
+        self.order.push(Reverse((key, self.arrived)));
+        self.arrivals
+            .insert(self.arrived, (Instant::now(), idx, tx));
+        self.release(ready);
+    }
+
+    fn close(&mut self, idx: usize, ready: &mut VecDeque<(usize, TxRecord)>) {
+        self.open[idx] = false;
+        self.release(ready);
+    }
+
+    fn pop(&mut self, ready: &mut VecDeque<(usize, TxRecord)>) -> Option<u64> {
+        let Reverse((_, arrival)) = self.order.pop()?;
+        if let Some((_, idx, tx)) = self.arrivals.remove(&arrival) {
+            ready.push_back((idx, Ok(tx)));
+        }
+        Some(arrival)
+    }
+
+    // Releases the transactions no open source can precede anymore.
+    fn release(&mut self, ready: &mut VecDeque<(usize, TxRecord)>) {
+        let mut open = self
+            .watermarks
+            .iter()
+            .zip(&self.open)
+            .filter_map(|(watermark, open)| open.then_some(*watermark));
+        let low = match open.next() {
+            Some(first) => open.fold(first, |low, watermark| low.min(watermark)),
+            None => Some(u64::MAX),
+        };
+        let Some(low) = low else {
+            return;
+        };
+        while self
+            .order
+            .peek()
+            .is_some_and(|Reverse((key, _))| *key <= low)
+        {
+            self.pop(ready);
+        }
+    }
+
+    // When the longest buffered transaction has to be released.
+    fn deadline(&self) -> Option<Instant> {
+        let (_, (arrived_at, _, _)) = self.arrivals.iter().next()?;
+        Some(*arrived_at + self.window.lateness)
+    }
+
+    fn release_expired(&mut self, ready: &mut VecDeque<(usize, TxRecord)>) {
+        let now = Instant::now();
+        while let Some((&oldest, _)) = self
+            .arrivals
+            .iter()
+            .find(|(_, (arrived_at, _, _))| *arrived_at + self.window.lateness <= now)
+        {
+            while self.pop(ready).is_some_and(|arrival| arrival != oldest) {}
+        }
+    }
+
+    fn drain(&mut self, ready: &mut VecDeque<(usize, TxRecord)>) {
+        while self.pop(ready).is_some() {}
+    }
+}
+
+// Merges the sources within the window, every record being tagged with the index of its source.
+pub fn merged_indexed(
+    sources: Vec<BoxedSource>,
+    window: MergeWindow,
+) -> impl Stream<Item = (usize, TxRecord)> + Send + Unpin {
+    let merger = Merger::new(window, sources.len());
+    // Every source ends with a `None` marking it closed.
+    let tagged = stream::select_all(sources.into_iter().enumerate().map(|(idx, source)| {
+        source
+            .map(move |record| (idx, Some(record)))
+            .chain(stream::once(async move { (idx, None) }))
+            .boxed()
+    }));
+    stream::unfold(
+        (tagged, merger, VecDeque::new(), false),
+        |(mut tagged, mut merger, mut ready, mut done)| async move {
+            loop {
+                if let Some(record) = ready.pop_front() {
+                    return Some((record, (tagged, merger, ready, done)));
+                }
+                if done {
+                    return None;
+                }
+                let next = match merger.deadline() {
+                    Some(deadline) => {
+                        match tokio::time::timeout_at(deadline, tagged.next()).await {
+                            Ok(next) => next,
+                            Err(_) => {
+                                merger.release_expired(&mut ready);
+                                continue;
+                            }
+                        }
+                    }
+                    None => tagged.next().await,
+                };
+                match next {
+                    Some((idx, Some(Ok(tx)))) => merger.push(idx, tx, &mut ready),
+                    Some((idx, Some(Err(err)))) => ready.push_back((idx, Err(err))),
+                    Some((idx, None)) => merger.close(idx, &mut ready),
+                    None => {
+                        done = true;
+                        merger.drain(&mut ready);
+                    }
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+// Merges the sources within the window.
+pub fn merged(sources: Vec<BoxedSource>, window: MergeWindow) -> impl TxSource {
+    merged_indexed(sources, window).map(|(_, record)| record)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        payments::{Engine, Tx, TxType},
+        reorder::ReorderKey,
+        source::{self, BoxedSource},
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{merged, merged_indexed, MergeWindow};
+
+    fn window(lateness: Duration) -> MergeWindow {
+        MergeWindow {
+            key: ReorderKey::Timestamp,
+            lateness,
+        }
+    }
+
+    #[tokio::test]
+    async fn merges_by_key() {
+        let deposits = "type,client,tx,amount,timestamp\ndeposit,1,1,5.0,10\ndeposit,2,2,3.0,30";
+        let disputes = "type,client,tx,amount,timestamp\ndispute,1,1,,20\nwithdrawal,2,3,1.0,40";
+        let sources: Vec<BoxedSource> = vec![
+            Box::new(source::from_csv(disputes.as_bytes())),
+            Box::new(source::from_csv(deposits.as_bytes())),
+        ];
+        let ids: Vec<(usize, u32)> = merged_indexed(sources, window(Duration::from_secs(60)))
+            .map(|(idx, record)| (idx, record.unwrap().id()))
+            .collect()
+            .await;
+        assert_eq!(ids, vec![(1, 1), (0, 1), (1, 2), (0, 3)]);
+
+        let sources: Vec<BoxedSource> = vec![
+            Box::new(source::from_csv(disputes.as_bytes())),
+            Box::new(source::from_csv(deposits.as_bytes())),
+        ];
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let report = engine
+            .handle_source(merged(sources, window(Duration::from_secs(60))))
+            .await
+            .unwrap();
+        assert_eq!(report.rejected, 0);
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.held().to_string(), "5.0");
+    }
+
+    #[tokio::test]
+    async fn idle_sources_hold_back_until_late() {
+        let (sender, receiver) = mpsc::channel(10);
+        let (_idle, idle) = mpsc::channel::<Tx>(10);
+        let sources: Vec<BoxedSource> = vec![
+            Box::new(source::from_channel(receiver)),
+            Box::new(source::from_channel(idle)),
+        ];
+        let mut txs = merged(sources, window(Duration::from_millis(20)));
+        sender
+            .send(Tx::new(TxType::Deposit, 1, 1, None).with_timestamp(10))
+            .await
+            .unwrap();
+
+        let released = tokio::time::timeout(Duration::from_secs(5), txs.next())
+            .await
+            .unwrap();
+        assert_eq!(released.unwrap().unwrap().id(), 1);
+    }
+}
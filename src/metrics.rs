@@ -0,0 +1,110 @@
+use std::{convert::TryFrom, time::Duration};
+
+const BUCKETS: usize = 65;
+
+// Latency histogram using power of two buckets over nanoseconds (bucket `i` holds latencies in
+// `[2^(i-1), 2^i)`), so memory stays constant regardless of how many transactions are recorded.
+// Percentiles are reported as the upper bound of the bucket they fall in.
+#[derive(Clone, Debug)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    max: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: [0; BUCKETS],
+            count: 0,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[(u64::BITS - nanos.leading_zeros()) as usize] += 1;
+        self.count += 1;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    // Returns the latency under which `p` percent of the recorded transactions were handled.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = ((p / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                return match idx {
+                    0 => Some(Duration::ZERO),
+                    64 => Some(self.max),
+                    _ => Some(Duration::from_nanos((1 << idx) - 1).min(self.max)),
+                };
+            }
+        }
+
+        Some(self.max)
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::LatencyHistogram;
+
+    #[test]
+    fn percentile_empty() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(50.0), None);
+    }
+
+    #[test]
+    fn percentile_success() {
+        let mut histogram = LatencyHistogram::default();
+        for _ in 0..90 {
+            histogram.record(Duration::from_nanos(100));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_micros(100));
+        }
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.percentile(50.0), Some(Duration::from_nanos(127)));
+        assert_eq!(histogram.percentile(90.0), Some(Duration::from_nanos(127)));
+        assert_eq!(histogram.percentile(95.0), Some(Duration::from_micros(100)));
+        assert_eq!(histogram.percentile(99.0), Some(Duration::from_micros(100)));
+        assert_eq!(histogram.max(), Duration::from_micros(100));
+    }
+
+    #[test]
+    fn record_zero_and_huge() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(Duration::ZERO);
+        histogram.record(Duration::MAX);
+        assert_eq!(histogram.percentile(50.0), Some(Duration::ZERO));
+        assert_eq!(histogram.percentile(100.0), Some(Duration::MAX));
+    }
+}
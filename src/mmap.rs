@@ -0,0 +1,87 @@
+// Memory-mapped reading of local input files, for very large inputs on fast storage: bytes are
+// copied straight from the page cache to the parser, without a read syscall per buffer nor going
+// through the blocking thread pool as `tokio::fs::File` does.
+use std::{
+    fs::File,
+    io,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use memmap2::Mmap;
+use tokio::io::{AsyncRead, ReadBuf};
+
+// Bytes handed out by a single read, bounding the page faults a poll can go through so that large
+// inputs don't hold up the other tasks of the runtime.
+pub const CHUNK: usize = 256 * 1024;
+
+pub struct MappedFile {
+    map: Mmap,
+    pos: usize,
+    chunk: usize,
+}
+
+impl MappedFile {
+    // Maps the file, which must not be truncated while being read: accessing the pages past its
+    // new end would crash the process.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the mapping is read-only, and only accessed through this reader.
+        let map = unsafe { Mmap::map(&file)? };
+        #[cfg(unix)]
+        let _ = map.advise(memmap2::Advice::Sequential);
+        Ok(MappedFile {
+            map,
+            pos: 0,
+            chunk: CHUNK,
+        })
+    }
+
+    pub fn with_chunk(mut self, chunk: usize) -> Self {
+        self.chunk = chunk.max(1);
+        self
+    }
+}
+
+impl AsyncRead for MappedFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let len = buf
+            .remaining()
+            .min(this.chunk)
+            .min(this.map.len() - this.pos);
+        buf.put_slice(&this.map[this.pos..this.pos + len]);
+        this.pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::MappedFile;
+
+    #[tokio::test]
+    async fn reads_in_chunks() {
+        let dir = std::env::temp_dir().join("payments-engine-mmap");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,0.5\n".repeat(10);
+        for (name, content) in [("input.csv", input.as_str()), ("empty.csv", "")] {
+            let path = dir.join(name);
+            tokio::fs::write(&path, content).await.unwrap();
+            for chunk in [1, 7, super::CHUNK] {
+                let mut reader = MappedFile::open(&path).unwrap().with_chunk(chunk);
+                let mut read = String::new();
+                reader.read_to_string(&mut read).await.unwrap();
+                assert_eq!(read, content);
+            }
+        }
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
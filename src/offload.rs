@@ -0,0 +1,175 @@
+use std::{collections::HashMap, future::Future, io, sync::Arc};
+
+use tokio::{
+    runtime::{Builder, Handle, Runtime},
+    sync::Mutex,
+};
+
+use crate::{
+    account::Account,
+    payments::Tx,
+    storage::{AccountsDal, TxsDal},
+};
+
+// Dedicated runtime for storage work, shut down once all its clones are dropped.
+#[derive(Clone)]
+pub struct StorageRuntime(Arc<Owned>);
+
+struct Owned(Option<Runtime>);
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        // Dropping a runtime blocks, which isn't allowed from within another runtime.
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl StorageRuntime {
+    pub fn new(threads: usize) -> io::Result<Self> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(threads.max(1))
+            .thread_name("storage")
+            .enable_all()
+            .build()?;
+        Ok(StorageRuntime(Arc::new(Owned(Some(runtime)))))
+    }
+
+    fn handle(&self) -> &Handle {
+        self.0
+             .0
+            .as_ref()
+            .expect("runtime only taken on drop")
+            .handle()
+    }
+}
+
+// Decorator running the calls to any accounts and/or transactions DAL on a `StorageRuntime`, for
+// backends doing blocking or CPU heavy work (e.g. embedded databases, compressed or encrypted
+// entries), so that it doesn't stall the runtime ingesting transactions. Calls run on a clone of
+// the wrapped storage, so its clones must share their entries, as the bundled ledgers do. Listing
+// the ledgers hands out guards borrowing the storage, and stays on the caller's runtime.
+#[derive(Clone)]
+pub struct Offloaded<D> {
+    inner: D,
+    runtime: StorageRuntime,
+}
+
+impl<D: Clone + Send + 'static> Offloaded<D> {
+    pub fn new(inner: D, runtime: StorageRuntime) -> Self {
+        Offloaded { inner, runtime }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    async fn run<F, R>(&self, call: impl FnOnce(D) -> F) -> R
+    where
+        F: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        match self.runtime.handle().spawn(call(self.inner.clone())).await {
+            Ok(res) => res,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => panic!("Storage call didn't complete: {}", err),
+        }
+    }
+}
+
+impl<D: AccountsDal + Clone + Send + Sync + 'static> AccountsDal for Offloaded<D> {
+    async fn account(&self, id: u16) -> Option<Arc<Mutex<Account>>> {
+        self.run(move |inner| async move { inner.account(id).await })
+            .await
+    }
+
+    async fn insert(&mut self, account: Account) {
+        self.run(move |mut inner| async move { inner.insert(account).await })
+            .await
+    }
+
+    async fn accounts(
+        &self,
+    ) -> tokio::sync::RwLockReadGuard<'_, HashMap<u16, Arc<Mutex<Account>>>> {
+        self.inner.accounts().await
+    }
+
+    async fn prefetch(&self, ids: &[u16]) {
+        let ids = ids.to_vec();
+        self.run(move |inner| async move { inner.prefetch(&ids).await })
+            .await
+    }
+}
+
+impl<D: TxsDal + Clone + Send + Sync + 'static> TxsDal for Offloaded<D> {
+    async fn tx(&self, id: u32) -> Option<Arc<Mutex<Tx>>> {
+        self.run(move |inner| async move { inner.tx(id).await })
+            .await
+    }
+
+    async fn insert(&self, tx: Tx) {
+        self.run(move |inner| async move { inner.insert(tx).await })
+            .await
+    }
+
+    async fn txs(&self) -> tokio::sync::RwLockReadGuard<'_, HashMap<u32, Arc<Mutex<Tx>>>> {
+        self.inner.txs().await
+    }
+
+    async fn remove(&self, id: u32) {
+        self.run(move |inner| async move { inner.remove(id).await })
+            .await
+    }
+
+    async fn prefetch(&self, ids: &[u32]) {
+        let ids = ids.to_vec();
+        self.run(move |inner| async move { inner.prefetch(&ids).await })
+            .await
+    }
+
+    async fn client_txs(&self, client: u16) -> Vec<Arc<Mutex<Tx>>> {
+        self.run(move |inner| async move { inner.client_txs(client).await })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        payments::{Engine, Tx, TxType},
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+        test_utils::conformance::{check_accounts_dal, check_txs_dal},
+    };
+
+    use super::{Offloaded, StorageRuntime};
+
+    #[tokio::test]
+    async fn offloaded_conforms() {
+        let runtime = StorageRuntime::new(2).unwrap();
+        check_accounts_dal(|| Offloaded::new(InMemoryAccountLedger::default(), runtime.clone()))
+            .await;
+        check_txs_dal(|| Offloaded::new(InMemoryTxLedger::default(), runtime.clone())).await;
+    }
+
+    #[tokio::test]
+    async fn engine_over_offloaded_storage() {
+        let runtime = StorageRuntime::new(1).unwrap();
+        let accounts = InMemoryAccountLedger::default();
+        let mut engine = Engine::new(
+            Offloaded::new(accounts.clone(), runtime.clone()),
+            Offloaded::new(InMemoryTxLedger::default(), runtime),
+        );
+        for tx in [
+            Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(3))),
+            Tx::new(TxType::Dispute, 1, 1, None),
+        ] {
+            assert!(engine.handle_tx(tx).await.is_applied());
+        }
+
+        let account = accounts.account(1).await.unwrap();
+        assert_eq!(account.lock().await.held(), BigDecimal::from(3));
+    }
+}
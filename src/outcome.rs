@@ -0,0 +1,71 @@
+use crate::error::Error;
+
+// Outcome of a processed transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxOutcome {
+    Applied,
+    // Rejected by the business rules (or as an invalid record), leaving the ledgers untouched.
+    RejectedBusinessRule(Error),
+    // Not applied because of a bug or a storage inconsistency (see `Error::is_internal`).
+    Failed(Error),
+    // Neither applied nor rejected, e.g. a row already applied by a previous run, a transaction
+    // queued while its client is under review or one parked until approved.
+    Ignored(String),
+}
+
+impl TxOutcome {
+    pub fn is_applied(&self) -> bool {
+        matches!(self, TxOutcome::Applied)
+    }
+
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            TxOutcome::RejectedBusinessRule(_) | TxOutcome::Failed(_)
+        )
+    }
+
+    // The error the transaction was rejected with, if any.
+    pub fn error(&self) -> Option<&Error> {
+        match self {
+            TxOutcome::RejectedBusinessRule(err) | TxOutcome::Failed(err) => Some(err),
+            TxOutcome::Applied | TxOutcome::Ignored(_) => None,
+        }
+    }
+}
+
+impl From<Result<(), Error>> for TxOutcome {
+    fn from(res: Result<(), Error>) -> Self {
+        match res {
+            Ok(()) => TxOutcome::Applied,
+            Err(
+                err @ (Error::ReplayedRow(_) | Error::UnderReview(_) | Error::PendingApproval(_)),
+            ) => TxOutcome::Ignored(err.to_string()),
+            Err(err) if err.is_internal() => TxOutcome::Failed(err),
+            Err(err) => TxOutcome::RejectedBusinessRule(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    use super::TxOutcome;
+
+    #[test]
+    fn classify_results() {
+        assert_eq!(TxOutcome::from(Ok(())), TxOutcome::Applied);
+        assert_eq!(
+            TxOutcome::from(Err(Error::TxNotFound)),
+            TxOutcome::RejectedBusinessRule(Error::TxNotFound)
+        );
+        assert!(matches!(
+            TxOutcome::from(Err(Error::UnexpectedMissingAccount(1))),
+            TxOutcome::Failed(_)
+        ));
+        let replayed = TxOutcome::from(Err(Error::ReplayedRow(1)));
+        assert!(matches!(replayed, TxOutcome::Ignored(_)));
+        assert!(!replayed.is_applied() && !replayed.is_rejected() && replayed.error().is_none());
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncWrite, AsyncWriteExt},
+};
+
+// File written through a temporary file next to it, renamed over `path` once `persist`ed, so that
+// readers never see a partially written file. The temporary file gets removed if never persisted
+// (e.g. on errors while writing).
+pub struct AtomicFile {
+    file: File,
+    tmp: PathBuf,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl AtomicFile {
+    pub async fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut tmp = OsString::from(path.as_os_str());
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let file = File::create(&tmp).await?;
+        Ok(AtomicFile {
+            file,
+            tmp,
+            path,
+            persisted: false,
+        })
+    }
+
+    // Flushes the contents to disk and moves them to the final path.
+    pub async fn persist(mut self) -> io::Result<()> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        tokio::fs::rename(&self.tmp, &self.path).await?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl AsyncWrite for AtomicFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_shutdown(cx)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::AtomicFile;
+
+    #[tokio::test]
+    async fn renamed_once_persisted() {
+        let path = std::env::temp_dir().join("payments-engine-output.csv");
+        let _ = std::fs::remove_file(&path);
+
+        let mut file = AtomicFile::create(&path).await.unwrap();
+        file.write_all(b"client\n").await.unwrap();
+        assert!(!path.exists());
+        file.persist().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "client\n");
+
+        // Dropped without being persisted, the previous contents are kept.
+        let mut file = AtomicFile::create(&path).await.unwrap();
+        file.write_all(b"partial").await.unwrap();
+        drop(file);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "client\n");
+        assert!(!path.with_extension("csv.tmp").exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,20 +1,39 @@
-use std::{str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use crate::error::Error;
-use bigdecimal::BigDecimal;
-use csv_async::Trim;
-use futures::StreamExt;
-use serde::{de, Deserialize};
+use crate::error::{Error, RowError};
+use bigdecimal::{BigDecimal, Zero};
+use futures::{stream, Stream, StreamExt};
+use serde::{de, Deserialize, Serialize};
 use tokio::{io::AsyncRead, sync::Mutex};
-use tracing::debug;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use crate::{
-    account::Account,
-    storage::{AccountsDal, TxsDal},
+    account::{Account, ClearingAccount},
+    history,
+    lockout::Lockouts,
+    logging::LogSampler,
+    metrics::LatencyHistogram,
+    outcome::TxOutcome,
+    plugin::Plugin,
+    quota::Quota,
+    retention::RetentionPolicy,
+    review::ReviewQueue,
+    snapshot::{ClearingEntry, Snapshot},
+    source::{self, TxRecord, TxSource},
+    stats::Stats,
+    storage::{
+        AccountsDal, AccountsFork, InMemoryPendingLedger, PendingDal, PendingTx, TxsDal, TxsFork,
+    },
 };
 
 // Transaction type
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum TxType {
     Dispute,
@@ -24,11 +43,61 @@ pub enum TxType {
     Withdrawal,
 }
 
+// Summary of the transactions processed from a source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessingReport {
+    pub rows: u64,
+    pub rejected: u64,
+    pub ignored: u64,
+    // Set when processing was cancelled before the end of the source.
+    pub cancelled: bool,
+}
+
+impl ProcessingReport {
+    fn record(&mut self, outcome: &TxOutcome) {
+        self.rows += 1;
+        if outcome.is_rejected() {
+            self.rejected += 1;
+        } else if !outcome.is_applied() {
+            self.ignored += 1;
+        }
+    }
+}
+
+// How processing a source reacts to transactions which couldn't be applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorPolicy {
+    // Rejected rows are counted and logged, and processing goes on.
+    #[default]
+    Lenient,
+    // Processing stops at the first rejected row, returning its `RowError`.
+    Strict,
+}
+
+// Which transactions clients can dispute.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DisputePolicy {
+    // Only deposits, disputes of anything else being rejected as `InvalidDispute`.
+    #[default]
+    Deposits,
+    // Withdrawals too: the funds of a disputed withdrawal flow back into held, until the dispute
+    // is resolved (the withdrawal standing) or charged back (the funds being credited back).
+    // Withdrawals have to be kept for that, so it can't be combined with
+    // `RetentionPolicy::prune_terminal`, which `EngineBuilder::build` rejects.
+    DepositsAndWithdrawals,
+}
+
 pub trait TxHandle<A: AccountsDal + Send + Sync + Clone, T: TxsDal + Send + Sync + Clone> {
     fn handle(
         &self,
         engine: &mut Engine<A, T>,
     ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+    // Applies the transaction on its client's account, which the caller already locked.
+    fn apply(
+        &self,
+        engine: &mut Engine<A, T>,
+        account: &mut Account,
+    ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
 }
 
 // There is a bug with the `serde` feature of the bigdecimal create, which if used to deserialize
@@ -52,7 +121,7 @@ where
 }
 
 // Transaction model
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct Tx {
     r#type: TxType,
     client: u16,
@@ -63,9 +132,53 @@ pub struct Tx {
     amount: Option<BigDecimal>,
     #[serde(skip_deserializing)]
     disputed: bool,
+    // Optional per-client sequence number (see `sequence::Sequencer`).
+    #[serde(default)]
+    seq: Option<u64>,
+    // Optional event time (e.g. milliseconds since the epoch, see `reorder::ReorderBuffer`).
+    #[serde(default)]
+    timestamp: Option<u64>,
+    // Ingestion batch the transaction arrived in, e.g. its input file (see `batch::batched`).
+    #[serde(skip_deserializing)]
+    batch: Option<Arc<str>>,
+    // Line of the input the transaction was read from, when tracked (see `rejects::Rejects`).
+    #[serde(skip_deserializing)]
+    line: Option<u64>,
 }
 
 impl Tx {
+    pub fn new(r#type: TxType, client: u16, id: u32, amount: Option<BigDecimal>) -> Self {
+        Tx {
+            r#type,
+            client,
+            id,
+            amount,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        }
+    }
+
+    pub fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = Some(seq);
+        self
+    }
+
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
     pub fn mark_disputed(&mut self) {
         self.disputed = true;
     }
@@ -90,35 +203,109 @@ impl Tx {
         self.id
     }
 
+    pub fn tx_type(&self) -> &TxType {
+        &self.r#type
+    }
+
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    // Moves the transaction to another client id (see `remap::Remapping`).
+    pub fn remap_client(&mut self, client: u16) {
+        self.client = client;
+    }
+
     pub fn amount(&self) -> Option<&BigDecimal> {
         self.amount.as_ref()
     }
+
+    // Checks a parsed transaction before it gets near the ledgers: deposits and withdrawals with
+    // a zero or negative amount would otherwise move balances the wrong way.
+    pub fn validate(self) -> Result<Self, Error> {
+        if self.non_positive() {
+            return Err(Error::NonPositiveAmount(self.id));
+        }
+        Ok(self)
+    }
+
+    fn non_positive(&self) -> bool {
+        matches!(self.r#type, TxType::Deposit | TxType::Withdrawal)
+            && self
+                .amount
+                .as_ref()
+                .is_some_and(|amount| *amount <= BigDecimal::zero())
+    }
+
+    // Rewrites the amount, e.g. while enriching the transaction (see `Plugin::enrich`).
+    pub fn set_amount(&mut self, amount: Option<BigDecimal>) {
+        self.amount = amount;
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: Option<u64>) {
+        self.timestamp = timestamp;
+    }
+
+    pub fn with_batch(mut self, batch: Arc<str>) -> Self {
+        self.batch = Some(batch);
+        self
+    }
+
+    pub fn set_batch(&mut self, batch: Option<Arc<str>>) {
+        self.batch = batch;
+    }
+
+    pub fn batch(&self) -> Option<&str> {
+        self.batch.as_deref()
+    }
+
+    pub fn with_line(mut self, line: u64) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn line(&self) -> Option<u64> {
+        self.line
+    }
 }
 
 impl<A: AccountsDal + Send + Sync + Clone, T: TxsDal + Send + Sync + Clone> TxHandle<A, T> for Tx {
     async fn handle(&self, engine: &mut Engine<A, T>) -> std::result::Result<(), Error> {
-        let account = match engine.account(self.client).await {
-            Some(inner) => inner,
-            None => {
-                AccountsDal::insert(engine, Account::new_unlocked(self.client)).await;
-                engine
-                    .account(self.client)
-                    .await
-                    .ok_or(Error::UnexpectedMissingAccount(self.client))?
-            }
-        };
+        let account = engine.account_or_insert(self.client).await?;
+        let inner = &mut account.lock().await;
+        self.apply(engine, inner).await
+    }
 
+    async fn apply(
+        &self,
+        engine: &mut Engine<A, T>,
+        account: &mut Account,
+    ) -> std::result::Result<(), Error> {
+        // Checked again here, as transactions don't necessarily come from a parser (e.g. embedders
+        // building them).
+        if self.non_positive() {
+            return Err(Error::NonPositiveAmount(self.id));
+        }
+        // Deposits and withdrawals reusing the id of a stored transaction would replace it, and
+        // the ones reusing the id of a dropped one would be mistaken for it.
+        if self.storable()
+            && (engine.tx(self.id).await.is_some() || engine.retention.dropped(self.id))
+        {
+            return Err(Error::DuplicateTx(self.id));
+        }
         match self.r#type {
             TxType::Deposit => {
-                let inner = &mut account.lock().await;
+                let inner = &mut *account;
                 if inner.is_locked() {
                     return Err(Error::AccountLocked(inner.client_id()));
                 }
 
-                inner.add_available(self.amount().ok_or(Error::MissingAmount(self.id))?);
+                let amount = self.amount().ok_or(Error::MissingAmount(self.id))?;
+                engine.quota.admit_deposit(self.id, amount)?;
+                inner.add_available(amount);
             }
             TxType::Withdrawal => {
-                let inner = &mut account.lock().await;
+                let inner = &mut *account;
                 if inner.is_locked() {
                     return Err(Error::AccountLocked(inner.client_id()));
                 }
@@ -128,70 +315,19 @@ impl<A: AccountsDal + Send + Sync + Clone, T: TxsDal + Send + Sync + Clone> TxHa
             TxType::Dispute => match engine.tx(self.id).await {
                 None => Err(Error::TxNotFound)?,
                 Some(to_be_disputed_tx) => {
-                    let inner_tx = &mut to_be_disputed_tx.lock().await;
-                    let inner_account = &mut account.lock().await;
-
-                    if inner_account.is_locked() {
-                        return Err(Error::AccountLocked(inner_account.client_id()));
-                    }
-
-                    if inner_tx.r#type != TxType::Deposit {
-                        return Err(Error::InvalidDispute(inner_tx.id));
-                    }
-
-                    if inner_tx.disputed() {
-                        return Err(Error::TxAlreadyDisputed(inner_tx.id));
-                    }
-                    let amount = inner_tx
-                        .amount()
-                        .ok_or(Error::MissingAmount(inner_tx.id))?
-                        .clone();
-                    inner_account.sub_available(&amount)?;
-                    inner_tx.mark_disputed();
-                    inner_account.add_held(&amount);
+                    let to_be_disputed_tx = &mut *to_be_disputed_tx.lock().await;
+                    dispute(&mut *account, to_be_disputed_tx, engine.dispute_policy)?
                 }
             },
             TxType::Resolve => match engine.tx(self.id).await {
                 None => Err(Error::TxNotFound)?,
-                Some(disputed_tx) => {
-                    let inner_tx = &mut disputed_tx.lock().await;
-                    if !inner_tx.disputed() {
-                        return Err(Error::TxNotDisputed(inner_tx.id));
-                    }
-
-                    let inner_account = &mut account.lock().await;
-                    if inner_account.is_locked() {
-                        return Err(Error::AccountLocked(inner_account.client_id()));
-                    }
-
-                    let amount = inner_tx
-                        .amount()
-                        .ok_or(Error::MissingAmount(inner_tx.id()))?
-                        .clone();
-                    inner_account.sub_held(&amount)?;
-                    inner_tx.mark_resolved();
-                    inner_account.add_available(&amount);
-                }
+                Some(disputed_tx) => resolve(&mut *account, &mut *disputed_tx.lock().await)?,
             },
             TxType::Chargeback => match engine.tx(self.id).await {
                 None => Err(Error::TxNotFound)?,
                 Some(disputed_tx) => {
-                    let inner_tx = &mut disputed_tx.lock().await;
-                    if !inner_tx.disputed() {
-                        return Err(Error::TxNotDisputed(inner_tx.id));
-                    }
-
-                    let inner_account = &mut account.lock().await;
-                    if inner_account.is_locked() {
-                        return Err(Error::AccountLocked(inner_account.client_id()));
-                    }
-
-                    let amount = inner_tx
-                        .amount()
-                        .ok_or(Error::MissingAmount(inner_tx.id()))?;
-                    inner_account.sub_held(amount)?;
-                    inner_account.set_locked(true);
-                    inner_tx.mark_charged_back();
+                    let amount = charge_back(&mut *account, &mut *disputed_tx.lock().await)?;
+                    engine.clearing.credit(account.client_id(), &amount);
                 }
             },
         }
@@ -200,10 +336,213 @@ impl<A: AccountsDal + Send + Sync + Clone, T: TxsDal + Send + Sync + Clone> TxHa
     }
 }
 
+// Clients can only dispute, resolve and charge back their own transactions.
+fn check_owner(account: &Account, tx: &Tx) -> Result<(), Error> {
+    if tx.client != account.client_id() {
+        return Err(Error::ClientMismatch(tx.id));
+    }
+    Ok(())
+}
+
+// Moves the funds of a deposit to held, or holds the funds of a withdrawal if the policy allows
+// disputing them, with the account locked before the transaction, like every other path locking
+// both of them.
+fn dispute(account: &mut Account, tx: &mut Tx, policy: DisputePolicy) -> Result<(), Error> {
+    check_owner(account, tx)?;
+    if account.is_locked() {
+        return Err(Error::AccountLocked(account.client_id()));
+    }
+
+    match (&tx.r#type, policy) {
+        (TxType::Deposit, _) | (TxType::Withdrawal, DisputePolicy::DepositsAndWithdrawals) => (),
+        _ => return Err(Error::InvalidDispute(tx.id)),
+    }
+
+    if tx.disputed() {
+        return Err(Error::TxAlreadyDisputed(tx.id));
+    }
+    let amount = tx.amount().ok_or(Error::MissingAmount(tx.id))?.clone();
+    if tx.r#type == TxType::Deposit {
+        account.sub_available(&amount)?;
+    }
+    tx.mark_disputed();
+    account.add_held(&amount);
+    Ok(())
+}
+
+fn resolve(account: &mut Account, tx: &mut Tx) -> Result<(), Error> {
+    check_owner(account, tx)?;
+    if !tx.disputed() {
+        return Err(Error::TxNotDisputed(tx.id));
+    }
+
+    if account.is_locked() {
+        return Err(Error::AccountLocked(account.client_id()));
+    }
+
+    let amount = tx.amount().ok_or(Error::MissingAmount(tx.id()))?.clone();
+    account.sub_held(&amount)?;
+    tx.mark_resolved();
+    // A resolved withdrawal stands, its funds having left the account.
+    if tx.r#type == TxType::Deposit {
+        account.add_available(&amount);
+    }
+    Ok(())
+}
+
+// Seconds since the epoch, as pending transactions expire.
+fn now() -> u64 {
+    history::now_millis() / 1000
+}
+
+// Removes the disputed funds and locks the account, returning the amount charged back. The funds
+// of a charged back withdrawal are credited back to the account instead, the amount returned
+// being negative.
+fn charge_back(account: &mut Account, tx: &mut Tx) -> Result<BigDecimal, Error> {
+    check_owner(account, tx)?;
+    if !tx.disputed() {
+        return Err(Error::TxNotDisputed(tx.id));
+    }
+
+    if account.is_locked() {
+        return Err(Error::AccountLocked(account.client_id()));
+    }
+
+    let amount = tx.amount().ok_or(Error::MissingAmount(tx.id()))?.clone();
+    account.sub_held(&amount)?;
+    account.set_locked(true);
+    tx.mark_charged_back();
+    if tx.r#type == TxType::Withdrawal {
+        account.add_available(&amount);
+        return Ok(-amount);
+    }
+    Ok(amount)
+}
+
 #[derive(Clone)]
 pub struct Engine<A: AccountsDal, T: TxsDal> {
     accounts: A,
     txs: T,
+    clearing: ClearingAccount,
+    quota: Quota,
+    retention: RetentionPolicy,
+    reviews: ReviewQueue,
+    // Transactions awaiting approval, how long they can wait (in seconds) and the ones approved
+    // but not handled yet.
+    pending: Arc<dyn PendingDal>,
+    pending_ttl: Option<u64>,
+    approved: HashSet<u32>,
+    lockouts: Lockouts,
+    latency: LatencyHistogram,
+    rejected: u64,
+    stats: Stats,
+    slow_tx_threshold: Option<Duration>,
+    tx_timeout: Option<Duration>,
+    // Transactions processed by `handle_source` and the like before yielding to the runtime, and
+    // the ones processed since the last yield.
+    yield_budget: Option<u64>,
+    unyielded: u64,
+    error_policy: ErrorPolicy,
+    dispute_policy: DisputePolicy,
+    log_sampler: LogSampler,
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+// Builds an engine with a non-default configuration.
+pub struct EngineBuilder<A: AccountsDal, T: TxsDal> {
+    engine: Engine<A, T>,
+}
+
+impl<A: AccountsDal + Send + Sync + Clone, T: TxsDal + Send + Sync + Clone> EngineBuilder<A, T> {
+    // Transactions taking longer than `threshold` to be handled get logged.
+    pub fn slow_tx_threshold(mut self, threshold: Duration) -> Self {
+        self.engine.slow_tx_threshold = Some(threshold);
+        self
+    }
+
+    // Accounts locked by a chargeback get unlocked by their first transaction once `cooling_off`
+    // transactions were processed after the chargeback.
+    pub fn chargeback_cooling_off(mut self, cooling_off: u64) -> Self {
+        self.engine.lockouts = Lockouts::new(Some(cooling_off));
+        self
+    }
+
+    // Transactions taking longer than `timeout` to be applied (e.g. stuck on a remote storage) are
+    // abandoned and rejected, so that the following ones keep being processed.
+    pub fn tx_timeout(mut self, timeout: Duration) -> Self {
+        self.engine.tx_timeout = Some(timeout);
+        self
+    }
+
+    // Processing sources yields to the runtime every `budget` transactions, so that replaying a
+    // massive input doesn't starve the other tasks sharing the runtime (e.g. request handlers of an
+    // embedding server), even when the ledgers never make it wait.
+    pub fn yield_every(mut self, budget: u64) -> Self {
+        self.engine.yield_budget = Some(budget.max(1));
+        self
+    }
+
+    // Stores the transactions awaiting approval in `store` rather than in memory.
+    pub fn pending_store(mut self, store: impl PendingDal + 'static) -> Self {
+        self.engine.pending = Arc::new(store);
+        self
+    }
+
+    // Transactions parked for approval get dropped once they waited for `ttl` seconds (see
+    // `Engine::expire_pending`).
+    pub fn pending_ttl(mut self, ttl: u64) -> Self {
+        self.engine.pending_ttl = Some(ttl);
+        self
+    }
+
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.engine.error_policy = policy;
+        self
+    }
+
+    pub fn dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.engine.dispute_policy = policy;
+        self
+    }
+
+    // Only one in `rate` rejected rows gets logged, with an aggregated summary of the rejections
+    // logged every `summary_interval` rows.
+    pub fn log_sampling(mut self, rate: u64, summary_interval: Option<u64>) -> Self {
+        self.engine.log_sampler = LogSampler::new(rate, summary_interval);
+        self
+    }
+
+    pub fn quota(mut self, quota: Quota) -> Self {
+        self.engine.quota = quota;
+        self
+    }
+
+    pub fn retention(mut self, retention: RetentionPolicy) -> Self {
+        self.engine.retention = retention;
+        self
+    }
+
+    // Registers a plugin. Plugins are called in registration order.
+    pub fn plugin(mut self, plugin: impl Plugin + 'static) -> Self {
+        self.engine.plugins.push(Arc::new(plugin));
+        self
+    }
+
+    // Rejects a configuration which would silently misbehave, like disputable withdrawals which
+    // never get stored.
+    pub fn build(self) -> Result<Engine<A, T>, Error> {
+        if self.engine.retention.prune_terminal()
+            && self.engine.dispute_policy == DisputePolicy::DepositsAndWithdrawals
+        {
+            return Err(Error::InvalidConfig(
+                "withdrawals can't be disputed when pruned by the retention policy".to_string(),
+            ));
+        }
+        for plugin in self.engine.plugins.iter() {
+            plugin.on_startup();
+        }
+        Ok(self.engine)
+    }
 }
 
 impl<
@@ -221,9 +560,13 @@ impl<
 
     async fn accounts(
         &self,
-    ) -> tokio::sync::RwLockReadGuard<std::collections::HashMap<u16, Arc<Mutex<Account>>>> {
+    ) -> tokio::sync::RwLockReadGuard<'_, std::collections::HashMap<u16, Arc<Mutex<Account>>>> {
         self.accounts.accounts().await
     }
+
+    async fn prefetch(&self, ids: &[u16]) {
+        self.accounts.prefetch(ids).await
+    }
 }
 
 impl<
@@ -238,137 +581,868 @@ impl<
     async fn insert(&self, tx: Tx) {
         self.txs.insert(tx).await
     }
+
+    async fn txs(
+        &self,
+    ) -> tokio::sync::RwLockReadGuard<'_, std::collections::HashMap<u32, Arc<Mutex<Tx>>>> {
+        self.txs.txs().await
+    }
+
+    async fn prefetch(&self, ids: &[u32]) {
+        self.txs.prefetch(ids).await
+    }
+
+    async fn remove(&self, id: u32) {
+        self.txs.remove(id).await
+    }
+
+    async fn client_txs(&self, client: u16) -> Vec<Arc<Mutex<Tx>>> {
+        self.txs.client_txs(client).await
+    }
 }
 
 impl<A: AccountsDal + Send + Sync + Clone, T: TxsDal + Send + Sync + Clone> Engine<A, T> {
     pub fn new(accounts: A, txs: T) -> Self {
-        Engine { accounts, txs }
+        Engine {
+            accounts,
+            txs,
+            clearing: ClearingAccount::default(),
+            quota: Quota::default(),
+            retention: RetentionPolicy::default(),
+            reviews: ReviewQueue::default(),
+            pending: Arc::new(InMemoryPendingLedger::default()),
+            pending_ttl: None,
+            approved: HashSet::new(),
+            lockouts: Lockouts::default(),
+            latency: LatencyHistogram::default(),
+            rejected: 0,
+            stats: Stats::default(),
+            slow_tx_threshold: None,
+            tx_timeout: None,
+            yield_budget: None,
+            unyielded: 0,
+            error_policy: ErrorPolicy::default(),
+            dispute_policy: DisputePolicy::default(),
+            log_sampler: LogSampler::default(),
+            plugins: Vec::new(),
+        }
     }
 
-    pub async fn handle_txs(
-        &mut self,
-        tx_stream: impl AsyncRead + Send + Unpin,
-    ) -> anyhow::Result<()> {
-        let rdr = csv_async::AsyncReaderBuilder::new()
-            .trim(Trim::All)
-            .create_deserializer(tx_stream);
-        let mut records = rdr.into_deserialize::<Tx>();
-        while let Some(record) = records.next().await {
-            let tx: Tx = match record {
-                Ok(inner) => inner,
-                Err(err) => {
-                    debug!("Errored while processing transaction: {err}");
-                    continue;
-                }
-            };
-            let _ = tx.handle(self).await.map_err(|err| {
-                debug!("TX handling: {err}");
-                err
-            });
-            if tx.storable() {
-                TxsDal::insert(self, tx).await;
-            }
+    pub fn builder(accounts: A, txs: T) -> EngineBuilder<A, T> {
+        EngineBuilder {
+            engine: Engine::new(accounts, txs),
         }
-        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
+    pub fn quota(&self) -> &Quota {
+        &self.quota
+    }
 
-    use bigdecimal::BigDecimal;
+    pub fn retention(&self) -> &RetentionPolicy {
+        &self.retention
+    }
 
-    use crate::{
-        error::Error,
-        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger, TxsDal},
-    };
+    // Lockouts of the accounts locked by chargebacks.
+    pub fn lockouts(&self) -> &Lockouts {
+        &self.lockouts
+    }
 
-    use super::{Engine, Tx, TxHandle, TxType};
+    // Unlocks an account locked by a chargeback once approved by an admin, regardless of its
+    // cooling-off period.
+    pub async fn unlock(&mut self, client: u16) -> Result<(), Error> {
+        let account = self
+            .account(client)
+            .await
+            .ok_or(Error::AccountNotLocked(client))?;
+        let inner = &mut account.lock().await;
+        if !inner.is_locked() {
+            return Err(Error::AccountNotLocked(client));
+        }
+        inner.set_locked(false);
+        self.lockouts.record_unlock(client);
+        Ok(())
+    }
 
-    #[test]
-    fn parse_amount() {
-        let mut tx = Tx {
-            r#type: TxType::Deposit,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.1").unwrap()),
-            disputed: false,
-        };
+    // Clients under review and their queued transactions.
+    pub fn reviews(&self) -> &ReviewQueue {
+        &self.reviews
+    }
 
-        // Success
-        let val = tx.amount().unwrap();
-        assert_eq!(val.to_string(), "10.1");
+    // Takes the client out of review and handles its queued transactions, in arrival order,
+    // returning their outcomes.
+    pub async fn release(&mut self, client: u16) -> Vec<(u32, TxOutcome)> {
+        let mut outcomes = Vec::new();
+        for tx in self.reviews.release(client) {
+            let id = tx.id;
+            outcomes.push((id, self.handle_tx(tx).await));
+        }
+        outcomes
+    }
 
-        // Success leading zero decimal part
-        tx.amount = Some(BigDecimal::from_str("10.01").unwrap());
-        let val = tx.amount().unwrap();
-        assert_eq!(val.to_string(), "10.01");
+    // Transactions awaiting approval.
+    pub fn pending(&self) -> &dyn PendingDal {
+        self.pending.as_ref()
+    }
 
-        // Success leading zero whole part
-        tx.amount = Some(BigDecimal::from_str("001.01").unwrap());
-        let val = tx.amount().unwrap();
-        assert_eq!(val.to_string(), "1.01");
+    // Parks a transaction until approved, e.g. one scheduled for later or awaiting screening by an
+    // external system.
+    pub async fn park(&self, tx: Tx, reason: String) {
+        let expires_at = self.pending_ttl.map(|ttl| now() + ttl);
+        self.pending
+            .park(PendingTx {
+                tx,
+                reason,
+                expires_at,
+            })
+            .await;
     }
 
-    #[tokio::test]
-    async fn new_account_deposit_success() {
-        let mut engine = Engine::new(
-            InMemoryAccountLedger::default(),
-            InMemoryTxLedger::default(),
-        );
-        let tx = Tx {
-            r#type: TxType::Deposit,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.1").unwrap()),
-            disputed: false,
-        };
+    // Handles a pending transaction once approved, bypassing the screening which parked it, and
+    // returns its outcome. Expired transactions can't be approved anymore.
+    pub async fn approve_pending(&mut self, id: u32) -> Result<TxOutcome, Error> {
+        let pending = self.pending.take(id).await.ok_or(Error::NotPending(id))?;
+        if pending.is_expired(now()) {
+            return Err(Error::NotPending(id));
+        }
+        self.approved.insert(id);
+        Ok(self.handle_tx(pending.tx).await)
+    }
 
-        tx.handle(&mut engine).await.unwrap();
-        let account = engine.account(0).await.unwrap();
-        assert_eq!(
-            account.lock().await.available().to_string(),
-            tx.amount().unwrap().to_string()
-        );
+    // Drops a pending transaction rejected by its approver, returning it.
+    pub async fn reject_pending(&mut self, id: u32) -> Result<PendingTx, Error> {
+        self.pending.take(id).await.ok_or(Error::NotPending(id))
     }
 
-    #[tokio::test]
-    async fn withdrawal_success() {
-        let mut engine = Engine::new(
-            InMemoryAccountLedger::default(),
-            InMemoryTxLedger::default(),
-        );
-        let tx = Tx {
-            r#type: TxType::Deposit,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.01").unwrap()),
-            disputed: false,
-        };
+    // Drops the pending transactions expired as of `now` (seconds since the epoch), returning them.
+    pub async fn expire_pending(&mut self, now: u64) -> Vec<PendingTx> {
+        let mut expired = Vec::new();
+        for pending in self.pending.pending().await {
+            if pending.is_expired(now) {
+                expired.extend(self.pending.take(pending.tx.id).await);
+            }
+        }
+        expired
+    }
 
-        tx.handle(&mut engine).await.unwrap();
-        let account = engine.account(0).await.unwrap();
-        assert_eq!(
-            account.lock().await.available().to_string(),
-            tx.amount().unwrap().to_string()
-        );
+    // Parks the transaction if a plugin screens it out, handing it back otherwise.
+    async fn screen(&mut self, tx: Tx) -> Option<Tx> {
+        if self.approved.remove(&tx.id) {
+            return Some(tx);
+        }
+        match self.plugins.iter().find_map(|plugin| plugin.screen(&tx)) {
+            Some(reason) => {
+                self.park(tx, reason).await;
+                None
+            }
+            None => Some(tx),
+        }
+    }
 
-        let tx = Tx {
-            r#type: TxType::Withdrawal,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from(10)),
-            disputed: false,
-        };
-        tx.handle(&mut engine).await.unwrap();
-        assert_eq!(account.lock().await.available().to_string(), "0.01");
+    // Funds removed from client accounts by chargebacks.
+    pub fn clearing(&self) -> &ClearingAccount {
+        &self.clearing
     }
 
-    #[tokio::test]
-    async fn withdrawal_fail_with_underflow() {
-        let mut engine = Engine::new(
-            InMemoryAccountLedger::default(),
+    // Handling latencies of all the transactions processed so far.
+    pub fn latency(&self) -> &LatencyHistogram {
+        &self.latency
+    }
+
+    // Number of transactions handled so far which were rejected.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    // Counters of the rows processed so far.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    // Accounts for a record of a source which couldn't be read, returning its outcome.
+    pub fn skip_invalid(&mut self, err: Error) -> TxOutcome {
+        self.stats.parse_failures += 1;
+        TxOutcome::from(Err(err))
+    }
+
+    // Processes the transactions from a CSV input.
+    pub async fn handle_txs(
+        &mut self,
+        tx_stream: impl AsyncRead + Send + Unpin,
+    ) -> anyhow::Result<ProcessingReport> {
+        self.handle_source(source::from_csv(tx_stream)).await
+    }
+
+    // Same as `handle_txs`, stopping at the next transaction boundary once `cancel` is cancelled.
+    pub async fn handle_txs_until(
+        &mut self,
+        tx_stream: impl AsyncRead + Send + Unpin,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<ProcessingReport> {
+        self.handle_source_until(source::from_csv(tx_stream), cancel)
+            .await
+    }
+
+    // Processes the transactions from any source. Records which couldn't be read are skipped.
+    pub async fn handle_source(
+        &mut self,
+        source: impl TxSource,
+    ) -> anyhow::Result<ProcessingReport> {
+        self.handle_source_until(source, &CancellationToken::new())
+            .await
+    }
+
+    // Same as `handle_source`, stopping at the next transaction boundary once `cancel` is
+    // cancelled, even while waiting for the source. The report then only covers the transactions
+    // processed until then. Under `ErrorPolicy::Strict`, the first rejected row stops processing
+    // with its `RowError` instead.
+    pub async fn handle_source_until(
+        &mut self,
+        mut source: impl TxSource,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<ProcessingReport> {
+        let mut report = ProcessingReport::default();
+        loop {
+            let record = tokio::select! {
+                biased;
+                () = cancel.cancelled() => {
+                    report.cancelled = true;
+                    break;
+                }
+                record = source.next() => record,
+            };
+            let Some(record) = record else {
+                break;
+            };
+            let outcome = match record {
+                // Rejections are already logged while processing.
+                Ok(tx) => self.handle_tx(tx).await,
+                Err(err) => {
+                    if self.log_sampler.rejection() {
+                        debug!("Errored while processing transaction: {err}");
+                    }
+                    self.skip_invalid(err)
+                }
+            };
+            report.record(&outcome);
+            self.log_summary();
+            self.spend_budget().await;
+            if self.error_policy == ErrorPolicy::Strict {
+                if let Some(err) = outcome.error() {
+                    return Err(RowError {
+                        row: report.rows,
+                        error: err.clone(),
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    // Yields to the runtime once the budget of transactions between yields is spent.
+    async fn spend_budget(&mut self) {
+        let Some(budget) = self.yield_budget else {
+            return;
+        };
+        self.unyielded += 1;
+        if self.unyielded >= budget {
+            self.unyielded = 0;
+            tokio::task::yield_now().await;
+        }
+    }
+
+    // Processes the transactions from a CSV input as they are consumed, yielding every record
+    // along with its outcome, so callers can react to each transaction as it gets handled.
+    pub fn handle_txs_stream<'e>(
+        &'e mut self,
+        tx_stream: impl AsyncRead + Send + Unpin + 'e,
+    ) -> impl Stream<Item = (TxRecord, TxOutcome)> + 'e {
+        self.handle_source_stream(source::from_csv(tx_stream))
+    }
+
+    // Same as `handle_txs_stream`, for any source.
+    pub fn handle_source_stream<'e>(
+        &'e mut self,
+        source: impl TxSource + 'e,
+    ) -> impl Stream<Item = (TxRecord, TxOutcome)> + 'e {
+        stream::unfold((self, source), |(engine, mut source)| async move {
+            let record = source.next().await?;
+            let outcome = match &record {
+                Ok(tx) => engine.handle_tx(tx.clone()).await,
+                Err(err) => TxOutcome::from(Err(err.clone())),
+            };
+            engine.log_summary();
+            engine.spend_budget().await;
+            Some(((record, outcome), (engine, source)))
+        })
+    }
+
+    // Processes a single transaction, returning whether it was applied, rejected or ignored.
+    pub async fn handle_tx(&mut self, tx: Tx) -> TxOutcome {
+        let span = info_span!("tx", id = tx.id, client = tx.client, r#type = ?tx.r#type);
+        self.process(tx, None).instrument(span).await
+    }
+
+    // Handles a single transaction and stores it, if it can be referenced by later transactions.
+    async fn process(&mut self, tx: Tx, account: Option<&mut Account>) -> TxOutcome {
+        let id = tx.id;
+        let Some(tx) = self.reviews.hold(tx) else {
+            return TxOutcome::from(Err(Error::UnderReview(id)));
+        };
+        let Some(mut tx) = self.screen(tx).await else {
+            return TxOutcome::from(Err(Error::PendingApproval(id)));
+        };
+        self.quota.record_tx();
+        let start = Instant::now();
+        let timeout = self.tx_timeout;
+        let enriched = self
+            .plugins
+            .iter()
+            .try_for_each(|plugin| plugin.enrich(&mut tx));
+        let applying = async {
+            let checked = enriched
+                .and_then(|()| self.plugins.iter().try_for_each(|plugin| plugin.on_tx(&tx)));
+            match checked {
+                // Enrichment may have moved the transaction to another client.
+                Ok(()) => match account {
+                    Some(inner) if inner.client_id() == tx.client => {
+                        self.apply_observed(&tx, inner).await
+                    }
+                    _ => match self.account_or_insert(tx.client).await {
+                        Ok(account) => self.apply_observed(&tx, &mut *account.lock().await).await,
+                        Err(err) => Err(err),
+                    },
+                },
+                Err(err) => Err(err),
+            }
+        };
+        // Transactions only change the ledgers after their last storage lookup, so abandoning
+        // one which timed out leaves them untouched.
+        let outcome = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, applying)
+                .await
+                .unwrap_or(Err(Error::Timeout(id))),
+            None => applying.await,
+        };
+        let outcome = TxOutcome::from(outcome);
+        for plugin in self.plugins.iter() {
+            plugin.on_outcome(&tx, &outcome);
+            if outcome.is_applied() {
+                if let Some(reason) = plugin.review(&tx) {
+                    warn!("Client {} placed under review: {reason}", tx.client);
+                    self.reviews.place(tx.client, reason);
+                }
+            }
+        }
+        self.stats.record(&tx, &outcome);
+        let locked = outcome.is_applied() && tx.r#type == TxType::Chargeback;
+        match &outcome {
+            TxOutcome::Applied => (),
+            TxOutcome::Failed(err) => {
+                self.rejected += 1;
+                error!("Internal error while handling tx {}: {err}", tx.id);
+            }
+            TxOutcome::RejectedBusinessRule(err) => {
+                self.rejected += 1;
+                if self.log_sampler.rejection() {
+                    debug!("TX handling: {err}");
+                }
+            }
+            TxOutcome::Ignored(reason) => debug!("TX ignored: {reason}"),
+        }
+        let elapsed = start.elapsed();
+        self.latency.record(elapsed);
+        if self
+            .slow_tx_threshold
+            .is_some_and(|threshold| elapsed > threshold)
+        {
+            warn!("Slow tx {} ({:?}) handled in {elapsed:?}", tx.id, tx.r#type);
+        }
+        let client = tx.client;
+        self.lockouts.tick();
+        if locked {
+            self.lockouts.record_chargeback(client);
+        }
+        let expired = self.retention.tick();
+        // Rejected transactions aren't stored, so that they can't be referenced (e.g. disputed)
+        // later on, nor replace a previously applied transaction with the same id.
+        match tx.r#type {
+            _ if !outcome.is_applied() => (),
+            TxType::Deposit => {
+                TxsDal::insert(self, tx).await;
+                self.retention.track(id);
+            }
+            TxType::Withdrawal if !self.retention.prune_terminal() => {
+                TxsDal::insert(self, tx).await
+            }
+            TxType::Withdrawal => self.retention.record_dropped(id),
+            _ => (),
+        }
+        self.prune(expired, locked.then_some(client)).await;
+        outcome
+    }
+
+    // Drops the deposits which fell out of the dispute window, unless still disputed, and all the
+    // transactions of a newly locked client, if terminal transactions are pruned.
+    async fn prune(&mut self, expired: Vec<u32>, locked: Option<u16>) {
+        let mut pruned = 0;
+        for id in expired {
+            let Some(tx) = self.tx(id).await else {
+                continue;
+            };
+            // Disputed deposits are kept for another window, so they can still be resolved or
+            // charged back.
+            if tx.lock().await.disputed() {
+                self.retention.track(id);
+            } else {
+                TxsDal::remove(self, id).await;
+                self.retention.record_dropped(id);
+                pruned += 1;
+            }
+        }
+        if let Some(client) = locked.filter(|_| self.retention.prune_terminal()) {
+            for tx in self.client_txs(client).await {
+                let id = tx.lock().await.id();
+                TxsDal::remove(self, id).await;
+                self.retention.record_dropped(id);
+                pruned += 1;
+            }
+        }
+        self.retention.record_pruned(pruned);
+    }
+
+    // Applies the transaction on its already locked account, letting the plugins observe the state
+    // of the account after a successful transaction.
+    async fn apply_observed(&mut self, tx: &Tx, account: &mut Account) -> Result<(), Error> {
+        if account.is_locked() && self.lockouts.cooled_off(account.client_id()) {
+            info!("Client {} unlocked after its cooling-off period", tx.client);
+            account.set_locked(false);
+            self.lockouts.record_unlock(account.client_id());
+        }
+        let outcome = tx.apply(self, account).await;
+        if outcome.is_ok() {
+            for plugin in self.plugins.iter() {
+                plugin.on_account(tx, account);
+            }
+        }
+        outcome
+    }
+
+    fn log_summary(&mut self) {
+        if let Some(rejections) = self.log_sampler.row() {
+            info!(
+                "Processed {} rows, {rejections} rejected since the last summary, {} in total",
+                self.log_sampler.rows(),
+                self.log_sampler.rejections()
+            );
+        }
+    }
+
+    // Processes a batch of transactions, acquiring the lock of every touched account only once.
+    // Transactions are applied in order for each client, but clients are processed one after
+    // another, so the relative order of transactions from different clients isn't kept. The
+    // accounts and transactions referenced by the batch are prefetched from storage upfront.
+    // Returns the outcome of every transaction, in the order they were given.
+    pub async fn handle_batch(&mut self, txs: &[Tx]) -> Vec<TxOutcome> {
+        let mut clients: Vec<u16> = Vec::new();
+        let mut by_client: HashMap<u16, Vec<(usize, &Tx)>> = HashMap::new();
+        let mut outcomes = vec![TxOutcome::Applied; txs.len()];
+        // Deposits and withdrawals are looked up as well, as duplicates of stored transactions.
+        let referenced: Vec<u32> = txs.iter().map(|tx| tx.id).collect();
+        for (idx, tx) in txs.iter().enumerate() {
+            by_client
+                .entry(tx.client)
+                .or_insert_with(|| {
+                    clients.push(tx.client);
+                    Vec::new()
+                })
+                .push((idx, tx));
+        }
+        AccountsDal::prefetch(self, &clients).await;
+        TxsDal::prefetch(self, &referenced).await;
+
+        for client in clients {
+            let batch = by_client.remove(&client).unwrap_or_default();
+            let account = match self.account_or_insert(client).await {
+                Ok(inner) => inner,
+                Err(err) => {
+                    error!("Internal error while handling batch: {err}");
+                    for (idx, _) in batch {
+                        outcomes[idx] = TxOutcome::from(Err(err.clone()));
+                    }
+                    continue;
+                }
+            };
+            let inner = &mut account.lock().await;
+            for (idx, tx) in batch {
+                let span = info_span!("tx", id = tx.id, client = tx.client, r#type = ?tx.r#type);
+                outcomes[idx] = self.process(tx.clone(), Some(inner)).instrument(span).await;
+                self.log_summary();
+            }
+        }
+        outcomes
+    }
+
+    async fn account_or_insert(&mut self, client: u16) -> Result<Arc<Mutex<Account>>, Error> {
+        if let Some(inner) = self.account(client).await {
+            return Ok(inner);
+        }
+
+        AccountsDal::insert(self, Account::new_unlocked(client)).await;
+        self.account(client)
+            .await
+            .ok_or(Error::UnexpectedMissingAccount(client))
+    }
+
+    // Lets the plugins know processing is over.
+    pub fn shutdown(&self) {
+        for plugin in self.plugins.iter() {
+            plugin.on_shutdown();
+        }
+    }
+
+    // Entries contributed by every plugin to the end of run report.
+    pub fn plugin_reports(&self) -> Vec<(&str, Vec<(String, String)>)> {
+        self.plugins
+            .iter()
+            .map(|plugin| (plugin.name(), plugin.report()))
+            .collect()
+    }
+
+    // Captures the state of the ledgers, to be restored by a later run.
+    pub async fn snapshot(&self) -> Snapshot {
+        let mut accounts = Vec::new();
+        for account in self.accounts().await.values() {
+            accounts.push(account.lock().await.clone());
+        }
+        accounts.sort_by_key(Account::client_id);
+        let mut txs = Vec::new();
+        for tx in self.txs().await.values() {
+            txs.push(tx.lock().await.clone());
+        }
+        txs.sort_by_key(Tx::id);
+        let mut clearing: Vec<ClearingEntry> = self
+            .clearing
+            .clients()
+            .map(|(client, amount)| ClearingEntry {
+                client: *client,
+                amount: amount.to_string(),
+            })
+            .collect();
+        clearing.sort_by_key(|entry| entry.client);
+
+        Snapshot {
+            accounts: accounts.iter().map(Into::into).collect(),
+            txs: txs.iter().map(Into::into).collect(),
+            clearing,
+            pending: self
+                .pending
+                .pending()
+                .await
+                .iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+
+    // Loads the ledgers from a snapshot, replacing any accounts and transactions with the same
+    // ids. Nothing is loaded if the snapshot holds invalid entries.
+    pub async fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        let accounts = snapshot.accounts()?;
+        let txs = snapshot.txs()?;
+        let clearing = snapshot.clearing()?;
+        let pending = snapshot.pending()?;
+        for account in accounts {
+            AccountsDal::insert(self, account).await;
+        }
+        for tx in txs {
+            TxsDal::insert(self, tx).await;
+        }
+        for (client, amount) in clearing {
+            self.clearing.credit(client, &amount);
+        }
+        for pending in pending {
+            self.pending.park(pending).await;
+        }
+        Ok(())
+    }
+
+    // Applies a change shipped by a primary engine on the ledgers of a standby (see
+    // `shipping::Shipper`): the account takes the state it had on the primary, and the transaction
+    // gets recorded like the primary did, without going through the business rules again.
+    pub async fn replicate(&mut self, tx: Tx, account: Account) -> Result<(), Error> {
+        let client = account.client_id();
+        match tx.tx_type() {
+            TxType::Deposit | TxType::Withdrawal => TxsDal::insert(self, tx).await,
+            r#type => {
+                let stored = self.tx(tx.id()).await.ok_or(Error::TxNotFound)?;
+                let stored = &mut *stored.lock().await;
+                match r#type {
+                    TxType::Dispute => stored.mark_disputed(),
+                    TxType::Resolve => stored.mark_resolved(),
+                    _ => {
+                        stored.mark_charged_back();
+                        let amount = stored.amount().ok_or(Error::MissingAmount(stored.id()))?;
+                        // Charged back withdrawals are credited back to the account.
+                        let amount = match stored.tx_type() {
+                            TxType::Withdrawal => -amount,
+                            _ => amount.clone(),
+                        };
+                        self.clearing.credit(client, &amount);
+                    }
+                }
+            }
+        }
+        AccountsDal::insert(self, account).await;
+        Ok(())
+    }
+
+    // Creates an engine over the given ledgers with the same configuration (and plugins) as this
+    // one, e.g. to process an independent input which gets merged back afterwards. The quota is
+    // shared, so its limits hold across both engines.
+    pub fn isolated<A2, T2>(&self, accounts: A2, txs: T2) -> Engine<A2, T2>
+    where
+        A2: AccountsDal + Send + Sync + Clone,
+        T2: TxsDal + Send + Sync + Clone,
+    {
+        Engine {
+            quota: self.quota.clone(),
+            retention: self.retention.clone(),
+            reviews: self.reviews.clone(),
+            pending: self.pending.clone(),
+            pending_ttl: self.pending_ttl,
+            lockouts: self.lockouts.fork(),
+            slow_tx_threshold: self.slow_tx_threshold,
+            tx_timeout: self.tx_timeout,
+            yield_budget: self.yield_budget,
+            error_policy: self.error_policy,
+            dispute_policy: self.dispute_policy,
+            log_sampler: LogSampler::new(
+                self.log_sampler.rate(),
+                self.log_sampler.summary_interval(),
+            ),
+            plugins: self.plugins.clone(),
+            ..Engine::new(accounts, txs)
+        }
+    }
+
+    // Creates an engine over the same ledgers, with the same configuration, to process a disjoint
+    // set of clients concurrently with this one (see `shard::process_sharded`). Its metrics get
+    // folded back into this engine by `join`.
+    pub fn worker(&self) -> Engine<A, T> {
+        self.isolated(self.accounts.clone(), self.txs.clone())
+    }
+
+    // Creates an engine like `isolated`, over copy-on-write forks of this engine's ledgers, so that
+    // it sees their state (e.g. loaded from a snapshot) without changing it. Its changes get folded
+    // back into this engine by `merge_fork`.
+    pub fn isolated_fork(&self) -> Engine<AccountsFork<A>, TxsFork<T>> {
+        self.isolated(
+            AccountsFork::new(self.accounts.clone()),
+            TxsFork::new(self.txs.clone()),
+        )
+    }
+
+    // Folds the clearing entries and metrics of a worker back into this engine.
+    pub fn join<A2, T2>(&mut self, worker: Engine<A2, T2>)
+    where
+        A2: AccountsDal + Send + Sync + Clone,
+        T2: TxsDal + Send + Sync + Clone,
+    {
+        self.clearing.merge(&worker.clearing);
+        self.latency.merge(&worker.latency);
+        self.lockouts.merge(&worker.lockouts);
+        self.rejected += worker.rejected;
+        self.stats.merge(&worker.stats);
+    }
+
+    // Creates a copy-on-write view of the engine. Transactions handled by the fork are applied on
+    // copies of the touched accounts and transactions, leaving this engine's ledgers untouched.
+    pub fn fork(&self) -> Engine<AccountsFork<A>, TxsFork<T>> {
+        Engine {
+            accounts: AccountsFork::new(self.accounts.clone()),
+            txs: TxsFork::new(self.txs.clone()),
+            clearing: self.clearing.clone(),
+            // What-if transactions don't use up the quota of the run.
+            quota: self.quota.detached(),
+            // Forks never drop transactions from their base.
+            retention: self.retention.keeping_all(),
+            reviews: ReviewQueue::default(),
+            // What-if transactions never wait for an approval from outside.
+            pending: Arc::new(InMemoryPendingLedger::default()),
+            pending_ttl: self.pending_ttl,
+            approved: HashSet::new(),
+            lockouts: self.lockouts.clone(),
+            latency: LatencyHistogram::default(),
+            rejected: 0,
+            stats: Stats::default(),
+            slow_tx_threshold: self.slow_tx_threshold,
+            tx_timeout: self.tx_timeout,
+            yield_budget: self.yield_budget,
+            unyielded: 0,
+            error_policy: self.error_policy,
+            dispute_policy: self.dispute_policy,
+            log_sampler: LogSampler::new(
+                self.log_sampler.rate(),
+                self.log_sampler.summary_interval(),
+            ),
+            // What-if transactions must not reach plugins with external effects (e.g. webhooks).
+            plugins: Vec::new(),
+        }
+    }
+
+    // Folds the ledgers of another engine (e.g. one which processed a different shard of the
+    // input) into this one. Shards are expected to be partitioned by client, so any client id or
+    // tx id present in both engines is reported as a conflict and nothing gets merged.
+    pub async fn merge(&mut self, other: Engine<A, T>) -> Result<(), Error> {
+        let accounts: Vec<Arc<Mutex<Account>>> = other.accounts().await.values().cloned().collect();
+        let txs: Vec<Arc<Mutex<Tx>>> = other.txs().await.values().cloned().collect();
+
+        for account in accounts.iter() {
+            let client_id = account.lock().await.client_id();
+            if self.account(client_id).await.is_some() {
+                return Err(Error::AccountConflict(client_id));
+            }
+        }
+        for tx in txs.iter() {
+            let id = tx.lock().await.id();
+            if self.tx(id).await.is_some() {
+                return Err(Error::TxConflict(id));
+            }
+        }
+
+        for account in accounts {
+            let inner = account.lock().await.clone();
+            AccountsDal::insert(self, inner).await;
+        }
+        for tx in txs {
+            let inner = tx.lock().await.clone();
+            TxsDal::insert(self, inner).await;
+        }
+        self.join(other);
+
+        Ok(())
+    }
+
+    // Folds the accounts and transactions changed (or removed, e.g. by the retention policy) by an
+    // engine over forks of this engine's ledgers (see `isolated_fork`) into this one. Forks are expected to change disjoint sets of clients
+    // and transactions, so any of them already changed by a fork merged before (tracked by
+    // `merged`) is reported as a conflict and nothing gets merged. Accounts only created by the
+    // fork, without changes, never conflict.
+    pub async fn merge_fork(
+        &mut self,
+        fork: Engine<AccountsFork<A>, TxsFork<T>>,
+        merged: &mut ForkChanges,
+    ) -> Result<(), Error> {
+        let accounts = fork.accounts.changes().await;
+        let txs = fork.txs.changes().await;
+        let removed = fork.txs.removed().await;
+
+        if let Some(account) = accounts
+            .iter()
+            .find(|account| merged.clients.contains(&account.client_id()))
+        {
+            return Err(Error::AccountConflict(account.client_id()));
+        }
+        if let Some(tx) = txs.iter().find(|tx| merged.txs.contains(&tx.id())) {
+            return Err(Error::TxConflict(tx.id()));
+        }
+        if let Some(id) = removed.iter().find(|id| merged.txs.contains(id)) {
+            return Err(Error::TxConflict(*id));
+        }
+
+        for account in accounts {
+            merged.clients.insert(account.client_id());
+            AccountsDal::insert(self, account).await;
+        }
+        for tx in txs {
+            merged.txs.insert(tx.id());
+            TxsDal::insert(self, tx).await;
+        }
+        for id in removed {
+            merged.txs.insert(id);
+            TxsDal::remove(self, id).await;
+        }
+        for id in fork.accounts.ids().await {
+            if self.account(id).await.is_none() {
+                AccountsDal::insert(self, Account::new_unlocked(id)).await;
+            }
+        }
+        self.join(fork);
+
+        Ok(())
+    }
+}
+
+// Clients and transactions changed by the forks merged so far (see `Engine::merge_fork`).
+#[derive(Debug, Default)]
+pub struct ForkChanges {
+    clients: HashSet<u16>,
+    txs: HashSet<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        str::FromStr,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use bigdecimal::BigDecimal;
+    use futures::StreamExt;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        error::{Error, RowError},
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger, TxsDal},
+    };
+
+    use crate::{
+        account::Account, amounts::AmountChecks, outcome::TxOutcome, plugin::Plugin,
+        quarantine::Quarantine, quota::Quota, retention::RetentionPolicy, source,
+        test_utils::dal::MockDal,
+    };
+
+    use super::{now, DisputePolicy, Engine, ErrorPolicy, ProcessingReport, Tx, TxHandle, TxType};
+
+    #[test]
+    fn parse_amount() {
+        let mut tx = Tx {
+            r#type: TxType::Deposit,
+            client: 0,
+            id: 0,
+            amount: Some(BigDecimal::from_str("10.1").unwrap()),
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+
+        // Success
+        let val = tx.amount().unwrap();
+        assert_eq!(val.to_string(), "10.1");
+
+        // Success leading zero decimal part
+        tx.amount = Some(BigDecimal::from_str("10.01").unwrap());
+        let val = tx.amount().unwrap();
+        assert_eq!(val.to_string(), "10.01");
+
+        // Success leading zero whole part
+        tx.amount = Some(BigDecimal::from_str("001.01").unwrap());
+        let val = tx.amount().unwrap();
+        assert_eq!(val.to_string(), "1.01");
+    }
+
+    #[tokio::test]
+    async fn new_account_deposit_success() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
         );
         let tx = Tx {
@@ -377,6 +1451,10 @@ mod tests {
             id: 0,
             amount: Some(BigDecimal::from_str("10.1").unwrap()),
             disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
         };
 
         tx.handle(&mut engine).await.unwrap();
@@ -385,85 +1463,136 @@ mod tests {
             account.lock().await.available().to_string(),
             tx.amount().unwrap().to_string()
         );
-
-        let tx = Tx {
-            r#type: TxType::Withdrawal,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.2").unwrap()),
-            disputed: false,
-        };
-        let res = tx.handle(&mut engine).await;
-        assert_eq!(res, Err(Error::MinAvailableUnderflow));
     }
 
     #[tokio::test]
-    async fn dispute_success() {
+    async fn withdrawal_success() {
         let mut engine = Engine::new(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
         );
-
         let tx = Tx {
             r#type: TxType::Deposit,
             client: 0,
             id: 0,
-            amount: Some(BigDecimal::from_str("10.1").unwrap()),
+            amount: Some(BigDecimal::from_str("10.01").unwrap()),
             disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
         };
-        tx.handle(&mut engine).await.unwrap();
-        TxsDal::insert(&mut engine, tx).await;
 
+        tx.handle(&mut engine).await.unwrap();
         let account = engine.account(0).await.unwrap();
-        assert_eq!(account.lock().await.available().to_string(), "10.1");
+        assert_eq!(
+            account.lock().await.available().to_string(),
+            tx.amount().unwrap().to_string()
+        );
 
         let tx = Tx {
-            r#type: TxType::Dispute,
+            r#type: TxType::Withdrawal,
             client: 0,
             id: 0,
-            amount: None,
+            amount: Some(BigDecimal::from(10)),
             disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
         };
         tx.handle(&mut engine).await.unwrap();
-        let tx = engine.tx(0).await.unwrap();
-        assert_eq!(tx.lock().await.disputed(), true);
-        assert_eq!(account.lock().await.available().to_string(), "0.0");
-        assert_eq!(account.lock().await.held().to_string(), "10.1");
+        assert_eq!(account.lock().await.available().to_string(), "0.01");
     }
 
     #[tokio::test]
-    async fn dispute_fail_with_tx_not_found() {
+    async fn withdrawal_fail_with_underflow() {
         let mut engine = Engine::new(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
         );
-
         let tx = Tx {
-            r#type: TxType::Dispute,
+            r#type: TxType::Deposit,
             client: 0,
             id: 0,
-            amount: None,
+            amount: Some(BigDecimal::from_str("10.1").unwrap()),
             disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
         };
-        let res = tx.handle(&mut engine).await;
-        assert_eq!(res, Err(Error::TxNotFound));
-    }
 
-    #[tokio::test]
-    async fn dispute_fail_with_invalid_dispute() {
-        let mut engine = Engine::new(
-            InMemoryAccountLedger::default(),
-            InMemoryTxLedger::default(),
-        );
+        tx.handle(&mut engine).await.unwrap();
+        let account = engine.account(0).await.unwrap();
+        assert_eq!(
+            account.lock().await.available().to_string(),
+            tx.amount().unwrap().to_string()
+        );
 
         let tx = Tx {
             r#type: TxType::Withdrawal,
             client: 0,
             id: 0,
+            amount: Some(BigDecimal::from_str("10.2").unwrap()),
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        let res = tx.handle(&mut engine).await;
+        assert_eq!(res, Err(Error::MinAvailableUnderflow));
+    }
+
+    #[tokio::test]
+    async fn dispute_success() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Deposit,
+            client: 0,
+            id: 0,
             amount: Some(BigDecimal::from_str("10.1").unwrap()),
             disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        tx.handle(&mut engine).await.unwrap();
+        TxsDal::insert(&engine, tx).await;
+
+        let account = engine.account(0).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "10.1");
+
+        let tx = Tx {
+            r#type: TxType::Dispute,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
         };
-        TxsDal::insert(&mut engine, tx).await;
+        tx.handle(&mut engine).await.unwrap();
+        let tx = engine.tx(0).await.unwrap();
+        assert!(tx.lock().await.disputed());
+        assert_eq!(account.lock().await.available().to_string(), "0.0");
+        assert_eq!(account.lock().await.held().to_string(), "10.1");
+    }
+
+    #[tokio::test]
+    async fn dispute_fail_with_tx_not_found() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
 
         let tx = Tx {
             r#type: TxType::Dispute,
@@ -471,239 +1600,1307 @@ mod tests {
             id: 0,
             amount: None,
             disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        let res = tx.handle(&mut engine).await;
+        assert_eq!(res, Err(Error::TxNotFound));
+    }
+
+    #[tokio::test]
+    async fn dispute_fail_with_invalid_dispute() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Withdrawal,
+            client: 0,
+            id: 0,
+            amount: Some(BigDecimal::from_str("10.1").unwrap()),
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        TxsDal::insert(&engine, tx).await;
+
+        let tx = Tx {
+            r#type: TxType::Dispute,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
         };
         let res = tx.handle(&mut engine).await;
         assert_eq!(res, Err(Error::InvalidDispute(0)));
     }
 
     #[tokio::test]
-    async fn dispute_fail_with_tx_already_disputed() {
-        let mut engine = Engine::new(
+    async fn dispute_fail_with_tx_already_disputed() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Deposit,
+            client: 0,
+            id: 0,
+            amount: Some(BigDecimal::from_str("10.1").unwrap()),
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        tx.handle(&mut engine).await.unwrap();
+        TxsDal::insert(&engine, tx).await;
+
+        let tx = Tx {
+            r#type: TxType::Dispute,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        tx.handle(&mut engine).await.unwrap();
+        let res = tx.handle(&mut engine).await;
+        assert_eq!(res, Err(Error::TxAlreadyDisputed(0)));
+    }
+
+    #[tokio::test]
+    async fn resolve_success() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Deposit,
+            client: 0,
+            id: 0,
+            amount: Some(BigDecimal::from_str("10.1").unwrap()),
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        tx.handle(&mut engine).await.unwrap();
+        TxsDal::insert(&engine, tx).await;
+
+        let mut tx = Tx {
+            r#type: TxType::Dispute,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        tx.handle(&mut engine).await.unwrap();
+        tx.r#type = TxType::Resolve;
+        tx.handle(&mut engine).await.unwrap();
+        assert!(!tx.disputed());
+
+        let account = engine.account(0).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "10.1");
+        assert_eq!(account.lock().await.held().to_string(), "0.0");
+    }
+
+    #[tokio::test]
+    async fn resolve_fail_tx_not_disputed() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Deposit,
+            client: 0,
+            id: 0,
+            amount: Some(BigDecimal::from_str("10.1").unwrap()),
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        TxsDal::insert(&engine, tx).await;
+
+        let tx = Tx {
+            r#type: TxType::Resolve,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        let res = tx.handle(&mut engine).await;
+        assert_eq!(res, Err(Error::TxNotDisputed(0)));
+    }
+
+    #[tokio::test]
+    async fn resolve_fail_with_tx_not_found() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Resolve,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        let res = tx.handle(&mut engine).await;
+        assert_eq!(res, Err(Error::TxNotFound));
+    }
+
+    #[tokio::test]
+    async fn chargeback_success() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Deposit,
+            client: 0,
+            id: 0,
+            amount: Some(BigDecimal::from_str("10.1").unwrap()),
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        tx.handle(&mut engine).await.unwrap();
+        TxsDal::insert(&engine, tx).await;
+
+        let mut tx = Tx {
+            r#type: TxType::Dispute,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        tx.handle(&mut engine).await.unwrap();
+
+        tx.r#type = TxType::Chargeback;
+        tx.handle(&mut engine).await.unwrap();
+
+        let account = engine.account(0).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "0.0");
+        assert_eq!(account.lock().await.held().to_string(), "0.0");
+        assert!(account.lock().await.is_locked());
+        assert_eq!(engine.clearing().balance().to_string(), "10.1");
+        assert_eq!(engine.clearing().client_balance(0).to_string(), "10.1");
+    }
+
+    #[tokio::test]
+    async fn chargeback_fail_tx_not_disputed() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Deposit,
+            client: 0,
+            id: 0,
+            amount: Some(BigDecimal::from_str("10.1").unwrap()),
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        TxsDal::insert(&engine, tx).await;
+
+        let tx = Tx {
+            r#type: TxType::Chargeback,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        let res = tx.handle(&mut engine).await;
+        assert_eq!(res, Err(Error::TxNotDisputed(0)));
+    }
+
+    #[tokio::test]
+    async fn chargeback_fail_with_tx_not_found() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let tx = Tx {
+            r#type: TxType::Chargeback,
+            client: 0,
+            id: 0,
+            amount: None,
+            disputed: false,
+            seq: None,
+            timestamp: None,
+            batch: None,
+            line: None,
+        };
+        let res = tx.handle(&mut engine).await;
+        assert_eq!(res, Err(Error::TxNotFound));
+    }
+
+    #[tokio::test]
+    async fn handle_txs() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let txs = r#"type , client,tx ,amount
+        deposit, 1, 1, 1.0
+        deposit, 2, 2, 2.0
+        deposit, 1, 3, 2.0
+        withdrawal, 1, 4, 1.5
+        withdrawal,2, 5, 3.0
+        dispute,1,1,"#;
+        engine
+            .handle_txs(tokio::io::BufReader::new(txs.as_bytes()))
+            .await
+            .unwrap();
+        assert_eq!(2, engine.accounts().await.len());
+        assert_eq!(
+            engine
+                .account(1)
+                .await
+                .unwrap()
+                .lock()
+                .await
+                .available()
+                .to_string(),
+            "0.5"
+        );
+        assert_eq!(
+            engine
+                .account(2)
+                .await
+                .unwrap()
+                .lock()
+                .await
+                .available()
+                .to_string(),
+            "2.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_success() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let mut shard = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,1.0".as_bytes(),
+            ))
+            .await
+            .unwrap();
+        shard
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,2,2,2.0\ndispute,2,2,".as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        engine.merge(shard).await.unwrap();
+        assert_eq!(2, engine.accounts().await.len());
+        assert_eq!(2, engine.txs().await.len());
+        let account = engine.account(2).await.unwrap();
+        assert_eq!(account.lock().await.held().to_string(), "2.0");
+        assert!(engine.tx(2).await.unwrap().lock().await.disputed());
+    }
+
+    #[tokio::test]
+    async fn merge_fail_with_account_conflict() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let mut shard = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,1.0".as_bytes(),
+            ))
+            .await
+            .unwrap();
+        shard
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,2,2.0".as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        let res = engine.merge(shard).await;
+        assert_eq!(res, Err(Error::AccountConflict(1)));
+        assert!(engine.tx(2).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn merge_fail_with_tx_conflict() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let mut shard = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,1.0".as_bytes(),
+            ))
+            .await
+            .unwrap();
+        shard
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,2,1,2.0".as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        let res = engine.merge(shard).await;
+        assert_eq!(res, Err(Error::TxConflict(1)));
+        assert!(engine.account(2).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn fork_does_not_affect_base() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\ndispute,1,1,".as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        let mut fork = engine.fork();
+        fork.handle_txs(tokio::io::BufReader::new(
+            "type,client,tx,amount\nchargeback,1,1,\ndeposit,3,3,3.0".as_bytes(),
+        ))
+        .await
+        .unwrap();
+
+        let forked = fork.account(1).await.unwrap();
+        assert!(forked.lock().await.is_locked());
+        assert_eq!(forked.lock().await.held().to_string(), "0.0");
+        assert!(!fork.tx(1).await.unwrap().lock().await.disputed());
+        assert_eq!(3, fork.accounts().await.len());
+
+        let base = engine.account(1).await.unwrap();
+        assert!(!base.lock().await.is_locked());
+        assert_eq!(base.lock().await.held().to_string(), "1.0");
+        assert!(engine.tx(1).await.unwrap().lock().await.disputed());
+        assert_eq!(2, engine.accounts().await.len());
+        assert_eq!(fork.clearing().balance().to_string(), "1.0");
+        assert_eq!(engine.clearing().balance().to_string(), "0");
+    }
+
+    #[tokio::test]
+    async fn client_txs_index() {
+        let engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        for (client, id) in [(1, 3), (2, 1), (1, 2)] {
+            TxsDal::insert(
+                &engine,
+                Tx::new(TxType::Deposit, client, id, Some(BigDecimal::from(1))),
+            )
+            .await;
+        }
+
+        let mut ids = Vec::new();
+        for tx in engine.client_txs(1).await {
+            ids.push(tx.lock().await.id());
+        }
+        assert_eq!(ids, vec![3, 2]);
+        assert!(engine.client_txs(3).await.is_empty());
+
+        let fork = engine.fork();
+        TxsDal::insert(
+            &fork,
+            Tx::new(TxType::Deposit, 1, 4, Some(BigDecimal::from(1))),
+        )
+        .await;
+        let mut ids = Vec::new();
+        for tx in fork.client_txs(1).await {
+            ids.push(tx.lock().await.id());
+        }
+        assert_eq!(ids, vec![3, 2, 4]);
+        assert_eq!(engine.client_txs(1).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn client_txs_index_follows_replaced_txs() {
+        let engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        for client in [1, 2] {
+            TxsDal::insert(
+                &engine,
+                Tx::new(TxType::Deposit, client, 1, Some(BigDecimal::from(1))),
+            )
+            .await;
+        }
+
+        assert!(engine.client_txs(1).await.is_empty());
+        assert_eq!(engine.client_txs(2).await.len(), 1);
+
+        // Removals keep the insertion order of the other transactions.
+        for id in [3, 4, 5] {
+            TxsDal::insert(&engine, Tx::new(TxType::Deposit, 2, id, None)).await;
+        }
+        TxsDal::remove(&engine, 4).await;
+        let mut ids = Vec::new();
+        for tx in engine.client_txs(2).await {
+            ids.push(tx.lock().await.id());
+        }
+        assert_eq!(ids, [1, 3, 5]);
+    }
+
+    #[tokio::test]
+    async fn handle_txs_records_latency() {
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .slow_tx_threshold(std::time::Duration::ZERO)
+        .build()
+        .unwrap();
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0".as_bytes(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(engine.latency().count(), 2);
+        assert!(engine.latency().percentile(99.0).is_some());
+    }
+
+    #[tokio::test]
+    async fn handle_txs_with_retention() {
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .retention(RetentionPolicy::new(Some(2), true))
+        .build()
+        .unwrap();
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\ndispute,2,2,\n\
+                withdrawal,2,3,1.0\ndeposit,3,4,1.0\ndeposit,3,5,1.0\nchargeback,2,2,"
+                    .as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        // Out of the window, a withdrawal and from a locked account, respectively.
+        assert!(engine.tx(1).await.is_none());
+        assert!(engine.tx(3).await.is_none());
+        assert!(engine.tx(2).await.is_none());
+        assert!(engine.tx(4).await.is_some());
+        assert_eq!(engine.retention().pruned(), 2);
+        let account = engine.account(2).await.unwrap();
+        assert!(account.lock().await.is_locked());
+    }
+
+    #[tokio::test]
+    async fn handle_txs_with_quota() {
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .quota(Quota::new(None, Some(BigDecimal::from(3))))
+        .build()
+        .unwrap();
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,2.0\nwithdrawal,1,3,1.0\ndeposit,1,4,1.0"
+                    .as_bytes(),
+            ))
+            .await
+            .unwrap();
+        assert!(engine.quota().exceeded());
+        assert_eq!(engine.quota().rejected_deposits(), 1);
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "2.0");
+        let account = engine.account(2).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "0");
+    }
+
+    #[derive(Default)]
+    struct CountingPlugin {
+        started: AtomicBool,
+        txs: AtomicU64,
+        rejected: AtomicU64,
+        stopped: AtomicBool,
+    }
+
+    impl Plugin for Arc<CountingPlugin> {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn on_startup(&self) {
+            self.started.store(true, Ordering::SeqCst);
+        }
+
+        // Rejects all withdrawals.
+        fn on_tx(&self, tx: &Tx) -> Result<(), Error> {
+            if tx.tx_type() == &TxType::Withdrawal {
+                return Err(Error::PluginRejected("counting".to_string()));
+            }
+            Ok(())
+        }
+
+        fn on_outcome(&self, _tx: &Tx, outcome: &TxOutcome) {
+            self.txs.fetch_add(1, Ordering::SeqCst);
+            if outcome.is_rejected() {
+                self.rejected.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn on_shutdown(&self) {
+            self.stopped.store(true, Ordering::SeqCst);
+        }
+
+        fn report(&self) -> Vec<(String, String)> {
+            vec![(
+                "rejected".to_string(),
+                self.rejected.load(Ordering::SeqCst).to_string(),
+            )]
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_txs_with_plugin() {
+        let plugin = Arc::new(CountingPlugin::default());
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(plugin.clone())
+        .build()
+        .unwrap();
+        assert!(plugin.started.load(Ordering::SeqCst));
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,1.0\ndispute,1,3,"
+                    .as_bytes(),
+            ))
+            .await
+            .unwrap();
+        engine.shutdown();
+
+        assert!(plugin.stopped.load(Ordering::SeqCst));
+        assert_eq!(plugin.txs.load(Ordering::SeqCst), 3);
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "2.0");
+        assert_eq!(
+            engine.plugin_reports(),
+            vec![("counting", vec![("rejected".to_string(), "2".to_string())])]
+        );
+    }
+
+    #[tokio::test]
+    async fn unlock_after_cooling_off() {
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .chargeback_cooling_off(2)
+        .build()
+        .unwrap();
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,\nchargeback,1,1,\n\
+                deposit,1,2,1.0\ndeposit,2,3,1.0\ndeposit,2,5,1.0\ndeposit,1,4,3.0\ndispute,1,4,\nchargeback,1,4,"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        // The deposit right after the chargeback was rejected, the later one unlocked the account.
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.total().to_string(), "0.0");
+        assert!(account.lock().await.is_locked());
+        assert_eq!(engine.lockouts().unlocks(), 1);
+        assert_eq!(engine.lockouts().repeat_offenders(), vec![(1, 2)]);
+
+        // Admins don't have to wait for the cooling-off period.
+        engine.unlock(1).await.unwrap();
+        assert!(!account.lock().await.is_locked());
+        assert_eq!(engine.unlock(1).await, Err(Error::AccountNotLocked(1)));
+        assert_eq!(engine.unlock(3).await, Err(Error::AccountNotLocked(3)));
+    }
+
+    #[tokio::test]
+    async fn handle_tx_timeout() {
+        let accounts = MockDal::default().with_latency(Duration::from_millis(200));
+        let mut engine = Engine::builder(accounts.clone(), MockDal::default())
+            .tx_timeout(Duration::from_millis(10))
+            .build()
+            .unwrap();
+
+        let deposit = Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(3)));
+        assert_eq!(
+            engine.handle_tx(deposit).await,
+            TxOutcome::RejectedBusinessRule(Error::Timeout(1))
+        );
+        assert_eq!(engine.rejected(), 1);
+        assert!(AccountsDal::accounts(&accounts).await.is_empty());
+        assert!(engine.tx(1).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn handle_source_until_cancelled() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let (sender, receiver) = tokio::sync::mpsc::channel(10);
+        sender
+            .send(Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(3))))
+            .await
+            .unwrap();
+        sender
+            .send(Tx::new(TxType::Withdrawal, 1, 2, Some(BigDecimal::from(5))))
+            .await
+            .unwrap();
+        // The source stays open, waiting for more transactions until processing gets cancelled.
+        let cancel = CancellationToken::new();
+        tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                cancel.cancel();
+            }
+        });
+
+        let report = engine
+            .handle_source_until(source::from_channel(receiver), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ProcessingReport {
+                rows: 2,
+                rejected: 1,
+                ignored: 0,
+                cancelled: true
+            }
+        );
+        drop(sender);
+    }
+
+    #[tokio::test]
+    async fn duplicate_tx_ids() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,1,100.0\nwithdrawal,1,1,1.0\n\
+                dispute,1,1,"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(engine.stats().failures.get("DuplicateTx"), Some(&2));
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "0.0");
+        assert_eq!(account.lock().await.held().to_string(), "5.0");
+    }
+
+    #[tokio::test]
+    async fn duplicate_tx_ids_after_pruning() {
+        let mut engine = Engine::builder(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
-        );
-
-        let tx = Tx {
-            r#type: TxType::Deposit,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.1").unwrap()),
-            disputed: false,
-        };
-        tx.handle(&mut engine).await.unwrap();
-        TxsDal::insert(&mut engine, tx).await;
+        )
+        .retention(RetentionPolicy::new(Some(1), true))
+        .build()
+        .unwrap();
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.0\ndeposit,1,3,1.0\n\
+                deposit,1,4,1.0\ndeposit,1,1,100.0\nwithdrawal,1,2,1.0"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
 
-        let tx = Tx {
-            r#type: TxType::Dispute,
-            client: 0,
-            id: 0,
-            amount: None,
-            disputed: false,
-        };
-        tx.handle(&mut engine).await.unwrap();
-        let res = tx.handle(&mut engine).await;
-        assert_eq!(res, Err(Error::TxAlreadyDisputed(0)));
+        // Neither the pruned deposit nor the withdrawal, which was never stored, can be reused.
+        assert!(engine.tx(1).await.is_none());
+        assert_eq!(engine.stats().failures.get("DuplicateTx"), Some(&2));
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "6.0");
     }
 
     #[tokio::test]
-    async fn resolve_success() {
+    async fn disputes_of_another_client() {
         let mut engine = Engine::new(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
         );
+        engine
+            .handle_txs("type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,10.0".as_bytes())
+            .await
+            .unwrap();
 
-        let tx = Tx {
-            r#type: TxType::Deposit,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.1").unwrap()),
-            disputed: false,
-        };
-        tx.handle(&mut engine).await.unwrap();
-        TxsDal::insert(&mut engine, tx).await;
-
-        let mut tx = Tx {
-            r#type: TxType::Dispute,
-            client: 0,
-            id: 0,
-            amount: None,
-            disputed: false,
-        };
-        tx.handle(&mut engine).await.unwrap();
-        tx.r#type = TxType::Resolve;
-        tx.handle(&mut engine).await.unwrap();
-        assert!(!tx.disputed());
-
-        let account = engine.account(0).await.unwrap();
-        assert_eq!(account.lock().await.available().to_string(), "10.1");
-        assert_eq!(account.lock().await.held().to_string(), "0.0");
+        for (r#type, client) in [
+            (TxType::Dispute, 2),
+            (TxType::Dispute, 1),
+            (TxType::Resolve, 2),
+            (TxType::Chargeback, 2),
+        ] {
+            let outcome = engine.handle_tx(Tx::new(r#type, client, 1, None)).await;
+            if client == 2 {
+                assert_eq!(
+                    outcome,
+                    TxOutcome::RejectedBusinessRule(Error::ClientMismatch(1))
+                );
+            }
+        }
+        let account = engine.account(2).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "10.0");
+        assert!(!account.lock().await.is_locked());
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.held().to_string(), "5.0");
     }
 
     #[tokio::test]
-    async fn resolve_fail_tx_not_disputed() {
+    async fn withdrawal_disputes() {
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .dispute_policy(DisputePolicy::DepositsAndWithdrawals)
+        .build()
+        .unwrap();
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\nwithdrawal,1,3,1.0\n\
+                dispute,1,2,\ndispute,1,3,\nresolve,1,3,"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "5.0");
+        assert_eq!(account.lock().await.held().to_string(), "4.0");
+
+        let outcome = engine
+            .handle_tx(Tx::new(TxType::Chargeback, 1, 2, None))
+            .await;
+        assert!(outcome.is_applied());
+        let account = account.lock().await;
+        assert_eq!(account.available().to_string(), "9.0");
+        assert_eq!(account.held().to_string(), "0.0");
+        assert!(account.is_locked());
+        assert_eq!(engine.clearing().client_balance(1).to_string(), "-4.0");
+
+        // Withdrawals can't be disputed by default.
         let mut engine = Engine::new(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
         );
+        engine
+            .handle_txs("type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0".as_bytes())
+            .await
+            .unwrap();
+        let outcome = engine.handle_tx(Tx::new(TxType::Dispute, 1, 2, None)).await;
+        assert_eq!(
+            outcome,
+            TxOutcome::RejectedBusinessRule(Error::InvalidDispute(2))
+        );
+    }
 
-        let tx = Tx {
-            r#type: TxType::Deposit,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.1").unwrap()),
-            disputed: false,
-        };
-        TxsDal::insert(&mut engine, tx).await;
-
-        let tx = Tx {
-            r#type: TxType::Resolve,
-            client: 0,
-            id: 0,
-            amount: None,
-            disputed: false,
-        };
-        let res = tx.handle(&mut engine).await;
-        assert_eq!(res, Err(Error::TxNotDisputed(0)));
+    #[test]
+    fn withdrawal_disputes_need_stored_withdrawals() {
+        let res = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .retention(RetentionPolicy::new(None, true))
+        .dispute_policy(DisputePolicy::DepositsAndWithdrawals)
+        .build();
+        assert!(matches!(res, Err(Error::InvalidConfig(_))));
     }
 
     #[tokio::test]
-    async fn resolve_fail_with_tx_not_found() {
+    async fn non_positive_amounts() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,-5.0\ndeposit,1,3,0\n\
+            withdrawal,1,4,-1.0\nwithdrawal,1,5,0.0\nwithdrawal,1,6,2.5";
         let mut engine = Engine::new(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
         );
+        let report = engine.handle_txs(input.as_bytes()).await.unwrap();
+        assert_eq!((report.rows, report.rejected), (6, 4));
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "7.5");
 
-        let tx = Tx {
-            r#type: TxType::Resolve,
-            client: 0,
-            id: 0,
-            amount: None,
-            disputed: false,
-        };
-        let res = tx.handle(&mut engine).await;
-        assert_eq!(res, Err(Error::TxNotFound));
+        let records: Vec<_> = source::from_csv_fast(input.as_bytes()).collect().await;
+        assert_eq!(
+            records[1].as_ref().err(),
+            Some(&Error::NonPositiveAmount(2))
+        );
+        assert_eq!(
+            records[4].as_ref().err(),
+            Some(&Error::NonPositiveAmount(5))
+        );
+        assert!(Tx::new(TxType::Dispute, 1, 1, None).validate().is_ok());
+
+        let amount = BigDecimal::from_str("-1.0").unwrap();
+        let outcome = engine
+            .handle_tx(Tx::new(TxType::Withdrawal, 1, 7, Some(amount)))
+            .await;
+        assert_eq!(
+            outcome,
+            TxOutcome::RejectedBusinessRule(Error::NonPositiveAmount(7))
+        );
+        assert_eq!(account.lock().await.available().to_string(), "7.5");
     }
 
     #[tokio::test]
-    async fn chargeback_success() {
+    async fn strict_policy_stops_at_first_rejection() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,5.0\ndeposit,1,3,1.0";
         let mut engine = Engine::new(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
         );
+        let report = engine.handle_txs(input.as_bytes()).await.unwrap();
+        assert_eq!((report.rows, report.rejected), (3, 1));
 
-        let tx = Tx {
-            r#type: TxType::Deposit,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.1").unwrap()),
-            disputed: false,
-        };
-        tx.handle(&mut engine).await.unwrap();
-        TxsDal::insert(&mut engine, tx).await;
-
-        let mut tx = Tx {
-            r#type: TxType::Dispute,
-            client: 0,
-            id: 0,
-            amount: None,
-            disputed: false,
-        };
-        tx.handle(&mut engine).await.unwrap();
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .error_policy(ErrorPolicy::Strict)
+        .build()
+        .unwrap();
+        let err = engine.handle_txs(input.as_bytes()).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RowError>(),
+            Some(&RowError {
+                row: 2,
+                error: Error::MinAvailableUnderflow
+            })
+        );
+        assert!(engine.tx(3).await.is_none());
+    }
 
-        tx.r#type = TxType::Chargeback;
-        tx.handle(&mut engine).await.unwrap();
+    #[tokio::test]
+    async fn yields_every_budget() {
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .yield_every(2)
+        .build()
+        .unwrap();
+        // Counts how many times the engine let other tasks of the (single threaded) runtime run.
+        let ticks = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    ticks.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
 
-        let account = engine.account(0).await.unwrap();
-        assert_eq!(account.lock().await.available().to_string(), "0.0");
-        assert_eq!(account.lock().await.held().to_string(), "0.0");
-        assert!(account.lock().await.is_locked());
+        let txs = (1..=20).map(|id| Tx::new(TxType::Deposit, 1, id, Some(BigDecimal::from(1))));
+        let report = engine.handle_source(source::from_iter(txs)).await.unwrap();
+        assert_eq!(report.rows, 20);
+        assert!(ticks.load(std::sync::atomic::Ordering::Relaxed) >= 10);
+        ticker.abort();
     }
 
     #[tokio::test]
-    async fn chargeback_fail_tx_not_disputed() {
+    async fn handle_txs_stream() {
         let mut engine = Engine::new(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
         );
-
-        let tx = Tx {
-            r#type: TxType::Deposit,
-            client: 0,
-            id: 0,
-            amount: Some(BigDecimal::from_str("10.1").unwrap()),
-            disputed: false,
-        };
-        TxsDal::insert(&mut engine, tx).await;
-
-        let tx = Tx {
-            r#type: TxType::Chargeback,
-            client: 0,
-            id: 0,
-            amount: None,
-            disputed: false,
-        };
-        let res = tx.handle(&mut engine).await;
-        assert_eq!(res, Err(Error::TxNotDisputed(0)));
+        let outcomes: Vec<_> = engine
+            .handle_txs_stream(
+                "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,5.0\nrefund,1,3,1.0\n\
+                dispute,1,1,"
+                    .as_bytes(),
+            )
+            .map(|(record, outcome)| (record.map(|tx| tx.id()).ok(), outcome))
+            .collect()
+            .await;
+
+        assert_eq!(outcomes.len(), 4);
+        assert_eq!(outcomes[0], (Some(1), TxOutcome::Applied));
+        assert_eq!(
+            outcomes[1],
+            (
+                Some(2),
+                TxOutcome::RejectedBusinessRule(Error::MinAvailableUnderflow)
+            )
+        );
+        assert!(outcomes[2].0.is_none() && outcomes[2].1.is_rejected());
+        assert_eq!(outcomes[3], (Some(1), TxOutcome::Applied));
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.held().to_string(), "2.0");
     }
 
     #[tokio::test]
-    async fn chargeback_fail_with_tx_not_found() {
-        let mut engine = Engine::new(
+    async fn handle_txs_with_review() {
+        let mut engine = Engine::builder(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
+        )
+        .plugin(AmountChecks::new(None, Some(10)).review_flagged())
+        .build()
+        .unwrap();
+
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,2,1\ndeposit,1,3,1\ndeposit,1,4,100\nwithdrawal,1,5,2\ndeposit,2,6,1\ndispute,1,4,"
+                    .as_bytes(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            engine.reviews().clients(),
+            vec![(1, "suspicious amount of tx 4".to_string())]
         );
+        let queued: Vec<u32> = engine.reviews().queued(1).iter().map(Tx::id).collect();
+        assert_eq!(queued, vec![5, 4]);
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "103");
+        assert!(engine.account(2).await.is_some());
 
-        let tx = Tx {
-            r#type: TxType::Chargeback,
-            client: 0,
-            id: 0,
-            amount: None,
-            disputed: false,
-        };
-        let res = tx.handle(&mut engine).await;
-        assert_eq!(res, Err(Error::TxNotFound));
+        let outcomes = engine.release(1).await;
+        assert_eq!(
+            outcomes,
+            vec![(5, TxOutcome::Applied), (4, TxOutcome::Applied)]
+        );
+        assert!(engine.reviews().clients().is_empty());
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "1");
+        assert_eq!(account.lock().await.held().to_string(), "100");
     }
 
     #[tokio::test]
-    async fn handle_txs() {
-        let mut engine = Engine::new(
+    async fn pending_until_approved() {
+        let mut engine = Engine::builder(
             InMemoryAccountLedger::default(),
             InMemoryTxLedger::default(),
-        );
+        )
+        .plugin(Quarantine::new(BigDecimal::from(100)))
+        .pending_ttl(60)
+        .build()
+        .unwrap();
 
-        let txs = r#"type , client,tx ,amount
-        deposit, 1, 1, 1.0
-        deposit, 2, 2, 2.0
-        deposit, 1, 3, 2.0
-        withdrawal, 1, 4, 1.5
-        withdrawal,2, 5, 3.0
-        dispute,1,1,"#;
         engine
-            .handle_txs(tokio::io::BufReader::new(txs.as_bytes()))
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,500\ndeposit,1,2,100\ndeposit,2,3,200\n\
+                withdrawal,1,4,50\ndispute,1,1,"
+                    .as_bytes(),
+            )
             .await
             .unwrap();
-        assert_eq!(2, engine.accounts().await.len());
+        engine
+            .park(
+                Tx::new(TxType::Deposit, 3, 5, None),
+                "scheduled".to_string(),
+            )
+            .await;
+        let pending: Vec<(u32, String)> = engine
+            .pending()
+            .pending()
+            .await
+            .into_iter()
+            .map(|pending| (pending.tx.id(), pending.reason))
+            .collect();
         assert_eq!(
-            engine
-                .account(1)
-                .await
-                .unwrap()
-                .lock()
-                .await
-                .available()
-                .to_string(),
-            "0.5"
+            pending,
+            vec![
+                (1, "amount above 100".to_string()),
+                (3, "amount above 100".to_string()),
+                (5, "scheduled".to_string())
+            ]
         );
-        assert_eq!(
-            engine
-                .account(2)
-                .await
-                .unwrap()
-                .lock()
-                .await
-                .available()
-                .to_string(),
-            "2.0"
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available().to_string(), "50");
+
+        // Pending transactions survive a snapshot.
+        let mut restored = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        restored.restore(&engine.snapshot().await).await.unwrap();
+        assert_eq!(restored.pending().pending().await.len(), 3);
+
+        assert_eq!(engine.approve_pending(1).await, Ok(TxOutcome::Applied));
+        assert_eq!(account.lock().await.available().to_string(), "550");
+        assert_eq!(engine.reject_pending(3).await.unwrap().tx.id(), 3);
+        assert!(engine.account(2).await.is_none());
+        assert_eq!(engine.approve_pending(3).await, Err(Error::NotPending(3)));
+
+        // The scheduled deposit expires once its time to live is over.
+        assert!(engine.expire_pending(now()).await.is_empty());
+        let expired = engine.expire_pending(now() + 60).await;
+        assert_eq!(expired[0].tx.id(), 5);
+        assert!(engine.pending().pending().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn handle_batch() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
         );
+        let txs = vec![
+            Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(5))),
+            Tx::new(TxType::Deposit, 2, 2, Some(BigDecimal::from(3))),
+            Tx::new(TxType::Withdrawal, 1, 3, Some(BigDecimal::from(2))),
+            Tx::new(TxType::Dispute, 2, 2, None),
+            Tx::new(TxType::Withdrawal, 2, 4, Some(BigDecimal::from(1))),
+            Tx::new(TxType::Dispute, 1, 1, None),
+        ];
+
+        engine.handle_batch(&txs).await;
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(3));
+        let account = engine.account(2).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(0));
+        assert_eq!(account.lock().await.held(), BigDecimal::from(3));
+        assert_eq!(engine.latency().count(), 6);
+    }
+
+    #[derive(Default, Clone)]
+    struct PrefetchRecorder {
+        inner: InMemoryAccountLedger,
+        prefetched: Arc<std::sync::Mutex<Vec<u16>>>,
+    }
+
+    impl AccountsDal for PrefetchRecorder {
+        async fn account(&self, id: u16) -> Option<Arc<tokio::sync::Mutex<Account>>> {
+            self.inner.account(id).await
+        }
+
+        async fn insert(&mut self, account: Account) {
+            self.inner.insert(account).await
+        }
+
+        async fn accounts(
+            &self,
+        ) -> tokio::sync::RwLockReadGuard<'_, HashMap<u16, Arc<tokio::sync::Mutex<Account>>>>
+        {
+            self.inner.accounts().await
+        }
+
+        async fn prefetch(&self, ids: &[u16]) {
+            self.prefetched.lock().unwrap().extend_from_slice(ids);
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_batch_prefetches_accounts() {
+        let accounts = PrefetchRecorder::default();
+        let mut engine = Engine::new(accounts.clone(), InMemoryTxLedger::default());
+        let txs = vec![
+            Tx::new(TxType::Deposit, 2, 1, Some(BigDecimal::from(5))),
+            Tx::new(TxType::Deposit, 1, 2, Some(BigDecimal::from(3))),
+            Tx::new(TxType::Dispute, 2, 1, None),
+        ];
+
+        engine.handle_batch(&txs).await;
+        assert_eq!(*accounts.prefetched.lock().unwrap(), vec![2, 1]);
+    }
+}
+
+// Model checks of the locking protocol (the account is always locked before the transaction)
+// over every interleaving of racing disputes, resolves, chargebacks and withdrawals.
+#[cfg(all(test, payments_loom))]
+mod loom_tests {
+    use bigdecimal::BigDecimal;
+    use loom::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use crate::account::Account;
+
+    use super::{charge_back, dispute, resolve, DisputePolicy, Tx, TxType};
+
+    fn ledgers(disputed: bool) -> (Arc<Mutex<Account>>, Arc<Mutex<Tx>>) {
+        let mut deposit = Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(5)));
+        let (available, held) = if disputed {
+            deposit.mark_disputed();
+            (BigDecimal::from(0), BigDecimal::from(5))
+        } else {
+            (BigDecimal::from(5), BigDecimal::from(0))
+        };
+        (
+            Arc::new(Mutex::new(Account::new(1, available, held, false))),
+            Arc::new(Mutex::new(deposit)),
+        )
+    }
+
+    #[test]
+    fn concurrent_disputes() {
+        loom::model(|| {
+            let (account, tx) = ledgers(false);
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let (account, tx) = (account.clone(), tx.clone());
+                    thread::spawn(move || {
+                        let mut account = account.lock().unwrap();
+                        dispute(
+                            &mut account,
+                            &mut tx.lock().unwrap(),
+                            DisputePolicy::default(),
+                        )
+                        .is_ok()
+                    })
+                })
+                .collect();
+            let disputed: Vec<bool> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+            assert_eq!(disputed.iter().filter(|ok| **ok).count(), 1);
+            let account = account.lock().unwrap();
+            assert_eq!(account.held(), BigDecimal::from(5));
+            assert_eq!(account.available(), BigDecimal::from(0));
+        });
+    }
+
+    #[test]
+    fn dispute_races_withdrawal() {
+        loom::model(|| {
+            let (account, tx) = ledgers(false);
+            let disputing = {
+                let (account, tx) = (account.clone(), tx.clone());
+                thread::spawn(move || {
+                    let mut account = account.lock().unwrap();
+                    dispute(
+                        &mut account,
+                        &mut tx.lock().unwrap(),
+                        DisputePolicy::default(),
+                    )
+                    .is_ok()
+                })
+            };
+            let withdrawing = {
+                let account = account.clone();
+                thread::spawn(move || {
+                    let mut account = account.lock().unwrap();
+                    account.sub_available(&BigDecimal::from(3)).is_ok()
+                })
+            };
+            let disputed = disputing.join().unwrap();
+            let withdrawn = withdrawing.join().unwrap();
+
+            // The funds can't be both held and withdrawn.
+            assert!(disputed != withdrawn);
+            let account = account.lock().unwrap();
+            assert_eq!(tx.lock().unwrap().disputed(), disputed);
+            let withdrawn = if withdrawn { 3 } else { 0 };
+            assert_eq!(
+                account.total() + BigDecimal::from(withdrawn),
+                BigDecimal::from(5)
+            );
+        });
+    }
+
+    #[test]
+    fn resolve_races_chargeback() {
+        loom::model(|| {
+            let (account, tx) = ledgers(true);
+            let resolving = {
+                let (account, tx) = (account.clone(), tx.clone());
+                thread::spawn(move || {
+                    let mut account = account.lock().unwrap();
+                    resolve(&mut account, &mut tx.lock().unwrap()).is_ok()
+                })
+            };
+            let charging_back = {
+                let (account, tx) = (account.clone(), tx.clone());
+                thread::spawn(move || {
+                    let mut account = account.lock().unwrap();
+                    charge_back(&mut account, &mut tx.lock().unwrap()).is_ok()
+                })
+            };
+            let resolved = resolving.join().unwrap();
+            let charged_back = charging_back.join().unwrap();
+
+            assert!(resolved != charged_back);
+            let account = account.lock().unwrap();
+            assert_eq!(account.is_locked(), charged_back);
+            assert_eq!(account.held(), BigDecimal::from(0));
+            assert!(!tx.lock().unwrap().disputed());
+        });
     }
 }
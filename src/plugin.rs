@@ -0,0 +1,52 @@
+use crate::{account::Account, error::Error, outcome::TxOutcome, payments::Tx};
+
+// Extension point for features which hook into the engine lifecycle (fraud checks, webhooks,
+// metrics, ...), registered through `EngineBuilder::plugin`. Plugins are shared between threads,
+// so any state they keep needs interior mutability.
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Called once, when the engine gets built.
+    fn on_startup(&self) {}
+
+    // Called before a transaction is handled, to rewrite or annotate it (e.g. normalizing amounts,
+    // mapping external ids or defaulting missing fields), each plugin seeing the transaction as
+    // enriched by the ones registered before it. Transactions held back while their client is
+    // under review are enriched once released. Returning an error rejects the transaction.
+    fn enrich(&self, _tx: &mut Tx) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Called before a transaction is handled, and before it gets enriched. Returning a reason parks
+    // it in the engine's pending store, neither applied nor rejected until approved (see
+    // `Engine::approve_pending`).
+    fn screen(&self, _tx: &Tx) -> Option<String> {
+        None
+    }
+
+    // Called before a transaction is handled. Returning an error rejects the transaction.
+    fn on_tx(&self, _tx: &Tx) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Called after a transaction was successfully applied, with the resulting state of its
+    // client's account.
+    fn on_account(&self, _tx: &Tx, _account: &Account) {}
+
+    // Called after a transaction was successfully applied. Returning a reason places its client
+    // under review, queueing the client's later transactions until it gets released.
+    fn review(&self, _tx: &Tx) -> Option<String> {
+        None
+    }
+
+    // Called after a transaction was handled with its outcome: applied, rejected or ignored.
+    fn on_outcome(&self, _tx: &Tx, _outcome: &TxOutcome) {}
+
+    // Called once, when the engine is shut down.
+    fn on_shutdown(&self) {}
+
+    // Key/value entries the plugin contributes to the end of run report.
+    fn report(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
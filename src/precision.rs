@@ -0,0 +1,107 @@
+use bigdecimal::{BigDecimal, RoundingMode};
+use futures::StreamExt;
+
+use crate::{error::Error, payments::Tx, source::TxSource};
+
+// Decimal places amounts are supported with.
+pub const MAX_DECIMAL_PLACES: i64 = 4;
+
+// How amounts with more than `MAX_DECIMAL_PLACES` decimal places are handled on ingestion, rather
+// than carrying arbitrary precision into the ledgers. Trailing zeros don't count.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PrecisionPolicy {
+    // The transaction is rejected with `Error::PrecisionExceeded`.
+    #[default]
+    Reject,
+    // The amount is rounded to `MAX_DECIMAL_PLACES` decimal places.
+    Round(RoundingMode),
+}
+
+impl PrecisionPolicy {
+    pub fn apply(&self, mut tx: Tx) -> Result<Tx, Error> {
+        let Some(amount) = tx.amount() else {
+            return Ok(tx);
+        };
+        if amount.with_scale(MAX_DECIMAL_PLACES) == *amount {
+            return Ok(tx);
+        }
+        match self {
+            PrecisionPolicy::Reject => Err(Error::PrecisionExceeded(tx.id())),
+            PrecisionPolicy::Round(mode) => {
+                let rounded: BigDecimal = amount.with_scale_round(MAX_DECIMAL_PLACES, *mode);
+                tx.set_amount(Some(rounded));
+                // Amounts too small to be represented round to zero.
+                tx.validate()
+            }
+        }
+    }
+}
+
+// Applies the precision policy on the transactions of `source`.
+pub fn precise<'s>(source: impl TxSource + 's, policy: PrecisionPolicy) -> impl TxSource + 's {
+    source
+        .map(move |record| record.and_then(|tx| policy.apply(tx)))
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::{BigDecimal, RoundingMode};
+    use futures::StreamExt;
+
+    use crate::{
+        error::Error,
+        payments::{Tx, TxType},
+        source,
+    };
+
+    use super::{precise, PrecisionPolicy};
+
+    #[tokio::test]
+    async fn excess_precision() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.2345\ndeposit,1,2,1.23450000\n\
+            deposit,1,3,1.23456\ndispute,1,1,";
+        let records: Vec<_> = precise(
+            source::from_csv(input.as_bytes()),
+            PrecisionPolicy::default(),
+        )
+        .collect()
+        .await;
+        assert!(records[0].is_ok() && records[1].is_ok() && records[3].is_ok());
+        assert_eq!(
+            records[2].as_ref().err(),
+            Some(&Error::PrecisionExceeded(3))
+        );
+
+        let round = |amount: &str, mode| {
+            let tx = Tx::new(
+                TxType::Deposit,
+                1,
+                1,
+                Some(BigDecimal::from_str(amount).unwrap()),
+            );
+            let tx = PrecisionPolicy::Round(mode).apply(tx).unwrap();
+            tx.amount().unwrap().to_string()
+        };
+        assert_eq!(round("1.23455", RoundingMode::HalfEven), "1.2346");
+        assert_eq!(round("1.23445", RoundingMode::HalfEven), "1.2344");
+        assert_eq!(round("1.23449", RoundingMode::Down), "1.2344");
+        assert_eq!(round("1.5", RoundingMode::Down), "1.5");
+
+        let tiny = Tx::new(
+            TxType::Withdrawal,
+            1,
+            4,
+            Some(BigDecimal::from_str("0.00001").unwrap()),
+        );
+        assert_eq!(
+            PrecisionPolicy::Round(RoundingMode::HalfEven)
+                .apply(tiny)
+                .as_ref()
+                .err(),
+            Some(&Error::NonPositiveAmount(4))
+        );
+    }
+}
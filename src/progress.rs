@@ -0,0 +1,137 @@
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{outcome::TxOutcome, payments::Tx, plugin::Plugin};
+
+// Contents of the progress file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Checkpoint {
+    pub rows: u64,
+    pub rejected: u64,
+    pub last_tx: Option<u32>,
+    // Seconds since the epoch of the last update.
+    pub updated_at: u64,
+    pub finished: bool,
+}
+
+// Plugin periodically writing a JSON checkpoint of the run (rows handled, last tx id and when it
+// was written) so that external schedulers can detect stuck runs and find where to resume from.
+// The file gets written at most once per `interval` while processing, and once more on shutdown.
+pub struct Progress {
+    path: PathBuf,
+    interval: Duration,
+    state: Mutex<(Checkpoint, Option<Instant>)>,
+}
+
+impl Progress {
+    pub fn new(path: PathBuf, interval: Duration) -> Self {
+        Progress {
+            path,
+            interval,
+            state: Mutex::new((Checkpoint::default(), None)),
+        }
+    }
+
+    // Replaces the file atomically, so readers never see a partially written checkpoint.
+    fn write(&self, checkpoint: &mut Checkpoint) {
+        checkpoint.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let res = serde_json::to_vec(checkpoint)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| std::fs::write(&tmp, contents))
+            .and_then(|()| std::fs::rename(&tmp, &self.path));
+        if let Err(err) = res {
+            warn!(
+                "Error while writing the progress file {:?}: {err}",
+                self.path
+            );
+        }
+    }
+}
+
+impl Plugin for Progress {
+    fn name(&self) -> &str {
+        "progress"
+    }
+
+    fn on_startup(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.write(&mut state.0);
+        state.1 = Some(Instant::now());
+    }
+
+    fn on_outcome(&self, tx: &Tx, outcome: &TxOutcome) {
+        let mut state = self.state.lock().unwrap();
+        let (checkpoint, written) = &mut *state;
+        checkpoint.rows += 1;
+        if outcome.is_rejected() {
+            checkpoint.rejected += 1;
+        }
+        checkpoint.last_tx = Some(tx.id());
+        if written.is_none_or(|at| at.elapsed() >= self.interval) {
+            self.write(checkpoint);
+            *written = Some(Instant::now());
+        }
+    }
+
+    fn on_shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0.finished = true;
+        self.write(&mut state.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{Checkpoint, Progress};
+
+    fn read(path: &std::path::Path) -> Checkpoint {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn checkpoints_progress() {
+        let path = std::env::temp_dir().join("payments-engine-progress.json");
+        let _ = std::fs::remove_file(&path);
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(Progress::new(path.clone(), Duration::from_secs(3600)))
+        .build()
+        .unwrap();
+        assert_eq!(read(&path).rows, 0);
+
+        engine
+            .handle_txs("type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,5.0".as_bytes())
+            .await
+            .unwrap();
+        // Not due yet.
+        assert_eq!(read(&path).rows, 0);
+
+        engine.shutdown();
+        let checkpoint = read(&path);
+        assert_eq!(
+            (checkpoint.rows, checkpoint.rejected, checkpoint.last_tx),
+            (2, 1, Some(2))
+        );
+        assert!(checkpoint.finished && checkpoint.updated_at > 0);
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    fmt::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::fs;
+
+use crate::{
+    payments::Engine,
+    storage::{AccountsDal, TxsDal},
+};
+
+const PREFIX: &str = "payments_engine";
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: Vec<(String, String)>) {
+    let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {PREFIX}_{name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{PREFIX}_{name}{labels} {value}");
+    }
+}
+
+fn unlabeled(value: impl ToString) -> Vec<(String, String)> {
+    vec![(String::new(), value.to_string())]
+}
+
+// Renders the metrics of a finished run in the Prometheus text exposition format.
+pub async fn render<A, T>(engine: &Engine<A, T>) -> String
+where
+    A: AccountsDal + Send + Sync + Clone,
+    T: TxsDal + Send + Sync + Clone,
+{
+    let mut accounts = 0;
+    let mut locked = 0;
+    for account in engine.accounts().await.values() {
+        accounts += 1;
+        if account.lock().await.is_locked() {
+            locked += 1;
+        }
+    }
+    let latency = engine.latency();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "txs_total",
+        "counter",
+        "Transactions handled.",
+        unlabeled(latency.count()),
+    );
+    metric(
+        &mut out,
+        "txs_rejected_total",
+        "counter",
+        "Transactions rejected.",
+        unlabeled(engine.rejected()),
+    );
+    let mut samples: Vec<(String, String)> = [("0.5", 50.0), ("0.95", 95.0), ("0.99", 99.0)]
+        .iter()
+        .filter_map(|&(quantile, p)| {
+            let value = latency.percentile(p)?.as_secs_f64();
+            Some((format!("{{quantile=\"{quantile}\"}}"), value.to_string()))
+        })
+        .collect();
+    samples.push(("_count".to_string(), latency.count().to_string()));
+    metric(
+        &mut out,
+        "tx_latency_seconds",
+        "summary",
+        "Transaction handling latency.",
+        samples,
+    );
+    metric(
+        &mut out,
+        "accounts",
+        "gauge",
+        "Client accounts.",
+        unlabeled(accounts),
+    );
+    metric(
+        &mut out,
+        "locked_accounts",
+        "gauge",
+        "Locked client accounts.",
+        unlabeled(locked),
+    );
+    metric(
+        &mut out,
+        "clearing_balance",
+        "gauge",
+        "Funds removed from client accounts by chargebacks.",
+        unlabeled(engine.clearing().balance()),
+    );
+    metric(
+        &mut out,
+        "quota_rejected_deposits_total",
+        "counter",
+        "Deposits rejected once the quota was exceeded.",
+        unlabeled(engine.quota().rejected_deposits()),
+    );
+    metric(
+        &mut out,
+        "pruned_txs_total",
+        "counter",
+        "Transactions dropped from the ledger.",
+        unlabeled(engine.retention().pruned()),
+    );
+    // Only the numeric entries of the plugin reports can be exported.
+    let samples: Vec<(String, String)> = engine
+        .plugin_reports()
+        .into_iter()
+        .flat_map(|(plugin, entries)| {
+            entries
+                .into_iter()
+                .filter(|(_, value)| value.parse::<f64>().is_ok())
+                .map(move |(key, value)| (format!("{{plugin=\"{plugin}\",key=\"{key}\"}}"), value))
+        })
+        .collect();
+    if !samples.is_empty() {
+        metric(
+            &mut out,
+            "plugin_report",
+            "gauge",
+            "Numeric entries of the plugin reports.",
+            samples,
+        );
+    }
+    metric(
+        &mut out,
+        "last_run_timestamp_seconds",
+        "gauge",
+        "When the run finished.",
+        unlabeled(timestamp),
+    );
+    out
+}
+
+// Writes the metrics of a finished run for the node_exporter textfile collector. The file is
+// replaced atomically, so the collector never reads a partially written one.
+pub async fn write_textfile<A, T>(path: &Path, engine: &Engine<A, T>) -> anyhow::Result<()>
+where
+    A: AccountsDal + Send + Sync + Clone,
+    T: TxsDal + Send + Sync + Clone,
+{
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, render(engine).await).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        amounts::AmountChecks,
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::render;
+
+    #[tokio::test]
+    async fn render_run_metrics() {
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(AmountChecks::new(None, None))
+        .build()
+        .unwrap();
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0\ndispute,1,1,\nchargeback,1,1,\nwithdrawal,2,3,5"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let metrics = render(&engine).await;
+        for line in [
+            "# TYPE payments_engine_txs_total counter",
+            "payments_engine_txs_total 5",
+            "payments_engine_txs_rejected_total 1",
+            "payments_engine_tx_latency_seconds_count 5",
+            "payments_engine_accounts 2",
+            "payments_engine_locked_accounts 1",
+            "payments_engine_clearing_balance 2.0",
+            "payments_engine_plugin_report{plugin=\"amounts\",key=\"flagged\"} 0",
+        ] {
+            assert!(metrics.lines().any(|metric| metric == line), "{}", line);
+        }
+        assert!(metrics.contains("payments_engine_tx_latency_seconds{quantile=\"0.99\"}"));
+    }
+}
@@ -0,0 +1,35 @@
+use bigdecimal::BigDecimal;
+
+use crate::{
+    payments::{Tx, TxType},
+    plugin::Plugin,
+};
+
+// Plugin parking the deposits and withdrawals above a threshold in the engine's pending store,
+// instead of applying them, until an admin approves or rejects them (see `Engine::approve_pending`
+// and `Engine::reject_pending`), while the rest of the stream keeps flowing.
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    threshold: BigDecimal,
+}
+
+impl Quarantine {
+    pub fn new(threshold: BigDecimal) -> Self {
+        Quarantine { threshold }
+    }
+}
+
+impl Plugin for Quarantine {
+    fn name(&self) -> &str {
+        "quarantine"
+    }
+
+    fn screen(&self, tx: &Tx) -> Option<String> {
+        match (tx.tx_type(), tx.amount()) {
+            (TxType::Deposit | TxType::Withdrawal, Some(amount)) if *amount > self.threshold => {
+                Some(format!("amount above {}", self.threshold))
+            }
+            _ => None,
+        }
+    }
+}
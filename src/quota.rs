@@ -0,0 +1,141 @@
+use std::sync::{Arc, Mutex};
+
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::error::Error;
+
+#[derive(Debug, Clone)]
+struct Usage {
+    txs: u64,
+    deposit_volume: BigDecimal,
+    rejected_deposits: u64,
+}
+
+// Per-run processing limits. Once any of them is reached, the engine stops accepting deposits
+// (other transactions keep flowing, so funds can still leave or be disputed) and flags the
+// overflow, protecting a shared deployment from runaway inputs. Clones share the same usage, so
+// the limits hold across the engines of a run (e.g. per-file engines and shard workers).
+#[derive(Debug, Clone)]
+pub struct Quota {
+    max_txs: Option<u64>,
+    max_deposit_volume: Option<BigDecimal>,
+    usage: Arc<Mutex<Usage>>,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota::new(None, None)
+    }
+}
+
+impl Quota {
+    pub fn new(max_txs: Option<u64>, max_deposit_volume: Option<BigDecimal>) -> Self {
+        Quota {
+            max_txs,
+            max_deposit_volume,
+            usage: Arc::new(Mutex::new(Usage {
+                txs: 0,
+                deposit_volume: BigDecimal::zero(),
+                rejected_deposits: 0,
+            })),
+        }
+    }
+
+    // Copy with the same limits and usage so far, whose usage isn't shared with this one anymore
+    // (e.g. for what-if transactions, see `Engine::fork`).
+    pub fn detached(&self) -> Self {
+        Quota {
+            max_txs: self.max_txs,
+            max_deposit_volume: self.max_deposit_volume.clone(),
+            usage: Arc::new(Mutex::new(self.usage.lock().unwrap().clone())),
+        }
+    }
+
+    pub fn record_tx(&self) {
+        self.usage.lock().unwrap().txs += 1;
+    }
+
+    // Accounts for a deposit of `amount`, failing if it would go over any of the limits.
+    pub fn admit_deposit(&self, id: u32, amount: &BigDecimal) -> Result<(), Error> {
+        let mut usage = self.usage.lock().unwrap();
+        let volume = &usage.deposit_volume + amount;
+        let over_txs = self.max_txs.is_some_and(|max| usage.txs > max);
+        let over_volume = self
+            .max_deposit_volume
+            .as_ref()
+            .is_some_and(|max| &volume > max);
+        if over_txs || over_volume {
+            usage.rejected_deposits += 1;
+            return Err(Error::QuotaExceeded(id));
+        }
+
+        usage.deposit_volume = volume;
+        Ok(())
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.rejected_deposits() > 0
+    }
+
+    pub fn rejected_deposits(&self) -> u64 {
+        self.usage.lock().unwrap().rejected_deposits
+    }
+
+    pub fn deposit_volume(&self) -> BigDecimal {
+        self.usage.lock().unwrap().deposit_volume.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::error::Error;
+
+    use super::Quota;
+
+    #[test]
+    fn admit_deposit_unlimited() {
+        let quota = Quota::default();
+        quota.record_tx();
+        quota.admit_deposit(0, &BigDecimal::from(1000)).unwrap();
+        assert!(!quota.exceeded());
+        assert_eq!(quota.deposit_volume(), BigDecimal::from(1000));
+    }
+
+    #[test]
+    fn admit_deposit_over_max_txs() {
+        let quota = Quota::new(Some(1), None);
+        quota.record_tx();
+        quota.admit_deposit(0, &BigDecimal::from(1)).unwrap();
+        quota.record_tx();
+        let res = quota.admit_deposit(1, &BigDecimal::from(1));
+        assert_eq!(res, Err(Error::QuotaExceeded(1)));
+        assert!(quota.exceeded());
+    }
+
+    #[test]
+    fn admit_deposit_over_max_volume() {
+        let quota = Quota::new(None, Some(BigDecimal::from(10)));
+        quota.admit_deposit(0, &BigDecimal::from(6)).unwrap();
+        let res = quota.admit_deposit(1, &BigDecimal::from(5));
+        assert_eq!(res, Err(Error::QuotaExceeded(1)));
+        quota.admit_deposit(2, &BigDecimal::from(4)).unwrap();
+        assert_eq!(quota.deposit_volume(), BigDecimal::from(10));
+        assert_eq!(quota.rejected_deposits(), 1);
+    }
+
+    #[test]
+    fn clones_share_usage() {
+        let quota = Quota::new(None, Some(BigDecimal::from(10)));
+        let worker = quota.clone();
+        quota.admit_deposit(0, &BigDecimal::from(6)).unwrap();
+        let res = worker.admit_deposit(1, &BigDecimal::from(6));
+        assert_eq!(res, Err(Error::QuotaExceeded(1)));
+        assert!(quota.exceeded());
+
+        let detached = quota.detached();
+        detached.admit_deposit(2, &BigDecimal::from(4)).unwrap();
+        assert_eq!(quota.deposit_volume(), BigDecimal::from(6));
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::{Arc, Mutex};
+
+use csv_async::AsyncWriterBuilder;
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::io::AsyncWrite;
+
+use crate::{
+    error::Error,
+    outcome::TxOutcome,
+    payments::Tx,
+    plugin::Plugin,
+    source::{BoxedSource, TxSource},
+};
+
+// Row of the input which failed parsing or handling.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Reject {
+    // Ingestion batch of the transaction (see `batch::batched`), unknown for unparsable rows.
+    pub batch: Option<String>,
+    // Line of the input, the header being line 1.
+    pub line: Option<u64>,
+    pub tx: Option<u32>,
+    // Variant of the error (see `Error::variant`).
+    pub error: &'static str,
+    pub message: String,
+}
+
+// Plugin collecting the rows which failed handling, along with the rows of the sources it
+// `watch`es which failed parsing, for operators to triage them. Clones share the same rejects, so
+// a clone can be kept around for writing them after registering the plugin.
+#[derive(Clone, Default)]
+pub struct Rejects {
+    rejects: Arc<Mutex<Vec<Reject>>>,
+}
+
+impl Rejects {
+    // Numbers the transactions of the source by input line, assuming the header and every record
+    // span a single line, and records its unparsable rows.
+    pub fn watch(&self, source: impl TxSource + 'static) -> BoxedSource {
+        let rejects = self.clone();
+        Box::new(source.enumerate().map(move |(row, record)| {
+            // Rows are numbered from 0, after the header.
+            let line = row as u64 + 2;
+            match record {
+                Ok(tx) => Ok(tx.with_line(line)),
+                Err(err) => {
+                    rejects.record(None, Some(line), None, &err);
+                    Err(err)
+                }
+            }
+        }))
+    }
+
+    pub fn rejects(&self) -> Vec<Reject> {
+        self.rejects.lock().unwrap().clone()
+    }
+
+    fn record(&self, batch: Option<&str>, line: Option<u64>, tx: Option<u32>, err: &Error) {
+        self.rejects.lock().unwrap().push(Reject {
+            batch: batch.map(str::to_string),
+            line,
+            tx,
+            error: err.variant(),
+            message: err.to_string(),
+        });
+    }
+
+    // Writes the rejects as CSV, in the order they were met.
+    pub async fn write<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut serializer = AsyncWriterBuilder::new().create_serializer(writer);
+        for reject in self.rejects() {
+            serializer.serialize(reject).await?;
+        }
+        serializer.flush().await
+    }
+}
+
+impl Plugin for Rejects {
+    fn name(&self) -> &str {
+        "rejects"
+    }
+
+    fn on_outcome(&self, tx: &Tx, outcome: &TxOutcome) {
+        if let Some(err) = outcome.error() {
+            self.record(tx.batch(), tx.line(), Some(tx.id()), err);
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        let rejects = self.rejects.lock().unwrap().len();
+        vec![("rejected_rows".to_string(), rejects.to_string())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        payments::Engine,
+        source,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::Rejects;
+
+    #[tokio::test]
+    async fn rejected_rows() {
+        let rejects = Rejects::default();
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(rejects.clone())
+        .build()
+        .unwrap();
+
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,3.0\n\
+            refund,1,3,1.0\ndispute,1,4,";
+        let txs = rejects.watch(source::from_csv(input.as_bytes()));
+        engine.handle_source(txs).await.unwrap();
+
+        let mut output = Vec::new();
+        rejects.write(&mut output).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.lines();
+        assert_eq!(lines.next(), Some("batch,line,tx,error,message"));
+        assert_eq!(
+            lines.next(),
+            Some(",3,2,MinAvailableUnderflow,Min available underflow")
+        );
+        assert!(lines.next().unwrap().starts_with(",4,,InvalidRecord,"));
+        assert_eq!(lines.next(), Some(",5,4,TxNotFound,Transaction not found"));
+        assert_eq!(lines.next(), None);
+    }
+}
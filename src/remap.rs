@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+use tracing::warn;
+
+use crate::{error::Error, payments::Tx, source::TxSource};
+
+#[derive(Deserialize)]
+struct Alias {
+    old: u16,
+    new: u16,
+}
+
+// Client id remapping applied during ingestion, for when upstream systems renumber customers.
+// Transactions of a remapped client are moved to its new id, unless that id is already used by
+// another client of the input: the first client seen under an id owns it, while the transactions
+// of any other client colliding with it get rejected instead of being silently merged. Clones
+// share the ownership of the ids, so that a remapping can be applied to several inputs.
+#[derive(Debug, Clone, Default)]
+pub struct Remapping {
+    aliases: HashMap<u16, u16>,
+    owners: Arc<Mutex<HashMap<u16, u16>>>,
+}
+
+impl Remapping {
+    // Loads the remapping from a CSV with an `old,new` header. Two clients can't be remapped to the
+    // same id.
+    pub async fn load(reader: impl AsyncRead + Send + Unpin) -> Result<Self, Error> {
+        let mut remapping = Remapping::default();
+        let mut records = csv_async::AsyncReaderBuilder::new()
+            .trim(csv_async::Trim::All)
+            .create_deserializer(reader)
+            .into_deserialize::<Alias>();
+        while let Some(record) = records.next().await {
+            let alias = record.map_err(|err| Error::InvalidRecord(err.to_string()))?;
+            remapping.alias(alias.old, alias.new)?;
+        }
+        Ok(remapping)
+    }
+
+    pub fn alias(&mut self, old: u16, new: u16) -> Result<(), Error> {
+        let taken = self
+            .aliases
+            .iter()
+            .any(|(other, target)| *target == new && *other != old);
+        if taken || self.aliases.contains_key(&old) {
+            return Err(Error::ClientCollision(new));
+        }
+        self.aliases.insert(old, new);
+        Ok(())
+    }
+
+    // Moves the transaction to the new id of its client, if any.
+    pub fn apply(&self, mut tx: Tx) -> Result<Tx, Error> {
+        let client = tx.client();
+        let target = self.aliases.get(&client).copied().unwrap_or(client);
+        let mut owners = self.owners.lock().unwrap();
+        let owner = *owners.entry(target).or_insert(client);
+        if owner != client {
+            warn!("Client {client} collides with client {owner} on id {target}");
+            return Err(Error::ClientCollision(target));
+        }
+        tx.remap_client(target);
+        Ok(tx)
+    }
+}
+
+// Applies the remapping on the transactions of `source`.
+pub fn remapped<'s>(source: impl TxSource + 's, remapping: Remapping) -> impl TxSource + 's {
+    source
+        .map(move |record| record.and_then(|tx| remapping.apply(tx)))
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        error::Error,
+        payments::{Engine, Tx, TxType},
+        source,
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{remapped, Remapping};
+
+    #[tokio::test]
+    async fn load_rejects_colliding_aliases() {
+        let res = Remapping::load("old,new\n1,10\n2,10".as_bytes()).await;
+        assert_eq!(res.err(), Some(Error::ClientCollision(10)));
+    }
+
+    #[test]
+    fn collisions_are_rejected() {
+        let mut remapping = Remapping::default();
+        remapping.alias(1, 10).unwrap();
+
+        let tx = remapping
+            .apply(Tx::new(TxType::Deposit, 1, 1, None))
+            .unwrap();
+        assert_eq!(tx.client(), 10);
+        assert!(remapping
+            .apply(Tx::new(TxType::Deposit, 2, 2, None))
+            .is_ok());
+        assert_eq!(
+            remapping.apply(Tx::new(TxType::Deposit, 10, 3, None)).err(),
+            Some(Error::ClientCollision(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_remapped_source() {
+        let remapping = Remapping::load("old,new\n1,2".as_bytes()).await.unwrap();
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let txs = source::from_csv(
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\ndeposit,3,3,3.0".as_bytes(),
+        );
+
+        engine
+            .handle_source(remapped(txs, remapping))
+            .await
+            .unwrap();
+        let account = engine.account(2).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(1));
+        assert!(engine.account(1).await.is_none());
+        assert!(engine.account(3).await.is_some());
+    }
+}
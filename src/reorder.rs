@@ -0,0 +1,200 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+    time::Duration,
+};
+
+use futures::{stream, StreamExt};
+use tokio::time::Instant;
+
+use crate::{error::Error, payments::Tx, source::TxSource};
+
+// Field the reordering buffer sorts transactions by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReorderKey {
+    Sequence,
+    Timestamp,
+}
+
+impl ReorderKey {
+    pub fn of(&self, tx: &Tx) -> Option<u64> {
+        match self {
+            ReorderKey::Sequence => tx.seq(),
+            ReorderKey::Timestamp => tx.timestamp(),
+        }
+    }
+}
+
+// Bounded buffer restoring the order of transactions which arrive slightly out of order from
+// streaming sources. A transaction is held back until either more than `capacity` transactions are
+// buffered or it waited for `max_delay`, then released along with all the buffered ones ordered
+// before it. Transactions without the key are passed through as they arrive.
+#[derive(Debug, Clone, Copy)]
+pub struct ReorderBuffer {
+    pub key: ReorderKey,
+    pub capacity: usize,
+    pub max_delay: Duration,
+}
+
+struct Buffered {
+    config: ReorderBuffer,
+    // Keys of the buffered transactions along with their arrival order, smallest first.
+    order: BinaryHeap<Reverse<(u64, u64)>>,
+    // Buffered transactions by arrival order.
+    arrivals: BTreeMap<u64, (Instant, Tx)>,
+    arrived: u64,
+}
+
+impl Buffered {
+    fn new(config: ReorderBuffer) -> Self {
+        Buffered {
+            config,
+            order: BinaryHeap::new(),
+            arrivals: BTreeMap::new(),
+            arrived: 0,
+        }
+    }
+
+    fn push(&mut self, tx: Tx, ready: &mut VecDeque<Result<Tx, Error>>) {
+        let Some(key) = self.config.key.of(&tx) else {
+            ready.push_back(Ok(tx));
+            return;
+        };
+        self.arrived += 1;
+        self.order.push(Reverse((key, self.arrived)));
+        self.arrivals.insert(self.arrived, (Instant::now(), tx));
+        while self.arrivals.len() > self.config.capacity {
+            self.pop(ready);
+        }
+    }
+
+    fn pop(&mut self, ready: &mut VecDeque<Result<Tx, Error>>) -> Option<u64> {
+        let Reverse((_, arrival)) = self.order.pop()?;
+        if let Some((_, tx)) = self.arrivals.remove(&arrival) {
+            ready.push_back(Ok(tx));
+        }
+        Some(arrival)
+    }
+
+    // When the longest buffered transaction has to be released.
+    fn deadline(&self) -> Option<Instant> {
+        let (_, (arrived_at, _)) = self.arrivals.iter().next()?;
+        Some(*arrived_at + self.config.max_delay)
+    }
+
+    fn release_expired(&mut self, ready: &mut VecDeque<Result<Tx, Error>>) {
+        let now = Instant::now();
+        while let Some((&oldest, _)) = self
+            .arrivals
+            .iter()
+            .find(|(_, (arrived_at, _))| *arrived_at + self.config.max_delay <= now)
+        {
+            while self.pop(ready).is_some_and(|arrival| arrival != oldest) {}
+        }
+    }
+
+    fn drain(&mut self, ready: &mut VecDeque<Result<Tx, Error>>) {
+        while self.pop(ready).is_some() {}
+    }
+}
+
+// Applies the reordering buffer on the transactions of `source`.
+pub fn reordered<'s>(source: impl TxSource + 's, config: ReorderBuffer) -> impl TxSource + 's {
+    stream::unfold(
+        (source, Buffered::new(config), VecDeque::new(), false),
+        |(mut source, mut buffer, mut ready, mut done)| async move {
+            loop {
+                if let Some(record) = ready.pop_front() {
+                    return Some((record, (source, buffer, ready, done)));
+                }
+                if done {
+                    return None;
+                }
+                let next = match buffer.deadline() {
+                    Some(deadline) => {
+                        match tokio::time::timeout_at(deadline, source.next()).await {
+                            Ok(next) => next,
+                            Err(_) => {
+                                buffer.release_expired(&mut ready);
+                                continue;
+                            }
+                        }
+                    }
+                    None => source.next().await,
+                };
+                match next {
+                    Some(Ok(tx)) => buffer.push(tx, &mut ready),
+                    Some(Err(err)) => ready.push_back(Err(err)),
+                    None => {
+                        done = true;
+                        buffer.drain(&mut ready);
+                    }
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        payments::{Tx, TxType},
+        source,
+    };
+
+    use super::{reordered, ReorderBuffer, ReorderKey};
+
+    fn tx(id: u32, timestamp: u64) -> Tx {
+        Tx::new(TxType::Deposit, 1, id, None).with_timestamp(timestamp)
+    }
+
+    #[tokio::test]
+    async fn reorders_within_capacity() {
+        let config = ReorderBuffer {
+            key: ReorderKey::Timestamp,
+            capacity: 2,
+            max_delay: Duration::from_secs(60),
+        };
+        let txs = vec![
+            tx(1, 10),
+            tx(2, 30),
+            tx(3, 20),
+            Tx::new(TxType::Deposit, 1, 4, None),
+            tx(5, 40),
+            tx(6, 5),
+        ];
+        let ids: Vec<u32> = reordered(source::from_iter(txs), config)
+            .map(|record| record.unwrap().id())
+            .collect()
+            .await;
+        // Transaction 6 arrived after its predecessors were pushed out of the full buffer.
+        assert_eq!(ids, vec![1, 4, 3, 6, 2, 5]);
+    }
+
+    #[tokio::test]
+    async fn releases_after_max_delay() {
+        let config = ReorderBuffer {
+            key: ReorderKey::Timestamp,
+            capacity: 100,
+            max_delay: Duration::from_millis(20),
+        };
+        let (sender, receiver) = mpsc::channel(10);
+        let mut txs = reordered(source::from_channel(receiver), config);
+        sender.send(tx(2, 20)).await.unwrap();
+        sender.send(tx(1, 10)).await.unwrap();
+
+        let released = tokio::time::timeout(Duration::from_secs(5), txs.next())
+            .await
+            .unwrap();
+        assert_eq!(released.unwrap().unwrap().id(), 1);
+        assert_eq!(txs.next().await.unwrap().unwrap().id(), 2);
+        drop(sender);
+        assert!(txs.next().await.is_none());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use tracing::warn;
+
+use crate::{error::Error, payments::Tx, plugin::Plugin};
+
+// FNV-1a, which unlike `DefaultHasher` is stable across builds, as hashes get persisted.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Default)]
+struct ReplayState {
+    applied: HashSet<u64>,
+    occurrences: HashMap<u64, u64>,
+    new: Vec<u64>,
+}
+
+// Plugin protecting against the same input being fed twice: every row is hashed (along with the
+// number of identical rows before it in the run, so that rows legitimately repeated within an
+// input, like disputes raised again, are told apart) and rows already applied by a previous run
+// are rejected. The hashes of the applied rows are appended to the log on shutdown.
+pub struct ReplayGuard {
+    log: PathBuf,
+    state: Mutex<ReplayState>,
+}
+
+impl ReplayGuard {
+    // Loads the hashes of the previously applied rows from the log, if it exists.
+    pub fn load(log: PathBuf) -> std::io::Result<Self> {
+        let mut state = ReplayState::default();
+        match fs::read_to_string(&log) {
+            Ok(content) => {
+                for line in content.lines().filter(|line| !line.is_empty()) {
+                    let hash = u64::from_str_radix(line, 16)
+                        .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))?;
+                    state.applied.insert(hash);
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        Ok(ReplayGuard {
+            log,
+            state: Mutex::new(state),
+        })
+    }
+
+    fn hash(tx: &Tx, occurrence: u64) -> u64 {
+        let row = format!(
+            "{:?},{},{},{},{occurrence}",
+            tx.tx_type(),
+            tx.client(),
+            tx.id(),
+            tx.amount()
+                .map(|amount| amount.normalized().to_string())
+                .unwrap_or_default()
+        );
+        fnv1a(row.as_bytes())
+    }
+}
+
+impl Plugin for ReplayGuard {
+    fn name(&self) -> &str {
+        "replay"
+    }
+
+    fn on_tx(&self, tx: &Tx) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let content = Self::hash(tx, 0);
+        let occurrence = state.occurrences.entry(content).or_default();
+        let hash = Self::hash(tx, *occurrence);
+        *occurrence += 1;
+        if state.applied.contains(&hash) {
+            return Err(Error::ReplayedRow(tx.id()));
+        }
+        state.new.push(hash);
+        Ok(())
+    }
+
+    fn on_shutdown(&self) {
+        let state = self.state.lock().unwrap();
+        let res = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log)
+            .and_then(|mut file| {
+                let lines: String = state.new.iter().map(|hash| format!("{hash:x}\n")).collect();
+                file.write_all(lines.as_bytes())
+            });
+        if let Err(err) = res {
+            warn!("Error while writing the replay log {:?}: {err}", self.log);
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        let state = self.state.lock().unwrap();
+        vec![("applied".to_string(), state.new.len().to_string())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        payments::Engine,
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::ReplayGuard;
+
+    const INPUT: &str = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\ndispute,1,1,\n\
+        resolve,1,1,\ndispute,1,1,";
+
+    // Runs the input against the given ledgers, returning the held funds of the client.
+    async fn run(
+        log: &std::path::Path,
+        input: &str,
+        ledgers: &(InMemoryAccountLedger, InMemoryTxLedger),
+    ) -> String {
+        let mut engine = Engine::builder(ledgers.0.clone(), ledgers.1.clone())
+            .plugin(ReplayGuard::load(log.to_path_buf()).unwrap())
+            .build()
+            .unwrap();
+        engine
+            .handle_txs(tokio::io::BufReader::new(input.as_bytes()))
+            .await
+            .unwrap();
+        engine.shutdown();
+        let account = engine.account(1).await.unwrap();
+        let held = account.lock().await.held().to_string();
+        held
+    }
+
+    #[tokio::test]
+    async fn skips_rows_applied_by_previous_runs() {
+        let log = std::env::temp_dir().join("payments-engine-replay.log");
+        let _ = std::fs::remove_file(&log);
+        let ledgers = Default::default();
+
+        // Repeated rows within a run are applied.
+        assert_eq!(run(&log, INPUT, &ledgers).await, "1.0");
+        // Feeding the same input again changes nothing, while new rows still get applied.
+        let next = format!("{INPUT}\nresolve,1,1,\ndispute,1,2,");
+        assert_eq!(run(&log, &next, &ledgers).await, "2.0");
+        std::fs::remove_file(&log).unwrap();
+    }
+}
@@ -0,0 +1,641 @@
+use std::{collections::HashMap, io::Write, str::FromStr};
+
+use bigdecimal::{BigDecimal, RoundingMode, Zero};
+use csv_async::{AsyncWriterBuilder, QuoteStyle};
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    account::Account,
+    format::write_amount,
+    storage::{AccountsDal, TxsDal},
+    tags::Tags,
+};
+
+// Output format of the accounts report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    // A JSON array of `AccountReport`s.
+    Json,
+    // An `AccountReport` JSON object per line.
+    Jsonl,
+}
+
+// How the amounts and fields of a report are written, as downstream systems disagree on these.
+#[derive(Debug, Clone, Copy)]
+pub struct Style {
+    // Decimal places the amounts are rounded to (half to even), if any.
+    pub precision: Option<u32>,
+    // Whether amounts keep their trailing zeros (e.g. `1.50` with a precision of 2) or are written
+    // with as few digits as possible (`1.5`, and `0` rather than `0.00`).
+    pub trailing_zeros: bool,
+    pub decimal_separator: u8,
+    // When the fields of CSV reports get quoted. Fields holding the decimal separator (if not `.`)
+    // or a comma need quoting for the report to be parsed back.
+    pub quote: QuoteStyle,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            precision: None,
+            trailing_zeros: true,
+            decimal_separator: b'.',
+            quote: QuoteStyle::Necessary,
+        }
+    }
+}
+
+impl Style {
+    // Appends `amount` to `buf`, as written by `format::write_amount` in this style.
+    pub fn write_amount(&self, buf: &mut Vec<u8>, amount: BigDecimal) {
+        let mut amount = match self.precision {
+            Some(precision) => {
+                amount.with_scale_round(i64::from(precision), RoundingMode::HalfEven)
+            }
+            None => amount,
+        };
+        if !self.trailing_zeros {
+            amount = amount.normalized();
+        }
+        let start = buf.len();
+        write_amount(buf, amount);
+        if self.decimal_separator != b'.' {
+            for byte in &mut buf[start..] {
+                if *byte == b'.' {
+                    *byte = self.decimal_separator;
+                }
+            }
+        }
+    }
+
+    pub fn amount(&self, amount: BigDecimal) -> String {
+        let mut buf = Vec::with_capacity(24);
+        self.write_amount(&mut buf, amount);
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+// Column of an account report, computed from the account and the `Extras` of the report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    // Total balance before this run, empty without opening balances.
+    Opening,
+    // Net activity of this run, empty without opening balances.
+    Activity,
+    OpenDisputes,
+    // Latest `timestamp` of the client's transactions in the ledger, empty if none has one.
+    LastActivity,
+    // Tags of the client, separated by `;`.
+    Tags,
+}
+
+impl Column {
+    pub const ALL: [Column; 10] = [
+        Column::Client,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+        Column::Opening,
+        Column::Activity,
+        Column::OpenDisputes,
+        Column::LastActivity,
+        Column::Tags,
+    ];
+    // Columns of `write_accounts_filtered`.
+    pub const DEFAULT: [Column; 5] = [
+        Column::Client,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+    ];
+    // Columns of `write_period_filtered`.
+    pub const PERIOD: [Column; 7] = [
+        Column::Client,
+        Column::Opening,
+        Column::Activity,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Client => "client",
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Total => "total",
+            Column::Locked => "locked",
+            Column::Opening => "opening",
+            Column::Activity => "activity",
+            Column::OpenDisputes => "open_disputes",
+            Column::LastActivity => "last_activity",
+            Column::Tags => "tags",
+        }
+    }
+
+    // Whether the column is computed from the transactions ledger (see `tx_activity`).
+    pub fn needs_txs(self) -> bool {
+        matches!(self, Column::OpenDisputes | Column::LastActivity)
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Column::ALL
+            .iter()
+            .copied()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| format!("Unknown report column: {name}"))
+    }
+}
+
+// Activity of a client in the transactions ledger.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TxActivity {
+    pub open_disputes: u64,
+    pub last_activity: Option<u64>,
+}
+
+// Activity of every client with transactions in the ledger.
+pub async fn tx_activity<T: TxsDal>(txs: &T) -> HashMap<u16, TxActivity> {
+    let mut activity: HashMap<u16, TxActivity> = HashMap::new();
+    for tx in txs.txs().await.values() {
+        let tx = tx.lock().await;
+        let client = activity.entry(tx.client()).or_default();
+        if tx.disputed() {
+            client.open_disputes += 1;
+        }
+        if let Some(timestamp) = tx.timestamp() {
+            client.last_activity = client.last_activity.max(Some(timestamp));
+        }
+    }
+    activity
+}
+
+// Data the columns beyond the account's own state are computed from.
+#[derive(Default)]
+pub struct Extras<'e> {
+    pub opening: Option<&'e HashMap<u16, BigDecimal>>,
+    pub tags: Option<&'e Tags>,
+    pub txs: HashMap<u16, TxActivity>,
+}
+
+impl Extras<'_> {
+    // Accounts opened during the period start at 0.
+    fn opening(&self, client: u16) -> Option<BigDecimal> {
+        let opening = self.opening?;
+        Some(
+            opening
+                .get(&client)
+                .cloned()
+                .unwrap_or_else(BigDecimal::zero),
+        )
+    }
+}
+
+// Row of the default accounts report, in any format. Amounts are kept as their decimal
+// representation (see `Style::write_amount`).
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AccountReport {
+    pub client: u16,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+impl AccountReport {
+    // View of the account with its amounts written in the given style.
+    pub fn new(account: &Account, style: &Style) -> Self {
+        AccountReport {
+            client: account.client_id(),
+            available: style.amount(account.available()),
+            held: style.amount(account.held()),
+            total: style.amount(account.total()),
+            locked: account.is_locked(),
+        }
+    }
+}
+
+impl From<&Account> for AccountReport {
+    fn from(account: &Account) -> Self {
+        AccountReport::new(account, &Style::default())
+    }
+}
+
+// Writes the final state of all the accounts as CSV.
+pub async fn write_accounts<A: AccountsDal, W: AsyncWrite + Unpin>(
+    accounts: &A,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    write_accounts_filtered(accounts, writer, |_| true, false).await
+}
+
+// Copies of the accounts of the clients matching `filter`, ordered by client id if `sorted`, or in
+// the storage's (unspecified) order otherwise.
+async fn selected<A: AccountsDal>(
+    accounts: &A,
+    filter: impl Fn(u16) -> bool,
+    sorted: bool,
+) -> Vec<Account> {
+    let mut selected = Vec::new();
+    for account in accounts.accounts().await.values() {
+        let inner = account.lock().await;
+        if filter(inner.client_id()) {
+            selected.push(inner.clone());
+        }
+    }
+    if sorted {
+        selected.sort_by_key(Account::client_id);
+    }
+    selected
+}
+
+// Writes the final state of the accounts of the clients matching `filter` as CSV, ordered by
+// client id if `sorted`.
+pub async fn write_accounts_filtered<A: AccountsDal, W: AsyncWrite + Unpin>(
+    accounts: &A,
+    writer: &mut W,
+    filter: impl Fn(u16) -> bool,
+    sorted: bool,
+) -> std::io::Result<()> {
+    write_accounts_as(
+        accounts,
+        writer,
+        Format::Csv,
+        &Style::default(),
+        filter,
+        sorted,
+    )
+    .await
+}
+
+// Writes the final state of the accounts of the clients matching `filter` in the given format,
+// ordered by client id if `sorted`, in the given style.
+pub async fn write_accounts_as<A: AccountsDal, W: AsyncWrite + Unpin>(
+    accounts: &A,
+    writer: &mut W,
+    format: Format,
+    style: &Style,
+    filter: impl Fn(u16) -> bool,
+    sorted: bool,
+) -> std::io::Result<()> {
+    let rows = selected(accounts, filter, sorted)
+        .await
+        .into_iter()
+        .map(|inner| AccountReport::new(&inner, style));
+    match format {
+        Format::Csv => {
+            // The header is written upfront, for reports without accounts to still have one.
+            let mut serializer = AsyncWriterBuilder::new()
+                .has_headers(false)
+                .quote_style(style.quote)
+                .create_serializer(writer);
+            serializer
+                .serialize(Column::DEFAULT.map(Column::name))
+                .await?;
+            for row in rows {
+                serializer.serialize(row).await?;
+            }
+            serializer.flush().await
+        }
+        Format::Json => {
+            let mut json = serde_json::to_vec(&rows.collect::<Vec<_>>())?;
+            json.push(b'\n');
+            writer.write_all(&json).await?;
+            writer.flush().await
+        }
+        Format::Jsonl => {
+            let mut line = Vec::new();
+            for row in rows {
+                line.clear();
+                serde_json::to_writer(&mut line, &row)?;
+                line.push(b'\n');
+                writer.write_all(&line).await?;
+            }
+            writer.flush().await
+        }
+    }
+}
+
+// Writes the given columns of the accounts of the clients matching `filter` as CSV in the given
+// style, ordered by client id if `sorted`.
+pub async fn write_columns<A: AccountsDal, W: AsyncWrite + Unpin>(
+    accounts: &A,
+    writer: &mut W,
+    columns: &[Column],
+    extras: &Extras<'_>,
+    style: &Style,
+    filter: impl Fn(u16) -> bool,
+    sorted: bool,
+) -> std::io::Result<()> {
+    let mut serializer = AsyncWriterBuilder::new()
+        .has_headers(false)
+        .quote_style(style.quote)
+        .create_serializer(writer);
+    let header: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+    serializer.serialize(header).await?;
+    let mut field = Vec::with_capacity(32);
+    for inner in selected(accounts, filter, sorted).await {
+        let mut record = Vec::with_capacity(columns.len());
+        for column in columns {
+            field.clear();
+            write_column(&mut field, *column, &inner, extras, style)?;
+            record.push(String::from_utf8_lossy(&field).into_owned());
+        }
+        serializer.serialize(record).await?;
+    }
+    serializer.flush().await
+}
+
+// Appends a column of a report row to the field's buffer.
+fn write_column(
+    row: &mut Vec<u8>,
+    column: Column,
+    account: &Account,
+    extras: &Extras<'_>,
+    style: &Style,
+) -> std::io::Result<()> {
+    let client = account.client_id();
+    match column {
+        Column::Client => write!(row, "{client}")?,
+        Column::Available => style.write_amount(row, account.available()),
+        Column::Held => style.write_amount(row, account.held()),
+        Column::Total => style.write_amount(row, account.total()),
+        Column::Locked => write!(row, "{}", account.is_locked())?,
+        Column::Opening => {
+            if let Some(opening) = extras.opening(client) {
+                style.write_amount(row, opening);
+            }
+        }
+        Column::Activity => {
+            if let Some(opening) = extras.opening(client) {
+                style.write_amount(row, account.total() - opening);
+            }
+        }
+        Column::OpenDisputes => {
+            let activity = extras.txs.get(&client).copied().unwrap_or_default();
+            write!(row, "{}", activity.open_disputes)?;
+        }
+        Column::LastActivity => {
+            if let Some(timestamp) = extras.txs.get(&client).and_then(|tx| tx.last_activity) {
+                write!(row, "{timestamp}")?;
+            }
+        }
+        Column::Tags => {
+            let tags = extras.tags.into_iter().flat_map(|tags| tags.tags(client));
+            for (idx, tag) in tags.enumerate() {
+                if idx > 0 {
+                    row.push(b';');
+                }
+                row.extend_from_slice(tag.as_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Total balance of every account, captured before processing a period's transactions.
+pub async fn opening_balances<A: AccountsDal>(accounts: &A) -> HashMap<u16, BigDecimal> {
+    let mut balances = HashMap::new();
+    for (client, account) in accounts.accounts().await.iter() {
+        balances.insert(*client, account.lock().await.total());
+    }
+    balances
+}
+
+// Similar to `write_accounts_filtered`, but for a period starting from the `opening` balances (e.g.
+// loaded from a previous run's snapshot): every row holds the opening total, the net activity of
+// the period and the closing state of the account. Accounts opened during the period start at 0.
+pub async fn write_period_filtered<A: AccountsDal, W: AsyncWrite + Unpin>(
+    accounts: &A,
+    opening: &HashMap<u16, BigDecimal>,
+    writer: &mut W,
+    filter: impl Fn(u16) -> bool,
+    sorted: bool,
+) -> std::io::Result<()> {
+    let extras = Extras {
+        opening: Some(opening),
+        ..Default::default()
+    };
+    write_columns(
+        accounts,
+        writer,
+        &Column::PERIOD,
+        &extras,
+        &Style::default(),
+        filter,
+        sorted,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+    use csv_async::QuoteStyle;
+
+    use crate::{
+        account::Account,
+        payments::Engine,
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+        tags::Tags,
+    };
+
+    use super::{
+        opening_balances, tx_activity, write_accounts_as, write_accounts_filtered, write_columns,
+        write_period_filtered, Column, Extras, Format, Style,
+    };
+
+    #[tokio::test]
+    async fn period_report() {
+        let mut ledger = InMemoryAccountLedger::default();
+        ledger
+            .insert(Account::new(
+                1,
+                BigDecimal::from(5),
+                BigDecimal::from(0),
+                false,
+            ))
+            .await;
+        let opening = opening_balances(&ledger).await;
+
+        let account = ledger.account(1).await.unwrap();
+        account
+            .lock()
+            .await
+            .add_available(&BigDecimal::from_str("1.5").unwrap());
+        ledger.insert(Account::new_unlocked(2)).await;
+
+        let mut output = Vec::new();
+        write_period_filtered(&ledger, &opening, &mut output, |client| client == 1, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,opening,activity,available,held,total,locked\n1,5,1.5,6.5,0,6.5,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn selected_columns() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        engine
+            .handle_txs(
+                "type,client,tx,amount,timestamp\ndeposit,1,1,2.0,10\ndeposit,1,2,1.0,30\n\
+                    dispute,1,1,,40\ndeposit,2,3,1.0,20"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut tags = Tags::default();
+        tags.tag(1, "retail");
+        tags.tag(1, "vip, gold");
+        let extras = Extras {
+            tags: Some(&tags),
+            txs: tx_activity(&engine).await,
+            ..Default::default()
+        };
+
+        let columns: Vec<Column> = ["client", "total", "open_disputes", "last_activity", "tags"]
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+        let mut output = Vec::new();
+        write_columns(
+            &engine,
+            &mut output,
+            &columns,
+            &extras,
+            &Style::default(),
+            |_| true,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,total,open_disputes,last_activity,tags\n1,3.0,1,30,\"retail;vip, gold\"\n2,1.0,0,20,\n"
+        );
+        assert!("merchant".parse::<Column>().is_err());
+    }
+
+    #[tokio::test]
+    async fn sorted_by_client() {
+        let mut ledger = InMemoryAccountLedger::default();
+        for client in (0..64).rev() {
+            ledger.insert(Account::new_unlocked(client)).await;
+        }
+
+        let mut output = Vec::new();
+        write_accounts_filtered(&ledger, &mut output, |_| true, true)
+            .await
+            .unwrap();
+        let clients: Vec<u16> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|row| row.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(clients, (0..64).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn styled_amounts() {
+        let mut ledger = InMemoryAccountLedger::default();
+        ledger
+            .insert(Account::new(
+                1,
+                BigDecimal::from_str("1.2050").unwrap(),
+                BigDecimal::from(2),
+                false,
+            ))
+            .await;
+
+        let style = Style {
+            precision: Some(2),
+            trailing_zeros: false,
+            decimal_separator: b',',
+            quote: QuoteStyle::Always,
+        };
+        let mut output = Vec::new();
+        write_accounts_as(&ledger, &mut output, Format::Csv, &style, |_| true, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\"client\",\"available\",\"held\",\"total\",\"locked\"\n\
+                \"1\",\"1,2\",\"2\",\"3,2\",\"false\"\n"
+        );
+
+        let style = Style {
+            precision: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(style.amount(BigDecimal::from_str("1.2").unwrap()), "1.200");
+    }
+
+    #[tokio::test]
+    async fn json_reports() {
+        let mut ledger = InMemoryAccountLedger::default();
+        ledger
+            .insert(Account::new(
+                1,
+                BigDecimal::from_str("1.2345").unwrap(),
+                BigDecimal::from(0),
+                true,
+            ))
+            .await;
+        ledger.insert(Account::new_unlocked(2)).await;
+
+        let mut output = Vec::new();
+        let style = Style {
+            precision: Some(2),
+            ..Default::default()
+        };
+        write_accounts_as(&ledger, &mut output, Format::Json, &style, |_| true, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[{\"client\":1,\"available\":\"1.23\",\"held\":\"0.00\",\"total\":\"1.23\",\
+                \"locked\":true},{\"client\":2,\"available\":\"0.00\",\"held\":\"0.00\",\
+                \"total\":\"0.00\",\"locked\":false}]\n"
+        );
+
+        let mut output = Vec::new();
+        let style = Style::default();
+        write_accounts_as(
+            &ledger,
+            &mut output,
+            Format::Jsonl,
+            &style,
+            |c| c == 1,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"client\":1,\"available\":\"1.2345\",\"held\":\"0\",\"total\":\"1.2345\",\
+                \"locked\":true}\n"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+// Set of transaction ids, held as ranges of consecutive ids (from the first id to the last one),
+// as inputs mostly number their transactions sequentially.
+#[derive(Debug, Default)]
+struct IdRanges(BTreeMap<u32, u32>);
+
+impl IdRanges {
+    fn contains(&self, id: u32) -> bool {
+        self.0
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(_, last)| *last >= id)
+    }
+
+    fn insert(&mut self, id: u32) {
+        if self.contains(id) {
+            return;
+        }
+        let mut first = id;
+        let mut last = id;
+        if let Some((start, end)) = self.0.range(..id).next_back() {
+            if end.checked_add(1) == Some(id) {
+                first = *start;
+            }
+        }
+        if let Some(next) = id.checked_add(1) {
+            if let Some(end) = self.0.remove(&next) {
+                last = end;
+            }
+        }
+        self.0.insert(first, last);
+    }
+}
+
+// Retention of the stored transactions, keeping the ledger proportional to the horizon in which
+// transactions can still be disputed rather than to the whole history. There are no timestamps in
+// the input, so the dispute window is measured in processed transactions. The ids of the dropped
+// transactions are still remembered, so that they can't be reused, and shared between clones, as
+// the engines of a run (e.g. shard workers) share their ids.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    dispute_window: Option<u64>,
+    prune_terminal: bool,
+    seq: u64,
+    tracked: VecDeque<(u64, u32)>,
+    pruned: u64,
+    dropped: Arc<Mutex<IdRanges>>,
+}
+
+impl RetentionPolicy {
+    // Deposits become non-disputable once `dispute_window` transactions were processed after them.
+    // With `prune_terminal`, withdrawals (which can't be disputed) aren't kept at all, and neither
+    // are the transactions of locked accounts.
+    pub fn new(dispute_window: Option<u64>, prune_terminal: bool) -> Self {
+        RetentionPolicy {
+            dispute_window,
+            prune_terminal,
+            ..Default::default()
+        }
+    }
+
+    pub fn prune_terminal(&self) -> bool {
+        self.prune_terminal
+    }
+
+    // Policy keeping every transaction, which still knows about the ones dropped by this one (e.g.
+    // for what-if transactions, see `Engine::fork`).
+    pub fn keeping_all(&self) -> Self {
+        RetentionPolicy {
+            dropped: self.dropped.clone(),
+            ..Default::default()
+        }
+    }
+
+    // Records a processed transaction and returns the ids of the deposits which fell out of the
+    // dispute window.
+    pub fn tick(&mut self) -> Vec<u32> {
+        self.seq += 1;
+        let Some(window) = self.dispute_window else {
+            return Vec::new();
+        };
+
+        let mut expired = Vec::new();
+        while let Some((seq, id)) = self.tracked.front() {
+            if self.seq - seq <= window {
+                break;
+            }
+            expired.push(*id);
+            self.tracked.pop_front();
+        }
+        expired
+    }
+
+    // Starts the dispute window of a stored deposit.
+    pub fn track(&mut self, id: u32) {
+        if self.dispute_window.is_some() {
+            self.tracked.push_back((self.seq, id));
+        }
+    }
+
+    pub fn record_pruned(&mut self, count: u64) {
+        self.pruned += count;
+    }
+
+    // Records a deposit or withdrawal which got applied but isn't stored (anymore).
+    pub fn record_dropped(&self, id: u32) {
+        self.dropped.lock().unwrap().insert(id);
+    }
+
+    // Whether the id belongs to a deposit or withdrawal which got dropped.
+    pub fn dropped(&self, id: u32) -> bool {
+        self.dropped.lock().unwrap().contains(id)
+    }
+
+    // Number of transactions dropped from the ledger so far.
+    pub fn pruned(&self) -> u64 {
+        self.pruned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdRanges, RetentionPolicy};
+
+    #[test]
+    fn dispute_window_expiry() {
+        let mut retention = RetentionPolicy::new(Some(2), false);
+        retention.tick();
+        retention.track(1);
+        retention.tick();
+        retention.track(2);
+        assert!(retention.tick().is_empty());
+        assert_eq!(retention.tick(), vec![1]);
+        assert_eq!(retention.tick(), vec![2]);
+        assert!(retention.tick().is_empty());
+    }
+
+    #[test]
+    fn id_ranges() {
+        let mut ids = IdRanges::default();
+        for id in [1, 3, 2, 7, 5, 6, u32::MAX, 0] {
+            ids.insert(id);
+        }
+        assert_eq!(
+            ids.0.into_iter().collect::<Vec<_>>(),
+            [(0, 3), (5, 7), (u32::MAX, u32::MAX)]
+        );
+    }
+
+    #[test]
+    fn keeps_everything_by_default() {
+        let mut retention = RetentionPolicy::default();
+        retention.track(1);
+        assert!((0..10).all(|_| retention.tick().is_empty()));
+        assert!(!retention.prune_terminal());
+    }
+}
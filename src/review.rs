@@ -0,0 +1,73 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::payments::Tx;
+
+#[derive(Debug, Clone)]
+struct Review {
+    reason: String,
+    queued: Vec<Tx>,
+}
+
+// Clients placed under review by plugins (see `Plugin::review`), along with the transactions they
+// sent since. Queued transactions are neither applied nor rejected until the client gets released
+// (see `Engine::release`).
+#[derive(Debug, Clone, Default)]
+pub struct ReviewQueue(Arc<Mutex<BTreeMap<u16, Review>>>);
+
+impl ReviewQueue {
+    // Places the client under review, keeping the first reason if it already is.
+    pub fn place(&self, client: u16, reason: String) {
+        self.0.lock().unwrap().entry(client).or_insert(Review {
+            reason,
+            queued: Vec::new(),
+        });
+    }
+
+    pub fn is_under_review(&self, client: u16) -> bool {
+        self.0.lock().unwrap().contains_key(&client)
+    }
+
+    // Queues the transaction if its client is under review, handing it back otherwise.
+    pub fn hold(&self, tx: Tx) -> Option<Tx> {
+        match self.0.lock().unwrap().get_mut(&tx.client()) {
+            Some(review) => {
+                review.queued.push(tx);
+                None
+            }
+            None => Some(tx),
+        }
+    }
+
+    // Clients under review, along with the reason they were placed under review for.
+    pub fn clients(&self) -> Vec<(u16, String)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(client, review)| (*client, review.reason.clone()))
+            .collect()
+    }
+
+    // Transactions queued for the client, in arrival order.
+    pub fn queued(&self, client: u16) -> Vec<Tx> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&client)
+            .map(|review| review.queued.clone())
+            .unwrap_or_default()
+    }
+
+    // Takes the client out of review, returning its queued transactions.
+    pub fn release(&self, client: u16) -> Vec<Tx> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&client)
+            .map(|review| review.queued)
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,1220 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use futures::{stream, StreamExt};
+use tokio::{fs::File, io::AsyncWrite, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::{
+    batch::batched,
+    error::Error,
+    history::{self, RunStats},
+    import,
+    merge::{self, MergeWindow},
+    outcome::TxOutcome,
+    output::AtomicFile,
+    payments::{Engine, EngineBuilder, ForkChanges, Tx},
+    precision::{self, PrecisionPolicy},
+    prometheus,
+    rejects::Rejects,
+    remap::{self, Remapping},
+    reorder::{self, ReorderBuffer},
+    report::{self, Column, Extras, Format, Style},
+    sequence::{self, SequencePolicy, SequenceStats, Sequencer},
+    shard::{self, HotAccounts, Routing},
+    shipping::Shipper,
+    snapshot,
+    source::{self, BoxedSource, CsvParser, InputReader, SourceLayer},
+    storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger, TxsDal},
+    tags::Tags,
+};
+
+pub type InMemoryEngine = Engine<InMemoryAccountLedger, InMemoryTxLedger>;
+
+// How the transactions of several independent inputs reach the accounts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EngineMode {
+    // Every file is processed by its own engine, over a fork of the main one's ledgers, whose
+    // changes get merged into the main one at the end. Files must not change the same clients or
+    // transactions, otherwise they don't get merged.
+    Isolated,
+    // Files are read and parsed concurrently, while their transactions are applied by the main
+    // engine, in order within each file.
+    Shared,
+    // Files are read and parsed concurrently, while their transactions are applied by the main
+    // engine in input order (all of the first file, then all of the second one and so on), with the
+    // same results as processing them one after another.
+    Ordered,
+    // Files are read and parsed concurrently, while their transactions are applied by the main
+    // engine ordered by a global key across files (see `merge::MergeWindow`).
+    Merged(MergeWindow),
+}
+
+// Per-file (or per-shard, see `shard::process_sharded`) summary of a run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileOutcome {
+    pub path: String,
+    pub rows: u64,
+    pub rejected: u64,
+    pub ignored: u64,
+    // Set when the file couldn't be processed, or its results couldn't be merged.
+    pub error: Option<String>,
+}
+
+impl FileOutcome {
+    fn new(path: &str) -> Self {
+        FileOutcome {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, outcome: &TxOutcome) {
+        self.rows += 1;
+        match outcome {
+            TxOutcome::Applied => (),
+            TxOutcome::RejectedBusinessRule(err) | TxOutcome::Failed(err) => {
+                self.rejected += 1;
+                debug!("{}: {err}", self.path);
+            }
+            TxOutcome::Ignored(_) => self.ignored += 1,
+        }
+    }
+}
+
+// Expands the glob patterns (e.g. `shards/2024-06-01-*.csv`) among the given inputs into the files
+// matching them, in alphabetical order, for shells not expanding them (or quoted patterns). Other
+// inputs are kept as is.
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    for input in inputs {
+        if input == source::STDIN || !input.contains(['*', '?', '[']) {
+            expanded.push(input.clone());
+            continue;
+        }
+        let paths = glob::glob(input).map_err(|err| format!("Invalid pattern {input}: {err}"))?;
+        let start = expanded.len();
+        for path in paths {
+            let path = path.map_err(|err| format!("Error while expanding {input}: {err}"))?;
+            expanded.push(path.to_string_lossy().into_owned());
+        }
+        if expanded.len() == start {
+            return Err(format!("No input matches {input}"));
+        }
+    }
+    Ok(expanded)
+}
+
+// Processes the given files concurrently, with at most `jobs` of them in flight at once, into
+// `engine`, with the files read by `reader`, parsed by `parser` and `layer` applied on the
+// transactions of every file. Returns the outcome of every file, in the order they were given.
+pub async fn process_files(
+    engine: &mut InMemoryEngine,
+    paths: &[String],
+    jobs: usize,
+    mode: EngineMode,
+    reader: InputReader,
+    parser: CsvParser,
+    layer: SourceLayer,
+) -> Vec<FileOutcome> {
+    match mode {
+        EngineMode::Isolated => process_isolated(engine, paths, jobs, reader, parser, layer).await,
+        EngineMode::Shared => process_shared(engine, paths, jobs, reader, parser, layer).await,
+        EngineMode::Ordered => {
+            process_scheduled(engine, paths, &mut InputOrder, reader, parser, layer).await
+        }
+        EngineMode::Merged(window) => {
+            process_merged(engine, paths, window, reader, parser, layer).await
+        }
+    }
+}
+
+async fn process_isolated(
+    engine: &mut InMemoryEngine,
+    paths: &[String],
+    jobs: usize,
+    reader: InputReader,
+    parser: CsvParser,
+    layer: SourceLayer,
+) -> Vec<FileOutcome> {
+    let workers: Vec<_> = stream::iter(paths.iter().map(|path| {
+        let worker = engine.isolated_fork();
+        let path = path.clone();
+        let layer = layer.clone();
+        async move {
+            let outcome = FileOutcome::new(&path);
+            tokio::spawn(process_file(path, worker, reader, parser, layer))
+                .await
+                .map_err(|err| FileOutcome {
+                    error: Some(format!("Worker failed: {err}")),
+                    ..outcome
+                })
+        }
+    }))
+    .buffered(jobs.max(1))
+    .collect()
+    .await;
+
+    let mut outcomes = Vec::with_capacity(workers.len());
+    let mut merged = ForkChanges::default();
+    for worker in workers {
+        match worker {
+            Ok((mut outcome, Some(worker))) => {
+                if let Err(err) = engine.merge_fork(worker, &mut merged).await {
+                    outcome.error = Some(format!("Not merged: {err}"));
+                }
+                outcomes.push(outcome);
+            }
+            Ok((outcome, None)) | Err(outcome) => outcomes.push(outcome),
+        }
+    }
+    outcomes
+}
+
+async fn process_file<A, T>(
+    path: String,
+    mut engine: Engine<A, T>,
+    reader: InputReader,
+    parser: CsvParser,
+    layer: SourceLayer,
+) -> (FileOutcome, Option<Engine<A, T>>)
+where
+    A: AccountsDal + Send + Sync + Clone,
+    T: TxsDal + Send + Sync + Clone,
+{
+    let mut outcome = FileOutcome::new(&path);
+    let file = match reader.open(&path).await {
+        Ok(file) => file,
+        Err(err) => {
+            outcome.error = Some(format!("Error while opening file: {err}"));
+            return (outcome, None);
+        }
+    };
+
+    let mut txs = layer(batched(parser.parse(file), Arc::from(path.as_str())));
+    while let Some(record) = txs.next().await {
+        let res = match record {
+            Ok(tx) => engine.handle_tx(tx).await,
+            Err(err) => engine.skip_invalid(err),
+        };
+        outcome.record(&res);
+    }
+    (outcome, Some(engine))
+}
+
+enum Record {
+    Tx(usize, Result<Tx, Error>),
+    Failed(usize, String),
+}
+
+// Parses the file, sending its transactions tagged with its index.
+async fn read_file(
+    idx: usize,
+    path: String,
+    sender: mpsc::Sender<Record>,
+    reader: InputReader,
+    parser: CsvParser,
+    layer: SourceLayer,
+) {
+    let file = match reader.open(&path).await {
+        Ok(file) => file,
+        Err(err) => {
+            let err = format!("Error while opening file: {err}");
+            let _ = sender.send(Record::Failed(idx, err)).await;
+            return;
+        }
+    };
+    let mut txs = layer(batched(parser.parse(file), Arc::from(path.as_str())));
+    while let Some(record) = txs.next().await {
+        if sender.send(Record::Tx(idx, record)).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn process_shared(
+    engine: &mut InMemoryEngine,
+    paths: &[String],
+    jobs: usize,
+    reader: InputReader,
+    parser: CsvParser,
+    layer: SourceLayer,
+) -> Vec<FileOutcome> {
+    let (sender, mut receiver) = mpsc::channel(1024);
+    let readers = paths.to_vec();
+    let reading = tokio::spawn(async move {
+        stream::iter(readers.into_iter().enumerate())
+            .for_each_concurrent(jobs.max(1), |(idx, path)| {
+                let sender = sender.clone();
+                let layer = layer.clone();
+                read_file(idx, path, sender, reader, parser, layer)
+            })
+            .await
+    });
+
+    let mut outcomes: Vec<FileOutcome> = paths.iter().map(|path| FileOutcome::new(path)).collect();
+    while let Some(record) = receiver.recv().await {
+        match record {
+            Record::Tx(idx, Ok(tx)) => {
+                let res = engine.handle_tx(tx).await;
+                outcomes[idx].record(&res);
+            }
+            Record::Tx(idx, Err(err)) => outcomes[idx].record(&engine.skip_invalid(err)),
+            Record::Failed(idx, err) => outcomes[idx].error = Some(err),
+        }
+    }
+    if let Err(err) = reading.await {
+        for outcome in outcomes
+            .iter_mut()
+            .filter(|outcome| outcome.error.is_none())
+        {
+            outcome.error = Some(format!("Reader failed: {err}"));
+        }
+    }
+    outcomes
+}
+
+// Processes the given files into `engine` like `EngineMode::Shared`, with their transactions
+// merged by a global key. All the files are opened at once, as any of them can hold the next
+// transaction.
+async fn process_merged(
+    engine: &mut InMemoryEngine,
+    paths: &[String],
+    window: MergeWindow,
+    reader: InputReader,
+    parser: CsvParser,
+    layer: SourceLayer,
+) -> Vec<FileOutcome> {
+    let mut outcomes: Vec<FileOutcome> = paths.iter().map(|path| FileOutcome::new(path)).collect();
+    // Indices of the files opened, by source index.
+    let mut opened = Vec::with_capacity(paths.len());
+    let mut sources: Vec<BoxedSource> = Vec::with_capacity(paths.len());
+    for (idx, path) in paths.iter().enumerate() {
+        match reader.open(path).await {
+            Ok(file) => {
+                opened.push(idx);
+                sources.push(layer(batched(parser.parse(file), Arc::from(path.as_str()))));
+            }
+            Err(err) => outcomes[idx].error = Some(format!("Error while opening file: {err}")),
+        }
+    }
+
+    let mut txs = merge::merged_indexed(sources, window);
+    while let Some((source, record)) = txs.next().await {
+        let res = match record {
+            Ok(tx) => engine.handle_tx(tx).await,
+            Err(err) => engine.skip_invalid(err),
+        };
+        outcomes[opened[source]].record(&res);
+    }
+    outcomes
+}
+
+// Decides which input's next transaction gets applied when processing several inputs on a shared
+// engine, making the interleaving of their transactions reproducible, e.g. to replay the one which
+// triggered a bug.
+pub trait Scheduler: Send {
+    // Picks one of `ready`, the (increasing) indices of the inputs with a transaction ready.
+    fn pick(&mut self, ready: &[usize]) -> usize;
+}
+
+// Takes one transaction from every input in turn.
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobin {
+    last: Option<usize>,
+}
+
+impl Scheduler for RoundRobin {
+    fn pick(&mut self, ready: &[usize]) -> usize {
+        let next = ready
+            .iter()
+            .copied()
+            .find(|idx| self.last.is_none_or(|last| *idx > last))
+            .unwrap_or(ready[0]);
+        self.last = Some(next);
+        next
+    }
+}
+
+// Applies the inputs one after another, in the order they were given.
+#[derive(Debug, Clone, Default)]
+pub struct InputOrder;
+
+impl Scheduler for InputOrder {
+    fn pick(&mut self, ready: &[usize]) -> usize {
+        ready[0]
+    }
+}
+
+// Pseudo-random interleaving, which is the same for a given seed.
+#[derive(Debug, Clone)]
+pub struct Seeded(u64);
+
+impl Seeded {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift can't leave the all zeros state.
+        Seeded(seed.max(1))
+    }
+}
+
+impl Scheduler for Seeded {
+    fn pick(&mut self, ready: &[usize]) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        ready[(self.0 % ready.len() as u64) as usize]
+    }
+}
+
+// Processes the given files into `engine` like `EngineMode::Shared`, with the order their
+// transactions are applied in decided by `scheduler` instead of by which of them got parsed
+// first. All the files are read concurrently, but a transaction is only applied once every input
+// has one ready (or is exhausted), so the same scheduler always yields the same interleaving.
+pub async fn process_scheduled(
+    engine: &mut InMemoryEngine,
+    paths: &[String],
+    scheduler: &mut dyn Scheduler,
+    reader: InputReader,
+    parser: CsvParser,
+    layer: SourceLayer,
+) -> Vec<FileOutcome> {
+    let mut receivers: Vec<Option<mpsc::Receiver<Record>>> = Vec::with_capacity(paths.len());
+    for (idx, path) in paths.iter().enumerate() {
+        let (sender, receiver) = mpsc::channel(1024);
+        receivers.push(Some(receiver));
+        tokio::spawn(read_file(
+            idx,
+            path.clone(),
+            sender,
+            reader,
+            parser,
+            layer.clone(),
+        ));
+    }
+
+    let mut outcomes: Vec<FileOutcome> = paths.iter().map(|path| FileOutcome::new(path)).collect();
+    let mut heads: Vec<Option<Record>> = paths.iter().map(|_| None).collect();
+    loop {
+        for (head, receiver) in heads.iter_mut().zip(receivers.iter_mut()) {
+            if let (None, Some(inner)) = (&head, &mut *receiver) {
+                match inner.recv().await {
+                    Some(record) => *head = Some(record),
+                    None => *receiver = None,
+                }
+            }
+        }
+        let ready: Vec<usize> = (0..heads.len())
+            .filter(|idx| heads[*idx].is_some())
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        let picked = scheduler.pick(&ready);
+        let idx = if ready.contains(&picked) {
+            picked
+        } else {
+            ready[0]
+        };
+        match heads[idx].take() {
+            Some(Record::Tx(_, Ok(tx))) => {
+                let res = engine.handle_tx(tx).await;
+                outcomes[idx].record(&res);
+            }
+            Some(Record::Tx(_, Err(err))) => outcomes[idx].record(&engine.skip_invalid(err)),
+            Some(Record::Failed(_, err)) => outcomes[idx].error = Some(err),
+            None => (),
+        }
+    }
+    outcomes
+}
+
+// Configuration of a whole run over some inputs, from building the engine and loading its previous
+// state to reporting the accounts, as done by the `payments-engine` binary. Fields not set by
+// `Run::new` default to processing the inputs as they are and writing a CSV report to stdout.
+pub struct Run {
+    // Input files, a single one being streamed (and optionally sharded) rather than processed as
+    // described by `mode`.
+    pub inputs: Vec<String>,
+    pub builder: EngineBuilder<InMemoryAccountLedger, InMemoryTxLedger>,
+    pub reader: InputReader,
+    pub parser: CsvParser,
+    pub mode: EngineMode,
+    // Files processed concurrently, in `EngineMode::Isolated` and `EngineMode::Shared`.
+    pub jobs: usize,
+    pub shards: Option<usize>,
+    pub routing: Routing,
+    pub hot: Option<HotAccounts>,
+    // Opening balances to seed the accounts with (see `import::import_accounts`).
+    pub import_accounts: Option<PathBuf>,
+    // Snapshot of a previous run to start from, and the one to save once done.
+    pub state: Option<PathBuf>,
+    pub save_state: Option<PathBuf>,
+    // Clients unlocked before processing, like `Engine::unlock`.
+    pub unlock: Vec<u16>,
+    // Directory the snapshots of the run are shipped to for standbys (see `shipping::Shipper`).
+    pub ship_to: Option<PathBuf>,
+    pub ship_interval: Duration,
+    pub precision: PrecisionPolicy,
+    pub batch_id: Option<Arc<str>>,
+    pub remap: Option<PathBuf>,
+    pub reorder: Option<ReorderBuffer>,
+    pub sequence_policy: Option<SequencePolicy>,
+    // File the rejected rows are written to (see `rejects::Rejects`).
+    pub rejects: Option<PathBuf>,
+    pub metrics_file: Option<PathBuf>,
+    pub stats_history: Option<PathBuf>,
+    pub run_id: Option<String>,
+    // Client tags, and the ones the reported clients must all have.
+    pub tags: Option<PathBuf>,
+    pub tag: Vec<String>,
+    // Report written to stdout, unless given a file.
+    pub output: Option<PathBuf>,
+    pub format: Format,
+    pub style: Style,
+    pub columns: Vec<Column>,
+    pub sorted: bool,
+}
+
+// Engine a run ended with, along with the summary of its files (or shards), if several.
+pub struct Finished {
+    pub engine: InMemoryEngine,
+    pub outcomes: Vec<FileOutcome>,
+}
+
+impl Run {
+    pub fn new(
+        inputs: Vec<String>,
+        builder: EngineBuilder<InMemoryAccountLedger, InMemoryTxLedger>,
+    ) -> Self {
+        Run {
+            inputs,
+            builder,
+            reader: InputReader::default(),
+            parser: CsvParser::default(),
+            mode: EngineMode::Isolated,
+            jobs: 1,
+            shards: None,
+            routing: Routing::Modulo,
+            hot: None,
+            import_accounts: None,
+            state: None,
+            save_state: None,
+            unlock: Vec::new(),
+            ship_to: None,
+            ship_interval: Duration::from_secs(5),
+            precision: PrecisionPolicy::default(),
+            batch_id: None,
+            remap: None,
+            reorder: None,
+            sequence_policy: None,
+            rejects: None,
+            metrics_file: None,
+            stats_history: None,
+            run_id: None,
+            tags: None,
+            tag: Vec::new(),
+            output: None,
+            format: Format::Csv,
+            style: Style::default(),
+            columns: Vec::new(),
+            sorted: false,
+        }
+    }
+
+    // Runs the engine over the inputs and writes the report. Cancelling stops the processing of a
+    // single streamed input after the current transaction, the accounts still getting reported.
+    // Inputs which fail don't fail the run, but are reported by the outcomes.
+    pub async fn execute(self, cancel: &CancellationToken) -> anyhow::Result<Finished> {
+        let started_at = history::now_millis();
+        let mut builder = self.builder;
+        let rejects = self.rejects.as_ref().map(|_| Rejects::default());
+        if let Some(rejects) = &rejects {
+            builder = builder.plugin(rejects.clone());
+        }
+        let shipper = match &self.ship_to {
+            Some(dir) => Some(
+                Shipper::open(dir.clone(), self.ship_interval)
+                    .map_err(|err| anyhow!("Error while opening shipping destination: {err}"))?,
+            ),
+            None => None,
+        };
+        if let Some(shipper) = &shipper {
+            builder = builder.plugin(shipper.clone());
+        }
+        let mut engine = builder.build()?;
+        if let Some(path) = &self.import_accounts {
+            let file = File::open(path)
+                .await
+                .map_err(|err| anyhow!("Error while opening accounts file: {err}"))?;
+            let count = import::import_accounts(&mut engine, file)
+                .await
+                .map_err(|err| anyhow!("Error while importing accounts: {err}"))?;
+            info!("Imported {count} accounts");
+        }
+        if let Some(state) = &self.state {
+            let snapshot = snapshot::load(state)
+                .await
+                .map_err(|err| anyhow!("Error while loading state: {err}"))?;
+            engine
+                .restore(&snapshot)
+                .await
+                .map_err(|err| anyhow!("Invalid state: {err}"))?;
+            for expired in engine.expire_pending(history::now_millis() / 1000).await {
+                warn!(
+                    "Pending tx {} expired ({})",
+                    expired.tx.id(),
+                    expired.reason
+                );
+            }
+        }
+        for client in self.unlock.iter() {
+            engine
+                .unlock(*client)
+                .await
+                .map_err(|err| anyhow!("Error while unlocking client {client}: {err}"))?;
+            info!("Client {client} unlocked");
+        }
+        // Standbys always find a snapshot of the state the run started from.
+        if let Some(shipper) = &shipper {
+            shipper
+                .ship(&engine.snapshot().await)
+                .await
+                .map_err(|err| anyhow!("Error while shipping state: {err}"))?;
+        }
+        // Reports distinguish the opening balances from the activity of this run when starting
+        // from previously existing accounts.
+        let opening = if self.state.is_some() || self.import_accounts.is_some() {
+            Some(report::opening_balances(&engine).await)
+        } else {
+            None
+        };
+        let remapping = match &self.remap {
+            Some(path) => {
+                let file = File::open(path)
+                    .await
+                    .map_err(|err| anyhow!("Error while opening remapping file: {err}"))?;
+                let remapping = Remapping::load(file)
+                    .await
+                    .map_err(|err| anyhow!("Invalid remapping file: {err}"))?;
+                Some(remapping)
+            }
+            None => None,
+        };
+        let sequence_stats = SequenceStats::default();
+        let layer: SourceLayer = {
+            let (precision, batch_id, reorder, sequence_policy) = (
+                self.precision,
+                self.batch_id.clone(),
+                self.reorder,
+                self.sequence_policy,
+            );
+            let stats = sequence_stats.clone();
+            let rejects = rejects.clone();
+            Arc::new(move |mut txs: BoxedSource| {
+                txs = Box::new(precision::precise(txs, precision));
+                if let Some(rejects) = &rejects {
+                    txs = rejects.watch(txs);
+                }
+                if let Some(batch) = &batch_id {
+                    txs = batched(txs, batch.clone());
+                }
+                if let Some(remapping) = &remapping {
+                    txs = Box::new(remap::remapped(txs, remapping.clone()));
+                }
+                if let Some(config) = reorder {
+                    txs = Box::new(reorder::reordered(txs, config));
+                }
+                if let Some(policy) = sequence_policy {
+                    txs = Box::new(sequence::sequenced(
+                        txs,
+                        Sequencer::new(policy, stats.clone()),
+                    ));
+                }
+                txs
+            })
+        };
+
+        let mut outcomes = Vec::new();
+        if let [input] = self.inputs.as_slice() {
+            let file = self
+                .reader
+                .open(input)
+                .await
+                .map_err(|err| anyhow!("Error while opening file: {err}"))?;
+            let txs = layer(batched(self.parser.parse(file), Arc::from(input.as_str())));
+            match self.shards {
+                Some(shards) => {
+                    outcomes = shard::process_sharded(
+                        &mut engine,
+                        txs,
+                        shards,
+                        Arc::new(self.routing.clone()),
+                        self.hot,
+                    )
+                    .await;
+                }
+                None => {
+                    let report = engine.handle_source_until(txs, cancel).await?;
+                    if report.cancelled {
+                        warn!(
+                            "Processing interrupted after {} rows, {} rejected",
+                            report.rows, report.rejected
+                        );
+                    }
+                }
+            }
+        } else {
+            outcomes = process_files(
+                &mut engine,
+                &self.inputs,
+                self.jobs,
+                self.mode,
+                self.reader,
+                self.parser,
+                layer,
+            )
+            .await;
+        }
+        engine.shutdown();
+
+        if engine.quota().exceeded() {
+            warn!(
+                "Quota exceeded, {} deposits were rejected",
+                engine.quota().rejected_deposits()
+            );
+        }
+        if sequence_stats.gaps() > 0 || sequence_stats.out_of_order() > 0 {
+            warn!(
+                "{} sequence gaps and {} out of order transactions",
+                sequence_stats.gaps(),
+                sequence_stats.out_of_order()
+            );
+        }
+        for (client, reason) in engine.reviews().clients() {
+            warn!(
+                "Client {client} left under review ({reason}) with {} queued transactions",
+                engine.reviews().queued(client).len()
+            );
+        }
+        let pending = engine.pending().pending().await.len();
+        if pending > 0 {
+            warn!("{pending} transactions left pending approval");
+        }
+        if engine.lockouts().unlocks() > 0 {
+            info!("Unlocked {} accounts", engine.lockouts().unlocks());
+        }
+        for (client, chargebacks) in engine.lockouts().repeat_offenders() {
+            warn!("Client {client} charged back {chargebacks} times");
+        }
+        if engine.retention().pruned() > 0 {
+            info!(
+                "Pruned {} non-disputable transactions",
+                engine.retention().pruned()
+            );
+        }
+        info!(
+            "Clearing account balance (charged back funds): {}",
+            engine.clearing().balance()
+        );
+        for (plugin, entries) in engine.plugin_reports() {
+            for (key, value) in entries {
+                info!("{plugin}: {key} {value}");
+            }
+        }
+
+        if let Some(path) = &self.metrics_file {
+            prometheus::write_textfile(path, &engine)
+                .await
+                .map_err(|err| anyhow!("Error while writing metrics: {err}"))?;
+        }
+        if let Some(path) = &self.stats_history {
+            let run_id = self
+                .run_id
+                .clone()
+                .unwrap_or_else(|| format!("{started_at:x}-{:x}", std::process::id()));
+            let stats = RunStats::collect(&engine, run_id, self.inputs.clone(), started_at).await;
+            history::append(path, &stats)
+                .await
+                .map_err(|err| anyhow!("Error while appending to the stats history: {err}"))?;
+        }
+        if let Some(save_state) = &self.save_state {
+            snapshot::save(save_state, &engine.snapshot().await)
+                .await
+                .map_err(|err| anyhow!("Error while saving state: {err}"))?;
+        }
+        if let Some(shipper) = &shipper {
+            shipper
+                .ship(&engine.snapshot().await)
+                .await
+                .map_err(|err| anyhow!("Error while shipping state: {err}"))?;
+        }
+        if let (Some(path), Some(rejects)) = (&self.rejects, &rejects) {
+            let mut file = AtomicFile::create(path)
+                .await
+                .map_err(|err| anyhow!("Error while creating rejects file: {err}"))?;
+            rejects
+                .write(&mut file)
+                .await
+                .map_err(|err| anyhow!("Error while writing rejects: {err}"))?;
+            file.persist()
+                .await
+                .map_err(|err| anyhow!("Error while writing rejects: {err}"))?;
+        }
+
+        let tags = match &self.tags {
+            Some(path) => {
+                let file = File::open(path)
+                    .await
+                    .map_err(|err| anyhow!("Error while opening tags file: {err}"))?;
+                Tags::load(file)
+                    .await
+                    .map_err(|err| anyhow!("Invalid tags file: {err}"))?
+            }
+            None => Tags::default(),
+        };
+        let selected = &self.tag;
+        let filter = |client| selected.iter().all(|tag| tags.has_tag(client, tag));
+        let mut output = match &self.output {
+            Some(path) => Some(
+                AtomicFile::create(path)
+                    .await
+                    .map_err(|err| anyhow!("Error while creating the output file: {err}"))?,
+            ),
+            None => None,
+        };
+        let mut stdout = tokio::io::stdout();
+        let mut writer: &mut (dyn AsyncWrite + Send + Unpin) = match output.as_mut() {
+            Some(file) => file,
+            None => &mut stdout,
+        };
+        let columns = match (self.columns.is_empty(), &opening) {
+            (false, _) => Some(self.columns.clone()),
+            (true, Some(_)) if self.format == Format::Csv => Some(Column::PERIOD.to_vec()),
+            (true, _) => None,
+        };
+        match columns {
+            Some(columns) => {
+                let mut extras = Extras {
+                    opening: opening.as_ref(),
+                    tags: Some(&tags),
+                    ..Default::default()
+                };
+                if columns.iter().any(|column| column.needs_txs()) {
+                    extras.txs = report::tx_activity(&engine).await;
+                }
+                report::write_columns(
+                    &engine,
+                    &mut writer,
+                    &columns,
+                    &extras,
+                    &self.style,
+                    filter,
+                    self.sorted,
+                )
+                .await
+            }
+            None => {
+                report::write_accounts_as(
+                    &engine,
+                    &mut writer,
+                    self.format,
+                    &self.style,
+                    filter,
+                    self.sorted,
+                )
+                .await
+            }
+        }
+        .map_err(|err| anyhow!("Error while writing accounts: {err}"))?;
+        if let Some(file) = output {
+            file.persist()
+                .await
+                .map_err(|err| anyhow!("Error while writing the output file: {err}"))?;
+        }
+
+        Ok(Finished { engine, outcomes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::{BigDecimal, Zero};
+
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        account::Account,
+        merge::MergeWindow,
+        payments::{Engine, Tx, TxType},
+        quota::Quota,
+        reorder::ReorderKey,
+        retention::RetentionPolicy,
+        source::{self, CsvParser, InputReader},
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger, TxsDal},
+    };
+
+    use super::{
+        expand_inputs, process_files, process_scheduled, EngineMode, RoundRobin, Run, Scheduler,
+        Seeded,
+    };
+
+    async fn write_inputs(dir: &std::path::Path) -> Vec<String> {
+        let inputs = [
+            "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0",
+            "type,client,tx,amount\ndeposit,2,3,2.0\ndeposit,2,4,1.0",
+        ];
+        let mut paths = Vec::new();
+        for (idx, input) in inputs.iter().enumerate() {
+            let path = dir.join(format!("{idx}.csv"));
+            tokio::fs::write(&path, input).await.unwrap();
+            paths.push(path.to_string_lossy().to_string());
+        }
+        paths.push(dir.join("missing.csv").to_string_lossy().to_string());
+        paths
+    }
+
+    async fn check_mode(mode: EngineMode, dir: &str) {
+        let dir = std::env::temp_dir().join(dir);
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let paths = write_inputs(&dir).await;
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let outcomes = process_files(
+            &mut engine,
+            &paths,
+            2,
+            mode,
+            InputReader::default(),
+            CsvParser::default(),
+            source::identity(),
+        )
+        .await;
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!((outcomes[0].rows, outcomes[0].rejected), (2, 1));
+        assert_eq!((outcomes[1].rows, outcomes[1].rejected), (2, 0));
+        assert!(outcomes[1].error.is_none());
+        assert!(outcomes[2].error.is_some());
+
+        let account = engine.account(2).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(3));
+        assert_eq!(engine.accounts().await.len(), 2);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn execute_runs() {
+        let dir = std::env::temp_dir().join("payments-engine-runs");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let paths = write_inputs(&dir).await;
+        let (state, output) = (dir.join("state.json"), dir.join("accounts.csv"));
+        let builder = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let run = Run {
+            save_state: Some(state.clone()),
+            output: Some(output.clone()),
+            sorted: true,
+            ..Run::new(paths.clone(), builder)
+        };
+        let finished = run.execute(&CancellationToken::new()).await.unwrap();
+        assert_eq!(finished.outcomes.len(), 3);
+        assert!(finished.outcomes[2].error.is_some());
+        let report = tokio::fs::read_to_string(&output).await.unwrap();
+        assert_eq!(
+            report,
+            "client,available,held,total,locked\n1,1.0,0,1.0,false\n2,3.0,0,3.0,false\n"
+        );
+
+        // Later runs report the activity since the saved state.
+        let input = dir.join("later.csv");
+        tokio::fs::write(&input, "type,client,tx,amount\ndeposit,2,5,1.5")
+            .await
+            .unwrap();
+        let builder = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let run = Run {
+            state: Some(state),
+            output: Some(output.clone()),
+            sorted: true,
+            ..Run::new(vec![input.to_string_lossy().to_string()], builder)
+        };
+        let finished = run.execute(&CancellationToken::new()).await.unwrap();
+        assert!(finished.outcomes.is_empty());
+        let report = tokio::fs::read_to_string(&output).await.unwrap();
+        assert!(report.starts_with("client,opening,activity,"));
+        assert!(report.ends_with("\n2,3.0,1.5,4.5,0,4.5,false\n"));
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn glob_inputs() {
+        let dir = std::env::temp_dir().join("payments-engine-globs");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let paths = write_inputs(&dir).await;
+
+        let pattern = dir.join("*.csv").to_string_lossy().to_string();
+        let inputs = [pattern, "-".to_string(), paths[2].clone()];
+        assert_eq!(
+            expand_inputs(&inputs).unwrap(),
+            [&paths[0], &paths[1], "-", &paths[2]]
+        );
+        let unmatched = dir.join("*.json").to_string_lossy().to_string();
+        assert!(expand_inputs(&[unmatched]).is_err());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn process_isolated_files() {
+        check_mode(EngineMode::Isolated, "payments-engine-isolated").await;
+    }
+
+    #[tokio::test]
+    async fn process_shared_files() {
+        check_mode(EngineMode::Shared, "payments-engine-shared").await;
+    }
+
+    #[tokio::test]
+    async fn process_ordered_files() {
+        check_mode(EngineMode::Ordered, "payments-engine-ordered").await;
+    }
+
+    #[tokio::test]
+    async fn process_merged_files() {
+        let window = MergeWindow {
+            key: ReorderKey::Timestamp,
+            lateness: Duration::from_secs(1),
+        };
+        check_mode(EngineMode::Merged(window), "payments-engine-merged").await;
+    }
+
+    #[tokio::test]
+    async fn isolated_conflicts_are_not_merged() {
+        let dir = std::env::temp_dir().join("payments-engine-conflicts");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut paths = Vec::new();
+        for idx in 0..2 {
+            let path = dir.join(format!("{idx}.csv"));
+            let input = format!("type,client,tx,amount\ndeposit,1,{idx},1.0");
+            tokio::fs::write(&path, input).await.unwrap();
+            paths.push(path.to_string_lossy().to_string());
+        }
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let outcomes = process_files(
+            &mut engine,
+            &paths,
+            2,
+            EngineMode::Isolated,
+            InputReader::Mmap,
+            CsvParser::Fast,
+            source::identity(),
+        )
+        .await;
+        assert!(outcomes[0].error.is_none());
+        assert!(outcomes[1].error.is_some());
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(1));
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn isolated_files_start_from_loaded_state() {
+        let dir = std::env::temp_dir().join("payments-engine-loaded");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let inputs = [
+            "type,client,tx,amount\ndispute,1,1,\ndeposit,1,2,1.0",
+            "type,client,tx,amount\ndeposit,2,3,2.0\nwithdrawal,1,4,100.0\nwithdrawal,3,5,1.0",
+        ];
+        let mut paths = Vec::new();
+        for (idx, input) in inputs.iter().enumerate() {
+            let path = dir.join(format!("{idx}.csv"));
+            tokio::fs::write(&path, input).await.unwrap();
+            paths.push(path.to_string_lossy().to_string());
+        }
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let deposit = Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(5)));
+        AccountsDal::insert(
+            &mut engine,
+            Account::new(1, BigDecimal::from(5), BigDecimal::zero(), false),
+        )
+        .await;
+        TxsDal::insert(&engine, deposit).await;
+
+        let outcomes = process_files(
+            &mut engine,
+            &paths,
+            2,
+            EngineMode::Isolated,
+            InputReader::default(),
+            CsvParser::default(),
+            source::identity(),
+        )
+        .await;
+        assert!(outcomes.iter().all(|outcome| outcome.error.is_none()));
+        assert_eq!(outcomes[1].rejected, 2);
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(1));
+        assert_eq!(account.lock().await.held(), BigDecimal::from(5));
+        assert!(engine.tx(1).await.unwrap().lock().await.disputed());
+        let account = engine.account(2).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(2));
+        let account = engine.account(3).await.unwrap();
+        assert_eq!(account.lock().await.total(), BigDecimal::zero());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn isolated_files_prune_transactions() {
+        let dir = std::env::temp_dir().join("payments-engine-pruned");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let inputs = [
+            "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,1.0\ndeposit,1,3,1.0\ndispute,1,1,",
+            "type,client,tx,amount\ndeposit,2,4,2.0",
+        ];
+        let mut paths = Vec::new();
+        for (idx, input) in inputs.iter().enumerate() {
+            let path = dir.join(format!("{idx}.csv"));
+            tokio::fs::write(&path, input).await.unwrap();
+            paths.push(path.to_string_lossy().to_string());
+        }
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .retention(RetentionPolicy::new(Some(1), false))
+        .build()
+        .unwrap();
+
+        let outcomes = process_files(
+            &mut engine,
+            &paths,
+            2,
+            EngineMode::Isolated,
+            InputReader::default(),
+            CsvParser::default(),
+            source::identity(),
+        )
+        .await;
+        assert!(outcomes.iter().all(|outcome| outcome.error.is_none()));
+        // The first deposit fell out of the dispute window before being disputed.
+        assert_eq!(outcomes[0].rejected, 1);
+        assert!(engine.tx(1).await.is_none());
+        assert!(engine.tx(3).await.is_some());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn quota_holds_across_isolated_files() {
+        let dir = std::env::temp_dir().join("payments-engine-quota");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut paths = Vec::new();
+        for idx in 0..2 {
+            let path = dir.join(format!("{idx}.csv"));
+            let input = format!("type,client,tx,amount\ndeposit,{idx},{idx},10.0");
+            tokio::fs::write(&path, input).await.unwrap();
+            paths.push(path.to_string_lossy().to_string());
+        }
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .quota(Quota::new(None, Some(BigDecimal::from(10))))
+        .build()
+        .unwrap();
+
+        let outcomes = process_files(
+            &mut engine,
+            &paths,
+            2,
+            EngineMode::Isolated,
+            InputReader::default(),
+            CsvParser::default(),
+            source::identity(),
+        )
+        .await;
+        assert_eq!(outcomes[0].rejected + outcomes[1].rejected, 1);
+        assert_eq!(engine.quota().rejected_deposits(), 1);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    // Always picks the last ready input.
+    struct Last;
+
+    impl Scheduler for Last {
+        fn pick(&mut self, ready: &[usize]) -> usize {
+            ready[ready.len() - 1]
+        }
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut scheduler = RoundRobin::default();
+        let picks: Vec<usize> = [&[0, 1, 2][..], &[0, 1, 2], &[0, 2], &[0, 2], &[0]]
+            .iter()
+            .map(|ready| scheduler.pick(ready))
+            .collect();
+        assert_eq!(picks, vec![0, 1, 2, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn scheduled_interleaving() {
+        let dir = std::env::temp_dir().join("payments-engine-scheduled");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut paths = Vec::new();
+        for (idx, input) in ["deposit,1,1,5.0\ndeposit,2,3,1.0", "withdrawal,1,2,5.0"]
+            .iter()
+            .enumerate()
+        {
+            let path = dir.join(format!("{idx}.csv"));
+            let input = format!("type,client,tx,amount\n{input}");
+            tokio::fs::write(&path, input).await.unwrap();
+            paths.push(path.to_string_lossy().to_string());
+        }
+
+        let mut schedulers: Vec<Box<dyn Scheduler>> = vec![
+            Box::new(RoundRobin::default()),
+            Box::new(Last),
+            Box::new(Seeded::new(7)),
+        ];
+        let mut available = Vec::new();
+        for scheduler in schedulers.iter_mut() {
+            let mut engine = Engine::new(
+                InMemoryAccountLedger::default(),
+                InMemoryTxLedger::default(),
+            );
+            let outcomes = process_scheduled(
+                &mut engine,
+                &paths,
+                scheduler.as_mut(),
+                InputReader::default(),
+                CsvParser::default(),
+                source::identity(),
+            )
+            .await;
+            assert_eq!(outcomes[0].rows + outcomes[1].rows, 3);
+            let account = engine.account(1).await.unwrap();
+            let inner = account.lock().await.available();
+            available.push(inner);
+        }
+        // The withdrawal only goes through when applied after the deposit.
+        assert_eq!(available[0], BigDecimal::from(0));
+        assert_eq!(available[1], BigDecimal::from(5));
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
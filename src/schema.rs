@@ -0,0 +1,318 @@
+use std::{convert::TryFrom, str::FromStr, sync::Arc};
+
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Account,
+    error::Error,
+    payments::{Tx, TxType},
+};
+
+// Versioned representations of the ledger entries, used whenever accounts or transactions leave
+// the process (snapshots, logs, network). Every record carries the version of the schema it was
+// written with, so records written by older versions of the crate can still be loaded: they are
+// upgraded step by step to the latest version before being converted to the in-memory models.
+//
+// When `Account` or `Tx` change in a way that affects their persisted form, add a new `V<n>`
+// struct and variant, write the `V<n-1> -> V<n>` upgrade and point the conversions to it.
+
+pub const ACCOUNT_SCHEMA_VERSION: u32 = 1;
+pub const TX_SCHEMA_VERSION: u32 = 3;
+
+// Amounts are persisted as strings to not lose precision and to not depend on the `serde`
+// feature of `bigdecimal` (see `payments::deserialize_explicitly`).
+pub fn parse_amount(amount: &str) -> Result<BigDecimal, Error> {
+    BigDecimal::from_str(amount).map_err(|_| Error::InvalidAmount(amount.to_string()))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountV1 {
+    pub client: u16,
+    pub available: String,
+    pub held: String,
+    pub locked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "version")]
+pub enum VersionedAccount {
+    #[serde(rename = "1")]
+    V1(AccountV1),
+}
+
+impl VersionedAccount {
+    pub fn version(&self) -> u32 {
+        match self {
+            VersionedAccount::V1(_) => 1,
+        }
+    }
+
+    // Upgrades the record to the latest schema version.
+    pub fn upgrade(self) -> AccountV1 {
+        match self {
+            VersionedAccount::V1(inner) => inner,
+        }
+    }
+}
+
+impl From<&Account> for VersionedAccount {
+    fn from(account: &Account) -> Self {
+        VersionedAccount::V1(AccountV1 {
+            client: account.client_id(),
+            available: account.available().to_string(),
+            held: account.held().to_string(),
+            locked: account.is_locked(),
+        })
+    }
+}
+
+impl TryFrom<VersionedAccount> for Account {
+    type Error = Error;
+
+    fn try_from(record: VersionedAccount) -> Result<Self, Self::Error> {
+        let latest = record.upgrade();
+        Ok(Account::new(
+            latest.client,
+            parse_amount(&latest.available)?,
+            parse_amount(&latest.held)?,
+            latest.locked,
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxV1 {
+    pub r#type: TxType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<String>,
+    pub disputed: bool,
+}
+
+// Adds the ingestion batch of the transaction (see `batch::batched`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxV2 {
+    pub r#type: TxType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<String>,
+    pub disputed: bool,
+    pub batch: Option<String>,
+}
+
+impl From<TxV1> for TxV2 {
+    fn from(tx: TxV1) -> Self {
+        TxV2 {
+            r#type: tx.r#type,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            disputed: tx.disputed,
+            batch: None,
+        }
+    }
+}
+
+// Adds the sequence number and the event time of the transaction, so that the last activity of
+// the accounts (see `report` and `activity`) survives snapshots.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxV3 {
+    pub r#type: TxType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<String>,
+    pub disputed: bool,
+    pub batch: Option<String>,
+    pub seq: Option<u64>,
+    pub timestamp: Option<u64>,
+}
+
+impl From<TxV2> for TxV3 {
+    fn from(tx: TxV2) -> Self {
+        TxV3 {
+            r#type: tx.r#type,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            disputed: tx.disputed,
+            batch: tx.batch,
+            seq: None,
+            timestamp: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "version")]
+pub enum VersionedTx {
+    #[serde(rename = "1")]
+    V1(TxV1),
+    #[serde(rename = "2")]
+    V2(TxV2),
+    #[serde(rename = "3")]
+    V3(TxV3),
+}
+
+impl VersionedTx {
+    pub fn version(&self) -> u32 {
+        match self {
+            VersionedTx::V1(_) => 1,
+            VersionedTx::V2(_) => 2,
+            VersionedTx::V3(_) => 3,
+        }
+    }
+
+    // Upgrades the record to the latest schema version.
+    pub fn upgrade(self) -> TxV3 {
+        match self {
+            VersionedTx::V1(inner) => TxV2::from(inner).into(),
+            VersionedTx::V2(inner) => inner.into(),
+            VersionedTx::V3(inner) => inner,
+        }
+    }
+}
+
+impl From<&Tx> for VersionedTx {
+    fn from(tx: &Tx) -> Self {
+        VersionedTx::V3(TxV3 {
+            r#type: tx.tx_type().clone(),
+            client: tx.client(),
+            tx: tx.id(),
+            amount: tx.amount().map(|amount| amount.to_string()),
+            disputed: tx.disputed(),
+            batch: tx.batch().map(str::to_string),
+            seq: tx.seq(),
+            timestamp: tx.timestamp(),
+        })
+    }
+}
+
+impl TryFrom<VersionedTx> for Tx {
+    type Error = Error;
+
+    fn try_from(record: VersionedTx) -> Result<Self, Self::Error> {
+        let latest = record.upgrade();
+        let amount = match latest.amount {
+            Some(inner) => Some(parse_amount(&inner)?),
+            None => None,
+        };
+        let mut tx = Tx::new(latest.r#type, latest.client, latest.tx, amount);
+        if latest.disputed {
+            tx.mark_disputed();
+        }
+        tx.set_batch(latest.batch.map(Arc::from));
+        if let Some(seq) = latest.seq {
+            tx = tx.with_seq(seq);
+        }
+        if let Some(timestamp) = latest.timestamp {
+            tx = tx.with_timestamp(timestamp);
+        }
+        Ok(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryFrom, str::FromStr};
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        account::Account,
+        error::Error,
+        payments::{Tx, TxType},
+    };
+
+    use super::{VersionedAccount, VersionedTx, ACCOUNT_SCHEMA_VERSION, TX_SCHEMA_VERSION};
+
+    #[test]
+    fn account_roundtrip() {
+        let account = Account::new(
+            1,
+            BigDecimal::from_str("1.5").unwrap(),
+            BigDecimal::from_str("0.0001").unwrap(),
+            true,
+        );
+        let record = VersionedAccount::from(&account);
+        assert_eq!(record.version(), ACCOUNT_SCHEMA_VERSION);
+
+        let json = serde_json::to_string(&record).unwrap();
+        let decoded: VersionedAccount = serde_json::from_str(&json).unwrap();
+        let restored = Account::try_from(decoded).unwrap();
+        assert_eq!(restored.client_id(), 1);
+        assert_eq!(restored.available().to_string(), "1.5");
+        assert_eq!(restored.held().to_string(), "0.0001");
+        assert!(restored.is_locked());
+    }
+
+    #[test]
+    fn account_load_v1() {
+        let json = r#"{"version":"1","client":2,"available":"10.1","held":"0","locked":false}"#;
+        let record: VersionedAccount = serde_json::from_str(json).unwrap();
+        let account = Account::try_from(record).unwrap();
+        assert_eq!(account.client_id(), 2);
+        assert_eq!(account.available().to_string(), "10.1");
+    }
+
+    #[test]
+    fn account_load_unknown_version() {
+        let json = r#"{"version":"0","client":2,"available":"10.1","held":"0","locked":false}"#;
+        assert!(serde_json::from_str::<VersionedAccount>(json).is_err());
+    }
+
+    #[test]
+    fn account_load_invalid_amount() {
+        let json = r#"{"version":"1","client":2,"available":"abc","held":"0","locked":false}"#;
+        let record: VersionedAccount = serde_json::from_str(json).unwrap();
+        let res = Account::try_from(record);
+        assert_eq!(res.err(), Some(Error::InvalidAmount("abc".to_string())));
+    }
+
+    #[test]
+    fn tx_roundtrip() {
+        let mut tx = Tx::new(
+            TxType::Deposit,
+            3,
+            7,
+            Some(BigDecimal::from_str("2.25").unwrap()),
+        );
+        tx.mark_disputed();
+        let tx = tx
+            .with_batch("monday.csv".into())
+            .with_seq(4)
+            .with_timestamp(1_700_000_000_000);
+        let record = VersionedTx::from(&tx);
+        assert_eq!(record.version(), TX_SCHEMA_VERSION);
+
+        let json = serde_json::to_string(&record).unwrap();
+        let decoded: VersionedTx = serde_json::from_str(&json).unwrap();
+        let restored = Tx::try_from(decoded).unwrap();
+        assert_eq!(restored.tx_type(), &TxType::Deposit);
+        assert_eq!(restored.client(), 3);
+        assert_eq!(restored.id(), 7);
+        assert_eq!(restored.amount().unwrap().to_string(), "2.25");
+        assert!(restored.disputed());
+        assert_eq!(restored.batch(), Some("monday.csv"));
+        assert_eq!(restored.seq(), Some(4));
+        assert_eq!(restored.timestamp(), Some(1_700_000_000_000));
+    }
+
+    #[test]
+    fn tx_load_v1() {
+        let json =
+            r#"{"version":"1","type":"deposit","client":3,"tx":7,"amount":"1","disputed":false}"#;
+        let record: VersionedTx = serde_json::from_str(json).unwrap();
+        let tx = Tx::try_from(record).unwrap();
+        assert_eq!((tx.id(), tx.batch()), (7, None));
+    }
+
+    #[test]
+    fn tx_load_v2() {
+        let json = r#"{"version":"2","type":"deposit","client":3,"tx":7,"amount":"1","disputed":false,"batch":"a.csv"}"#;
+        let record: VersionedTx = serde_json::from_str(json).unwrap();
+        let tx = Tx::try_from(record).unwrap();
+        assert_eq!(tx.batch(), Some("a.csv"));
+        assert_eq!((tx.seq(), tx.timestamp()), (None, None));
+    }
+}
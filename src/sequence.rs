@@ -0,0 +1,269 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use futures::{stream, StreamExt};
+use tracing::warn;
+
+use crate::{error::Error, payments::Tx, source::TxSource};
+
+// What happens to the transactions which don't follow their client's sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequencePolicy {
+    // Transactions after a gap or out of order are rejected.
+    Reject,
+    // Transactions after a gap are held back until the missing ones arrive, with at most
+    // `capacity` of them per client, past which the gap is given up on. Out of order transactions
+    // are rejected.
+    Reorder { capacity: usize },
+    // All transactions are applied in the order they arrive, gaps and out of order ones being only
+    // counted and logged.
+    Report,
+}
+
+// Sequencing anomalies, shared between all the sequencers it was given to.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceStats {
+    gaps: Arc<AtomicU64>,
+    out_of_order: Arc<AtomicU64>,
+}
+
+impl SequenceStats {
+    pub fn gaps(&self) -> u64 {
+        self.gaps.load(Ordering::Relaxed)
+    }
+
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order.load(Ordering::Relaxed)
+    }
+}
+
+// Checks the sequence numbers of the transactions of every client, which are expected to increase
+// by one from the first one seen. Transactions without a sequence number aren't checked.
+pub struct Sequencer {
+    policy: SequencePolicy,
+    stats: SequenceStats,
+    next: HashMap<u16, u64>,
+    buffered: HashMap<u16, BTreeMap<u64, Tx>>,
+}
+
+impl Sequencer {
+    pub fn new(policy: SequencePolicy, stats: SequenceStats) -> Self {
+        Sequencer {
+            policy,
+            stats,
+            next: HashMap::new(),
+            buffered: HashMap::new(),
+        }
+    }
+
+    // Sequences a transaction, returning the records ready to be applied.
+    pub fn push(&mut self, tx: Tx) -> Vec<Result<Tx, Error>> {
+        let Some(seq) = tx.seq() else {
+            return vec![Ok(tx)];
+        };
+        let client = tx.client();
+        let expected = *self.next.entry(client).or_insert(seq);
+        let duplicate = self
+            .buffered
+            .get(&client)
+            .is_some_and(|buffer| buffer.contains_key(&seq));
+
+        if seq < expected || duplicate {
+            self.stats.out_of_order.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Tx {} of client {client} is out of order (seq {seq}, expected {expected})",
+                tx.id()
+            );
+            return match self.policy {
+                SequencePolicy::Report => vec![Ok(tx)],
+                _ => vec![Err(Error::OutOfOrder(tx.id()))],
+            };
+        }
+        if seq == expected {
+            self.next.insert(client, seq + 1);
+            let mut ready = vec![Ok(tx)];
+            self.release(client, &mut ready);
+            return ready;
+        }
+
+        match self.policy {
+            SequencePolicy::Reorder { capacity } => {
+                let buffer = self.buffered.entry(client).or_default();
+                buffer.insert(seq, tx);
+                if buffer.len() > capacity {
+                    return self.skip_gap(client);
+                }
+                Vec::new()
+            }
+            SequencePolicy::Reject | SequencePolicy::Report => {
+                self.gap(client, expected, seq);
+                self.next.insert(client, seq + 1);
+                match self.policy {
+                    SequencePolicy::Reject => vec![Err(Error::SequenceGap(tx.id()))],
+                    _ => vec![Ok(tx)],
+                }
+            }
+        }
+    }
+
+    // Releases the transactions still held back at the end of the input, giving up on their gaps.
+    pub fn finish(&mut self) -> Vec<Result<Tx, Error>> {
+        let mut clients: Vec<u16> = self.buffered.keys().copied().collect();
+        clients.sort_unstable();
+        let mut ready = Vec::new();
+        for client in clients {
+            while self
+                .buffered
+                .get(&client)
+                .is_some_and(|buffer| !buffer.is_empty())
+            {
+                ready.extend(self.skip_gap(client));
+            }
+        }
+        ready
+    }
+
+    fn gap(&self, client: u16, expected: u64, seq: u64) {
+        self.stats.gaps.fetch_add(1, Ordering::Relaxed);
+        warn!("Sequence gap for client {client}: expected {expected}, got {seq}");
+    }
+
+    // Moves past the gap before the first held back transaction of the client.
+    fn skip_gap(&mut self, client: u16) -> Vec<Result<Tx, Error>> {
+        let Some(first) = self
+            .buffered
+            .get(&client)
+            .and_then(|buffer| buffer.keys().next().copied())
+        else {
+            return Vec::new();
+        };
+        self.gap(client, self.next[&client], first);
+        self.next.insert(client, first);
+        let mut ready = Vec::new();
+        self.release(client, &mut ready);
+        ready
+    }
+
+    fn release(&mut self, client: u16, ready: &mut Vec<Result<Tx, Error>>) {
+        let Some(buffer) = self.buffered.get_mut(&client) else {
+            return;
+        };
+        let next = self.next.entry(client).or_default();
+        while let Some(tx) = buffer.remove(next) {
+            *next += 1;
+            ready.push(Ok(tx));
+        }
+    }
+}
+
+// Applies the sequencer on the transactions of `source`.
+pub fn sequenced<'s>(source: impl TxSource + 's, sequencer: Sequencer) -> impl TxSource + 's {
+    stream::unfold(
+        (source, sequencer, VecDeque::new(), false),
+        |(mut source, mut sequencer, mut ready, mut done)| async move {
+            loop {
+                if let Some(record) = ready.pop_front() {
+                    return Some((record, (source, sequencer, ready, done)));
+                }
+                if done {
+                    return None;
+                }
+                match source.next().await {
+                    Some(Ok(tx)) => ready.extend(sequencer.push(tx)),
+                    Some(Err(err)) => ready.push_back(Err(err)),
+                    None => {
+                        done = true;
+                        ready.extend(sequencer.finish());
+                    }
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use crate::{
+        error::Error,
+        payments::{Tx, TxType},
+        source,
+    };
+
+    use super::{sequenced, SequencePolicy, SequenceStats, Sequencer};
+
+    async fn run(policy: SequencePolicy, seqs: &[u64]) -> (Vec<Result<u32, Error>>, SequenceStats) {
+        let txs = seqs
+            .iter()
+            .enumerate()
+            .map(|(id, seq)| Tx::new(TxType::Deposit, 1, id as u32, None).with_seq(*seq))
+            .collect::<Vec<_>>();
+        let stats = SequenceStats::default();
+        let records = sequenced(
+            source::from_iter(txs),
+            Sequencer::new(policy, stats.clone()),
+        )
+        .map(|record| record.map(|tx| tx.id()))
+        .collect()
+        .await;
+        (records, stats)
+    }
+
+    #[tokio::test]
+    async fn reject_policy() {
+        let (records, stats) = run(SequencePolicy::Reject, &[1, 2, 4, 3, 5]).await;
+        assert_eq!(
+            records,
+            vec![
+                Ok(0),
+                Ok(1),
+                Err(Error::SequenceGap(2)),
+                Err(Error::OutOfOrder(3)),
+                Ok(4)
+            ]
+        );
+        assert_eq!((stats.gaps(), stats.out_of_order()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn report_policy() {
+        let (records, stats) = run(SequencePolicy::Report, &[1, 3, 2]).await;
+        assert_eq!(records, vec![Ok(0), Ok(1), Ok(2)]);
+        assert_eq!((stats.gaps(), stats.out_of_order()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn reorder_policy() {
+        let (records, stats) = run(SequencePolicy::Reorder { capacity: 2 }, &[1, 3, 4, 2, 7]).await;
+        assert_eq!(records, vec![Ok(0), Ok(3), Ok(1), Ok(2), Ok(4)]);
+        assert_eq!((stats.gaps(), stats.out_of_order()), (1, 0));
+
+        // Past the capacity, the gap is given up on and late transactions are rejected.
+        let (records, stats) = run(SequencePolicy::Reorder { capacity: 1 }, &[1, 3, 4, 2]).await;
+        assert_eq!(
+            records,
+            vec![Ok(0), Ok(1), Ok(2), Err(Error::OutOfOrder(3))]
+        );
+        assert_eq!((stats.gaps(), stats.out_of_order()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn unsequenced_txs_pass_through() {
+        let txs = source::from_csv(
+            "type,client,tx,amount,seq\ndeposit,1,1,1.0,\ndeposit,1,2,1.0,5".as_bytes(),
+        );
+        let stats = SequenceStats::default();
+        let records: Vec<_> = sequenced(txs, Sequencer::new(SequencePolicy::Reject, stats))
+            .map(|record| record.map(|tx| tx.seq()))
+            .collect()
+            .await;
+        assert_eq!(records, vec![Ok(None), Ok(Some(5))]);
+    }
+}
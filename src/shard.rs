@@ -0,0 +1,385 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
+
+use futures::StreamExt;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info};
+
+use crate::{
+    payments::Tx,
+    runner::{FileOutcome, InMemoryEngine},
+    source::TxSource,
+};
+
+// Decides which shard handles the transactions of a client. Every transaction of a client must be
+// routed to the same shard, for shards not to share accounts.
+pub trait ShardRouter: Send + Sync {
+    // Shard of the client, lower than `shards`.
+    fn shard(&self, client: u16, shards: usize) -> usize;
+}
+
+// Routing strategies available out of the box.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Routing {
+    // Client id modulo the number of shards.
+    Modulo,
+    // Highest random weight hashing: only the clients of an added or removed shard move when the
+    // number of shards changes.
+    Rendezvous,
+    // Explicit inclusive client id ranges and their shards, e.g. to give a skewed range of clients
+    // shards of their own. Clients outside of the ranges fall back to `Modulo`.
+    Ranges(Vec<(u16, u16, usize)>),
+}
+
+// SplitMix64 finalizer, mixing the bits of the input.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl ShardRouter for Routing {
+    fn shard(&self, client: u16, shards: usize) -> usize {
+        let shards = shards.max(1);
+        match self {
+            Routing::Modulo => client as usize % shards,
+            Routing::Rendezvous => (0..shards)
+                .max_by_key(|shard| mix(((client as u64) << 32) | *shard as u64))
+                .unwrap_or(0),
+            Routing::Ranges(ranges) => ranges
+                .iter()
+                .find(|(from, to, _)| (*from..=*to).contains(&client))
+                .map(|(_, _, shard)| *shard % shards)
+                .unwrap_or(client as usize % shards),
+        }
+    }
+}
+
+impl FromStr for Routing {
+    type Err = String;
+
+    // `modulo`, `rendezvous` or `ranges:<from>-<to>=<shard>,...`.
+    fn from_str(routing: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid shard routing: {routing}");
+        match routing {
+            "modulo" => return Ok(Routing::Modulo),
+            "rendezvous" => return Ok(Routing::Rendezvous),
+            _ => (),
+        }
+        let ranges = routing.strip_prefix("ranges:").ok_or_else(invalid)?;
+        ranges
+            .split(',')
+            .map(|range| {
+                let (clients, shard) = range.split_once('=')?;
+                let (from, to) = clients.split_once('-')?;
+                let (from, to) = (from.trim().parse().ok()?, to.trim().parse().ok()?);
+                (from <= to).then_some((from, to, shard.trim().parse().ok()?))
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Routing::Ranges)
+            .ok_or_else(invalid)
+    }
+}
+
+// Detection of hot clients, which receive a disproportionate share of the transactions. Once a
+// client sent at least `min_txs` transactions making up at least `share` of all of them so far, its
+// later transactions are moved to a dedicated queue and applied in batches of up to `batch_size`,
+// with its account locked once per batch, so that it doesn't stall the other clients of its shard.
+#[derive(Debug, Clone, Copy)]
+pub struct HotAccounts {
+    pub min_txs: u64,
+    pub share: f64,
+    pub batch_size: usize,
+}
+
+enum ShardMsg {
+    Tx(Tx),
+    // Acknowledged once all the transactions sent before were handled.
+    Handoff(oneshot::Sender<()>),
+}
+
+enum HotMsg {
+    Tx(Tx),
+    // Waits for the previous shard of a newly hot client to hand it off.
+    Adopt(oneshot::Receiver<()>),
+}
+
+// Processes the transactions of `source` on `shards` engines working concurrently over the ledgers
+// of `engine`, each of them handling the clients `router` assigns to it, and folds their metrics
+// back into `engine` once the source is exhausted. Hot clients (see `HotAccounts`) get moved to an
+// additional worker. Returns the outcome of every worker. Transactions keep their relative order
+// within each client, but clients are no longer processed in lockstep.
+pub async fn process_sharded(
+    engine: &mut InMemoryEngine,
+    mut source: impl TxSource,
+    shards: usize,
+    router: Arc<dyn ShardRouter>,
+    hot: Option<HotAccounts>,
+) -> Vec<FileOutcome> {
+    let shards = shards.max(1);
+    let mut senders = Vec::with_capacity(shards);
+    let mut workers = Vec::with_capacity(shards + 1);
+    for idx in 0..shards {
+        let (sender, receiver) = mpsc::channel(1024);
+        senders.push(sender);
+        workers.push(tokio::spawn(run_shard(idx, engine.worker(), receiver)));
+    }
+    let hot_sender = hot.map(|hot| {
+        let (sender, receiver) = mpsc::channel(1024);
+        workers.push(tokio::spawn(run_hot(
+            engine.worker(),
+            receiver,
+            hot.batch_size.max(1),
+        )));
+        sender
+    });
+
+    let mut counts: HashMap<u16, u64> = HashMap::new();
+    let mut total = 0u64;
+    let mut hot_clients: HashSet<u16> = HashSet::new();
+    while let Some(record) = source.next().await {
+        let tx = match record {
+            Ok(tx) => tx,
+            Err(err) => {
+                debug!("Errored while reading transaction: {err}");
+                engine.skip_invalid(err);
+                continue;
+            }
+        };
+        let client = tx.client();
+        let shard = router.shard(client, shards).min(shards - 1);
+        if let (Some(config), Some(hot_sender)) = (hot, &hot_sender) {
+            total += 1;
+            let count = counts.entry(client).or_default();
+            *count += 1;
+            if !hot_clients.contains(&client)
+                && *count >= config.min_txs
+                && *count as f64 >= config.share * total as f64
+            {
+                info!("Client {client} is hot, moving it to a dedicated queue");
+                hot_clients.insert(client);
+                let (done, handed_off) = oneshot::channel();
+                let _ = senders[shard].send(ShardMsg::Handoff(done)).await;
+                let _ = hot_sender.send(HotMsg::Adopt(handed_off)).await;
+            }
+            if hot_clients.contains(&client) {
+                if hot_sender.send(HotMsg::Tx(tx)).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        }
+        if senders[shard].send(ShardMsg::Tx(tx)).await.is_err() {
+            break;
+        }
+    }
+    drop(senders);
+    drop(hot_sender);
+
+    let mut outcomes = Vec::with_capacity(workers.len());
+    for (idx, worker) in workers.into_iter().enumerate() {
+        match worker.await {
+            Ok((outcome, worker)) => {
+                engine.join(worker);
+                outcomes.push(outcome);
+            }
+            Err(err) => outcomes.push(FileOutcome {
+                path: format!("worker {idx}"),
+                error: Some(format!("Worker failed: {err}")),
+                ..Default::default()
+            }),
+        }
+    }
+    outcomes
+}
+
+async fn run_shard(
+    idx: usize,
+    mut engine: InMemoryEngine,
+    mut receiver: mpsc::Receiver<ShardMsg>,
+) -> (FileOutcome, InMemoryEngine) {
+    let mut outcome = FileOutcome {
+        path: format!("shard {idx}"),
+        ..Default::default()
+    };
+    while let Some(msg) = receiver.recv().await {
+        match msg {
+            ShardMsg::Tx(tx) => {
+                let res = engine.handle_tx(tx).await;
+                outcome.record(&res);
+            }
+            ShardMsg::Handoff(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+    (outcome, engine)
+}
+
+async fn run_hot(
+    mut engine: InMemoryEngine,
+    mut receiver: mpsc::Receiver<HotMsg>,
+    batch_size: usize,
+) -> (FileOutcome, InMemoryEngine) {
+    let mut outcome = FileOutcome {
+        path: "hot accounts".to_string(),
+        ..Default::default()
+    };
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut next = receiver.recv().await;
+    while let Some(msg) = next.take() {
+        match msg {
+            HotMsg::Tx(tx) => batch.push(tx),
+            HotMsg::Adopt(handed_off) => {
+                flush(&mut engine, &mut batch, &mut outcome).await;
+                // The shard is gone only if it failed, which gets reported on its own.
+                let _ = handed_off.await;
+            }
+        }
+        // Takes whatever is already queued into the current batch.
+        next = match receiver.try_recv() {
+            Ok(msg) if batch.len() < batch_size => Some(msg),
+            Ok(msg) => {
+                flush(&mut engine, &mut batch, &mut outcome).await;
+                Some(msg)
+            }
+            Err(_) => {
+                flush(&mut engine, &mut batch, &mut outcome).await;
+                receiver.recv().await
+            }
+        };
+    }
+    flush(&mut engine, &mut batch, &mut outcome).await;
+    (outcome, engine)
+}
+
+async fn flush(engine: &mut InMemoryEngine, batch: &mut Vec<Tx>, outcome: &mut FileOutcome) {
+    if batch.is_empty() {
+        return;
+    }
+    for res in engine.handle_batch(batch).await {
+        outcome.record(&res);
+    }
+    batch.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        payments::Engine,
+        source,
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+        test_utils::normalize_report,
+    };
+
+    use super::{process_sharded, HotAccounts, Routing, ShardRouter};
+
+    #[test]
+    fn routing() {
+        assert_eq!(Routing::Modulo.shard(7, 4), 3);
+
+        // Growing from 4 to 5 shards only moves clients to the new shard.
+        for client in 0..1000 {
+            let before = Routing::Rendezvous.shard(client, 4);
+            let after = Routing::Rendezvous.shard(client, 5);
+            assert!(after == before || after == 4);
+        }
+
+        let ranges = Routing::from_str("ranges:0-9=3,10-19=1").unwrap();
+        assert_eq!(ranges, Routing::Ranges(vec![(0, 9, 3), (10, 19, 1)]));
+        assert_eq!(ranges.shard(5, 4), 3);
+        assert_eq!(ranges.shard(15, 4), 1);
+        assert_eq!(ranges.shard(22, 4), 2);
+        assert!(Routing::from_str("ranges:9-0=1").is_err());
+        assert!(Routing::from_str("hash").is_err());
+    }
+
+    #[tokio::test]
+    async fn sharded_matches_sequential() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5\ndeposit,2,2,3\nwithdrawal,1,3,2\ndispute,2,2,\ndeposit,3,4,1\nchargeback,2,2,\nwithdrawal,3,5,4\ndeposit,4,6,x";
+        let mut sequential = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        sequential.handle_txs(input.as_bytes()).await.unwrap();
+
+        for routing in [Routing::Modulo, Routing::Rendezvous] {
+            let mut engine = Engine::new(
+                InMemoryAccountLedger::default(),
+                InMemoryTxLedger::default(),
+            );
+            let outcomes = process_sharded(
+                &mut engine,
+                source::from_csv(input.as_bytes()),
+                3,
+                Arc::new(routing),
+                None,
+            )
+            .await;
+            assert_eq!(outcomes.len(), 3);
+            assert_eq!(outcomes.iter().map(|outcome| outcome.rows).sum::<u64>(), 7);
+            assert!(outcomes.iter().all(|outcome| outcome.error.is_none()));
+            assert_eq!(engine.rejected(), sequential.rejected());
+            assert_eq!(engine.accounts().await.len(), 3);
+
+            let mut expected = Vec::new();
+            crate::report::write_accounts(&sequential, &mut expected)
+                .await
+                .unwrap();
+            let mut actual = Vec::new();
+            crate::report::write_accounts(&engine, &mut actual)
+                .await
+                .unwrap();
+            assert_eq!(
+                normalize_report(&String::from_utf8(actual).unwrap()),
+                normalize_report(&String::from_utf8(expected).unwrap())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn hot_accounts_get_batched() {
+        let mut input = "type,client,tx,amount\ndeposit,2,1,1".to_string();
+        for id in 2..50 {
+            input.push_str(&format!("\ndeposit,1,{id},1"));
+        }
+        input.push_str("\ndispute,1,10,\nwithdrawal,2,50,1\nwithdrawal,1,51,100");
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+
+        let hot = HotAccounts {
+            min_txs: 5,
+            share: 0.5,
+            batch_size: 8,
+        };
+        let outcomes = process_sharded(
+            &mut engine,
+            source::from_csv(input.as_bytes()),
+            2,
+            Arc::new(Routing::Modulo),
+            Some(hot),
+        )
+        .await;
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes.iter().map(|outcome| outcome.rows).sum::<u64>(), 52);
+        assert!(outcomes[2].rows > 40);
+        assert_eq!(outcomes[2].rejected, 1);
+
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(47));
+        assert_eq!(account.lock().await.held(), BigDecimal::from(1));
+        let account = engine.account(2).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(0));
+        assert_eq!(engine.latency().count(), 52);
+        assert_eq!(engine.rejected(), 1);
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    convert::TryFrom,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    account::Account,
+    payments::{Engine, Tx},
+    plugin::Plugin,
+    schema::{VersionedAccount, VersionedTx},
+    snapshot::{self, Snapshot},
+    storage::{AccountsDal, TxsDal},
+};
+
+// Change of the ledgers shipped in WAL segments: a transaction applied by the primary and the
+// resulting state of its client's account (see `Engine::replicate`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    pub tx: VersionedTx,
+    pub account: VersionedAccount,
+}
+
+const SNAPSHOT: &str = "snapshot";
+const SEGMENT: &str = "segment";
+
+// Shipped files are numbered by a sequence number, zero padded so that they sort in order:
+// `segment-<seq>.jsonl` holds the changes of a WAL segment, while `snapshot-<seq>.json` holds the
+// state of the ledgers with all the segments numbered below `seq` applied.
+fn shipped_path(dir: &Path, kind: &str, seq: u64) -> PathBuf {
+    let extension = if kind == SNAPSHOT { "json" } else { "jsonl" };
+    dir.join(format!("{kind}-{seq:020}.{extension}"))
+}
+
+// Sequence numbers of the files of a kind shipped to `dir`, in increasing order.
+fn shipped(dir: &Path, kind: &str) -> io::Result<Vec<u64>> {
+    let extension = if kind == SNAPSHOT { ".json" } else { ".jsonl" };
+    let mut seqs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let seq: Option<u64> = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(kind)?.strip_prefix('-'))
+            .and_then(|name| name.strip_suffix(extension)?.parse().ok());
+        seqs.extend(seq);
+    }
+    seqs.sort_unstable();
+    Ok(seqs)
+}
+
+// Writes the file next to `path` first and renames it over, so that a standby never reads a
+// partially shipped file.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+struct Shipment {
+    changes: Vec<Change>,
+    next: u64,
+    shipped_at: Instant,
+}
+
+// Plugin shipping the state of a primary engine to a destination directory (e.g. a volume mounted
+// from another zone), for a warm standby to take over from after a disaster: the changes applied
+// are shipped as a WAL segment at most every `interval` and on shutdown, while full snapshots get
+// shipped through `ship`, e.g. on startup and at the end of a run. Standbys bootstrap from the
+// latest snapshot and the segments following it (see `bootstrap`). Clones share the same
+// shipment, so a clone can be kept around for shipping snapshots after registering the plugin.
+#[derive(Clone)]
+pub struct Shipper {
+    dir: PathBuf,
+    interval: Duration,
+    shipment: Arc<Mutex<Shipment>>,
+}
+
+impl Shipper {
+    // Ships to `dir`, creating it if needed, continuing the numbering of what was shipped there.
+    pub fn open(dir: PathBuf, interval: Duration) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let segments = shipped(&dir, SEGMENT)?.last().map(|seq| seq + 1);
+        let snapshots = shipped(&dir, SNAPSHOT)?.last().copied();
+        let next = segments.max(snapshots).unwrap_or(0);
+        Ok(Shipper {
+            dir,
+            interval,
+            shipment: Arc::new(Mutex::new(Shipment {
+                changes: Vec::new(),
+                next,
+                shipped_at: Instant::now(),
+            })),
+        })
+    }
+
+    // Ships the changes applied since the last segment as a new segment, if any. Changes which
+    // couldn't be shipped are kept for the next segment.
+    fn ship_segment(&self, shipment: &mut Shipment) {
+        shipment.shipped_at = Instant::now();
+        if shipment.changes.is_empty() {
+            return;
+        }
+        let path = shipped_path(&self.dir, SEGMENT, shipment.next);
+        let mut contents = Vec::new();
+        let res = shipment.changes.iter().try_for_each(|change| {
+            serde_json::to_writer(&mut contents, change)?;
+            contents.write_all(b"\n")
+        });
+        match res.and_then(|()| write_atomically(&path, &contents)) {
+            Ok(()) => {
+                shipment.changes.clear();
+                shipment.next += 1;
+            }
+            Err(err) => warn!("Error while shipping WAL segment {path:?}: {err}"),
+        }
+    }
+
+    // Ships a snapshot of the whole state, which must have been captured while no transaction was
+    // being handled, along with the segment of the changes before it.
+    pub async fn ship(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let seq = {
+            let mut shipment = self.shipment.lock().unwrap();
+            self.ship_segment(&mut shipment);
+            // Changes which couldn't be shipped are part of the snapshot anyway.
+            shipment.changes.clear();
+            shipment.next
+        };
+        snapshot::save(&shipped_path(&self.dir, SNAPSHOT, seq), snapshot).await
+    }
+}
+
+impl Plugin for Shipper {
+    fn name(&self) -> &str {
+        "shipping"
+    }
+
+    fn on_account(&self, tx: &Tx, account: &Account) {
+        let mut shipment = self.shipment.lock().unwrap();
+        shipment.changes.push(Change {
+            tx: tx.into(),
+            account: account.into(),
+        });
+        if shipment.shipped_at.elapsed() >= self.interval {
+            self.ship_segment(&mut shipment);
+        }
+    }
+
+    fn on_shutdown(&self) {
+        self.ship_segment(&mut self.shipment.lock().unwrap());
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        let next = self.shipment.lock().unwrap().next;
+        vec![("next_segment".to_string(), next.to_string())]
+    }
+}
+
+// What a standby was bootstrapped from.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Bootstrap {
+    // Sequence number of the snapshot restored, if any was shipped.
+    pub snapshot: Option<u64>,
+    pub segments: u64,
+    pub changes: u64,
+}
+
+// Bootstraps a standby engine from the latest state shipped to `dir`: the latest snapshot, if any,
+// then the changes of the segments following it, which must all be there.
+pub async fn bootstrap<A, T>(engine: &mut Engine<A, T>, dir: &Path) -> anyhow::Result<Bootstrap>
+where
+    A: AccountsDal + Send + Sync + Clone,
+    T: TxsDal + Send + Sync + Clone,
+{
+    let mut bootstrap = Bootstrap {
+        snapshot: shipped(dir, SNAPSHOT)?.last().copied(),
+        ..Bootstrap::default()
+    };
+    if let Some(seq) = bootstrap.snapshot {
+        let snapshot = snapshot::load(&shipped_path(dir, SNAPSHOT, seq)).await?;
+        engine.restore(&snapshot).await?;
+    }
+    let mut expected = bootstrap.snapshot.unwrap_or(0);
+    for seq in shipped(dir, SEGMENT)? {
+        if seq < expected {
+            continue;
+        }
+        if seq != expected {
+            return Err(anyhow!("Missing WAL segment {expected}"));
+        }
+        let contents = tokio::fs::read_to_string(shipped_path(dir, SEGMENT, seq)).await?;
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let change: Change = serde_json::from_str(line)?;
+            engine
+                .replicate(Tx::try_from(change.tx)?, Account::try_from(change.account)?)
+                .await?;
+            bootstrap.changes += 1;
+        }
+        bootstrap.segments += 1;
+        expected += 1;
+    }
+    Ok(bootstrap)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        payments::{DisputePolicy, Engine},
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{bootstrap, Bootstrap, Shipper};
+
+    fn standby() -> Engine<InMemoryAccountLedger, InMemoryTxLedger> {
+        Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn standby_bootstrap() {
+        let dir = std::env::temp_dir().join("payments-engine-shipping");
+        let _ = std::fs::remove_dir_all(&dir);
+        let shipper = Shipper::open(dir.clone(), Duration::ZERO).unwrap();
+        let mut primary = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .dispute_policy(DisputePolicy::DepositsAndWithdrawals)
+        .plugin(shipper.clone())
+        .build()
+        .unwrap();
+        primary
+            .handle_txs("type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0".as_bytes())
+            .await
+            .unwrap();
+        shipper.ship(&primary.snapshot().await).await.unwrap();
+        primary
+            .handle_txs(
+                "type,client,tx,amount\nwithdrawal,1,3,2.0\ndispute,1,3,\nchargeback,1,3,\n\
+                dispute,2,2,\nwithdrawal,2,4,9.0"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        // The standby catches up from the snapshot and the segments shipped after it.
+        let mut engine = standby();
+        let bootstrapped = bootstrap(&mut engine, &dir).await.unwrap();
+        assert_eq!(
+            bootstrapped,
+            Bootstrap {
+                snapshot: Some(2),
+                segments: 4,
+                changes: 4
+            }
+        );
+        assert_eq!(engine.snapshot().await, primary.snapshot().await);
+
+        primary.shutdown();
+        shipper.ship(&primary.snapshot().await).await.unwrap();
+        let mut engine = standby();
+        let bootstrapped = bootstrap(&mut engine, &dir).await.unwrap();
+        assert_eq!(bootstrapped.snapshot, Some(6));
+        assert_eq!(bootstrapped.changes, 0);
+        assert_eq!(engine.snapshot().await, primary.snapshot().await);
+
+        // Numbering continues across runs.
+        let shipper = Shipper::open(dir.clone(), Duration::ZERO).unwrap();
+        assert_eq!(shipper.shipment.lock().unwrap().next, 6);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{convert::TryFrom, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    account::Account,
+    error::Error,
+    payments::Tx,
+    schema::{parse_amount, VersionedAccount, VersionedTx},
+    storage::PendingTx,
+};
+
+// Charged back funds of a client, as held by the clearing account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClearingEntry {
+    pub client: u16,
+    pub amount: String,
+}
+
+// State of the engine's ledgers at the end of a run, so that a later run can pick up from it and
+// only process new transactions (e.g. a daily batch job fed with the day's file only). Runtime
+// state like latencies, quotas or retention windows isn't part of it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub accounts: Vec<VersionedAccount>,
+    pub txs: Vec<VersionedTx>,
+    #[serde(default)]
+    pub clearing: Vec<ClearingEntry>,
+    #[serde(default)]
+    pub pending: Vec<PendingEntry>,
+}
+
+// Transaction awaiting approval (see `storage::PendingDal`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub tx: VersionedTx,
+    pub reason: String,
+    pub expires_at: Option<u64>,
+}
+
+impl From<&PendingTx> for PendingEntry {
+    fn from(pending: &PendingTx) -> Self {
+        PendingEntry {
+            tx: (&pending.tx).into(),
+            reason: pending.reason.clone(),
+            expires_at: pending.expires_at,
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn accounts(&self) -> Result<Vec<Account>, Error> {
+        self.accounts
+            .iter()
+            .cloned()
+            .map(Account::try_from)
+            .collect()
+    }
+
+    pub fn txs(&self) -> Result<Vec<Tx>, Error> {
+        self.txs.iter().cloned().map(Tx::try_from).collect()
+    }
+
+    pub fn pending(&self) -> Result<Vec<PendingTx>, Error> {
+        self.pending
+            .iter()
+            .map(|entry| {
+                Ok(PendingTx {
+                    tx: Tx::try_from(entry.tx.clone())?,
+                    reason: entry.reason.clone(),
+                    expires_at: entry.expires_at,
+                })
+            })
+            .collect()
+    }
+
+    pub fn clearing(&self) -> Result<Vec<(u16, bigdecimal::BigDecimal)>, Error> {
+        self.clearing
+            .iter()
+            .map(|entry| Ok((entry.client, parse_amount(&entry.amount)?)))
+            .collect()
+    }
+}
+
+pub async fn load(path: &Path) -> anyhow::Result<Snapshot> {
+    let content = fs::read(path).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+// Writes the snapshot next to `path` first and renames it over, so that an interrupted run never
+// leaves a truncated snapshot behind.
+pub async fn save(path: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_vec(snapshot)?).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        payments::Engine,
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger, TxsDal},
+    };
+
+    use super::{load, save};
+
+    #[tokio::test]
+    async fn incremental_runs() {
+        let path = std::env::temp_dir().join("payments-engine-snapshot.json");
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\ndispute,2,2,\nchargeback,2,2,"
+                    .as_bytes(),
+            ))
+            .await
+            .unwrap();
+        save(&path, &engine.snapshot().await).await.unwrap();
+
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        engine.restore(&load(&path).await.unwrap()).await.unwrap();
+        engine
+            .handle_txs(tokio::io::BufReader::new(
+                "type,client,tx,amount\ndispute,1,1,\ndeposit,2,3,1.0".as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.held(), BigDecimal::from(1));
+        let account = engine.account(2).await.unwrap();
+        assert!(account.lock().await.is_locked());
+        assert_eq!(account.lock().await.total(), BigDecimal::from(0));
+        assert!(engine.tx(3).await.is_none());
+        assert_eq!(engine.clearing().client_balance(2), BigDecimal::from(2));
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,521 @@
+use std::{convert::TryFrom, io, str::FromStr, sync::Arc};
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use bigdecimal::{num_bigint::BigInt, BigDecimal};
+use csv_async::ByteRecord;
+use futures::{stream, stream::BoxStream, Stream, StreamExt};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, BufReader},
+    sync::mpsc,
+};
+
+use crate::{
+    error::Error,
+    mmap::MappedFile,
+    payments::{Tx, TxType},
+};
+
+// Source of transactions the engine can consume (see `Engine::handle_source`), so that library
+// users can feed the engine from anywhere without going through CSV bytes. Any stream of
+// transactions is a source, while the functions below adapt the most common producers.
+pub trait TxSource: Stream<Item = Result<Tx, Error>> + Send + Unpin {}
+
+impl<S: Stream<Item = Result<Tx, Error>> + Send + Unpin> TxSource for S {}
+
+pub type BoxedSource = Box<dyn TxSource>;
+
+// Record read from a source: a transaction, or the reason it couldn't be read.
+pub type TxRecord = Result<Tx, Error>;
+
+// Transformation of every input's transactions before they reach the engine (e.g. remapping client
+// ids), for inputs opened on demand.
+pub type SourceLayer = Arc<dyn Fn(BoxedSource) -> BoxedSource + Send + Sync>;
+
+// How CSV inputs are parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CsvParser {
+    // Through serde, supporting the optional columns (see `from_csv`).
+    #[default]
+    Serde,
+    // By hand, for the canonical schema only (see `from_csv_fast`).
+    Fast,
+}
+
+impl CsvParser {
+    pub fn parse(self, reader: impl AsyncRead + Send + Unpin + 'static) -> BoxedSource {
+        match self {
+            CsvParser::Serde => Box::new(from_csv(reader)),
+            CsvParser::Fast => Box::new(from_csv_fast(reader)),
+        }
+    }
+}
+
+// Input path standing for the standard input, for the engine to be used in pipelines.
+pub const STDIN: &str = "-";
+
+// How local input files are read. The standard input (`STDIN`) is always read through read calls.
+// Files with a `.gz` or `.zst` extension are decompressed on the fly.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InputReader {
+    // Through read calls.
+    #[default]
+    File,
+    // Through a memory map (see `mmap::MappedFile`).
+    Mmap,
+}
+
+impl InputReader {
+    pub async fn open(self, path: &str) -> io::Result<Box<dyn AsyncRead + Send + Unpin>> {
+        if path == STDIN {
+            return Ok(Box::new(tokio::io::stdin()));
+        }
+        let file: Box<dyn AsyncRead + Send + Unpin> = match self {
+            InputReader::File => Box::new(File::open(path).await?),
+            InputReader::Mmap => {
+                let owned = path.to_string();
+                Box::new(tokio::task::spawn_blocking(move || MappedFile::open(owned)).await??)
+            }
+        };
+        // Exports made of several concatenated members (e.g. `cat a.gz b.gz`) are read whole.
+        Ok(if path.ends_with(".gz") {
+            let mut decoder = GzipDecoder::new(BufReader::new(file));
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        } else if path.ends_with(".zst") {
+            let mut decoder = ZstdDecoder::new(BufReader::new(file));
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        } else {
+            file
+        })
+    }
+}
+
+// Layer leaving the transactions untouched.
+pub fn identity() -> SourceLayer {
+    Arc::new(|source| source)
+}
+
+// Transactions deserialized from CSV bytes, with a header row.
+pub fn from_csv<'r>(reader: impl AsyncRead + Send + Unpin + 'r) -> impl TxSource + 'r {
+    csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .create_deserializer(reader)
+        .into_deserialize::<Tx>()
+        .map(|record| {
+            record
+                .map_err(|err| Error::InvalidRecord(err.to_string()))
+                .and_then(Tx::validate)
+        })
+        .boxed()
+}
+
+// Header of the canonical schema, the only one `from_csv_fast` reads.
+const CANONICAL_HEADER: [&[u8]; 4] = [b"type", b"client", b"tx", b"amount"];
+
+// Transactions read from CSV bytes with the canonical `type,client,tx,amount` header, like
+// `from_csv` but without going through serde: the fields of every byte record are parsed by hand,
+// amounts being accumulated as fixed-point integers. Inputs with any other header are rejected.
+// With the `simd-csv` feature, lines and fields are split by SIMD byte searches instead of the
+// CSV reader.
+pub fn from_csv_fast<'r>(reader: impl AsyncRead + Send + Unpin + 'r) -> impl TxSource + 'r {
+    #[cfg(feature = "simd-csv")]
+    return scan_lines(reader, SCAN_CHUNK);
+    #[cfg(not(feature = "simd-csv"))]
+    from_byte_records(reader)
+}
+
+#[cfg_attr(feature = "simd-csv", allow(dead_code))]
+fn from_byte_records<'r>(
+    reader: impl AsyncRead + Send + Unpin + 'r,
+) -> BoxStream<'r, Result<Tx, Error>> {
+    let reader = csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .flexible(true)
+        .create_reader(reader);
+    stream::unfold(
+        Some((reader, ByteRecord::new(), false)),
+        |state| async move {
+            let (mut reader, mut record, checked) = state?;
+            if !checked {
+                let header = reader
+                    .byte_headers()
+                    .await
+                    .map_err(|err| Error::InvalidRecord(err.to_string()));
+                match header {
+                    Ok(header) if header.iter().eq(CANONICAL_HEADER) => (),
+                    Ok(header) => {
+                        let header = String::from_utf8_lossy(header.as_slice()).to_string();
+                        let err = format!("Not the canonical header: {header}");
+                        return Some((Err(Error::InvalidRecord(err)), None));
+                    }
+                    Err(err) => return Some((Err(err), None)),
+                }
+            }
+            match reader.read_byte_record(&mut record).await {
+                Ok(true) => {
+                    let fields = [record.get(0), record.get(1), record.get(2), record.get(3)];
+                    let tx = parse_fields(fields);
+                    Some((tx, Some((reader, record, true))))
+                }
+                Ok(false) => None,
+                Err(err) => Some((
+                    Err(Error::InvalidRecord(err.to_string())),
+                    Some((reader, record, true)),
+                )),
+            }
+        },
+    )
+    .boxed()
+}
+
+// Parses the `type,client,tx,amount` fields of a record.
+fn parse_fields(fields: [Option<&[u8]>; 4]) -> Result<Tx, Error> {
+    let invalid = |column: &str, value: Option<&[u8]>| {
+        let value = String::from_utf8_lossy(value.unwrap_or_default());
+        Error::InvalidRecord(format!("Invalid {column}: {value:?}"))
+    };
+    let r#type = match fields[0] {
+        Some(b"deposit") => TxType::Deposit,
+        Some(b"withdrawal") => TxType::Withdrawal,
+        Some(b"dispute") => TxType::Dispute,
+        Some(b"resolve") => TxType::Resolve,
+        Some(b"chargeback") => TxType::Chargeback,
+        other => return Err(invalid("type", other)),
+    };
+    let client = parse_uint(fields[1])
+        .and_then(|client| u16::try_from(client).ok())
+        .ok_or_else(|| invalid("client", fields[1]))?;
+    let id = parse_uint(fields[2])
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or_else(|| invalid("tx", fields[2]))?;
+    let amount = match fields[3] {
+        None | Some(b"") => None,
+        Some(field) => Some(parse_amount(field).ok_or_else(|| invalid("amount", Some(field)))?),
+    };
+    Tx::new(r#type, client, id, amount).validate()
+}
+
+// Size of the reads of `scan_lines`.
+#[cfg(feature = "simd-csv")]
+const SCAN_CHUNK: usize = 64 * 1024;
+
+#[cfg(feature = "simd-csv")]
+struct Scanner<R> {
+    reader: R,
+    buf: Vec<u8>,
+    // Start of the first line of `buf` not scanned yet.
+    start: usize,
+    eof: bool,
+    header: bool,
+}
+
+// Splits the input in lines and fields with `memchr`, which looks for the separators many bytes at
+// a time through SIMD instructions, instead of going through the general CSV state machine. Quoted
+// fields aren't supported, as the canonical schema doesn't need them.
+#[cfg(feature = "simd-csv")]
+fn scan_lines<'r>(
+    reader: impl AsyncRead + Send + Unpin + 'r,
+    chunk: usize,
+) -> BoxStream<'r, Result<Tx, Error>> {
+    use tokio::io::AsyncReadExt;
+
+    let scanner = Scanner {
+        reader,
+        buf: Vec::with_capacity(chunk),
+        start: 0,
+        eof: false,
+        header: false,
+    };
+    stream::unfold(Some(scanner), move |state| async move {
+        let mut scanner = state?;
+        loop {
+            let pending = &scanner.buf[scanner.start..];
+            let line = match memchr::memchr(b'\n', pending) {
+                Some(len) => scanner.start..scanner.start + len,
+                None if !scanner.eof => {
+                    scanner.buf.drain(..scanner.start);
+                    scanner.start = 0;
+                    let len = scanner.buf.len();
+                    scanner.buf.resize(len + chunk, 0);
+                    let read = scanner.reader.read(&mut scanner.buf[len..]).await;
+                    let read = match read {
+                        Ok(read) => read,
+                        Err(err) => {
+                            return Some((Err(Error::InvalidRecord(err.to_string())), None))
+                        }
+                    };
+                    scanner.buf.truncate(len + read);
+                    scanner.eof = read == 0;
+                    continue;
+                }
+                None if pending.is_empty() => return None,
+                None => scanner.start..scanner.buf.len(),
+            };
+            scanner.start = (line.end + 1).min(scanner.buf.len());
+            let line = scanner.buf[line].trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+            let fields = split_fields(line);
+            if !scanner.header {
+                scanner.header = true;
+                let canonical = matches!(&fields, Ok(fields) if fields
+                    .iter()
+                    .map(|field| field.unwrap_or_default())
+                    .eq(CANONICAL_HEADER));
+                if canonical {
+                    continue;
+                }
+                let header = String::from_utf8_lossy(line);
+                let err = format!("Not the canonical header: {header}");
+                return Some((Err(Error::InvalidRecord(err)), None));
+            }
+            let tx = fields.and_then(parse_fields);
+            return Some((tx, Some(scanner)));
+        }
+    })
+    .boxed()
+}
+
+#[cfg(feature = "simd-csv")]
+fn split_fields(line: &[u8]) -> Result<[Option<&[u8]>; 4], Error> {
+    let invalid = |reason: &str| {
+        let line = String::from_utf8_lossy(line);
+        Error::InvalidRecord(format!("{reason}: {line}"))
+    };
+    if memchr::memchr(b'"', line).is_some() {
+        return Err(invalid("Quoted fields aren't supported"));
+    }
+    let mut fields = [None; 4];
+    let mut start = 0;
+    let ends = memchr::memchr_iter(b',', line).chain(std::iter::once(line.len()));
+    for (idx, end) in ends.enumerate() {
+        let field = fields
+            .get_mut(idx)
+            .ok_or_else(|| invalid("Too many fields"))?;
+        *field = Some(line[start..end].trim_ascii());
+        start = end + 1;
+    }
+    Ok(fields)
+}
+
+fn parse_uint(field: Option<&[u8]>) -> Option<u64> {
+    let field = field.filter(|field| !field.is_empty())?;
+    field.iter().try_fold(0u64, |value, &byte| {
+        let digit = char::from(byte).to_digit(10)?;
+        value.checked_mul(10)?.checked_add(u64::from(digit))
+    })
+}
+
+// Parses `[-+]digits[.digits]` amounts into the same representation `BigDecimal::from_str` does,
+// falling back to it for anything else (e.g. exponents or more digits than fit 64 bits).
+fn parse_amount(field: &[u8]) -> Option<BigDecimal> {
+    let (negative, digits) = match field.split_first() {
+        Some((b'-', digits)) => (true, digits),
+        Some((b'+', digits)) => (false, digits),
+        _ => (false, field),
+    };
+    let mut unscaled: u64 = 0;
+    let mut scale = 0;
+    let mut point = false;
+    let mut fast = !digits.is_empty();
+    for &byte in digits {
+        match byte {
+            b'0'..=b'9' => {
+                let Some(value) = unscaled
+                    .checked_mul(10)
+                    .and_then(|value| value.checked_add(u64::from(byte - b'0')))
+                else {
+                    fast = false;
+                    break;
+                };
+                unscaled = value;
+                if point {
+                    scale += 1;
+                }
+            }
+            b'.' if !point => point = true,
+            _ => {
+                fast = false;
+                break;
+            }
+        }
+    }
+    if !fast {
+        return BigDecimal::from_str(std::str::from_utf8(field).ok()?).ok();
+    }
+    let unscaled = BigInt::from(unscaled);
+    Some(BigDecimal::new(
+        if negative { -unscaled } else { unscaled },
+        scale,
+    ))
+}
+
+pub fn from_stream<'s>(
+    txs: impl Stream<Item = Result<Tx, Error>> + Send + 's,
+) -> impl TxSource + 's {
+    txs.boxed()
+}
+
+pub fn from_iter<I>(txs: I) -> impl TxSource
+where
+    I: IntoIterator<Item = Tx>,
+    I::IntoIter: Send,
+{
+    stream::iter(txs.into_iter().map(Ok))
+}
+
+// Transactions sent through a channel, until all its senders are dropped.
+pub fn from_channel(receiver: mpsc::Receiver<Tx>) -> impl TxSource {
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|tx| (Ok(tx), receiver))
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+    use bigdecimal::BigDecimal;
+    use futures::{stream, StreamExt};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::mpsc,
+    };
+
+    use crate::{
+        error::Error,
+        payments::{Engine, Tx, TxType},
+        storage::{AccountsDal, InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{from_channel, from_csv, from_csv_fast, from_iter, from_stream, InputReader};
+
+    #[tokio::test]
+    async fn compressed_inputs() {
+        let input = b"type,client,tx,amount\ndeposit,1,1,1.5\n";
+        let dir = std::env::temp_dir();
+        let gzip = dir.join("payments-engine-input.csv.gz");
+        let mut encoder = GzipEncoder::new(Vec::new());
+        encoder.write_all(input).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        tokio::fs::write(&gzip, encoder.into_inner()).await.unwrap();
+        let zstd = dir.join("payments-engine-input.csv.zst");
+        let mut encoder = ZstdEncoder::new(Vec::new());
+        encoder.write_all(input).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        tokio::fs::write(&zstd, encoder.into_inner()).await.unwrap();
+
+        for (path, reader) in [
+            (&gzip, InputReader::File),
+            (&gzip, InputReader::Mmap),
+            (&zstd, InputReader::File),
+        ] {
+            let mut decompressed = Vec::new();
+            reader
+                .open(&path.to_string_lossy())
+                .await
+                .unwrap()
+                .read_to_end(&mut decompressed)
+                .await
+                .unwrap();
+            assert_eq!(decompressed, input);
+        }
+        tokio::fs::remove_file(gzip).await.unwrap();
+        tokio::fs::remove_file(zstd).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fast_parse_matches_serde() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nwithdrawal,2,2,0.5000\n\
+            dispute,1,1,\nchargeback,1,1,\ndeposit,3,4,1e2\ndeposit,3,5,-2\ndeposit,3,6,.25\n\
+            refund,1,6,1\ndeposit,70000,7,1\ndeposit,1,8,1.2.3\ndeposit,1,9,99999999999999999999.9";
+        let parse = |source: Vec<Result<Tx, Error>>| -> Vec<Option<String>> {
+            source
+                .into_iter()
+                .map(|record| record.ok().map(|tx| format!("{tx:?}")))
+                .collect()
+        };
+        let serde = parse(from_csv(input.as_bytes()).collect().await);
+        let fast = parse(from_csv_fast(input.as_bytes()).collect().await);
+        assert_eq!(fast, serde);
+        assert_eq!(fast.iter().filter(|tx| tx.is_none()).count(), 4);
+    }
+
+    #[tokio::test]
+    async fn fast_parse_canonical_header_only() {
+        let input = "type,client,tx,amount,seq\ndeposit,1,1,1.0,1";
+        let records: Vec<_> = from_csv_fast(input.as_bytes()).collect().await;
+        assert_eq!(records.len(), 1);
+        assert!(records[0].is_err());
+    }
+
+    #[cfg(feature = "simd-csv")]
+    #[tokio::test]
+    async fn scan_lines_across_chunks() {
+        let input = "type,client,tx,amount\r\ndeposit,1,1,1.0\r\n\nwithdrawal, 2, 2, 0.5\n\
+            deposit,1,3,\"1\"\ndeposit,1,4,1,1\ndispute,1,1,";
+        let serde: Vec<_> = from_csv(input.as_bytes()).collect().await;
+        for chunk in [1, 3, 7, super::SCAN_CHUNK] {
+            let scanned: Vec<_> = super::scan_lines(input.as_bytes(), chunk).collect().await;
+            assert_eq!(scanned.len(), 5);
+            assert_eq!(format!("{:?}", &scanned[..2]), format!("{:?}", &serde[..2]));
+            assert!(scanned[2].is_err() && scanned[3].is_err());
+            assert_eq!(format!("{:?}", scanned[4]), format!("{:?}", serde[4]));
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_iter_source() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let txs = vec![
+            Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(3))),
+            Tx::new(TxType::Withdrawal, 1, 2, Some(BigDecimal::from(1))),
+        ];
+
+        engine.handle_source(from_iter(txs)).await.unwrap();
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(2));
+    }
+
+    #[tokio::test]
+    async fn handle_stream_source_skips_errors() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let txs = stream::iter(vec![
+            Err(Error::InvalidRecord("bad row".to_string())),
+            Ok(Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(3)))),
+        ]);
+
+        engine.handle_source(from_stream(txs)).await.unwrap();
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(3));
+    }
+
+    #[tokio::test]
+    async fn handle_channel_source() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        let (sender, receiver) = mpsc::channel(2);
+        let producer = tokio::spawn(async move {
+            for id in 0..4 {
+                let tx = Tx::new(TxType::Deposit, 1, id, Some(BigDecimal::from(1)));
+                sender.send(tx).await.unwrap();
+            }
+        });
+
+        engine.handle_source(from_channel(receiver)).await.unwrap();
+        producer.await.unwrap();
+        let account = engine.account(1).await.unwrap();
+        assert_eq!(account.lock().await.available(), BigDecimal::from(4));
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::{
+    outcome::TxOutcome,
+    payments::{Tx, TxType},
+};
+
+// Counters of the rows processed by an engine, summarized at the end of a run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    // Records of the sources which couldn't be read.
+    pub parse_failures: u64,
+    // Transactions which failed handling, by `Error` variant.
+    pub failures: BTreeMap<&'static str, u64>,
+    // Clients with at least one applied transaction.
+    pub accounts: BTreeSet<u16>,
+}
+
+impl Stats {
+    pub fn record(&mut self, tx: &Tx, outcome: &TxOutcome) {
+        match tx.tx_type() {
+            TxType::Deposit => self.deposits += 1,
+            TxType::Withdrawal => self.withdrawals += 1,
+            TxType::Dispute => self.disputes += 1,
+            TxType::Resolve => self.resolves += 1,
+            TxType::Chargeback => self.chargebacks += 1,
+        }
+        match outcome.error() {
+            Some(err) => *self.failures.entry(err.variant()).or_default() += 1,
+            None if outcome.is_applied() => {
+                self.accounts.insert(tx.client());
+            }
+            None => (),
+        }
+    }
+
+    // Transactions handled, whatever their outcome.
+    pub fn txs(&self) -> u64 {
+        self.deposits + self.withdrawals + self.disputes + self.resolves + self.chargebacks
+    }
+
+    pub fn merge(&mut self, other: &Stats) {
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.disputes += other.disputes;
+        self.resolves += other.resolves;
+        self.chargebacks += other.chargebacks;
+        self.parse_failures += other.parse_failures;
+        for (variant, count) in &other.failures {
+            *self.failures.entry(variant).or_default() += count;
+        }
+        self.accounts.extend(&other.accounts);
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} transactions: {} deposits, {} withdrawals, {} disputes, {} resolves, {} chargebacks",
+            self.txs(),
+            self.deposits,
+            self.withdrawals,
+            self.disputes,
+            self.resolves,
+            self.chargebacks
+        )?;
+        writeln!(f, "{} parse failures", self.parse_failures)?;
+        write!(
+            f,
+            "{} handling failures",
+            self.failures.values().sum::<u64>()
+        )?;
+        for (idx, (variant, count)) in self.failures.iter().enumerate() {
+            let separator = if idx == 0 { ": " } else { ", " };
+            write!(f, "{separator}{count} {variant}")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{} accounts touched", self.accounts.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    #[tokio::test]
+    async fn run_summary() {
+        let mut engine = Engine::new(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        );
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0\nwithdrawal,2,3,5.0\n\
+                refund,1,4,1.0\ndispute,1,1,\ndispute,1,1,\nresolve,3,9,"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let stats = engine.stats();
+        assert_eq!(stats.txs(), 6);
+        assert_eq!(stats.parse_failures, 1);
+        assert_eq!(
+            stats.to_string(),
+            "6 transactions: 2 deposits, 1 withdrawals, 2 disputes, 1 resolves, 0 chargebacks\n\
+            1 parse failures\n\
+            3 handling failures: 1 MinAvailableUnderflow, 1 TxAlreadyDisputed, 1 TxNotFound\n\
+            2 accounts touched\n"
+        );
+    }
+}
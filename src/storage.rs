@@ -1,6 +1,11 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
 
+use futures::future::BoxFuture;
 use tokio::sync::{Mutex, RwLock};
+use tracing::instrument;
 
 use crate::{account::Account, payments::Tx};
 
@@ -14,25 +19,39 @@ pub trait AccountsDal {
         &mut self,
         account: Account,
     ) -> impl std::future::Future<Output = ()> + std::marker::Send;
-    fn accounts(&self) ->  impl std::future::Future<Output = tokio::sync::RwLockReadGuard<HashMap<u16, Arc<Mutex<Account>>>>> + Send; 
+    fn accounts(
+        &self,
+    ) -> impl std::future::Future<
+        Output = tokio::sync::RwLockReadGuard<'_, HashMap<u16, Arc<Mutex<Account>>>>,
+    > + Send;
+    // Hints that the given accounts are about to be accessed, so that backends with expensive
+    // point lookups (e.g. databases) can warm their cache with a single bulk query. No-op by
+    // default.
+    fn prefetch(&self, _ids: &[u16]) -> impl std::future::Future<Output = ()> + Send {
+        async {}
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct InMemoryAccountLedger(Arc<RwLock<HashMap<u16, Arc<Mutex<Account>>>>>);
 
 impl AccountsDal for InMemoryAccountLedger {
+    #[instrument(level = "trace", skip(self))]
     async fn account(&self, id: u16) -> Option<Arc<Mutex<Account>>> {
-        self.0.read().await.get(&id).map(|inner| inner.clone())
+        self.0.read().await.get(&id).cloned()
     }
 
+    #[instrument(level = "trace", skip_all, fields(client = account.client_id()))]
     async fn insert(&mut self, account: Account) {
         self.0
             .write()
             .await
             .insert(account.client_id(), Arc::new(Mutex::new(account)));
     }
-    
-    async fn accounts(&self) ->  tokio::sync::RwLockReadGuard<'_, HashMap<u16,Arc<Mutex<Account>>>> {
+
+    async fn accounts(
+        &self,
+    ) -> tokio::sync::RwLockReadGuard<'_, HashMap<u16, Arc<Mutex<Account>>>> {
         self.0.read().await
     }
 }
@@ -42,21 +61,396 @@ pub trait TxsDal {
         &self,
         id: u32,
     ) -> impl std::future::Future<Output = Option<Arc<Mutex<Tx>>>> + std::marker::Send;
+    // Stores the transaction, replacing any transaction with the same id. The engine rejects
+    // deposits and withdrawals reusing an id before storing them (see `Error::DuplicateTx`).
     fn insert(&self, tx: Tx) -> impl std::future::Future<Output = ()> + Send;
+    fn txs(
+        &self,
+    ) -> impl std::future::Future<
+        Output = tokio::sync::RwLockReadGuard<'_, HashMap<u32, Arc<Mutex<Tx>>>>,
+    > + Send;
+    // Drops a transaction from the ledger. Backends which can't drop entries may ignore it.
+    fn remove(&self, _id: u32) -> impl std::future::Future<Output = ()> + Send {
+        async {}
+    }
+    // Similar to `AccountsDal::prefetch`, for transactions.
+    fn prefetch(&self, _ids: &[u32]) -> impl std::future::Future<Output = ()> + Send {
+        async {}
+    }
+    // Transactions of the given client, in insertion order. The default implementation scans the
+    // whole ledger and orders them by id instead, so backends are expected to maintain a
+    // secondary index.
+    fn client_txs(
+        &self,
+        client: u16,
+    ) -> impl std::future::Future<Output = Vec<Arc<Mutex<Tx>>>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let mut txs = Vec::new();
+            for (id, tx) in self.txs().await.iter() {
+                if tx.lock().await.client() == client {
+                    txs.push((*id, tx.clone()));
+                }
+            }
+            txs.sort_by_key(|(id, _)| *id);
+            txs.into_iter().map(|(_, tx)| tx).collect()
+        }
+    }
+}
+
+// Ids of the transactions of every client, in insertion order.
+#[derive(Default)]
+struct ClientIndex {
+    seq: u64,
+    ids: HashMap<u16, BTreeMap<u64, u32>>,
+    // Insertion sequence number of every indexed id.
+    positions: HashMap<u32, u64>,
 }
 
+impl ClientIndex {
+    fn insert(&mut self, client: u16, id: u32) {
+        self.seq += 1;
+        self.ids.entry(client).or_default().insert(self.seq, id);
+        self.positions.insert(id, self.seq);
+    }
+
+    fn remove(&mut self, client: u16, id: u32) {
+        let Some(position) = self.positions.remove(&id) else {
+            return;
+        };
+        if let Some(ids) = self.ids.get_mut(&client) {
+            ids.remove(&position);
+            if ids.is_empty() {
+                self.ids.remove(&client);
+            }
+        }
+    }
+}
+
+// Transactions by id, along with a per-client index of their ids.
 #[derive(Default, Clone)]
-pub struct InMemoryTxLedger(Arc<RwLock<HashMap<u32, Arc<Mutex<Tx>>>>>);
+pub struct InMemoryTxLedger {
+    txs: Arc<RwLock<HashMap<u32, Arc<Mutex<Tx>>>>>,
+    by_client: Arc<RwLock<ClientIndex>>,
+}
+
+impl InMemoryTxLedger {
+    async fn index(&self, client: u16, id: u32) {
+        self.by_client.write().await.insert(client, id);
+    }
+
+    async fn unindex(&self, client: u16, id: u32) {
+        self.by_client.write().await.remove(client, id);
+    }
+
+    // Ids of the client's transactions, in insertion order.
+    async fn client_ids(&self, client: u16) -> Vec<u32> {
+        self.by_client
+            .read()
+            .await
+            .ids
+            .get(&client)
+            .map(|ids| ids.values().copied().collect())
+            .unwrap_or_default()
+    }
+}
 
 impl TxsDal for InMemoryTxLedger {
+    #[instrument(level = "trace", skip(self))]
     async fn tx(&self, id: u32) -> Option<Arc<Mutex<Tx>>> {
-        self.0.read().await.get(&id).map(|inner| inner.clone())
+        self.txs.read().await.get(&id).cloned()
     }
 
+    #[instrument(level = "trace", skip_all, fields(id = tx.id()))]
     async fn insert(&self, tx: Tx) {
-        self.0
-            .write()
-            .await
-            .insert(tx.id(), Arc::new(Mutex::new(tx)));
+        let (client, id) = (tx.client(), tx.id());
+        let mut txs = self.txs.write().await;
+        match txs.insert(id, Arc::new(Mutex::new(tx))) {
+            None => self.index(client, id).await,
+            // Replaced by a transaction of another client, e.g. when restoring or replicating.
+            Some(replaced) => {
+                let previous = replaced.lock().await.client();
+                if previous != client {
+                    self.unindex(previous, id).await;
+                    self.index(client, id).await;
+                }
+            }
+        }
+    }
+
+    async fn txs(&self) -> tokio::sync::RwLockReadGuard<'_, HashMap<u32, Arc<Mutex<Tx>>>> {
+        self.txs.read().await
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn remove(&self, id: u32) {
+        let mut txs = self.txs.write().await;
+        if let Some(tx) = txs.remove(&id) {
+            let client = tx.lock().await.client();
+            self.unindex(client, id).await;
+        }
     }
-}
\ No newline at end of file
+
+    async fn client_txs(&self, client: u16) -> Vec<Arc<Mutex<Tx>>> {
+        let ids = self.client_ids(client).await;
+        let txs = self.txs.read().await;
+        ids.iter().filter_map(|id| txs.get(id).cloned()).collect()
+    }
+}
+
+// Copy-on-write view over an accounts ledger. An account is copied into the overlay the first
+// time it is accessed through the fork, so changes applied through the fork never reach the base.
+// The copies are kept as they were in the base too, to tell which accounts the fork changed.
+#[derive(Clone)]
+pub struct AccountsFork<A: AccountsDal> {
+    base: A,
+    overlay: InMemoryAccountLedger,
+    originals: Arc<RwLock<HashMap<u16, Account>>>,
+}
+
+impl<A: AccountsDal> AccountsFork<A> {
+    pub fn new(base: A) -> Self {
+        AccountsFork {
+            base,
+            overlay: InMemoryAccountLedger::default(),
+            originals: Arc::default(),
+        }
+    }
+
+    // Accounts changed through the fork, ordered by client id. Accounts missing from the base are
+    // compared with a new account, so the ones only created (e.g. by a rejected transaction) are
+    // left out.
+    pub async fn changes(&self) -> Vec<Account> {
+        let originals = self.originals.read().await;
+        let mut changes = Vec::new();
+        for (id, account) in self.overlay.0.read().await.iter() {
+            let account = account.lock().await.clone();
+            let unchanged = match originals.get(id) {
+                Some(original) => *original == account,
+                None => Account::new_unlocked(*id) == account,
+            };
+            if !unchanged {
+                changes.push(account);
+            }
+        }
+        changes.sort_by_key(Account::client_id);
+        changes
+    }
+
+    // Ids of the accounts accessed or created through the fork.
+    pub async fn ids(&self) -> Vec<u16> {
+        let mut ids: Vec<u16> = self.overlay.0.read().await.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+impl<A: AccountsDal + Send + Sync> AccountsDal for AccountsFork<A> {
+    async fn account(&self, id: u16) -> Option<Arc<Mutex<Account>>> {
+        let mut overlay = self.overlay.0.write().await;
+        if let Some(inner) = overlay.get(&id) {
+            return Some(inner.clone());
+        }
+
+        let copy = self.base.account(id).await?.lock().await.clone();
+        self.originals.write().await.insert(id, copy.clone());
+        let inner = Arc::new(Mutex::new(copy));
+        overlay.insert(id, inner.clone());
+        Some(inner)
+    }
+
+    async fn insert(&mut self, account: Account) {
+        self.overlay.insert(account).await
+    }
+
+    // Listing all the accounts materializes the whole base ledger into the overlay.
+    async fn accounts(
+        &self,
+    ) -> tokio::sync::RwLockReadGuard<'_, HashMap<u16, Arc<Mutex<Account>>>> {
+        {
+            let base = self.base.accounts().await;
+            let mut overlay = self.overlay.0.write().await;
+            let mut originals = self.originals.write().await;
+            for (id, account) in base.iter() {
+                if !overlay.contains_key(id) {
+                    let copy = account.lock().await.clone();
+                    originals.insert(*id, copy.clone());
+                    overlay.insert(*id, Arc::new(Mutex::new(copy)));
+                }
+            }
+        }
+        self.overlay.accounts().await
+    }
+
+    async fn prefetch(&self, ids: &[u16]) {
+        self.base.prefetch(ids).await
+    }
+}
+
+// Copy-on-write view over a transactions ledger, similar to `AccountsFork`. Transactions removed
+// through the fork are hidden from it, rather than removed from the base.
+#[derive(Clone)]
+pub struct TxsFork<T: TxsDal> {
+    base: T,
+    overlay: InMemoryTxLedger,
+    originals: Arc<RwLock<HashMap<u32, Tx>>>,
+    removed: Arc<RwLock<HashSet<u32>>>,
+}
+
+impl<T: TxsDal> TxsFork<T> {
+    pub fn new(base: T) -> Self {
+        TxsFork {
+            base,
+            overlay: InMemoryTxLedger::default(),
+            originals: Arc::default(),
+            removed: Arc::default(),
+        }
+    }
+
+    // Ids of the transactions removed through the fork, in order.
+    pub async fn removed(&self) -> Vec<u32> {
+        let mut removed: Vec<u32> = self.removed.read().await.iter().copied().collect();
+        removed.sort_unstable();
+        removed
+    }
+
+    // Transactions inserted or changed (e.g. disputed) through the fork, ordered by id.
+    pub async fn changes(&self) -> Vec<Tx> {
+        let originals = self.originals.read().await;
+        let mut changes = Vec::new();
+        for (id, tx) in self.overlay.txs.read().await.iter() {
+            let tx = tx.lock().await.clone();
+            if originals.get(id) != Some(&tx) {
+                changes.push(tx);
+            }
+        }
+        changes.sort_by_key(Tx::id);
+        changes
+    }
+}
+
+impl<T: TxsDal + Send + Sync> TxsDal for TxsFork<T> {
+    async fn tx(&self, id: u32) -> Option<Arc<Mutex<Tx>>> {
+        let mut overlay = self.overlay.txs.write().await;
+        if let Some(inner) = overlay.get(&id) {
+            return Some(inner.clone());
+        }
+        if self.removed.read().await.contains(&id) {
+            return None;
+        }
+
+        let copy = self.base.tx(id).await?.lock().await.clone();
+        self.originals.write().await.insert(id, copy.clone());
+        let client = copy.client();
+        let inner = Arc::new(Mutex::new(copy));
+        overlay.insert(id, inner.clone());
+        self.overlay.index(client, id).await;
+        Some(inner)
+    }
+
+    async fn insert(&self, tx: Tx) {
+        self.removed.write().await.remove(&tx.id());
+        self.overlay.insert(tx).await
+    }
+
+    async fn remove(&self, id: u32) {
+        self.overlay.remove(id).await;
+        self.removed.write().await.insert(id);
+    }
+
+    // Listing all the transactions materializes the whole base ledger into the overlay.
+    async fn txs(&self) -> tokio::sync::RwLockReadGuard<'_, HashMap<u32, Arc<Mutex<Tx>>>> {
+        {
+            let base = self.base.txs().await;
+            let mut overlay = self.overlay.txs.write().await;
+            let mut originals = self.originals.write().await;
+            let removed = self.removed.read().await;
+            for (id, tx) in base.iter() {
+                if !overlay.contains_key(id) && !removed.contains(id) {
+                    let copy = tx.lock().await.clone();
+                    originals.insert(*id, copy.clone());
+                    let client = copy.client();
+                    overlay.insert(*id, Arc::new(Mutex::new(copy)));
+                    self.overlay.index(client, *id).await;
+                }
+            }
+        }
+        self.overlay.txs().await
+    }
+
+    async fn prefetch(&self, ids: &[u32]) {
+        self.base.prefetch(ids).await
+    }
+
+    // The base transactions of the client come first, copied into the overlay, followed by the
+    // ones only inserted through the fork.
+    async fn client_txs(&self, client: u16) -> Vec<Arc<Mutex<Tx>>> {
+        let mut ids = Vec::new();
+        for tx in self.base.client_txs(client).await {
+            ids.push(tx.lock().await.id());
+        }
+        let mut seen: HashSet<u32> = ids.iter().copied().collect();
+        for id in self.overlay.client_ids(client).await {
+            if seen.insert(id) {
+                ids.push(id);
+            }
+        }
+
+        let mut txs = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(tx) = self.tx(id).await {
+                txs.push(tx);
+            }
+        }
+        txs
+    }
+}
+
+// Transaction awaiting external approval (screening, manual review, scheduled release) before
+// being handled, along with why it was parked and until when it can be approved.
+#[derive(Debug, Clone)]
+pub struct PendingTx {
+    pub tx: Tx,
+    pub reason: String,
+    // Seconds since the epoch after which the transaction gets dropped, if ever.
+    pub expires_at: Option<u64>,
+}
+
+impl PendingTx {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+// Abstraction over storage for the transactions awaiting approval (see `Engine::approve_pending`).
+// Stores are shared by the engines processing several inputs, hence the interior mutability, and
+// held as trait objects, hence the boxed futures.
+pub trait PendingDal: Send + Sync {
+    // Parks the transaction, replacing any pending transaction with the same id.
+    fn park(&self, pending: PendingTx) -> BoxFuture<'_, ()>;
+    // Pending transactions, ordered by id.
+    fn pending(&self) -> BoxFuture<'_, Vec<PendingTx>>;
+    // Takes the transaction out of the store.
+    fn take(&self, id: u32) -> BoxFuture<'_, Option<PendingTx>>;
+}
+
+#[derive(Default, Clone)]
+pub struct InMemoryPendingLedger(Arc<Mutex<BTreeMap<u32, PendingTx>>>);
+
+impl PendingDal for InMemoryPendingLedger {
+    fn park(&self, pending: PendingTx) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            self.0.lock().await.insert(pending.tx.id(), pending);
+        })
+    }
+
+    fn pending(&self) -> BoxFuture<'_, Vec<PendingTx>> {
+        Box::pin(async move { self.0.lock().await.values().cloned().collect() })
+    }
+
+    fn take(&self, id: u32) -> BoxFuture<'_, Option<PendingTx>> {
+        Box::pin(async move { self.0.lock().await.remove(&id) })
+    }
+}
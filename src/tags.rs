@@ -0,0 +1,82 @@
+use std::collections::{BTreeSet, HashMap};
+
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+
+use crate::error::Error;
+
+#[derive(Deserialize)]
+struct TagRecord {
+    client: u16,
+    tag: String,
+}
+
+// Labels attached to client accounts (e.g. customer segments like `vip`), used to slice reports.
+#[derive(Debug, Clone, Default)]
+pub struct Tags(HashMap<u16, BTreeSet<String>>);
+
+impl Tags {
+    // Loads tags from a CSV with a `client,tag` header, holding one tag of a client per row.
+    pub async fn load(reader: impl AsyncRead + Send + Unpin) -> Result<Self, Error> {
+        let mut tags = Tags::default();
+        let mut records = csv_async::AsyncReaderBuilder::new()
+            .trim(csv_async::Trim::All)
+            .create_deserializer(reader)
+            .into_deserialize::<TagRecord>();
+        while let Some(record) = records.next().await {
+            let record = record.map_err(|err| Error::InvalidRecord(err.to_string()))?;
+            tags.tag(record.client, record.tag);
+        }
+        Ok(tags)
+    }
+
+    pub fn tag(&mut self, client: u16, tag: impl Into<String>) {
+        self.0.entry(client).or_default().insert(tag.into());
+    }
+
+    pub fn untag(&mut self, client: u16, tag: &str) {
+        if let Some(tags) = self.0.get_mut(&client) {
+            tags.remove(tag);
+        }
+    }
+
+    pub fn has_tag(&self, client: u16, tag: &str) -> bool {
+        self.0.get(&client).is_some_and(|tags| tags.contains(tag))
+    }
+
+    pub fn tags(&self, client: u16) -> impl Iterator<Item = &str> {
+        self.0
+            .get(&client)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tags;
+
+    #[tokio::test]
+    async fn load_tags() {
+        let mut tags = Tags::load(tokio::io::BufReader::new(
+            "client,tag\n1,vip\n2, retail \n1,emea".as_bytes(),
+        ))
+        .await
+        .unwrap();
+        assert!(tags.has_tag(1, "vip"));
+        assert!(tags.has_tag(2, "retail"));
+        assert!(!tags.has_tag(2, "vip"));
+        assert_eq!(tags.tags(1).collect::<Vec<_>>(), vec!["emea", "vip"]);
+
+        tags.untag(1, "vip");
+        assert!(!tags.has_tag(1, "vip"));
+    }
+
+    #[tokio::test]
+    async fn load_invalid_tags() {
+        let res = Tags::load(tokio::io::BufReader::new("client,tag\nx,vip".as_bytes())).await;
+        assert!(res.is_err());
+    }
+}
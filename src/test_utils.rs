@@ -0,0 +1,61 @@
+pub mod conformance;
+pub mod dal;
+
+use crate::{
+    payments::Engine,
+    report,
+    storage::{InMemoryAccountLedger, InMemoryTxLedger},
+};
+
+// Sorts the report rows, keeping the header first, since accounts aren't reported in a
+// deterministic order.
+pub fn normalize_report(report: &str) -> Vec<String> {
+    let mut lines = report.lines().map(|line| line.trim().to_string());
+    let header = lines.next();
+    let mut rows: Vec<String> = lines.filter(|line| !line.is_empty()).collect();
+    rows.sort();
+    header.into_iter().chain(rows).collect()
+}
+
+// Runs a full CSV input through the engine and the report writer, and panics with both reports
+// if the produced accounts differ from `expected_output`.
+pub async fn run_case(input: &str, expected_output: &str) {
+    let mut engine = Engine::new(
+        InMemoryAccountLedger::default(),
+        InMemoryTxLedger::default(),
+    );
+    engine
+        .handle_txs(tokio::io::BufReader::new(input.as_bytes()))
+        .await
+        .expect("Processing the input failed");
+
+    let mut output = Vec::new();
+    report::write_accounts(&engine, &mut output)
+        .await
+        .expect("Writing the report failed");
+    let output = String::from_utf8(output).expect("Report is not valid UTF-8");
+
+    assert_eq!(
+        normalize_report(&output),
+        normalize_report(expected_output),
+        "\nactual report:\n{output}\nexpected report:\n{expected_output}"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::run_case;
+
+    #[tokio::test]
+    async fn golden_cases() {
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        for case in std::fs::read_dir(golden).unwrap() {
+            let case = case.unwrap().path();
+            let input = std::fs::read_to_string(case.join("input.csv")).unwrap();
+            let expected = std::fs::read_to_string(case.join("expected.csv")).unwrap();
+            run_case(&input, &expected).await;
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Conformance checks for storage backends. Any `AccountsDal`/`TxsDal` implementation can run them
+// against fresh instances built by `new` to verify the semantics the engine relies on: lookups see
+// earlier writes, changes made through returned handles are kept, clones share the same storage
+// and handles can be locked and modified from concurrent tasks. Failures panic with the violated
+// requirement.
+use bigdecimal::BigDecimal;
+use futures::future::join_all;
+
+use crate::{
+    account::Account,
+    payments::{Tx, TxType},
+    storage::{AccountsDal, TxsDal},
+};
+
+// Number of concurrent updates applied on a single entry.
+const CONCURRENT_UPDATES: u64 = 64;
+
+pub async fn check_accounts_dal<A, F>(new: F)
+where
+    A: AccountsDal + Clone + Send + Sync,
+    F: Fn() -> A,
+{
+    let mut dal = new();
+    assert!(
+        dal.account(1).await.is_none(),
+        "missing accounts must not be found"
+    );
+
+    dal.insert(Account::new(
+        1,
+        BigDecimal::from(5),
+        BigDecimal::from(1),
+        false,
+    ))
+    .await;
+    let account = dal.account(1).await.expect("inserted account not found");
+    assert_eq!(
+        account.lock().await.total(),
+        BigDecimal::from(6),
+        "inserted account must be returned as is"
+    );
+
+    account.lock().await.add_available(&BigDecimal::from(1));
+    drop(account);
+    let account = dal.account(1).await.expect("account not found");
+    assert_eq!(
+        account.lock().await.available(),
+        BigDecimal::from(6),
+        "changes made through a handle must be kept"
+    );
+    drop(account);
+
+    dal.insert(Account::new(
+        1,
+        BigDecimal::from(2),
+        BigDecimal::from(0),
+        true,
+    ))
+    .await;
+    let account = dal.account(1).await.expect("account not found");
+    assert!(
+        account.lock().await.is_locked(),
+        "inserting an existing account must replace it"
+    );
+    drop(account);
+
+    let mut clone = dal.clone();
+    clone.insert(Account::new_unlocked(2)).await;
+    assert!(
+        dal.account(2).await.is_some(),
+        "clones must share the same storage"
+    );
+
+    let mut ids: Vec<u16> = dal.accounts().await.keys().copied().collect();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![1, 2], "listing must return all the accounts");
+    dal.prefetch(&[1, 2, 3]).await;
+    assert!(
+        dal.account(3).await.is_none(),
+        "prefetching must not create accounts"
+    );
+
+    // Every update locks the account, so none of them may get lost.
+    join_all((0..CONCURRENT_UPDATES).map(|_| {
+        let dal = dal.clone();
+        async move {
+            let account = dal.account(2).await.expect("account not found");
+            let mut inner = account.lock().await;
+            let available = inner.available();
+            tokio::task::yield_now().await;
+            assert_eq!(
+                inner.available(),
+                available,
+                "a locked account must not be modified by others"
+            );
+            inner.add_available(&BigDecimal::from(1));
+        }
+    }))
+    .await;
+    let account = dal.account(2).await.expect("account not found");
+    assert_eq!(
+        account.lock().await.available(),
+        BigDecimal::from(CONCURRENT_UPDATES),
+        "concurrent updates of an account must not get lost"
+    );
+}
+
+pub async fn check_txs_dal<T, F>(new: F)
+where
+    T: TxsDal + Clone + Send + Sync,
+    F: Fn() -> T,
+{
+    let dal = new();
+    assert!(dal.tx(1).await.is_none(), "missing txs must not be found");
+
+    dal.insert(Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(5))))
+        .await;
+    dal.insert(Tx::new(TxType::Deposit, 2, 2, Some(BigDecimal::from(1))))
+        .await;
+    dal.insert(Tx::new(TxType::Withdrawal, 1, 3, Some(BigDecimal::from(2))))
+        .await;
+    let tx = dal.tx(1).await.expect("inserted tx not found");
+    assert_eq!(
+        tx.lock().await.amount(),
+        Some(&BigDecimal::from(5)),
+        "inserted tx must be returned as is"
+    );
+
+    tx.lock().await.mark_disputed();
+    drop(tx);
+    let tx = dal.tx(1).await.expect("tx not found");
+    assert!(
+        tx.lock().await.disputed(),
+        "changes made through a handle must be kept"
+    );
+    drop(tx);
+
+    let clone = dal.clone();
+    clone
+        .insert(Tx::new(TxType::Deposit, 1, 4, Some(BigDecimal::from(1))))
+        .await;
+    assert!(
+        dal.tx(4).await.is_some(),
+        "clones must share the same storage"
+    );
+
+    let mut ids: Vec<u32> = dal.txs().await.keys().copied().collect();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![1, 2, 3, 4], "listing must return all the txs");
+
+    let mut client_txs = Vec::new();
+    for tx in dal.client_txs(1).await {
+        client_txs.push(tx.lock().await.id());
+    }
+    assert_eq!(
+        client_txs,
+        vec![1, 3, 4],
+        "client txs must be returned in insertion order"
+    );
+
+    // Removing is optional, but must not break lookups of the other transactions.
+    dal.remove(3).await;
+    assert!(
+        dal.tx(1).await.is_some(),
+        "removing must only drop the given tx"
+    );
+
+    join_all((0..CONCURRENT_UPDATES).map(|idx| {
+        let dal = dal.clone();
+        async move {
+            let id = 100 + idx as u32;
+            dal.insert(Tx::new(TxType::Deposit, 3, id, Some(BigDecimal::from(1))))
+                .await;
+        }
+    }))
+    .await;
+    for idx in 0..CONCURRENT_UPDATES {
+        assert!(
+            dal.tx(100 + idx as u32).await.is_some(),
+            "concurrent inserts must not get lost"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cache::{CacheConfig, Cached},
+        storage::{AccountsFork, InMemoryAccountLedger, InMemoryTxLedger, TxsFork},
+    };
+
+    use super::{check_accounts_dal, check_txs_dal};
+
+    #[tokio::test]
+    async fn in_memory_ledgers_conform() {
+        check_accounts_dal(InMemoryAccountLedger::default).await;
+        check_txs_dal(InMemoryTxLedger::default).await;
+    }
+
+    #[tokio::test]
+    async fn forks_conform() {
+        check_accounts_dal(|| AccountsFork::new(InMemoryAccountLedger::default())).await;
+        check_txs_dal(|| TxsFork::new(InMemoryTxLedger::default())).await;
+    }
+
+    #[tokio::test]
+    async fn cached_ledgers_conform() {
+        let config = CacheConfig {
+            capacity: 2,
+            flush_threshold: 1,
+        };
+        check_accounts_dal(|| Cached::new(InMemoryAccountLedger::default(), config)).await;
+        check_txs_dal(|| Cached::new(InMemoryTxLedger::default(), config)).await;
+    }
+}
@@ -0,0 +1,305 @@
+// Storage doubles for unit-testing integrations with the engine without standing up real storage.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+
+use crate::{
+    account::Account,
+    payments::Tx,
+    storage::{AccountsDal, TxsDal},
+};
+
+// In-memory accounts and transactions storage whose lookups can be scripted: responses queued for
+// an id are returned by its next lookups, in order, before falling back to the stored entries.
+// Lookups can also be slowed down, simulating a remote backend.
+#[derive(Default, Clone)]
+pub struct MockDal {
+    accounts: Arc<RwLock<HashMap<u16, Arc<Mutex<Account>>>>>,
+    txs: Arc<RwLock<HashMap<u32, Arc<Mutex<Tx>>>>>,
+    account_responses: Arc<std::sync::Mutex<HashMap<u16, VecDeque<Option<Account>>>>>,
+    tx_responses: Arc<std::sync::Mutex<HashMap<u32, VecDeque<Option<Tx>>>>>,
+    latency: Option<Duration>,
+}
+
+impl MockDal {
+    pub fn with_account(self, account: Account) -> Self {
+        self.accounts
+            .try_write()
+            .expect("mock accessed while being set up")
+            .insert(account.client_id(), Arc::new(Mutex::new(account)));
+        self
+    }
+
+    pub fn with_tx(self, tx: Tx) -> Self {
+        self.txs
+            .try_write()
+            .expect("mock accessed while being set up")
+            .insert(tx.id(), Arc::new(Mutex::new(tx)));
+        self
+    }
+
+    // Delays every lookup by `latency`.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    async fn delay(&self) {
+        if let Some(latency) = self.latency {
+            tokio::time::sleep(latency).await;
+        }
+    }
+
+    // Queues the response of the next lookup of the account, `None` simulating a missing one.
+    pub fn respond_account(&self, id: u16, response: Option<Account>) {
+        self.account_responses
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_default()
+            .push_back(response);
+    }
+
+    // Same as `respond_account`, for transactions.
+    pub fn respond_tx(&self, id: u32, response: Option<Tx>) {
+        self.tx_responses
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_default()
+            .push_back(response);
+    }
+}
+
+impl AccountsDal for MockDal {
+    async fn account(&self, id: u16) -> Option<Arc<Mutex<Account>>> {
+        self.delay().await;
+        let scripted = self
+            .account_responses
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .and_then(VecDeque::pop_front);
+        match scripted {
+            Some(response) => response.map(|account| Arc::new(Mutex::new(account))),
+            None => self.accounts.read().await.get(&id).cloned(),
+        }
+    }
+
+    async fn insert(&mut self, account: Account) {
+        self.accounts
+            .write()
+            .await
+            .insert(account.client_id(), Arc::new(Mutex::new(account)));
+    }
+
+    async fn accounts(&self) -> RwLockReadGuard<'_, HashMap<u16, Arc<Mutex<Account>>>> {
+        self.accounts.read().await
+    }
+}
+
+impl TxsDal for MockDal {
+    async fn tx(&self, id: u32) -> Option<Arc<Mutex<Tx>>> {
+        self.delay().await;
+        let scripted = self
+            .tx_responses
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .and_then(VecDeque::pop_front);
+        match scripted {
+            Some(response) => response.map(|tx| Arc::new(Mutex::new(tx))),
+            None => self.txs.read().await.get(&id).cloned(),
+        }
+    }
+
+    async fn insert(&self, tx: Tx) {
+        self.txs
+            .write()
+            .await
+            .insert(tx.id(), Arc::new(Mutex::new(tx)));
+    }
+
+    async fn txs(&self) -> RwLockReadGuard<'_, HashMap<u32, Arc<Mutex<Tx>>>> {
+        self.txs.read().await
+    }
+
+    async fn remove(&self, id: u32) {
+        self.txs.write().await.remove(&id);
+    }
+}
+
+// Storage call recorded by `RecordingDal`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DalCall {
+    Account(u16),
+    InsertAccount(u16),
+    Accounts,
+    PrefetchAccounts(Vec<u16>),
+    Tx(u32),
+    InsertTx(u32),
+    Txs,
+    PrefetchTxs(Vec<u32>),
+    RemoveTx(u32),
+    ClientTxs(u16),
+}
+
+// Decorator logging every call made to the wrapped storage, in order.
+#[derive(Default, Clone)]
+pub struct RecordingDal<D> {
+    inner: D,
+    calls: Arc<std::sync::Mutex<Vec<DalCall>>>,
+}
+
+impl<D> RecordingDal<D> {
+    pub fn new(inner: D) -> Self {
+        RecordingDal {
+            inner,
+            calls: Arc::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    // Calls recorded so far, shared by all the clones of the decorator.
+    pub fn calls(&self) -> Vec<DalCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+
+    fn record(&self, call: DalCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+impl<D: AccountsDal + Send + Sync> AccountsDal for RecordingDal<D> {
+    async fn account(&self, id: u16) -> Option<Arc<Mutex<Account>>> {
+        self.record(DalCall::Account(id));
+        self.inner.account(id).await
+    }
+
+    async fn insert(&mut self, account: Account) {
+        self.record(DalCall::InsertAccount(account.client_id()));
+        self.inner.insert(account).await
+    }
+
+    async fn accounts(&self) -> RwLockReadGuard<'_, HashMap<u16, Arc<Mutex<Account>>>> {
+        self.record(DalCall::Accounts);
+        self.inner.accounts().await
+    }
+
+    async fn prefetch(&self, ids: &[u16]) {
+        self.record(DalCall::PrefetchAccounts(ids.to_vec()));
+        self.inner.prefetch(ids).await
+    }
+}
+
+impl<D: TxsDal + Send + Sync> TxsDal for RecordingDal<D> {
+    async fn tx(&self, id: u32) -> Option<Arc<Mutex<Tx>>> {
+        self.record(DalCall::Tx(id));
+        self.inner.tx(id).await
+    }
+
+    async fn insert(&self, tx: Tx) {
+        self.record(DalCall::InsertTx(tx.id()));
+        self.inner.insert(tx).await
+    }
+
+    async fn txs(&self) -> RwLockReadGuard<'_, HashMap<u32, Arc<Mutex<Tx>>>> {
+        self.record(DalCall::Txs);
+        self.inner.txs().await
+    }
+
+    async fn prefetch(&self, ids: &[u32]) {
+        self.record(DalCall::PrefetchTxs(ids.to_vec()));
+        self.inner.prefetch(ids).await
+    }
+
+    async fn remove(&self, id: u32) {
+        self.record(DalCall::RemoveTx(id));
+        self.inner.remove(id).await
+    }
+
+    async fn client_txs(&self, client: u16) -> Vec<Arc<Mutex<Tx>>> {
+        self.record(DalCall::ClientTxs(client));
+        self.inner.client_txs(client).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use crate::{
+        account::Account,
+        error::Error,
+        outcome::TxOutcome,
+        payments::{Engine, Tx, TxType},
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+        test_utils::conformance::{check_accounts_dal, check_txs_dal},
+    };
+
+    use super::{DalCall, MockDal, RecordingDal};
+
+    #[tokio::test]
+    async fn doubles_conform() {
+        check_accounts_dal(MockDal::default).await;
+        check_txs_dal(MockDal::default).await;
+        check_accounts_dal(|| RecordingDal::new(InMemoryAccountLedger::default())).await;
+        check_txs_dal(|| RecordingDal::new(InMemoryTxLedger::default())).await;
+    }
+
+    #[tokio::test]
+    async fn scripted_missing_account() {
+        let accounts = MockDal::default().with_account(Account::new_unlocked(2));
+        accounts.respond_account(1, None);
+        accounts.respond_account(1, None);
+        accounts.respond_account(2, None);
+        let mut engine = Engine::new(accounts, MockDal::default());
+
+        let deposit = Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(1)));
+        assert_eq!(
+            engine.handle_tx(deposit.clone()).await,
+            TxOutcome::Failed(Error::UnexpectedMissingAccount(1))
+        );
+        // Responses are used up, falling back to the stored accounts.
+        assert_eq!(engine.handle_tx(deposit).await, TxOutcome::Applied);
+    }
+
+    #[tokio::test]
+    async fn records_calls() {
+        let accounts = RecordingDal::new(InMemoryAccountLedger::default());
+        let txs = RecordingDal::new(InMemoryTxLedger::default());
+        let mut engine = Engine::new(accounts.clone(), txs.clone());
+        for tx in [
+            Tx::new(TxType::Deposit, 1, 1, Some(BigDecimal::from(1))),
+            Tx::new(TxType::Dispute, 1, 1, None),
+        ] {
+            assert!(engine.handle_tx(tx).await.is_applied());
+        }
+
+        assert_eq!(
+            accounts.calls(),
+            vec![
+                DalCall::Account(1),
+                DalCall::InsertAccount(1),
+                DalCall::Account(1),
+                DalCall::Account(1),
+            ]
+        );
+        // The deposit is looked up first, as a possible duplicate.
+        assert_eq!(
+            txs.calls(),
+            vec![DalCall::Tx(1), DalCall::InsertTx(1), DalCall::Tx(1)]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use bigdecimal::BigDecimal;
+use csv_async::AsyncWriterBuilder;
+use tokio::io::AsyncWrite;
+
+use crate::{aggregates::Aggregates, format::format_amount, storage::AccountsDal};
+
+// Measure accounts are ranked by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Measure {
+    // Held balance, from the accounts ledger.
+    Held,
+    // Deposited plus withdrawn funds, from the aggregates.
+    Volume,
+    // Number of chargebacks, from the aggregates.
+    Chargebacks,
+}
+
+impl Measure {
+    pub fn name(self) -> &'static str {
+        match self {
+            Measure::Held => "held",
+            Measure::Volume => "volume",
+            Measure::Chargebacks => "chargebacks",
+        }
+    }
+}
+
+// The `n` clients with the largest `measure`, largest first, ties being ordered by client id.
+// Clients with nothing to measure (e.g. no chargebacks) are left out.
+pub async fn top<A: AccountsDal>(
+    accounts: &A,
+    aggregates: &Aggregates,
+    measure: Measure,
+    n: usize,
+) -> Vec<(u16, BigDecimal)> {
+    let mut ranked: Vec<(u16, BigDecimal)> = match measure {
+        Measure::Held => {
+            let mut held = Vec::new();
+            for (client, account) in accounts.accounts().await.iter() {
+                held.push((*client, account.lock().await.held()));
+            }
+            held
+        }
+        Measure::Volume => aggregates
+            .clients()
+            .into_iter()
+            .map(|(client, totals)| (client, totals.deposited + totals.withdrawn))
+            .collect(),
+        Measure::Chargebacks => aggregates
+            .clients()
+            .into_iter()
+            .map(|(client, totals)| (client, BigDecimal::from(totals.chargebacks)))
+            .collect(),
+    };
+    ranked.retain(|(_, value)| *value > BigDecimal::from(0));
+    ranked.sort_by(|(client, value), (other_client, other)| {
+        other.cmp(value).then(client.cmp(other_client))
+    });
+    ranked.truncate(n);
+    ranked
+}
+
+// Writes the ranking as `client,<measure>` CSV rows.
+pub async fn write_top<W: AsyncWrite + Unpin>(
+    ranked: Vec<(u16, BigDecimal)>,
+    measure: Measure,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    let mut serializer = AsyncWriterBuilder::new()
+        .has_headers(false)
+        .create_serializer(writer);
+    serializer.serialize(("client", measure.name())).await?;
+    for (client, value) in ranked {
+        serializer.serialize((client, format_amount(value))).await?;
+    }
+    serializer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        aggregates::Aggregates,
+        payments::Engine,
+        storage::{InMemoryAccountLedger, InMemoryTxLedger},
+    };
+
+    use super::{top, write_top, Measure};
+
+    #[tokio::test]
+    async fn largest_accounts() {
+        let aggregates = Aggregates::default();
+        let mut engine = Engine::builder(
+            InMemoryAccountLedger::default(),
+            InMemoryTxLedger::default(),
+        )
+        .plugin(aggregates.clone())
+        .build()
+        .unwrap();
+        engine
+            .handle_txs(
+                "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,5.0\nwithdrawal,2,3,1.0\n\
+                deposit,3,4,6.0\ndeposit,1,5,4.0\ndispute,1,5,\ndispute,3,4,\nchargeback,3,4,"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        let ranked = top(&engine, &aggregates, Measure::Volume, 2).await;
+        write_top(ranked, Measure::Volume, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,volume\n1,6.0\n2,6.0\n"
+        );
+
+        let held = top(&engine, &aggregates, Measure::Held, 20).await;
+        assert_eq!(held, vec![(1, 4.into())]);
+        let chargebacks = top(&engine, &aggregates, Measure::Chargebacks, 20).await;
+        assert_eq!(chargebacks, vec![(3, 1.into())]);
+    }
+}
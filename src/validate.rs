@@ -0,0 +1,265 @@
+// Validation of the command line configuration, run before processing starts so that every
+// problem gets reported at once instead of one per attempt.
+use std::{fmt, str::FromStr};
+
+use bigdecimal::BigDecimal;
+use payments_engine::{
+    alerts::{AlertRule, AlertSink},
+    failpoints::CrashPoint,
+    report::Column,
+    shard::Routing,
+    source,
+};
+
+use crate::cli::Args;
+
+// Configuration problem, along with the option it was found in and how to fix it, if known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub field: &'static str,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl Problem {
+    fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Problem {
+            field,
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    fn suggest(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " ({suggestion})")?;
+        }
+        Ok(())
+    }
+}
+
+// Number of single character edits turning `a` into `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Closest candidate to a misspelled value, if close enough to be what was meant.
+fn closest<'c>(value: &str, candidates: impl IntoIterator<Item = &'c str>) -> Option<&'c str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (distance(value, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+fn amount(field: &'static str, value: &Option<String>, problems: &mut Vec<Problem>) {
+    if let Some(value) = value {
+        if BigDecimal::from_str(value).is_err() {
+            problems.push(
+                Problem::new(field, format!("invalid amount `{value}`"))
+                    .suggest("expected a decimal number, e.g. 1000.50"),
+            );
+        }
+    }
+}
+
+// Every problem of the configuration, in the order of the options.
+pub fn validate(args: &Args) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let stdin = args
+        .input
+        .iter()
+        .filter(|path| *path == source::STDIN)
+        .count();
+    if stdin > 1 {
+        problems.push(Problem::new(
+            "input",
+            "the standard input can only be read once",
+        ));
+    }
+    if args.shards.is_some() && args.input.len() > 1 {
+        problems.push(
+            Problem::new("--shards", "sharding only supports a single input file")
+                .suggest("use --jobs to process several files concurrently"),
+        );
+    }
+    if args.merge_by.is_some() && args.input.len() < 2 {
+        problems.push(
+            Problem::new("--merge-by", "merging only applies to several input files")
+                .suggest("use --reorder-by to reorder a single input"),
+        );
+    }
+    if let Err(err) = Routing::from_str(&args.shard_routing) {
+        problems.push(
+            Problem::new("--shard-routing", err)
+                .suggest("expected modulo, rendezvous or ranges:<from>-<to>=<shard>,..."),
+        );
+    }
+    if args.strict && (args.shards.is_some() || args.input.len() > 1) {
+        problems.push(Problem::new(
+            "--strict",
+            "strict mode only supports a single input file, without shards",
+        ));
+    }
+    if args.dispute_withdrawals && args.prune_terminal {
+        problems.push(
+            Problem::new(
+                "--dispute-withdrawals",
+                "withdrawals can't be disputed once dropped by --prune-terminal",
+            )
+            .suggest("drop one of them"),
+        );
+    }
+    amount(
+        "--max-deposit-volume",
+        &args.max_deposit_volume,
+        &mut problems,
+    );
+    amount("--max-amount", &args.max_amount, &mut problems);
+    amount("--quarantine-above", &args.quarantine_above, &mut problems);
+    let parse =
+        |value: &Option<String>| value.as_deref().and_then(|v| BigDecimal::from_str(v).ok());
+    if let (Some(max), Some(threshold)) = (parse(&args.max_amount), parse(&args.quarantine_above)) {
+        if threshold >= max {
+            problems.push(
+                Problem::new(
+                    "--quarantine-above",
+                    format!(
+                        "no transaction can be quarantined above {threshold}, amounts above \
+                        {max} being rejected by --max-amount"
+                    ),
+                )
+                .suggest("lower --quarantine-above below --max-amount"),
+            );
+        }
+    }
+    if args.review_suspicious && args.suspicious_factor.is_none() {
+        problems.push(
+            Problem::new(
+                "--review-suspicious",
+                "no amounts get flagged as suspicious",
+            )
+            .suggest("set --suspicious-factor"),
+        );
+    }
+    for column in &args.columns {
+        if let Err(err) = Column::from_str(column) {
+            let mut problem = Problem::new("--columns", err);
+            if let Some(name) = closest(column, Column::ALL.iter().map(|column| column.name())) {
+                problem = problem.suggest(format!("did you mean `{name}`?"));
+            }
+            problems.push(problem);
+        }
+    }
+    if !args.decimal_separator.is_ascii() {
+        problems.push(Problem::new(
+            "--decimal-separator",
+            "the decimal separator must be an ASCII character",
+        ));
+    }
+    for rule in &args.alert {
+        if let Err(err) = AlertRule::from_str(rule) {
+            let mut problem = Problem::new("--alert", err);
+            let name = rule.split_once('=').map_or(rule.as_str(), |(name, _)| name);
+            match closest(name, ["held_above", "chargebacks", "locked_accounts"]) {
+                Some(known) if known != name => {
+                    problem = problem.suggest(format!("did you mean `{known}`?"));
+                }
+                _ => {
+                    problem = problem.suggest(
+                        "expected held_above=<amount>, chargebacks=<count>/<window> or \
+                        locked_accounts=<count>/<window>",
+                    );
+                }
+            }
+            problems.push(problem);
+        }
+    }
+    for sink in &args.alert_sink {
+        if let Err(err) = AlertSink::from_str(sink) {
+            let suggestion = if sink.starts_with("webhook:") {
+                "webhook sinks require the `webhooks` feature"
+            } else {
+                "expected stderr, file:<path> or webhook:<url>"
+            };
+            problems.push(Problem::new("--alert-sink", err).suggest(suggestion));
+        }
+    }
+    for point in &args.crash_on {
+        if let Err(err) = CrashPoint::from_str(point) {
+            problems.push(Problem::new("--crash-on", err).suggest("expected tx=<id>"));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::cli::Args;
+
+    use super::validate;
+
+    #[test]
+    fn every_problem_at_once() {
+        let args = Args::parse_from([
+            "payments-engine",
+            "a.csv",
+            "b.csv",
+            "--shards",
+            "2",
+            "--max-amount",
+            "100",
+            "--quarantine-above",
+            "500",
+            "--review-suspicious",
+            "--columns",
+            "client,availble",
+            "--alert",
+            "chargeback=5/100",
+            "--alert-sink",
+            "kafka:payments",
+            "--decimal-separator",
+            "é",
+        ]);
+
+        let problems: Vec<String> = validate(&args).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            problems,
+            [
+                "--shards: sharding only supports a single input file (use --jobs to process \
+                several files concurrently)",
+                "--quarantine-above: no transaction can be quarantined above 500, amounts above \
+                100 being rejected by --max-amount (lower --quarantine-above below --max-amount)",
+                "--review-suspicious: no amounts get flagged as suspicious (set \
+                --suspicious-factor)",
+                "--columns: Unknown report column: availble (did you mean `available`?)",
+                "--decimal-separator: the decimal separator must be an ASCII character",
+                "--alert: Invalid alert rule: chargeback=5/100 (did you mean `chargebacks`?)",
+                "--alert-sink: Invalid alert sink: kafka:payments (expected stderr, file:<path> \
+                or webhook:<url>)",
+            ]
+        );
+    }
+}
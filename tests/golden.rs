@@ -0,0 +1,33 @@
+// End-to-end checks of the binary against the golden cases under `tests/golden`. Every case is a
+// directory holding an `input.csv` and the `expected.csv` account report.
+use std::{fs, path::Path, process::Command};
+
+fn normalize_report(report: &str) -> Vec<String> {
+    let mut lines = report.lines().map(|line| line.trim().to_string());
+    let header = lines.next();
+    let mut rows: Vec<String> = lines.filter(|line| !line.is_empty()).collect();
+    rows.sort();
+    header.into_iter().chain(rows).collect()
+}
+
+#[test]
+fn golden_cases() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for case in fs::read_dir(golden).unwrap() {
+        let case = case.unwrap().path();
+        let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+            .arg(case.join("input.csv"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "case {} failed", case.display());
+
+        let actual = String::from_utf8(output.stdout).unwrap();
+        let expected = fs::read_to_string(case.join("expected.csv")).unwrap();
+        assert_eq!(
+            normalize_report(&actual),
+            normalize_report(&expected),
+            "case {}",
+            case.display()
+        );
+    }
+}